@@ -0,0 +1,68 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::{
+    mongodb::{ inventory::StockAlert, stores::StoreInfo },
+    responses::ExpiryReport,
+};
+
+/// Emails everyone working at a store. Failing to reach one of them doesn't
+/// stop the rest from getting it.
+pub async fn email_store_staff(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    subject: &str,
+    html: &str,
+    text: &str,
+) -> Result<()> {
+    for (email, name) in crate::database::get_store_staff_contacts(db, store.id).await? {
+        if let Err(e) = utils::emails::send_email(None, email.clone(), name, subject, html, text).await {
+            tracing::error!(target: "backend", "Failed to email `{}` of `{}`: {}", email, store.name, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Emails the staff of a store the lots expiring soon and the units just written off.
+pub async fn email_expiry_report(db: &mongodb::Database, store: &StoreInfo, report: &ExpiryReport) -> Result<()> {
+    let template = crate::ENV.get_template("expiry_alert.html")?;
+    let html = template.render(minijinja::context! { report => report })?;
+
+    let mut text = vec![format!("Expiry report of {}", report.store)];
+    text.extend(report.expiring.iter().map(|lot| format!(
+        "Expiring in {} days: {} ({} units, lot {}){}",
+        lot.days_left,
+        lot.name,
+        lot.units,
+        lot.lot,
+        lot.markdown.map(|discount| format!(", marked down by {}%", (discount * 100.0).round())).unwrap_or_default(),
+    )));
+    text.extend(report.wasted.iter().map(|write_off| format!(
+        "Written off: {} units of lot {} of `{}`",
+        write_off.codes.len(),
+        write_off.lot,
+        write_off.coll,
+    )));
+
+    let subject = format!("Expiry report of {}", report.store);
+    email_store_staff(db, store, &subject, &html, &text.join("\n")).await
+}
+
+/// Emails the staff of a store the items which dropped to their reorder point.
+pub async fn email_low_stock(db: &mongodb::Database, store: &StoreInfo, alerts: &[StockAlert]) -> Result<()> {
+    let template = crate::ENV.get_template("low_stock_alert.html")?;
+    let html = template.render(minijinja::context! { store => &store.name, alerts => alerts })?;
+
+    let mut text = vec![format!("Items running out at {}", store.name)];
+    text.extend(alerts.iter().map(|alert| format!(
+        "{}: {} units left, reorder point {}, order {} to reach {}",
+        alert.name,
+        alert.units,
+        alert.reorder.point,
+        (alert.reorder.target - alert.units).max(0),
+        alert.reorder.target,
+    )));
+
+    let subject = format!("{} items running out at {}", alerts.len(), store.name);
+    email_store_staff(db, store, &subject, &html, &text.join("\n")).await
+}
@@ -0,0 +1,327 @@
+use serde_json::{ Map, Value };
+
+/// How the cell of a column is read and written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Text,
+    Number,
+    Integer,
+    Bool,
+    /// Texts separated by `|`.
+    List,
+    /// Hex id of an item.
+    Id,
+    /// Any JSON value, for fields which don't fit in a cell otherwise.
+    Json,
+}
+
+/// A column of a CSV file: the path of its field, with `.` between the
+/// keys of nested fields, and how to read it.
+pub type Column = (&'static str, Kind);
+
+const CLOTHES: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("gender", Kind::Text),
+    ("age", Kind::Text),
+    ("size", Kind::Text),
+    ("color", Kind::List),
+    ("type", Kind::Text),
+    ("brand", Kind::Text),
+    ("material", Kind::Json),
+];
+
+const FOOD: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("pricePerKg", Kind::Number),
+    ("type", Kind::Text),
+];
+
+const LIBRARY_ITEM: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("book.isbn", Kind::Text),
+    ("book.numPages", Kind::Integer),
+    ("book.author", Kind::List),
+    ("book.publisher", Kind::Text),
+    ("book.edition", Kind::Integer),
+    ("book.audience", Kind::List),
+    ("book.genre", Kind::List),
+];
+
+const TECH_CPU: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("brand", Kind::Text),
+    ("model", Kind::Text),
+    ("arch", Kind::Text),
+    ("cores", Kind::Integer),
+    ("threads", Kind::Integer),
+    ("socketType", Kind::Text),
+    ("overclockSupp", Kind::Bool),
+    ("soldSep", Kind::Bool),
+    ("warranty", Kind::Text),
+    ("memorySupp.type", Kind::Text),
+    ("memorySupp.maxSizeGb", Kind::Integer),
+    ("clock.coreSpeedGhz", Kind::Number),
+    ("clock.boostSpeedGhz", Kind::Number),
+];
+
+const TECH_GPU: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("brand", Kind::Text),
+    ("model", Kind::Text),
+    ("cuda_cores", Kind::Integer),
+    ("tdp", Kind::Integer),
+    ("ports", Kind::List),
+    ("dedicated", Kind::Bool),
+    ("warranty", Kind::Text),
+    ("memory.type", Kind::Text),
+    ("memory.sizeGb", Kind::Integer),
+    ("clock.coreSpeedGhz", Kind::Number),
+    ("clock.boostSpeedGhz", Kind::Number),
+];
+
+const TECH: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("brand", Kind::Text),
+    ("model", Kind::Text),
+    ("color", Kind::List),
+    ("type", Kind::Text),
+    ("memory", Kind::Integer),
+    ("cpu", Kind::Id),
+    ("gpu", Kind::Id),
+];
+
+const TECH_KEYBOARD: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("brand", Kind::Text),
+    ("model", Kind::Text),
+    ("type", Kind::Text),
+    ("keySwitch", Kind::Text),
+    ("backlight", Kind::Bool),
+    ("wireless", Kind::Bool),
+    ("dimensions.length", Kind::Number),
+    ("dimensions.width", Kind::Number),
+    ("dimensions.height", Kind::Number),
+    ("weightKg", Kind::Number),
+];
+
+const OTHER: &[Column] = &[
+    ("name", Kind::Text),
+    ("price", Kind::Number),
+    ("brand", Kind::Text),
+];
+
+/// Columns of the CSV files of an item collection, besides `sku`. They follow
+/// the schemas of `CatalogItem`.
+pub fn columns(coll: &str) -> &'static [Column] {
+    match coll {
+        "clothes" => CLOTHES,
+        "food" => FOOD,
+        "libraryItem" => LIBRARY_ITEM,
+        "techCpu" => TECH_CPU,
+        "techGpu" => TECH_GPU,
+        "tech" => TECH,
+        "techKeyboard" => TECH_KEYBOARD,
+        _ => OTHER,
+    }
+}
+
+/// Reads a cell into the fields of an item. Empty cells are left out, and so
+/// are nested fields whose cells are all empty.
+pub fn read_cell(fields: &mut Map<String, Value>, (path, kind): Column, cell: &str) -> Result<(), String> {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(());
+    }
+
+    let value = match kind {
+        Kind::Text | Kind::Id => Value::String(cell.to_string()),
+        Kind::Number => cell.parse::<f64>().ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| format!("`{}` must be a number.", path))?,
+        Kind::Integer => cell.parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| format!("`{}` must be a whole number.", path))?,
+        Kind::Bool => match cell.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "1" => Value::Bool(true),
+            "false" | "no" | "0" => Value::Bool(false),
+            _ => return Err(format!("`{}` must be `true` or `false`.", path)),
+        },
+        Kind::List => Value::Array(
+            cell.split('|')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| Value::String(value.to_string()))
+                .collect()
+        ),
+        Kind::Json => serde_json::from_str(cell).map_err(|e| format!("`{}` must be JSON: {}", path, e))?,
+    };
+
+    let mut keys = path.split('.').peekable();
+    let mut fields = fields;
+    while let Some(key) = keys.next() {
+        if keys.peek().is_none() {
+            fields.insert(key.to_string(), value);
+            break;
+        }
+        let nested = fields.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(nested) = nested else {
+            return Err(format!("`{}` is set twice.", path));
+        };
+        fields = nested;
+    }
+
+    Ok(())
+}
+
+/// Writes a field of an item, in relaxed extended JSON, as a cell. Missing fields are left empty.
+pub fn write_cell(fields: &Value, (path, kind): Column) -> String {
+    let value = path.split('.').try_fold(fields, |value, key| value.get(key));
+
+    match (value, kind) {
+        (None | Some(Value::Null), _) => String::new(),
+        (Some(Value::String(text)), _) => text.clone(),
+        (Some(Value::Array(values)), Kind::List) => values.iter()
+            .map(|value| match value {
+                Value::String(text) => text.clone(),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("|"),
+        (Some(Value::Array(values)), Kind::Json) if values.is_empty() => String::new(),
+        (Some(value), Kind::Id) => value.get("$oid")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string()),
+        (Some(value), _) => value.to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read(cells: &[(Column, &str)]) -> Result<Value, String> {
+        let mut fields = Map::new();
+        for (column, cell) in cells {
+            read_cell(&mut fields, *column, cell)?;
+        }
+        Ok(Value::Object(fields))
+    }
+
+    #[test]
+    fn reads_cells_by_kind() {
+        let fields = read(&[
+            (("name", Kind::Text), "  Keyboard "),
+            (("price", Kind::Number), "49.90"),
+            (("cores", Kind::Integer), "8"),
+            (("backlight", Kind::Bool), "Yes"),
+            (("wireless", Kind::Bool), "0"),
+            (("color", Kind::List), "black | white||"),
+            (("cpu", Kind::Id), "65f0c0ffee0000000000beef"),
+            (("material", Kind::Json), r#"[{"percentage": 100, "name": "wool"}]"#),
+        ]).unwrap();
+
+        assert_eq!(fields, json!({
+            "name": "Keyboard",
+            "price": 49.9,
+            "cores": 8,
+            "backlight": true,
+            "wireless": false,
+            "color": ["black", "white"],
+            "cpu": "65f0c0ffee0000000000beef",
+            "material": [{ "percentage": 100, "name": "wool" }],
+        }));
+    }
+
+    #[test]
+    fn reads_nested_cells_into_one_object() {
+        let fields = read(&[
+            (("book.isbn", Kind::Text), "0306406152"),
+            (("name", Kind::Text), "Book"),
+            (("book.numPages", Kind::Integer), "320"),
+        ]).unwrap();
+
+        assert_eq!(fields, json!({ "book": { "isbn": "0306406152", "numPages": 320 }, "name": "Book" }));
+    }
+
+    #[test]
+    fn leaves_out_empty_cells() {
+        let fields = read(&[
+            (("brand", Kind::Text), ""),
+            (("book.isbn", Kind::Text), "  "),
+            (("book.numPages", Kind::Integer), ""),
+        ]).unwrap();
+
+        assert_eq!(fields, json!({}));
+    }
+
+    #[test]
+    fn rejects_invalid_cells() {
+        assert!(read(&[(("price", Kind::Number), "12,5")]).is_err());
+        assert!(read(&[(("price", Kind::Number), "NaN")]).is_err());
+        assert!(read(&[(("cores", Kind::Integer), "8.5")]).is_err());
+        assert!(read(&[(("soldSep", Kind::Bool), "maybe")]).is_err());
+        assert!(read(&[(("material", Kind::Json), "[{")]).is_err());
+        // A field can't be both a value and an object
+        assert!(read(&[(("memory", Kind::Integer), "16"), (("memory.type", Kind::Text), "DDR5")]).is_err());
+    }
+
+    #[test]
+    fn writes_cells_by_kind() {
+        let fields = json!({
+            "name": "Laptop, 14\"",
+            "price": 899.99,
+            "memory": 16,
+            "dedicated": true,
+            "color": ["silver", "black"],
+            "cpu": { "$oid": "65f0c0ffee0000000000beef" },
+            "material": [],
+            "clock": { "coreSpeedGhz": 3.2 },
+        });
+
+        assert_eq!(write_cell(&fields, ("name", Kind::Text)), "Laptop, 14\"");
+        assert_eq!(write_cell(&fields, ("price", Kind::Number)), "899.99");
+        assert_eq!(write_cell(&fields, ("memory", Kind::Integer)), "16");
+        assert_eq!(write_cell(&fields, ("dedicated", Kind::Bool)), "true");
+        assert_eq!(write_cell(&fields, ("color", Kind::List)), "silver|black");
+        assert_eq!(write_cell(&fields, ("cpu", Kind::Id)), "65f0c0ffee0000000000beef");
+        assert_eq!(write_cell(&fields, ("material", Kind::Json)), "");
+        assert_eq!(write_cell(&fields, ("clock.coreSpeedGhz", Kind::Number)), "3.2");
+        assert_eq!(write_cell(&fields, ("clock.boostSpeedGhz", Kind::Number)), "");
+        assert_eq!(write_cell(&fields, ("gpu", Kind::Id)), "");
+    }
+
+    #[test]
+    fn written_cells_read_back() {
+        let fields = json!({
+            "name": "Jacket",
+            "price": 120.0,
+            "color": ["navy"],
+            "material": [{ "percentage": 80, "name": "cotton" }, { "percentage": 20, "name": "polyester" }],
+            "memorySupp": { "type": "DDR4", "maxSizeGb": 128 },
+        });
+        let columns: &[Column] = &[
+            ("name", Kind::Text),
+            ("price", Kind::Number),
+            ("color", Kind::List),
+            ("material", Kind::Json),
+            ("memorySupp.type", Kind::Text),
+            ("memorySupp.maxSizeGb", Kind::Integer),
+        ];
+
+        let cells: Vec<(Column, String)> = columns.iter().map(|column| (*column, write_cell(&fields, *column))).collect();
+        let cells: Vec<(Column, &str)> = cells.iter().map(|(column, cell)| (*column, cell.as_str())).collect();
+
+        assert_eq!(read(&cells).unwrap(), fields);
+    }
+}
@@ -0,0 +1,644 @@
+mod columns;
+
+use crate::prelude::*;
+use anyhow::Result;
+use actix_web::web::Bytes;
+use futures_util::{ Stream, StreamExt, stream::BoxStream };
+use serde_json::{ Map, Value };
+use crate::database::catalog::{
+    MANAGED_FIELDS, check_references, current_filter, field_changes, item_update, new_item_doc,
+};
+use crate::database::inventory::refresh_projection;
+use crate::types::{
+    error,
+    requests::catalog::{ CatalogFormat, CatalogItem, check_sku },
+    responses::{ ImportReport, ImportRowError },
+};
+
+/// Longest a row can be. Anything longer is most likely a CSV file with an
+/// unclosed quote, which would otherwise be read until its end.
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+/// Rows looked up and written together.
+const BATCH_SIZE: usize = 200;
+
+/// Most row errors listed in an import report. All of them are counted.
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// Imports a file of items into a collection, as it's read, updating the ones
+/// with the same SKU and adding the rest. Rows which can't be imported are
+/// reported and skipped, while the others are written batch by batch, so an
+/// import which stops halfway can just be run again. A dry run checks every row
+/// without writing anything.
+#[tracing::instrument(name = "Importing catalog items", skip(db, chunks))]
+pub async fn import_items<S, B, E>(
+    db: &mongodb::Database,
+    coll: &str,
+    format: CatalogFormat,
+    dry_run: bool,
+    by: Option<ObjectId>,
+    mut chunks: S,
+) -> Result<ImportReport>
+where
+    S: Stream<Item = std::result::Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut import = Import {
+        db,
+        coll,
+        dry_run,
+        by,
+        header: None,
+        skus: HashSet::new(),
+        batch: Vec::new(),
+        report: ImportReport { dry_run, ..Default::default() },
+    };
+    let mut splitter = RecordSplitter::new(format);
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| anyhow!(error::Catalog::InvalidFile(format!("Failed to read the file: {}", e))))?;
+        for (line, record) in splitter.push(chunk.as_ref())? {
+            import.add_record(format, line, &record).await?;
+        }
+    }
+    if let Some((line, record)) = splitter.finish() {
+        import.add_record(format, line, &record).await?;
+    }
+    if format == CatalogFormat::Csv && import.header.is_none() {
+        bail!(error::Catalog::InvalidFile("The file has no header.".into()));
+    }
+    import.flush().await?;
+
+    let report = import.report;
+    tracing::info!(
+        target: "mongodb",
+        "{} rows of `{}` imported{}: {} created, {} updated, {} unchanged, {} failed.",
+        report.rows, coll, if dry_run { " as a dry run" } else { "" },
+        report.created, report.updated, report.unchanged, report.failed,
+    );
+
+    Ok(report)
+}
+
+/// Exports the items of a collection, leaving out the archived ones unless asked
+/// for, as a stream of rows read from the database as they're sent. Marked down
+/// items are exported at their regular price.
+pub async fn export_items(
+    db: &mongodb::Database,
+    coll: &str,
+    format: CatalogFormat,
+    archived: bool,
+) -> Result<BoxStream<'static, Result<Bytes>>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let filter = if archived { doc! {} } else { doc! { "archived": { "$ne": true }} };
+    let items = item_coll
+        .find(filter)
+        .projection(doc! { "lot": 0 })
+        .sort(doc! { "_id": 1 })
+        .await?;
+
+    let columns = columns::columns(coll);
+    let rows = items.map(move |item| export_row(item?, format, columns));
+
+    Ok(match format {
+        CatalogFormat::Csv => {
+            let mut header = vec!["sku"];
+            header.extend(columns.iter().map(|(path, _)| *path));
+            let header = csv_record(&header);
+            futures_util::stream::once(async move { header }).chain(rows).boxed()
+        }
+        CatalogFormat::Ndjson => rows.boxed(),
+    })
+}
+
+/// Turns an item into a row of an export, with the fields it's imported with.
+fn export_row(item: Document, format: CatalogFormat, columns: &[columns::Column]) -> Result<Bytes> {
+    let fields = item_fields(item);
+
+    match format {
+        CatalogFormat::Csv => {
+            let mut cells = vec![columns::write_cell(&fields, ("sku", columns::Kind::Text))];
+            cells.extend(columns.iter().map(|column| columns::write_cell(&fields, *column)));
+            csv_record(&cells)
+        }
+        CatalogFormat::Ndjson => {
+            let mut line = serde_json::to_vec(&fields)?;
+            line.push(b'\n');
+            Ok(Bytes::from(line))
+        }
+    }
+}
+
+/// The fields an item is imported with, in relaxed extended JSON, at its regular
+/// price if it's marked down.
+fn item_fields(mut item: Document) -> Value {
+    if let Ok(regular_price) = item.get_document("markdown").and_then(|markdown| markdown.get_f64("regularPrice")) {
+        let price_key = if item.contains_key("price") { "price" } else { "pricePerKg" };
+        item.insert(price_key, regular_price);
+    }
+
+    let mut fields = Map::new();
+    if let Ok(sku) = item.get_str("sku") {
+        fields.insert("sku".to_string(), Value::String(sku.to_string()));
+    }
+    for (key, value) in item {
+        if !MANAGED_FIELDS.contains(&key.as_str()) {
+            fields.insert(key, value.into_relaxed_extjson());
+        }
+    }
+    Value::Object(fields)
+}
+
+fn csv_record<T: AsRef<[u8]>>(cells: &[T]) -> Result<Bytes> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(cells)?;
+    Ok(Bytes::from(writer.into_inner()?))
+}
+
+/// Splits the chunks of a file into records, each with the line it starts at:
+/// one per line, except for line breaks within the quoted cells of a CSV file.
+struct RecordSplitter {
+    format: CatalogFormat,
+    record: Vec<u8>,
+    quoted: bool,
+    line: u64,
+    start: u64,
+}
+
+impl RecordSplitter {
+    fn new(format: CatalogFormat) -> Self {
+        Self { format, record: Vec::new(), quoted: false, line: 1, start: 1 }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<(u64, Vec<u8>)>> {
+        let mut records = Vec::new();
+
+        for &byte in chunk {
+            match byte {
+                b'"' if self.format == CatalogFormat::Csv => {
+                    self.quoted = !self.quoted;
+                    self.record.push(byte);
+                }
+                b'\n' => {
+                    self.line += 1;
+                    if self.quoted {
+                        self.record.push(byte);
+                    } else {
+                        records.push((self.start, std::mem::take(&mut self.record)));
+                        self.start = self.line;
+                    }
+                }
+                _ => self.record.push(byte),
+            }
+
+            if self.record.len() > MAX_RECORD_BYTES {
+                bail!(error::Catalog::InvalidFile(format!(
+                    "The row at line {} is longer than {} bytes.", self.start, MAX_RECORD_BYTES
+                )));
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// The last record, if the file doesn't end with a line break.
+    fn finish(self) -> Option<(u64, Vec<u8>)> {
+        (!self.record.is_empty()).then_some((self.start, self.record))
+    }
+}
+
+/// Strips the line's `\r` and the byte order mark of the first line from a
+/// record, or returns `None` if it's blank.
+fn clean_record(format: CatalogFormat, line: u64, record: &[u8]) -> Option<&[u8]> {
+    let record = record.strip_suffix(b"\r").unwrap_or(record);
+    let record = if line == 1 { record.strip_prefix("\u{feff}".as_bytes()).unwrap_or(record) } else { record };
+    // Spreadsheets tend to save empty rows as commas
+    let blank = |byte: &u8| byte.is_ascii_whitespace() || (format == CatalogFormat::Csv && *byte == b',');
+
+    (!record.iter().all(blank)).then_some(record)
+}
+
+/// A row which can be written, once its fields are checked against the item
+/// it updates, if any.
+struct ImportRow {
+    line: u64,
+    sku: String,
+    fields: Map<String, Value>,
+}
+
+/// A row which can't be imported, without stopping the rest of the file from being.
+struct RowError {
+    sku: Option<String>,
+    error: String,
+}
+
+impl RowError {
+    fn new(sku: Option<&str>, error: impl std::fmt::Display) -> Self {
+        Self { sku: sku.map(str::to_string), error: error.to_string() }
+    }
+}
+
+struct Import<'a> {
+    db: &'a mongodb::Database,
+    coll: &'a str,
+    dry_run: bool,
+    by: Option<ObjectId>,
+    /// Columns of the CSV file, by position. `None` is the `sku` column.
+    header: Option<Vec<Option<columns::Column>>>,
+    /// SKUs seen so far, which can't be repeated within the file.
+    skus: HashSet<String>,
+    batch: Vec<ImportRow>,
+    report: ImportReport,
+}
+
+impl Import<'_> {
+    async fn add_record(&mut self, format: CatalogFormat, line: u64, record: &[u8]) -> Result<()> {
+        let Some(record) = clean_record(format, line, record) else {
+            return Ok(());
+        };
+
+        let fields = match format {
+            CatalogFormat::Csv => {
+                let Some(header) = &self.header else {
+                    self.header = Some(read_header(self.coll, record)?);
+                    return Ok(());
+                };
+                read_csv_row(header, record)
+            }
+            CatalogFormat::Ndjson => read_json_row(record),
+        };
+
+        self.report.rows += 1;
+        match self.parse_row(fields) {
+            Ok((sku, fields)) => self.batch.push(ImportRow { line, sku, fields }),
+            Err(e) => self.fail(line, e),
+        }
+
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    fn parse_row(&mut self, fields: Result<Map<String, Value>, String>) -> Result<(String, Map<String, Value>), RowError> {
+        let mut fields = fields.map_err(|e| RowError::new(None, e))?;
+
+        let sku = match fields.remove("sku") {
+            Some(Value::String(sku)) if !sku.trim().is_empty() => sku.trim().to_string(),
+            Some(Value::String(_)) | None => return Err(RowError::new(None, "The row has no SKU.")),
+            Some(_) => return Err(RowError::new(None, "The SKU must be a text.")),
+        };
+        check_sku(&sku).map_err(|e| RowError::new(Some(&sku), e))?;
+        if !self.skus.insert(sku.clone()) {
+            return Err(RowError::new(Some(&sku), "The SKU is repeated within the file."));
+        }
+
+        Ok((sku, fields))
+    }
+
+    /// Checks the fields of a row against the schema of the collection. A CSV
+    /// row updating an item only changes the columns of the file's header, so
+    /// the others are filled in from the item as they'd be exported.
+    fn parse_item(&self, mut fields: Map<String, Value>, current: Option<&Document>) -> Result<CatalogItem, String> {
+        if let (Some(header), Some(current)) = (&self.header, current) {
+            let current = item_fields(current.clone());
+            for column in columns::columns(self.coll) {
+                if !header.contains(&Some(*column)) {
+                    columns::read_cell(&mut fields, *column, &columns::write_cell(&current, *column))?;
+                }
+            }
+        }
+
+        CatalogItem::parse(self.coll, fields).map_err(|e| e.to_string())
+    }
+
+    /// Top-level fields a CSV row may unset: the ones of the file's header. Any
+    /// field may be unset by NDJSON rows, which replace the whole item.
+    fn may_unset(&self, key: &str) -> bool {
+        match &self.header {
+            Some(header) => header.iter().flatten().any(|(path, _)| path.split('.').next() == Some(key)),
+            None => true,
+        }
+    }
+
+    fn fail(&mut self, line: u64, e: RowError) {
+        self.report.failed += 1;
+        if self.report.errors.len() < MAX_REPORTED_ERRORS {
+            self.report.errors.push(ImportRowError { row: line, sku: e.sku, error: e.error });
+        }
+    }
+
+    /// Writes the rows read since the last batch, telling apart new items from
+    /// the ones already in the collection by their SKUs.
+    async fn flush(&mut self) -> Result<()> {
+        let batch = std::mem::take(&mut self.batch);
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let item_coll: Collection<Document> = self.db.collection(self.coll);
+        let skus: Vec<&str> = batch.iter().map(|row| row.sku.as_str()).collect();
+        let mut existing: HashMap<String, Document> = item_coll
+            .find(doc! { "sku": { "$in": skus }})
+            .projection(doc! { "lot": 0 })
+            .await?
+            .try_collect::<Vec<Document>>()
+            .await?
+            .into_iter()
+            .filter_map(|item| Some((item.get_str("sku").ok()?.to_string(), item)))
+            .collect();
+
+        for row in batch {
+            let current = existing.remove(&row.sku);
+            let item = match self.parse_item(row.fields, current.as_ref()) {
+                Ok(item) => item,
+                Err(e) => {
+                    self.fail(row.line, RowError::new(Some(&row.sku), e));
+                    continue;
+                }
+            };
+            if let Err(e) = check_references(self.db, &item).await {
+                let e = e.downcast::<error::Catalog>()?;
+                self.fail(row.line, RowError::new(Some(&row.sku), e));
+                continue;
+            }
+
+            let result = match current {
+                Some(current) => self.update_row(&row.sku, &item, current).await?,
+                None => self.create_row(&row.sku, &item).await?,
+            };
+            if let Err(e) = result {
+                self.fail(row.line, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn create_row(&mut self, sku: &str, item: &CatalogItem) -> Result<Result<(), RowError>> {
+        if !self.dry_run {
+            let item_doc = new_item_doc(item, Some(sku), self.by)?;
+            let item_coll: Collection<Document> = self.db.collection(self.coll);
+
+            if let Err(e) = item_coll.insert_one(&item_doc).await {
+                if crate::database::is_duplicate_key(&e) {
+                    // Added by someone else since the batch was looked up
+                    return Ok(Err(RowError::new(Some(sku), "An item with this SKU was added while importing.")));
+                }
+                return Err(e.into());
+            }
+            refresh_projection(self.db, self.coll, item_doc.get_object_id("_id")?).await;
+        }
+
+        self.report.created += 1;
+        Ok(Ok(()))
+    }
+
+    async fn update_row(&mut self, sku: &str, item: &CatalogItem, current: Document) -> Result<Result<(), RowError>> {
+        if current.get_bool("archived").unwrap_or(false) {
+            return Ok(Err(RowError::new(Some(sku), error::Catalog::Archived)));
+        }
+
+        let (set, unset) = field_changes(&current, item)?;
+        let unset: Document = unset.into_iter().filter(|(key, _)| self.may_unset(key)).collect();
+        let unchanged = unset.is_empty() && set.iter().all(|(key, value)| current.get(key) == Some(value));
+        if unchanged {
+            self.report.unchanged += 1;
+            return Ok(Ok(()));
+        }
+
+        if !self.dry_run {
+            let item_id = current.get_object_id("_id")?;
+            let item_coll: Collection<Document> = self.db.collection(self.coll);
+
+            let updated = item_coll
+                .update_one(current_filter(&current, item_id), item_update(set, unset, self.by))
+                .await?;
+            if updated.matched_count == 0 {
+                return Ok(Err(RowError::new(Some(sku), "The item was changed by someone else while importing.")));
+            }
+            refresh_projection(self.db, self.coll, item_id).await;
+        }
+
+        self.report.updated += 1;
+        Ok(Ok(()))
+    }
+}
+
+/// Reads the header of a CSV file. Its columns can be in any order, and any of
+/// them but `sku` can be left out.
+fn read_header(coll: &str, record: &[u8]) -> Result<Vec<Option<columns::Column>>> {
+    let invalid = |msg: String| anyhow!(error::Catalog::InvalidFile(msg));
+
+    let names = csv_cells(record).map_err(invalid)?;
+    let columns = columns::columns(coll);
+
+    let mut header = Vec::with_capacity(names.len());
+    for name in names.iter().map(|name| name.trim()) {
+        let column = if name == "sku" {
+            None
+        } else {
+            Some(*columns.iter()
+                .find(|(path, _)| *path == name)
+                .ok_or_else(|| invalid(format!("Unknown column `{}` for `{}`.", name, coll)))?)
+        };
+        if header.contains(&column) {
+            return Err(invalid(format!("The column `{}` is repeated.", name)));
+        }
+        header.push(column);
+    }
+
+    if !header.contains(&None) {
+        return Err(invalid("The file has no `sku` column.".into()));
+    }
+
+    Ok(header)
+}
+
+fn read_csv_row(header: &[Option<columns::Column>], record: &[u8]) -> Result<Map<String, Value>, String> {
+    let cells = csv_cells(record)?;
+    if cells.len() != header.len() {
+        return Err(format!("The row has {} cells, but the header has {} columns.", cells.len(), header.len()));
+    }
+
+    let mut fields = Map::new();
+    for (column, cell) in header.iter().zip(cells.iter()) {
+        match column {
+            Some(column) => columns::read_cell(&mut fields, *column, cell)?,
+            None => { fields.insert("sku".to_string(), Value::String(cell.to_string())); }
+        }
+    }
+
+    Ok(fields)
+}
+
+fn csv_cells(record: &[u8]) -> Result<csv::StringRecord, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(record);
+
+    match reader.records().next() {
+        Some(cells) => cells.map_err(|e| format!("Invalid CSV: {}", e)),
+        None => Ok(csv::StringRecord::new()),
+    }
+}
+
+fn read_json_row(record: &[u8]) -> Result<Map<String, Value>, String> {
+    serde_json::from_slice(record).map_err(|e| format!("Invalid JSON object: {}", e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a file fed in chunks of a few bytes, so records span chunks.
+    fn split(format: CatalogFormat, file: &[u8]) -> Vec<(u64, String)> {
+        let mut splitter = RecordSplitter::new(format);
+        let mut records = Vec::new();
+        for chunk in file.chunks(3) {
+            records.extend(splitter.push(chunk).unwrap());
+        }
+        records.extend(splitter.finish());
+
+        records.into_iter()
+            .map(|(line, record)| (line, String::from_utf8(record).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn splitter_keeps_line_breaks_of_quoted_cells() {
+        let records = split(CatalogFormat::Csv, b"sku,name\nA1,\"two\nlines\"\nA2,last");
+
+        assert_eq!(records, vec![
+            (1, "sku,name".to_string()),
+            (2, "A1,\"two\nlines\"".to_string()),
+            (4, "A2,last".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn splitter_reads_escaped_quotes() {
+        let records = split(CatalogFormat::Csv, b"A1,\"say \"\"hi\"\",\nbye\"\nA2,x\n");
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1], (3, "A2,x".to_string()));
+        let cells = csv_cells(records[0].1.as_bytes()).unwrap();
+        assert_eq!(&cells[1], "say \"hi\",\nbye");
+    }
+
+    #[test]
+    fn splitter_ignores_quotes_of_ndjson() {
+        let records = split(CatalogFormat::Ndjson, b"{\"name\":\"15\\\" screen\"}\n{\"name\":\"b\"}\n");
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1], (2, "{\"name\":\"b\"}".to_string()));
+    }
+
+    #[test]
+    fn splitter_rejects_unclosed_quotes() {
+        let mut file = b"A1,\"never closed\n".to_vec();
+        file.resize(MAX_RECORD_BYTES + 10, b'x');
+
+        let mut splitter = RecordSplitter::new(CatalogFormat::Csv);
+        assert!(splitter.push(&file).is_err());
+    }
+
+    #[test]
+    fn clean_record_strips_crlf_and_bom() {
+        assert_eq!(clean_record(CatalogFormat::Csv, 1, "\u{feff}sku,name\r".as_bytes()), Some(&b"sku,name"[..]));
+        assert_eq!(clean_record(CatalogFormat::Csv, 2, b"A1,x\r"), Some(&b"A1,x"[..]));
+        // Only a file starts with a byte order mark
+        assert_eq!(clean_record(CatalogFormat::Csv, 2, "\u{feff}A1".as_bytes()), Some("\u{feff}A1".as_bytes()));
+    }
+
+    #[test]
+    fn clean_record_skips_blank_rows() {
+        assert_eq!(clean_record(CatalogFormat::Csv, 3, b""), None);
+        assert_eq!(clean_record(CatalogFormat::Csv, 3, b" \t\r"), None);
+        assert_eq!(clean_record(CatalogFormat::Csv, 3, b",,,,\r"), None);
+        assert_eq!(clean_record(CatalogFormat::Ndjson, 3, b"  "), None);
+        assert_eq!(clean_record(CatalogFormat::Ndjson, 3, b",,"), Some(&b",,"[..]));
+    }
+
+    /// Exports an item as CSV, imports the file back, and checks the item is the same.
+    fn assert_csv_round_trip(coll: &str, item: Document) {
+        let sku = item.get_str("sku").unwrap().to_string();
+        let columns = columns::columns(coll);
+
+        let mut header = vec!["sku"];
+        header.extend(columns.iter().map(|(path, _)| *path));
+        let mut file = csv_record(&header).unwrap().to_vec();
+        file.extend_from_slice(&export_row(item.clone(), CatalogFormat::Csv, columns).unwrap());
+
+        let records = split(CatalogFormat::Csv, &file);
+        assert_eq!(records.len(), 2);
+        let header = read_header(coll, records[0].1.as_bytes()).unwrap();
+        let mut fields = read_csv_row(&header, records[1].1.as_bytes()).unwrap();
+
+        assert_eq!(fields.remove("sku"), Some(Value::String(sku)));
+        let imported = CatalogItem::parse(coll, fields).unwrap().to_document().unwrap();
+
+        let Value::Object(mut expected) = item_fields(item) else { unreachable!() };
+        expected.remove("sku");
+        let expected = CatalogItem::parse(coll, expected).unwrap().to_document().unwrap();
+        assert_eq!(imported, expected);
+    }
+
+    #[test]
+    fn csv_round_trip_keeps_nested_fields() {
+        assert_csv_round_trip("libraryItem", doc! {
+            "_id": ObjectId::new(),
+            "sku": "BK-001",
+            "version": 3_i64,
+            "name": "Cien años de soledad, \"edición\" especial",
+            "price": 24.5,
+            "book": {
+                "isbn": "978-0-306-40615-7",
+                "numPages": 471,
+                "author": ["Gabriel García Márquez"],
+                "publisher": "Sudamericana\nBuenos Aires",
+                "edition": 2,
+                "audience": ["adult", "young adult"],
+                "genre": [],
+            },
+            "lot": [],
+        });
+    }
+
+    #[test]
+    fn csv_round_trip_keeps_ids() {
+        assert_csv_round_trip("tech", doc! {
+            "_id": ObjectId::new(),
+            "sku": "LAP/14-B",
+            "name": "Laptop 14",
+            "price": 899.99,
+            "brand": "Acme",
+            "model": "L14",
+            "color": ["silver", "black"],
+            "type": "laptop",
+            "memory": 16,
+            "cpu": ObjectId::new(),
+            "gpu": ObjectId::new(),
+        });
+    }
+
+    #[test]
+    fn csv_round_trip_exports_regular_prices() {
+        let item = doc! {
+            "sku": "APL-1",
+            "name": "Apples",
+            "pricePerKg": 2.0,
+            "type": "fruit",
+            "markdown": { "regularPrice": 3.5 },
+        };
+
+        let row = export_row(item.clone(), CatalogFormat::Csv, columns::columns("food")).unwrap();
+        assert_eq!(&row[..], b"APL-1,Apples,,3.5,fruit\n");
+        assert_csv_round_trip("food", item);
+    }
+}
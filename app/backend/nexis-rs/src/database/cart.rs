@@ -0,0 +1,326 @@
+use crate::prelude::*;
+use anyhow::Result;
+use chrono::Utc;
+use crate::database::{ get_redis_conn, get_item_info, ItemInfo };
+use crate::types::{ error, mongodb::users::CartItem, responses::{ CartLine, CartResponse }};
+use deadpool_redis::redis::AsyncCommands;
+
+/// Store the guest cart key prefix as a const so it can't be typo'd anywhere it's used.
+const GUEST_CART_KEY_PREFIX: &str = "guest_cart_";
+
+/// Owner of a cart. Clients keep theirs in their user document, while
+/// anonymous shoppers get one in redis, identified by the `guest_cart` cookie.
+#[derive(Debug, Clone, Copy)]
+pub enum CartOwner {
+    User(ObjectId),
+    Guest(Uuid),
+}
+
+pub async fn get_cart(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+) -> Result<Vec<CartItem>> {
+    match owner {
+        CartOwner::User(user_id) => get_user_cart(db, user_id).await,
+        CartOwner::Guest(guest_id) => get_guest_cart(redis_pool, guest_id).await,
+    }
+}
+
+pub async fn save_cart(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+    cart: &[CartItem],
+) -> Result<()> {
+    match owner {
+        CartOwner::User(user_id) => {
+            let users_coll: Collection<Document> = db.collection("user");
+            users_coll.update_one(
+                doc! { "_id": user_id, "client": { "$exists": true }},
+                doc! { "$set": { "client.cart": bson::to_bson(cart)? }},
+            ).await?;
+        }
+        CartOwner::Guest(guest_id) => {
+            let settings = crate::settings::get_settings().expect("Failed to read settings.");
+            let mut redis_conn = get_guest_redis_conn(redis_pool).await?;
+            redis_conn.set_ex::<_, _, ()>(
+                format!("{}{}", GUEST_CART_KEY_PREFIX, guest_id),
+                serde_json::to_string(cart)?,
+                settings.cart.guest_cart_expiration_days * 24 * 60 * 60,
+            ).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn get_user_cart(db: &mongodb::Database, user_id: ObjectId) -> Result<Vec<CartItem>> {
+    let users_coll: Collection<Document> = db.collection("user");
+
+    let Some(user) = users_coll
+        .find_one(doc! { "_id": user_id })
+        .projection(doc! { "client.cart": 1 })
+        .await? else {
+        bail!("User {} not found.", user_id);
+    };
+
+    let cart = user.get_document("client")
+        .and_then(|client| client.get_array("cart"))
+        .map(|cart| cart.iter()
+            .filter_map(|entry| match bson::from_bson::<CartItem>(entry.clone()) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    tracing::warn!(target: "mongodb", "Skipping malformed cart entry of user {}: {}", user_id, e);
+                    None
+                }
+            })
+            .collect())
+        .unwrap_or_default();
+
+    Ok(cart)
+}
+
+async fn get_guest_cart(redis_pool: &deadpool_redis::Pool, guest_id: Uuid) -> Result<Vec<CartItem>> {
+    let mut redis_conn = get_guest_redis_conn(redis_pool).await?;
+
+    let cart: Option<String> = redis_conn.get(format!("{}{}", GUEST_CART_KEY_PREFIX, guest_id)).await?;
+
+    match cart {
+        Some(cart) => Ok(serde_json::from_str(&cart)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn get_guest_redis_conn(redis_pool: &deadpool_redis::Pool) -> Result<deadpool_redis::Connection> {
+    match get_redis_conn(redis_pool).await {
+        Ok(conn) => Ok(conn),
+        Err(_) => bail!(error::Redis::ConnError("Failed to obtain redis connection.".into())),
+    }
+}
+
+/// Adds units of an item to a cart, refreshing its price snapshot. Items sold by
+/// `pricePerKg` also need the weight wanted, which is added to the one in the cart.
+/// Fails if the item doesn't exist or there isn't enough stock for the new quantity.
+#[tracing::instrument(name = "Adding item to cart", skip(db, redis_pool))]
+pub async fn add_to_cart(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+    coll: &str,
+    item_id: ObjectId,
+    quantity: u32,
+    weight_kg: Option<f64>,
+) -> Result<()> {
+    let Some(item) = get_item_info(db, coll, item_id).await? else {
+        bail!(error::Cart::ItemNotFound(format!("No item {} in `{}`.", item_id, coll)));
+    };
+
+    let mut cart = get_cart(db, redis_pool, owner).await?;
+
+    let new_quantity = cart.iter()
+        .find(|entry| entry.item == item_id)
+        .map(|entry| entry.quantity)
+        .unwrap_or(0)
+        .saturating_add(quantity);
+    check_quantity(new_quantity, item.stock)?;
+    check_weight(&item, weight_kg)?;
+
+    match cart.iter_mut().find(|entry| entry.item == item_id) {
+        Some(entry) => {
+            entry.quantity = new_quantity;
+            entry.price = Some(item.price);
+            entry.weight_kg = match (entry.weight_kg, weight_kg) {
+                (Some(current), Some(added)) => Some(current + added),
+                (current, added) => added.or(current),
+            };
+        }
+        None => cart.push(CartItem {
+            date_added: Utc::now(),
+            coll: coll.to_string(),
+            item: item_id,
+            quantity: new_quantity,
+            weight_kg: if item.per_kg { weight_kg } else { None },
+            price: Some(item.price),
+        }),
+    }
+
+    save_cart(db, redis_pool, owner, &cart).await
+}
+
+/// Sets the units of an item already in a cart, and its weight if it's given,
+/// refreshing its price snapshot. A quantity of zero removes the item.
+#[tracing::instrument(name = "Setting cart item quantity", skip(db, redis_pool))]
+pub async fn set_cart_quantity(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+    item_id: ObjectId,
+    quantity: u32,
+    weight_kg: Option<f64>,
+) -> Result<()> {
+    let mut cart = get_cart(db, redis_pool, owner).await?;
+
+    let Some(position) = cart.iter().position(|entry| entry.item == item_id) else {
+        bail!(error::Cart::NotInCart);
+    };
+
+    if quantity == 0 {
+        cart.remove(position);
+    } else {
+        let entry = &mut cart[position];
+        let Some(item) = get_item_info(db, &entry.coll, item_id).await? else {
+            bail!(error::Cart::ItemNotFound(format!("Item {} no longer exists.", item_id)));
+        };
+        check_quantity(quantity, item.stock)?;
+
+        entry.quantity = quantity;
+        entry.price = Some(item.price);
+        if item.per_kg && weight_kg.is_some() {
+            check_weight(&item, weight_kg)?;
+            entry.weight_kg = weight_kg;
+        }
+    }
+
+    save_cart(db, redis_pool, owner, &cart).await
+}
+
+#[tracing::instrument(name = "Removing item from cart", skip(db, redis_pool))]
+pub async fn remove_from_cart(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+    item_id: ObjectId,
+) -> Result<()> {
+    set_cart_quantity(db, redis_pool, owner, item_id, 0, None).await
+}
+
+fn check_quantity(quantity: u32, stock: i64) -> Result<()> {
+    let settings = crate::settings::get_settings().expect("Failed to read settings.");
+
+    if quantity == 0 {
+        bail!(error::Cart::InvalidQuantity("The quantity must be at least 1.".into()));
+    }
+    if quantity > settings.cart.max_quantity {
+        bail!(error::Cart::InvalidQuantity(format!(
+            "At most {} units of an item can be added to the cart.", settings.cart.max_quantity
+        )));
+    }
+    if quantity as i64 > stock {
+        bail!(error::Cart::OutOfStock { available: stock.max(0) });
+    }
+
+    Ok(())
+}
+
+fn check_weight(item: &ItemInfo, weight_kg: Option<f64>) -> Result<()> {
+    match weight_kg {
+        None if item.per_kg =>
+            bail!(error::Cart::InvalidQuantity(format!("`{}` is sold by weight, so `weightKg` is required.", item.name))),
+        Some(weight_kg) if !(weight_kg.is_finite() && weight_kg > 0.0) =>
+            bail!(error::Cart::InvalidQuantity("The weight must be greater than 0.".into())),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the cart shown to its owner with the current price and stock of
+/// every item, flagging the items whose price changed since they were added.
+/// Items that no longer exist are dropped from the cart, and entries without
+/// a price snapshot (such as the ones made by the mock generator) get one.
+#[tracing::instrument(name = "Building cart", skip(db, redis_pool))]
+pub async fn get_cart_response(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+) -> Result<CartResponse> {
+    let cart = get_cart(db, redis_pool, owner).await?;
+    let mut cart_changed = false;
+    let mut lines = Vec::with_capacity(cart.len());
+    let mut kept = Vec::with_capacity(cart.len());
+
+    for mut entry in cart {
+        let Some(item) = get_item_info(db, &entry.coll, entry.item).await? else {
+            tracing::info!(target: "backend", "Dropping item {} of `{}` from cart, since it no longer exists.", entry.item, entry.coll);
+            cart_changed = true;
+            continue;
+        };
+
+        let price = match entry.price {
+            Some(price) => price,
+            None => {
+                entry.price = Some(item.price);
+                cart_changed = true;
+                item.price
+            }
+        };
+
+        let line_total = item.line_total(entry.quantity, entry.weight_kg);
+
+        lines.push(CartLine {
+            item: entry.item,
+            coll: entry.coll.clone(),
+            name: item.name,
+            quantity: entry.quantity,
+            weight_kg: entry.weight_kg,
+            per_kg: item.per_kg,
+            price,
+            current_price: item.price,
+            price_changed: price != item.price,
+            line_total,
+            stock: item.stock,
+            available: entry.quantity as i64 <= item.stock,
+            date_added: entry.date_added,
+        });
+        kept.push(entry);
+    }
+
+    if cart_changed {
+        save_cart(db, redis_pool, owner, &kept).await?;
+    }
+
+    Ok(CartResponse {
+        total: lines.iter().filter_map(|line| line.line_total).sum(),
+        price_changed: lines.iter().any(|line| line.price_changed),
+        items: lines,
+    })
+}
+
+/// Moves the items of a guest cart into a client's cart and deletes the guest cart.
+/// Quantities of items in both carts are added up, without exceeding the available stock.
+#[tracing::instrument(name = "Merging guest cart", skip(db, redis_pool))]
+pub async fn merge_guest_cart(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    guest_id: Uuid,
+    user_id: ObjectId,
+) -> Result<()> {
+    let settings = crate::settings::get_settings().expect("Failed to read settings.");
+
+    let guest_cart = get_guest_cart(redis_pool, guest_id).await?;
+
+    if !guest_cart.is_empty() && utils::user_has_role(db, user_id, utils::Role::Client).await? {
+        let mut cart = get_user_cart(db, user_id).await?;
+
+        for guest_entry in guest_cart {
+            let Some(item) = get_item_info(db, &guest_entry.coll, guest_entry.item).await? else { continue };
+            let max_quantity = (item.stock.max(0) as u32).min(settings.cart.max_quantity);
+
+            match cart.iter_mut().find(|entry| entry.item == guest_entry.item) {
+                Some(entry) => {
+                    entry.quantity = entry.quantity.saturating_add(guest_entry.quantity).min(max_quantity).max(entry.quantity);
+                    entry.price = guest_entry.price.or(entry.price);
+                    entry.weight_kg = guest_entry.weight_kg.or(entry.weight_kg);
+                }
+                None => cart.push(guest_entry),
+            }
+        }
+
+        save_cart(db, redis_pool, CartOwner::User(user_id), &cart).await?;
+        tracing::info!(target: "backend", "Guest cart merged into the cart of user {}.", user_id);
+    }
+
+    let mut redis_conn = get_guest_redis_conn(redis_pool).await?;
+    redis_conn.del::<_, ()>(format!("{}{}", GUEST_CART_KEY_PREFIX, guest_id)).await?;
+
+    Ok(())
+}
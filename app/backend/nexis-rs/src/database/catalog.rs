@@ -0,0 +1,358 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    inventory::{ build_lot, push_lot, refresh_projection },
+    orders::commit,
+};
+use crate::types::{
+    error,
+    mongodb::inventory::{ Lot, Markdown },
+    requests::catalog::{ CatalogItem, CatalogItemUpdate, NewCatalogItem, check_sku },
+};
+use mongodb::{ IndexModel, options::{ IndexOptions, ReturnDocument }};
+
+/// Items listed per page.
+const PAGE_SIZE: u64 = 50;
+
+/// Most lots a new item can start with.
+const MAX_INITIAL_LOTS: usize = 50;
+
+/// Fields of an item document which aren't part of its schema: its stock,
+/// what the inventory endpoints and the expiry check keep in it, and the
+/// catalog's own bookkeeping. Editing an item never removes them.
+pub(crate) const MANAGED_FIELDS: [&str; 13] = [
+    "_id", "sku", "lot", "markdown", "reorder", "version", "archived",
+    "archivedBy", "archivedAt", "createdBy", "createdAt", "updatedBy", "updatedAt",
+];
+
+/// Makes SKUs unique within every item collection. Items without one are left alone.
+pub async fn ensure_catalog_indexes(db: &mongodb::Database) -> Result<()> {
+    for coll in stores::item_colls() {
+        let item_coll: Collection<Document> = db.collection(&coll);
+        item_coll.create_index(
+            IndexModel::builder()
+                .keys(doc! { "sku": 1 })
+                .options(IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! { "sku": { "$type": "string" }})
+                    .build())
+                .build()
+        ).await?;
+    }
+
+    Ok(())
+}
+
+/// Version of an item document. Items made before the catalog existed have none.
+pub fn item_version(item: &Document) -> i64 {
+    item.get_i64("version")
+        .or_else(|_| item.get_i32("version").map(i64::from))
+        .unwrap_or(0)
+}
+
+/// Matches an item still at a version.
+pub(crate) fn version_filter(item_id: ObjectId, version: i64) -> Document {
+    if version == 0 {
+        doc! { "_id": item_id, "version": { "$exists": false }}
+    } else {
+        doc! { "_id": item_id, "version": version }
+    }
+}
+
+/// Adds an item to a collection, along with the lots it starts with, which
+/// are recorded as received in the inventory ledger.
+#[tracing::instrument(name = "Creating catalog item", skip(db, new_item))]
+pub async fn create_catalog_item(
+    db: &mongodb::Database,
+    coll: &str,
+    by: ObjectId,
+    new_item: NewCatalogItem,
+) -> Result<Document> {
+    let item = CatalogItem::parse(coll, new_item.fields)?;
+    if let Some(sku) = &new_item.sku {
+        check_sku(sku)?;
+    }
+    check_references(db, &item).await?;
+
+    if new_item.lots.len() > MAX_INITIAL_LOTS {
+        bail!(error::Catalog::Invalid(format!("An item can start with up to {} lots.", MAX_INITIAL_LOTS)));
+    }
+    let lots: Vec<Lot> = new_item.lots.iter().map(build_lot).collect::<Result<_>>()?;
+
+    let item_doc = new_item_doc(&item, new_item.sku.as_deref(), Some(by))?;
+    let item_id = item_doc.get_object_id("_id")?;
+
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        item_coll.insert_one(&item_doc).session(&mut session).await?;
+
+        for lot in &lots {
+            push_lot(db, &mut session, coll, item_id, by, lot, None).await?;
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(sku_error(e, new_item.sku.as_deref(), coll));
+    }
+
+    tracing::info!(target: "mongodb", "Item {} `{}` of `{}` created with {} lots.", item_id, item.name(), coll, lots.len());
+    refresh_projection(db, coll, item_id).await;
+
+    get_catalog_item(db, coll, item_id).await?.ok_or_else(|| anyhow!(error::Catalog::ItemNotFound))
+}
+
+/// Builds the document of a new item, without lots.
+pub(crate) fn new_item_doc(item: &CatalogItem, sku: Option<&str>, by: Option<ObjectId>) -> Result<Document> {
+    let mut item_doc = doc! { "_id": ObjectId::new() };
+    if let Some(sku) = sku {
+        item_doc.insert("sku", sku);
+    }
+    item_doc.extend(item.to_document()?);
+    item_doc.insert("lot", bson::Bson::Array(Vec::new()));
+    item_doc.insert("version", 1_i64);
+    item_doc.insert("archived", false);
+    if let Some(by) = by {
+        item_doc.insert("createdBy", by);
+    }
+    item_doc.insert("createdAt", bson::DateTime::now());
+
+    Ok(item_doc)
+}
+
+pub async fn get_catalog_item(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> Result<Option<Document>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+    Ok(item_coll.find_one(doc! { "_id": item_id }).await?)
+}
+
+/// Returns a page of the items of a collection by name, without their lots,
+/// leaving out the archived ones unless asked for.
+pub async fn get_catalog_items(db: &mongodb::Database, coll: &str, archived: bool, page: u64) -> Result<Vec<Document>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let filter = if archived { doc! {} } else { doc! { "archived": { "$ne": true }} };
+
+    let items = item_coll
+        .find(filter)
+        .projection(doc! { "lot": 0 })
+        .sort(doc! { "name": 1, "_id": 1 })
+        .skip(page * PAGE_SIZE)
+        .limit(PAGE_SIZE as i64)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(items)
+}
+
+/// Replaces the fields of an item, if it's still at the version the editor read.
+/// While the item is marked down, the new price is its regular price, and the
+/// discount is kept off it.
+#[tracing::instrument(name = "Updating catalog item", skip(db, update))]
+pub async fn update_catalog_item(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    by: ObjectId,
+    update: CatalogItemUpdate,
+) -> Result<Document> {
+    let Some(current) = get_catalog_item(db, coll, item_id).await? else {
+        bail!(error::Catalog::ItemNotFound);
+    };
+    if current.get_bool("archived").unwrap_or(false) {
+        bail!(error::Catalog::Archived);
+    }
+    if item_version(&current) != update.version {
+        bail!(error::Catalog::Changed(item_version(&current)));
+    }
+
+    let item = CatalogItem::parse(coll, update.fields)?;
+    if let Some(sku) = &update.sku {
+        check_sku(sku)?;
+    }
+    check_references(db, &item).await?;
+
+    let (mut set, unset) = field_changes(&current, &item)?;
+    if let Some(sku) = &update.sku {
+        set.insert("sku", sku);
+    }
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let updated = item_coll
+        .find_one_and_update(current_filter(&current, item_id), item_update(set, unset, Some(by)))
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(|e| sku_error(e.into(), update.sku.as_deref(), coll))?;
+    let Some(updated) = updated else {
+        return Err(changed_error(db, coll, item_id).await);
+    };
+
+    tracing::info!(target: "mongodb", "Item {} of `{}` updated to version {}.", item_id, coll, item_version(&updated));
+    refresh_projection(db, coll, item_id).await;
+
+    Ok(updated)
+}
+
+/// Fields to set and unset for an item to have the fields of another. While the
+/// item is marked down, the new price is its regular price, and the discount is
+/// kept off it.
+pub(crate) fn field_changes(current: &Document, item: &CatalogItem) -> Result<(Document, Document)> {
+    let mut set = item.to_document()?;
+    let (price_key, price) = item.price();
+
+    let markdown: Option<Markdown> = current.get_document("markdown").ok()
+        .and_then(|markdown| bson::from_document(markdown.clone()).ok());
+    if let Some(mut markdown) = markdown {
+        markdown.regular_price = price;
+        set.insert(price_key, ((price * (1.0 - markdown.discount)) * 100.0).round() / 100.0);
+        set.insert("markdown", bson::to_bson(&markdown)?);
+    }
+
+    // Fields of the current item left out of the new ones are removed
+    let unset: Document = current.keys()
+        .filter(|key| !MANAGED_FIELDS.contains(&key.as_str()) && !set.contains_key(key.as_str()))
+        .map(|key| (key.clone(), bson::Bson::String(String::new())))
+        .collect();
+
+    Ok((set, unset))
+}
+
+/// Matches an item if it's still as it was read: at the same version, and at the
+/// same price, since the expiry check changes it as it marks items down.
+pub(crate) fn current_filter(current: &Document, item_id: ObjectId) -> Document {
+    let mut filter = version_filter(item_id, item_version(current));
+    for key in ["price", "pricePerKg"] {
+        if let Some(value) = current.get(key) {
+            filter.insert(key, value.clone());
+        }
+    }
+    filter
+}
+
+/// Update applying field changes to an item, bumping its version.
+pub(crate) fn item_update(mut set: Document, unset: Document, by: Option<ObjectId>) -> Document {
+    if let Some(by) = by {
+        set.insert("updatedBy", by);
+    }
+    set.insert("updatedAt", bson::DateTime::now());
+
+    let mut update = doc! { "$set": set, "$inc": { "version": 1_i64 }};
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    update
+}
+
+/// Takes an item out of the search results and stops it from being sold, ordered
+/// or transferred. It's kept, since past orders, sales and returns reference it.
+#[tracing::instrument(name = "Archiving catalog item", skip(db))]
+pub async fn archive_catalog_item(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    by: ObjectId,
+    version: i64,
+) -> Result<Document> {
+    set_archived(db, coll, item_id, version, doc! {
+        "$set": { "archived": true, "archivedBy": by, "archivedAt": bson::DateTime::now() },
+        "$inc": { "version": 1_i64 },
+    }).await
+}
+
+/// Puts an archived item back on sale.
+#[tracing::instrument(name = "Restoring catalog item", skip(db))]
+pub async fn restore_catalog_item(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    by: ObjectId,
+    version: i64,
+) -> Result<Document> {
+    set_archived(db, coll, item_id, version, doc! {
+        "$set": { "archived": false, "updatedBy": by, "updatedAt": bson::DateTime::now() },
+        "$unset": { "archivedBy": "", "archivedAt": "" },
+        "$inc": { "version": 1_i64 },
+    }).await
+}
+
+/// Archives or restores an item. Nothing changes if it already is.
+async fn set_archived(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    version: i64,
+    changes: Document,
+) -> Result<Document> {
+    let archived = changes.get_document("$set").and_then(|set| set.get_bool("archived"))?;
+
+    let Some(current) = get_catalog_item(db, coll, item_id).await? else {
+        bail!(error::Catalog::ItemNotFound);
+    };
+    if current.get_bool("archived").unwrap_or(false) == archived {
+        return Ok(current);
+    }
+    if item_version(&current) != version {
+        bail!(error::Catalog::Changed(item_version(&current)));
+    }
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let Some(updated) = item_coll
+        .find_one_and_update(version_filter(item_id, version), changes)
+        .return_document(ReturnDocument::After)
+        .await? else {
+        return Err(changed_error(db, coll, item_id).await);
+    };
+
+    if archived {
+        tracing::info!(target: "mongodb", "Item {} of `{}` archived.", item_id, coll);
+    } else {
+        tracing::info!(target: "mongodb", "Item {} of `{}` restored.", item_id, coll);
+    }
+    refresh_projection(db, coll, item_id).await;
+
+    Ok(updated)
+}
+
+/// Error for an item which changed between reading and updating it.
+async fn changed_error(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> anyhow::Error {
+    match get_catalog_item(db, coll, item_id).await {
+        Ok(Some(item)) => anyhow!(error::Catalog::Changed(item_version(&item))),
+        Ok(None) => anyhow!(error::Catalog::ItemNotFound),
+        Err(e) => e,
+    }
+}
+
+/// Turns a broken unique SKU index into a validation error.
+fn sku_error(e: anyhow::Error, sku: Option<&str>, coll: &str) -> anyhow::Error {
+    match (e.downcast_ref::<mongodb::error::Error>(), sku) {
+        (Some(mongo_error), Some(sku)) if crate::database::is_duplicate_key(mongo_error) => {
+            anyhow!(error::Catalog::Invalid(format!("SKU `{}` is already used in `{}`.", sku, coll)))
+        }
+        _ => e,
+    }
+}
+
+/// Checks that the parts an item is built with exist.
+pub(crate) async fn check_references(db: &mongodb::Database, item: &CatalogItem) -> Result<()> {
+    let CatalogItem::Tech(tech) = item else {
+        return Ok(());
+    };
+
+    let parts = [("techCpu", Some(tech.cpu)), ("techGpu", tech.gpu)];
+    for (coll, part) in parts {
+        let Some(part) = part else { continue };
+
+        let part_coll: Collection<Document> = db.collection(coll);
+        if part_coll.find_one(doc! { "_id": part }).projection(doc! { "_id": 1 }).await?.is_none() {
+            bail!(error::Catalog::Invalid(format!("Item {} of `{}` not found.", part, coll)));
+        }
+    }
+
+    Ok(())
+}
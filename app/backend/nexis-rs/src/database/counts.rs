@@ -0,0 +1,433 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    inventory::refresh_projection,
+    movements::{ LotMovement, MOVEMENTS_COLL, record_movements },
+    orders::commit,
+};
+use crate::types::{
+    error,
+    mongodb::{
+        inventory::{ CountVariance, CountedUnit, Movement, MovementKind, StockCount, StockCountStatus },
+        stores::StoreInfo,
+    },
+    requests::inventory::{ CountApproval, NewStockCount },
+};
+use mongodb::{ ClientSession, IndexModel, options::ReturnDocument };
+
+pub const STOCK_COUNTS_COLL: &str = "stockCounts";
+
+/// Most codes a single scan request can submit.
+pub const MAX_SCANS_PER_REQUEST: usize = 1000;
+
+/// Reason of the ledger entries of count adjustments.
+const COUNT_REASON: &str = "Stock count";
+
+pub async fn ensure_stock_count_indexes(db: &mongodb::Database) -> Result<()> {
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+
+    counts_coll.create_index(IndexModel::builder().keys(doc! { "store": 1, "status": 1, "openedAt": -1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Opens a stock count of a store's item collections, or of only one of them,
+/// unless another count of them is still open or awaiting approval.
+#[tracing::instrument(name = "Opening stock count", skip(db, store), fields(store = %store.name))]
+pub async fn open_count(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    by: ObjectId,
+    new_count: NewStockCount,
+) -> Result<StockCount> {
+    let colls = match new_count.coll {
+        Some(coll) if store.item_colls.contains(&coll) => vec![coll],
+        Some(coll) => bail!(error::Inventory::InvalidCount(format!("`{}` doesn't sell `{}`.", store.name, coll))),
+        None => store.item_colls.clone(),
+    };
+
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+
+    let in_progress = counts_coll.find_one(doc! {
+        "store": store.id,
+        "status": { "$in": [StockCountStatus::Open.as_str(), StockCountStatus::Closed.as_str()] },
+        "colls": { "$in": &colls },
+    }).await?;
+    if in_progress.is_some() {
+        bail!(error::Inventory::CountInProgress);
+    }
+
+    let count = StockCount {
+        id: ObjectId::new(),
+        store: store.id,
+        colls,
+        status: StockCountStatus::Open,
+        scanned: Vec::new(),
+        opened_by: by,
+        opened_at: bson::DateTime::now(),
+        closed_by: None,
+        closed_at: None,
+        variance: None,
+        approved_by: None,
+        approved_at: None,
+        excluded: Vec::new(),
+    };
+    counts_coll.insert_one(&count).await?;
+
+    tracing::info!(target: "mongodb", "Stock count {} of `{}` opened by {}.", count.id, store.name, by);
+
+    Ok(count)
+}
+
+pub async fn get_count(db: &mongodb::Database, count_id: ObjectId) -> Result<Option<StockCount>> {
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+    Ok(counts_coll.find_one(doc! { "_id": count_id }).await?)
+}
+
+/// Returns the stock counts of a store, newest first, without their scanned codes.
+pub async fn get_store_counts(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    status: Option<StockCountStatus>,
+) -> Result<Vec<StockCount>> {
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+
+    let mut filter = doc! { "store": store.id };
+    if let Some(status) = status {
+        filter.insert("status", status.as_str());
+    }
+
+    let counts = counts_coll
+        .find(filter)
+        .projection(doc! { "scanned": 0 })
+        .sort(doc! { "openedAt": -1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Adds scanned unit codes to an open count. Codes scanned twice are only kept once.
+#[tracing::instrument(name = "Adding stock count scans", skip(db, codes), fields(codes = codes.len()))]
+pub async fn add_scans(db: &mongodb::Database, count_id: ObjectId, codes: &[ObjectId]) -> Result<StockCount> {
+    if codes.is_empty() || codes.len() > MAX_SCANS_PER_REQUEST {
+        bail!(error::Inventory::InvalidCount(format!("Between 1 and {} codes can be scanned at once.", MAX_SCANS_PER_REQUEST)));
+    }
+
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+
+    let count = counts_coll.find_one_and_update(
+        doc! { "_id": count_id, "status": StockCountStatus::Open.as_str() },
+        doc! { "$addToSet": { "scanned": { "$each": codes }}},
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match count {
+        Some(count) => Ok(count),
+        None => Err(state_error(db, count_id).await),
+    }
+}
+
+/// Ends the scanning of a count, and compares the scanned codes with the
+/// units in the lots of its collections.
+#[tracing::instrument(name = "Closing stock count", skip(db))]
+pub async fn close_count(db: &mongodb::Database, count_id: ObjectId, by: ObjectId) -> Result<StockCount> {
+    let Some(count) = get_count(db, count_id).await? else {
+        bail!(error::Inventory::CountNotFound);
+    };
+    if count.status != StockCountStatus::Open {
+        bail!(error::Inventory::CountState(count.status.as_str().into()));
+    }
+
+    let variance = compute_variance(db, &count).await?;
+
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+    let count = counts_coll.find_one_and_update(
+        doc! { "_id": count_id, "status": StockCountStatus::Open.as_str() },
+        doc! { "$set": {
+            "status": StockCountStatus::Closed.as_str(),
+            "closedBy": by,
+            "closedAt": bson::DateTime::now(),
+            "variance": bson::to_bson(&variance)?,
+        }},
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match count {
+        Some(count) => {
+            tracing::info!(
+                target: "mongodb",
+                "Stock count {} closed with {} units missing, {} found, {} sold and {} unknown.",
+                count_id, variance.missing.len(), variance.found.len(), variance.sold.len(), variance.unknown.len(),
+            );
+            Ok(count)
+        }
+        None => Err(state_error(db, count_id).await),
+    }
+}
+
+async fn compute_variance(db: &mongodb::Database, count: &StockCount) -> Result<CountVariance> {
+    let scanned: HashSet<ObjectId> = count.scanned.iter().copied().collect();
+    let mut in_lots: HashSet<ObjectId> = HashSet::new();
+    let mut variance = CountVariance { scanned: scanned.len(), ..Default::default() };
+
+    // Lots of the counted collections held by the store, and the ones of other
+    // stores' collections transferred to it if the whole store is counted
+    let mut sources: Vec<(String, bool)> = count.colls.iter().map(|coll| (coll.clone(), true)).collect();
+    if stores::get_store(&count.store).is_some_and(|store| store.item_colls.iter().all(|coll| count.colls.contains(coll))) {
+        sources.extend(stores::item_colls().into_iter().filter(|coll| !count.colls.contains(coll)).map(|coll| (coll, false)));
+    }
+
+    for (coll, own) in &sources {
+        let item_coll: Collection<Document> = db.collection(coll);
+        let filter = if *own { doc! {} } else { doc! { "lot.store": count.store } };
+        let mut cursor = item_coll.find(filter).projection(doc! { "lot._id": 1, "lot.code": 1, "lot.store": 1 }).await?;
+
+        while let Some(item) = cursor.try_next().await? {
+            let Ok(item_id) = item.get_object_id("_id") else { continue };
+
+            for lot in item.get_array("lot").map(|lots| lots.iter().filter_map(|lot| lot.as_document())).into_iter().flatten() {
+                let held = utils::stock::lot_held_by(lot, count.store, *own);
+                let Ok(lot_id) = lot.get_object_id("_id") else { continue };
+                if !held {
+                    continue;
+                }
+
+                for code in lot.get_array("code").map(|codes| codes.iter().filter_map(|code| code.as_object_id())).into_iter().flatten() {
+                    variance.expected += 1;
+                    in_lots.insert(code);
+                    if !scanned.contains(&code) {
+                        variance.missing.push(CountedUnit {
+                            coll: coll.clone(),
+                            item: item_id,
+                            lot: lot_id,
+                            code,
+                            last_movement: None,
+                            last_movement_at: None,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Scanned units not in a lot are told apart by the last ledger entry of their code
+    let not_in_lots: Vec<ObjectId> = count.scanned.iter().filter(|code| !in_lots.contains(code)).copied().collect();
+    if not_in_lots.is_empty() {
+        return Ok(variance);
+    }
+
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    let last_movements: Vec<Document> = movements_coll
+        .aggregate(vec![
+            doc! { "$match": { "code": { "$in": &not_in_lots }, "coll": { "$in": sources.iter().map(|(coll, _)| coll).collect::<Vec<_>>() }}},
+            doc! { "$sort": { "at": -1, "_id": -1 }},
+            doc! { "$group": { "_id": "$code", "last": { "$first": "$$ROOT" }}},
+        ])
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut known: HashMap<ObjectId, Movement> = HashMap::new();
+    for last in last_movements {
+        if let Ok(movement) = last.get_document("last").cloned().map(bson::from_document::<Movement>) {
+            let movement = movement?;
+            if let Some(code) = movement.code.as_object_id() {
+                known.insert(code, movement);
+            }
+        }
+    }
+
+    for code in not_in_lots {
+        let Some(movement) = known.remove(&code) else {
+            variance.unknown.push(code);
+            continue;
+        };
+
+        let unit = CountedUnit {
+            coll: movement.coll,
+            item: movement.item,
+            lot: movement.lot,
+            code,
+            last_movement: Some(movement.kind),
+            last_movement_at: Some(movement.at),
+        };
+        if movement.kind == MovementKind::Sold {
+            variance.sold.push(unit);
+        } else {
+            variance.found.push(unit);
+        }
+    }
+
+    Ok(variance)
+}
+
+/// Brings the lots in line with a closed count: units not scanned are taken out
+/// of their lot, and found units which were missing, written off or expired
+/// before the count opened are put back in theirs, unless the manager left them
+/// out. Counts stay open while the store trades, so units which left their lot
+/// any other way, sold ones above all, are never put back. Units which moved
+/// since the count closed are skipped. Every adjustment is recorded in the
+/// inventory ledger.
+#[tracing::instrument(name = "Approving stock count", skip(db, approval))]
+pub async fn approve_count(
+    db: &mongodb::Database,
+    count_id: ObjectId,
+    manager: ObjectId,
+    approval: CountApproval,
+) -> Result<StockCount> {
+    let Some(count) = get_count(db, count_id).await? else {
+        bail!(error::Inventory::CountNotFound);
+    };
+    if count.status != StockCountStatus::Closed {
+        bail!(error::Inventory::CountState(count.status.as_str().into()));
+    }
+    let variance = count.variance.clone().unwrap_or_default();
+    let excluded: HashSet<ObjectId> = approval.exclude.iter().copied().collect();
+
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+    let mut touched: HashSet<(String, ObjectId)> = HashSet::new();
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        let res = counts_coll.update_one(
+            doc! { "_id": count_id, "status": StockCountStatus::Closed.as_str() },
+            doc! { "$set": {
+                "status": StockCountStatus::Approved.as_str(),
+                "approvedBy": manager,
+                "approvedAt": bson::DateTime::now(),
+                "excluded": &approval.exclude,
+            }},
+        )
+        .session(&mut session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Inventory::CountState("being processed".into()));
+        }
+
+        for unit in variance.missing.iter().filter(|unit| !excluded.contains(&unit.code)) {
+            if adjust_unit(db, &mut session, count_id, manager, unit, MovementKind::Missing).await? {
+                touched.insert((unit.coll.clone(), unit.item));
+            }
+        }
+
+        let restockable = variance.found.iter()
+            .filter(|unit| !excluded.contains(&unit.code))
+            .filter(|unit| matches!(
+                unit.last_movement,
+                Some(MovementKind::Missing | MovementKind::WrittenOff | MovementKind::Expired)
+            ))
+            .filter(|unit| unit.last_movement_at.is_some_and(|at| at < count.opened_at));
+        for unit in restockable {
+            if adjust_unit(db, &mut session, count_id, manager, unit, MovementKind::Found).await? {
+                touched.insert((unit.coll.clone(), unit.item));
+            }
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    for (coll, item_id) in touched {
+        refresh_projection(db, &coll, item_id).await;
+    }
+
+    tracing::info!(target: "mongodb", "Stock count {} approved by {}.", count_id, manager);
+
+    get_count(db, count_id).await?.ok_or_else(|| anyhow!(error::Inventory::CountNotFound))
+}
+
+/// Takes a missing unit out of its lot, or puts a found one back in it.
+/// Returns whether the lot changed.
+async fn adjust_unit(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    count_id: ObjectId,
+    manager: ObjectId,
+    unit: &CountedUnit,
+    kind: MovementKind,
+) -> Result<bool> {
+    let item_coll: Collection<Document> = db.collection(&unit.coll);
+
+    let (filter, update) = match kind {
+        MovementKind::Missing => (
+            doc! { "_id": unit.item, "lot": { "$elemMatch": { "_id": unit.lot, "code": unit.code }}},
+            doc! { "$pull": { "lot.$[lot].code": unit.code }},
+        ),
+        _ => {
+            // Back in a lot since the count closed
+            if item_coll.find_one(doc! { "lot.code": unit.code }).session(&mut *session).await?.is_some() {
+                return Ok(false);
+            }
+            (
+                doc! { "_id": unit.item, "lot._id": unit.lot },
+                doc! { "$push": { "lot.$[lot].code": unit.code }},
+            )
+        }
+    };
+
+    let res = item_coll.update_one(filter, update)
+        .array_filters(vec![doc! { "lot._id": unit.lot }])
+        .session(&mut *session)
+        .await?;
+
+    if res.modified_count != 1 {
+        return Ok(false);
+    }
+
+    record_movements(db, session, LotMovement {
+        kind,
+        coll: &unit.coll,
+        item: unit.item,
+        lot: unit.lot,
+        codes: &[bson::Bson::ObjectId(unit.code)],
+        by: Some(manager),
+        reason: Some(COUNT_REASON),
+        reference: Some(count_id),
+    }).await?;
+
+    Ok(true)
+}
+
+/// Stops a count which is open or awaiting approval, leaving the lots as they are.
+#[tracing::instrument(name = "Cancelling stock count", skip(db))]
+pub async fn cancel_count(db: &mongodb::Database, count_id: ObjectId) -> Result<StockCount> {
+    let counts_coll: Collection<StockCount> = db.collection(STOCK_COUNTS_COLL);
+
+    let count = counts_coll.find_one_and_update(
+        doc! {
+            "_id": count_id,
+            "status": { "$in": [StockCountStatus::Open.as_str(), StockCountStatus::Closed.as_str()] },
+        },
+        doc! { "$set": { "status": StockCountStatus::Cancelled.as_str() }},
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match count {
+        Some(count) => {
+            tracing::info!(target: "mongodb", "Stock count {} cancelled.", count_id);
+            Ok(count)
+        }
+        None => Err(state_error(db, count_id).await),
+    }
+}
+
+/// Why a count couldn't be updated: it doesn't exist, or isn't in the right status.
+async fn state_error(db: &mongodb::Database, count_id: ObjectId) -> anyhow::Error {
+    match get_count(db, count_id).await {
+        Ok(Some(count)) => anyhow!(error::Inventory::CountState(count.status.as_str().into())),
+        Ok(None) => anyhow!(error::Inventory::CountNotFound),
+        Err(e) => e,
+    }
+}
@@ -0,0 +1,341 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    inventory::{ WRITE_OFFS_COLL, refresh_projection },
+    movements::{ LotMovement, record_movements },
+    orders::commit,
+};
+use crate::settings::ExpirySettings;
+use crate::types::{
+    mongodb::{ inventory::{ Lot, Markdown, MovementKind, WriteOff }, stores::StoreInfo },
+    responses::{ ExpiringLot, ExpiryReport },
+};
+use std::time::Duration;
+
+/// Reason of the write-offs of expired lots.
+pub const EXPIRED_REASON: &str = "Expired";
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Lots held by a store which expire within some days and still have units,
+/// soonest first. Lots that already expired are included.
+pub async fn get_expiring_lots(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    within_days: i64,
+    now: bson::DateTime,
+) -> Result<Vec<ExpiringLot>> {
+    let limit = now.timestamp_millis() + within_days * DAY_MS;
+    let mut lots = Vec::new();
+
+    for (coll, own) in utils::stock::holdable_colls(store) {
+        let item_coll: Collection<Document> = db.collection(&coll);
+
+        // Expiry dates may be strings, so they're compared here instead of in the query
+        let mut filter = doc! { "lot.expiry": { "$exists": true }};
+        if !own {
+            filter.insert("lot.store", store.id);
+        }
+        let mut cursor = item_coll
+            .find(filter)
+            .projection(doc! { "name": 1, "price": 1, "pricePerKg": 1, "lot": 1, "markdown": 1 })
+            .await?;
+
+        while let Some(item) = cursor.try_next().await? {
+            let (Ok(item_id), Ok(name)) = (item.get_object_id("_id"), item.get_str("name")) else { continue };
+            let price = item.get_f64("price").or_else(|_| item.get_f64("pricePerKg")).unwrap_or_default();
+            let markdown = get_markdown(&item);
+
+            for lot in item.get_array("lot").map(|lots| lots.iter().filter_map(|lot| lot.as_document())).into_iter().flatten() {
+                if !utils::stock::lot_held_by(lot, store.id, own) {
+                    continue;
+                }
+                let Ok(lot) = Lot::from_doc(lot) else { continue };
+                let Some(expiry) = lot.expiry else { continue };
+                if lot.code.is_empty() || expiry.timestamp_millis() > limit {
+                    continue;
+                }
+
+                lots.push(ExpiringLot {
+                    coll: coll.clone(),
+                    item: item_id,
+                    name: name.to_string(),
+                    lot: lot.id,
+                    store: lot.store,
+                    expiry,
+                    units: lot.code.len(),
+                    days_left: ((expiry.timestamp_millis() - now.timestamp_millis()) as f64 / DAY_MS as f64 * 10.0).round() / 10.0,
+                    price,
+                    markdown: markdown.as_ref().filter(|markdown| markdown.lot == lot.id).map(|markdown| markdown.discount),
+                });
+            }
+        }
+    }
+
+    lots.sort_by_key(|lot| lot.expiry);
+
+    Ok(lots)
+}
+
+/// Lots of a store expiring within some days, the expired ones still in stock,
+/// and the expired units written off within the same number of days.
+pub async fn get_expiry_report(db: &mongodb::Database, store: &StoreInfo, days: i64) -> Result<ExpiryReport> {
+    let now = bson::DateTime::now();
+
+    let (expired, expiring) = get_expiring_lots(db, store, days, now).await?
+        .into_iter()
+        .partition(|lot| lot.expiry <= now);
+
+    let write_offs_coll: Collection<WriteOff> = db.collection(WRITE_OFFS_COLL);
+    let wasted = write_offs_coll
+        .find(doc! {
+            "$or": [
+                { "coll": { "$in": &store.item_colls }, "store": { "$exists": false }},
+                { "store": store.id },
+            ],
+            "reason": EXPIRED_REASON,
+            "at": { "$gte": bson::DateTime::from_millis(now.timestamp_millis() - days * DAY_MS) },
+        })
+        .sort(doc! { "at": -1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(ExpiryReport { store: store.name.clone(), expiring, expired, wasted })
+}
+
+/// Checks the food lots of every store: expired lots are taken out of stock and
+/// written off as waste, and items with lots about to expire are marked down.
+/// Markdowns whose lot is gone are lifted. Returns the report of each store
+/// with something expiring or wasted.
+#[tracing::instrument(name = "Checking lot expiry", skip(db, settings))]
+pub async fn run_expiry_check(db: &mongodb::Database, settings: &ExpirySettings) -> Result<Vec<(StoreInfo, ExpiryReport)>> {
+    let now = bson::DateTime::now();
+    let horizon = settings.markdowns.iter()
+        .map(|rule| rule.within_days)
+        .fold(settings.warning_days, i64::max);
+
+    let mut reports = Vec::new();
+
+    for store in stores::all() {
+        let lots = get_expiring_lots(db, &store, horizon, now).await?;
+        let mut wasted = Vec::new();
+        let mut marked_down: HashSet<ObjectId> = HashSet::new();
+
+        for lot in &lots {
+            if lot.expiry <= now {
+                match write_off_expired_lot(db, lot).await {
+                    Ok(Some(write_off)) => wasted.push(write_off),
+                    Ok(None) => {}
+                    Err(e) => tracing::error!(target: "mongodb", "Failed to write off expired lot {}: {}", lot.lot, e),
+                }
+                continue;
+            }
+
+            // Lots are sorted by expiry, so the first one of an item sets its markdown.
+            // Only the store selling an item sets its price.
+            if lot.store.is_some() || !marked_down.insert(lot.item) {
+                continue;
+            }
+            let days_left = (lot.expiry.timestamp_millis() - now.timestamp_millis()) as f64 / DAY_MS as f64;
+            match settings.markdown(days_left) {
+                Some(discount) => apply_markdown(db, lot, discount, now).await?,
+                None => { marked_down.remove(&lot.item); }
+            }
+        }
+
+        lift_markdowns(db, &store, &marked_down).await?;
+
+        let expiring: Vec<ExpiringLot> = lots.into_iter()
+            .filter(|lot| lot.expiry > now && lot.expiry.timestamp_millis() <= now.timestamp_millis() + settings.warning_days * DAY_MS)
+            .collect();
+
+        if !expiring.is_empty() || !wasted.is_empty() {
+            let report = ExpiryReport { store: store.name.clone(), expiring, expired: Vec::new(), wasted };
+            reports.push((store, report));
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Takes an expired lot out of its item and records its units as waste, in the
+/// same transaction. Skipped if the lot changed since it was read.
+async fn write_off_expired_lot(db: &mongodb::Database, lot: &ExpiringLot) -> Result<Option<WriteOff>> {
+    let item_coll: Collection<Document> = db.collection(&lot.coll);
+    let write_offs_coll: Collection<WriteOff> = db.collection(WRITE_OFFS_COLL);
+
+    let Some(item) = item_coll.find_one(doc! { "_id": lot.item }).projection(doc! { "lot": 1 }).await? else {
+        return Ok(None);
+    };
+    let codes = item.get_array("lot").ok()
+        .and_then(|lots| lots.iter()
+            .filter_map(|doc| doc.as_document())
+            .find(|doc| doc.get_object_id("_id").ok() == Some(lot.lot))
+            .and_then(|doc| doc.get_array("code").ok().cloned()))
+        .unwrap_or_default();
+
+    let write_off = WriteOff {
+        id: ObjectId::new(),
+        coll: lot.coll.clone(),
+        item: lot.item,
+        lot: lot.lot,
+        codes,
+        reason: EXPIRED_REASON.to_string(),
+        store: lot.store,
+        by: None,
+        at: bson::DateTime::now(),
+    };
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        let res = item_coll.update_one(
+            doc! {
+                "_id": lot.item,
+                "lot": { "$elemMatch": {
+                    "_id": lot.lot,
+                    "code": { "$size": write_off.codes.len() as i64, "$all": &write_off.codes },
+                }},
+            },
+            doc! { "$pull": { "lot": { "_id": lot.lot }}},
+        )
+        .session(&mut session)
+        .await?;
+
+        if res.modified_count != 1 {
+            return Ok(false);
+        }
+
+        write_offs_coll.insert_one(&write_off).session(&mut session).await?;
+        record_movements(db, &mut session, LotMovement {
+            kind: MovementKind::Expired,
+            coll: &lot.coll,
+            item: lot.item,
+            lot: lot.lot,
+            codes: &write_off.codes,
+            by: None,
+            reason: Some(EXPIRED_REASON),
+            reference: Some(write_off.id),
+        }).await?;
+        commit(&mut session).await?;
+        Ok(true)
+    }.await;
+
+    match result {
+        Ok(true) => {
+            tracing::info!(target: "mongodb", "Expired lot {} of item {} written off with {} units.", lot.lot, lot.item, write_off.codes.len());
+            refresh_projection(db, &lot.coll, lot.item).await;
+            Ok(Some(write_off))
+        }
+        Ok(false) => {
+            let _ = session.abort_transaction().await;
+            tracing::warn!(target: "mongodb", "Lot {} changed while writing it off, retrying on the next check.", lot.lot);
+            Ok(None)
+        }
+        Err(e) => {
+            let _ = session.abort_transaction().await;
+            Err(e)
+        }
+    }
+}
+
+/// Cuts the price of an item by a discount of its regular price, unless it
+/// already has that markdown. Skipped if the price changed since it was read.
+async fn apply_markdown(db: &mongodb::Database, lot: &ExpiringLot, discount: f64, now: bson::DateTime) -> Result<()> {
+    let item_coll: Collection<Document> = db.collection(&lot.coll);
+
+    let Some(item) = item_coll.find_one(doc! { "_id": lot.item }).await? else {
+        return Ok(());
+    };
+    let (price_key, price) = match (item.get_f64("price"), item.get_f64("pricePerKg")) {
+        (Ok(price), _) => ("price", price),
+        (_, Ok(price)) => ("pricePerKg", price),
+        _ => return Ok(()),
+    };
+
+    let current = get_markdown(&item);
+    if current.as_ref().is_some_and(|current| current.discount == discount && current.lot == lot.lot) {
+        return Ok(());
+    }
+
+    let regular_price = current.map(|current| current.regular_price).unwrap_or(price);
+    let markdown = Markdown { discount, regular_price, lot: lot.lot, expiry: lot.expiry, at: now };
+
+    let res = item_coll.update_one(
+        doc! { "_id": lot.item, price_key: price },
+        doc! { "$set": {
+            price_key: ((regular_price * (1.0 - discount)) * 100.0).round() / 100.0,
+            "markdown": bson::to_bson(&markdown)?,
+        }},
+    ).await?;
+
+    if res.modified_count == 1 {
+        tracing::info!(target: "mongodb", "Item {} of `{}` marked down by {}%.", lot.item, lot.coll, (discount * 100.0).round());
+        refresh_projection(db, &lot.coll, lot.item).await;
+    }
+
+    Ok(())
+}
+
+/// Gives the items of a store their regular price back, except the ones still marked down.
+async fn lift_markdowns(db: &mongodb::Database, store: &StoreInfo, keep: &HashSet<ObjectId>) -> Result<()> {
+    for coll in &store.item_colls {
+        let item_coll: Collection<Document> = db.collection(coll);
+
+        let items: Vec<Document> = item_coll
+            .find(doc! { "markdown": { "$exists": true }, "_id": { "$nin": keep.iter().collect::<Vec<_>>() }})
+            .projection(doc! { "price": 1, "pricePerKg": 1, "markdown": 1 })
+            .await?
+            .try_collect()
+            .await?;
+
+        for item in items {
+            let (Ok(item_id), Some(markdown)) = (item.get_object_id("_id"), get_markdown(&item)) else { continue };
+            let price_key = if item.contains_key("price") { "price" } else { "pricePerKg" };
+
+            item_coll.update_one(
+                doc! { "_id": item_id, "markdown": { "$exists": true }},
+                doc! {
+                    "$set": { price_key: markdown.regular_price },
+                    "$unset": { "markdown": "" },
+                },
+            ).await?;
+
+            tracing::info!(target: "mongodb", "Markdown of item {} of `{}` lifted.", item_id, coll);
+            refresh_projection(db, coll, item_id).await;
+        }
+    }
+
+    Ok(())
+}
+
+fn get_markdown(item: &Document) -> Option<Markdown> {
+    item.get_document("markdown").ok()
+        .and_then(|markdown| bson::from_document(markdown.clone()).ok())
+}
+
+/// Runs the expiry check right away, then every `check_interval_hours`.
+pub fn spawn_expiry_check(db: mongodb::Database, settings: &ExpirySettings) {
+    let settings = settings.clone();
+    let check_interval = Duration::from_secs(settings.check_interval_hours * 60 * 60);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            match run_expiry_check(&db, &settings).await {
+                Ok(reports) if settings.email_staff => {
+                    for (store, report) in reports {
+                        if let Err(e) = crate::alerts::email_expiry_report(&db, &store, &report).await {
+                            tracing::error!(target: "backend", "Failed to email the expiry report of `{}`: {}", store.name, e);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!(target: "mongodb", "Failed to check lot expiry: {}", e),
+            }
+        }
+    });
+}
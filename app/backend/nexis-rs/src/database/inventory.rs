@@ -0,0 +1,315 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{ movements::{ LotMovement, record_movements }, orders::commit };
+use crate::types::{
+    error,
+    mongodb::inventory::{ Lot, MovementKind, WriteOff },
+    requests::inventory::{ LotUpdate, NewLot, NewWriteOff },
+};
+use chrono::{ DateTime, Utc };
+use mongodb::{ ClientSession, IndexModel };
+
+pub const WRITE_OFFS_COLL: &str = "writeOffs";
+
+/// Most units a single lot can be received with.
+const MAX_LOT_UNITS: u32 = 10_000;
+
+pub async fn ensure_inventory_indexes(db: &mongodb::Database) -> Result<()> {
+    let write_offs_coll: Collection<WriteOff> = db.collection(WRITE_OFFS_COLL);
+
+    write_offs_coll.create_index(IndexModel::builder().keys(doc! { "coll": 1, "item": 1, "at": -1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Returns the lots of an item, or `None` if the item doesn't exist.
+pub async fn get_lots(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> Result<Option<Vec<Lot>>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let Some(item) = item_coll.find_one(doc! { "_id": item_id }).projection(doc! { "lot": 1 }).await? else {
+        return Ok(None);
+    };
+
+    let lots = item.get_array("lot")
+        .map(|lots| lots.iter()
+            .filter_map(|lot| lot.as_document())
+            .filter_map(|lot| Lot::from_doc(lot).ok())
+            .collect())
+        .unwrap_or_default();
+
+    Ok(Some(lots))
+}
+
+/// Adds a lot of new units to an item, generating a unit code for each of them.
+#[tracing::instrument(name = "Receiving lot", skip(db))]
+pub async fn receive_lot(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    by: ObjectId,
+    new_lot: NewLot,
+) -> Result<Lot> {
+    let lot = build_lot(&new_lot)?;
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        push_lot(db, &mut session, coll, item_id, by, &lot, None).await?;
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    tracing::info!(target: "mongodb", "Lot {} of {} units received for item {} of `{}`.", lot.id, new_lot.quantity, item_id, coll);
+    refresh_projection(db, coll, item_id).await;
+
+    Ok(lot)
+}
+
+/// Checks a lot about to be received, and generates the codes of its units.
+pub(crate) fn build_lot(new_lot: &NewLot) -> Result<Lot> {
+    if new_lot.quantity == 0 || new_lot.quantity > MAX_LOT_UNITS {
+        bail!(error::Inventory::Invalid(format!("The quantity must be between 1 and {}.", MAX_LOT_UNITS)));
+    }
+
+    let enter_date = new_lot.enter_date.map(to_bson_date).unwrap_or_else(bson::DateTime::now);
+    let expiry = new_lot.expiry.map(to_bson_date);
+    check_dates(enter_date, expiry)?;
+    check_cost_price(new_lot.cost_price)?;
+
+    Ok(Lot {
+        id: ObjectId::new(),
+        enter_date: Some(enter_date),
+        expiry,
+        cost_price: new_lot.cost_price,
+        store: None,
+        code: (0..new_lot.quantity).map(|_| bson::Bson::ObjectId(ObjectId::new())).collect(),
+    })
+}
+
+/// Adds a lot to an item and records its units as received, in the session's
+/// transaction. `reference` is what the lot was received for, like a purchase order.
+pub(crate) async fn push_lot(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    coll: &str,
+    item_id: ObjectId,
+    by: ObjectId,
+    lot: &Lot,
+    reference: Option<ObjectId>,
+) -> Result<()> {
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let res = item_coll.update_one(
+        doc! { "_id": item_id },
+        doc! { "$push": { "lot": bson::to_bson(lot)? }},
+    )
+    .session(&mut *session)
+    .await?;
+
+    if res.matched_count != 1 {
+        bail!(error::Inventory::ItemNotFound);
+    }
+
+    record_movements(db, session, LotMovement {
+        kind: MovementKind::Received,
+        coll,
+        item: item_id,
+        lot: lot.id,
+        codes: &lot.code,
+        by: Some(by),
+        reason: None,
+        reference,
+    }).await
+}
+
+/// Corrects the dates or cost price of a lot.
+#[tracing::instrument(name = "Editing lot", skip(db))]
+pub async fn update_lot(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    lot_id: ObjectId,
+    changes: LotUpdate,
+) -> Result<Lot> {
+    let current = get_lot(db, coll, item_id, lot_id).await?;
+
+    let enter_date = changes.enter_date.map(to_bson_date);
+    let expiry = changes.expiry.map(to_bson_date);
+    if let Some(enter_date) = enter_date.or(current.enter_date) {
+        check_dates(enter_date, expiry.or(current.expiry))?;
+    }
+    check_cost_price(changes.cost_price)?;
+
+    let mut set = doc! {};
+    let mut unset = doc! {};
+    if let Some(enter_date) = enter_date {
+        set.insert("lot.$[lot].enterDate", enter_date);
+        unset.insert("lot.$[lot].enter_date", "");
+    }
+    if let Some(expiry) = expiry {
+        set.insert("lot.$[lot].expiry", expiry);
+    }
+    if let Some(cost_price) = changes.cost_price {
+        set.insert("lot.$[lot].costPrice", cost_price);
+    }
+    if set.is_empty() {
+        return Ok(current);
+    }
+
+    let mut update = doc! { "$set": set };
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    item_coll.update_one(doc! { "_id": item_id, "lot._id": lot_id }, update)
+        .array_filters(vec![doc! { "lot._id": lot_id }])
+        .await?;
+
+    tracing::info!(target: "mongodb", "Lot {} of item {} of `{}` edited.", lot_id, item_id, coll);
+    refresh_projection(db, coll, item_id).await;
+
+    get_lot(db, coll, item_id, lot_id).await
+}
+
+/// Takes units out of a lot without selling them, recording why. The units are
+/// pulled in the same transaction that records the write-off, and only if
+/// they're all still in the lot.
+#[tracing::instrument(name = "Writing off units", skip(db))]
+pub async fn write_off_units(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    lot_id: ObjectId,
+    by: ObjectId,
+    write_off: NewWriteOff,
+) -> Result<WriteOff> {
+    if write_off.reason.trim().is_empty() {
+        bail!(error::Inventory::Invalid("A reason is required.".into()));
+    }
+
+    let lot = get_lot(db, coll, item_id, lot_id).await?;
+
+    let codes: Vec<bson::Bson> = match (write_off.codes.is_empty(), write_off.quantity) {
+        (false, None) => {
+            let mut codes = Vec::with_capacity(write_off.codes.len());
+            for code in write_off.codes {
+                let code = bson::Bson::ObjectId(code);
+                if !lot.code.contains(&code) {
+                    bail!(error::Inventory::UnitNotInLot(code.to_string()));
+                }
+                if !codes.contains(&code) {
+                    codes.push(code);
+                }
+            }
+            codes
+        }
+        (true, Some(quantity)) if quantity > 0 => {
+            if quantity as usize > lot.code.len() {
+                bail!(error::Inventory::NotEnoughUnits(lot.code.len()));
+            }
+            lot.code.iter().take(quantity as usize).cloned().collect()
+        }
+        _ => bail!(error::Inventory::Invalid("Give either the `codes` or a `quantity` of units to write off.".into())),
+    };
+
+    let record = WriteOff {
+        id: ObjectId::new(),
+        coll: coll.to_string(),
+        item: item_id,
+        lot: lot_id,
+        codes,
+        reason: write_off.reason,
+        store: lot.store,
+        by: Some(by),
+        at: bson::DateTime::now(),
+    };
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let write_offs_coll: Collection<WriteOff> = db.collection(WRITE_OFFS_COLL);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        let res = item_coll.update_one(
+            doc! {
+                "_id": item_id,
+                "lot": { "$elemMatch": { "_id": lot_id, "code": { "$all": &record.codes }}},
+            },
+            doc! { "$pull": { "lot.$[lot].code": { "$in": &record.codes }}},
+        )
+        .array_filters(vec![doc! { "lot._id": lot_id }])
+        .session(&mut session)
+        .await?;
+
+        // Sold or written off since the lot was read
+        if res.modified_count != 1 {
+            bail!(error::Inventory::Changed);
+        }
+
+        write_offs_coll.insert_one(&record).session(&mut session).await?;
+        record_movements(db, &mut session, LotMovement {
+            kind: MovementKind::WrittenOff,
+            coll,
+            item: item_id,
+            lot: lot_id,
+            codes: &record.codes,
+            by: record.by,
+            reason: Some(&record.reason),
+            reference: Some(record.id),
+        }).await?;
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    tracing::info!(target: "mongodb", "{} units of lot {} of item {} of `{}` written off.", record.codes.len(), lot_id, item_id, coll);
+    refresh_projection(db, coll, item_id).await;
+
+    Ok(record)
+}
+
+pub async fn get_lot(db: &mongodb::Database, coll: &str, item_id: ObjectId, lot_id: ObjectId) -> Result<Lot> {
+    let Some(lots) = get_lots(db, coll, item_id).await? else {
+        bail!(error::Inventory::ItemNotFound);
+    };
+
+    lots.into_iter()
+        .find(|lot| lot.id == lot_id)
+        .ok_or_else(|| anyhow!(error::Inventory::LotNotFound))
+}
+
+fn check_dates(enter_date: bson::DateTime, expiry: Option<bson::DateTime>) -> Result<()> {
+    if expiry.is_some_and(|expiry| expiry <= enter_date) {
+        bail!(error::Inventory::Invalid("The expiry must be after the enter date.".into()));
+    }
+    Ok(())
+}
+
+fn check_cost_price(cost_price: Option<f64>) -> Result<()> {
+    if cost_price.is_some_and(|cost_price| !cost_price.is_finite() || cost_price < 0.0) {
+        bail!(error::Inventory::Invalid("The cost price can't be negative.".into()));
+    }
+    Ok(())
+}
+
+fn to_bson_date(date: DateTime<Utc>) -> bson::DateTime {
+    bson::DateTime::from_millis(date.timestamp_millis())
+}
+
+/// Updates the search projection of an item. If this fails, the change stream
+/// or the next reconciliation catches up.
+pub async fn refresh_projection(db: &mongodb::Database, coll: &str, item_id: ObjectId) {
+    if let Err(e) = crate::search::refresh_item(db, coll, item_id).await {
+        tracing::warn!(target: "backend", "Failed to refresh the projection of item {} of `{}`: {}", item_id, coll, e);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::prelude::*;
+use anyhow::Result;
+
+/// Current name, price and available stock of an item.
+#[derive(Debug, Clone)]
+pub struct ItemInfo {
+    pub name: String,
+    /// Price of a unit, or of a kilogram if `per_kg` is set.
+    pub price: f64,
+    /// Whether the item is food sold by `pricePerKg`.
+    pub per_kg: bool,
+    pub stock: i64,
+}
+
+impl ItemInfo {
+    /// Reads the name, price and stock at `now` of an item document.
+    pub fn from_doc(item: &Document, now: bson::DateTime) -> Result<Self> {
+        let (price, per_kg) = match (item.get_f64("price"), item.get_f64("pricePerKg")) {
+            (Ok(price), _) => (price, false),
+            (_, Ok(price_per_kg)) => (price_per_kg, true),
+            _ => bail!("Item {} has neither `price` nor `pricePerKg`", item.get_object_id("_id").map(|id| id.to_hex()).unwrap_or_default()),
+        };
+
+        Ok(ItemInfo {
+            name: item.get_str("name")?.to_string(),
+            price,
+            per_kg,
+            stock: utils::stock::available_units(&utils::stock::lot_units(item), now),
+        })
+    }
+
+    /// Total price of an amount of the item, rounded to cents. Items sold
+    /// by weight are charged by `weight_kg`, so it's `None` without it.
+    pub fn line_total(&self, quantity: u32, weight_kg: Option<f64>) -> Option<f64> {
+        let total = if self.per_kg {
+            self.price * weight_kg?
+        } else {
+            self.price * quantity as f64
+        };
+
+        Some((total * 100.0).round() / 100.0)
+    }
+}
+
+/// Reads an item from its item collection.
+/// Returns `None` if the collection isn't sold by any store, or the item doesn't
+/// exist or was archived.
+pub async fn get_item_info(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+) -> Result<Option<ItemInfo>> {
+    if !stores::is_item_coll(coll) {
+        return Ok(None);
+    }
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let Some(item) = item_coll.find_one(doc! { "_id": item_id, "archived": { "$ne": true }}).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(ItemInfo::from_doc(&item, bson::DateTime::now())?))
+}
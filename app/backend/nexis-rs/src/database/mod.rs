@@ -1,9 +1,149 @@
 pub mod users;
+pub mod stores;
+pub mod items;
+pub mod cart;
+pub mod orders;
+pub mod payments;
+pub mod pos;
+pub mod returns;
+pub mod inventory;
+pub mod expiry;
+pub mod reorder;
+pub mod movements;
+pub mod counts;
+pub mod purchasing;
+pub mod valuation;
+pub mod transfers;
+pub mod catalog;
 
 pub use users::{
     insert_created_user_into_db,
     get_db_user
 };
+pub use stores::{
+    insert_store,
+    update_store,
+    get_staff_stores,
+    get_store_staff_contacts,
+    is_store_owner,
+};
+pub use items::{ ItemInfo, get_item_info };
+pub use cart::{
+    CartOwner,
+    get_cart_response,
+    add_to_cart,
+    set_cart_quantity,
+    remove_from_cart,
+    save_cart,
+    merge_guest_cart,
+};
+pub use orders::{
+    checkout,
+    transition_order,
+    clear_refund_due,
+    get_order,
+    get_user_orders,
+    get_store_order_queue,
+    ensure_order_indexes,
+    spawn_reservation_expiry,
+};
+pub use payments::{
+    ensure_payment_indexes,
+    get_payment,
+    get_order_payments,
+    get_sale_payment,
+    pay_order,
+    charge_sale,
+    refund_payment,
+    settle_payment,
+    settle_cancelled_order_payments,
+    apply_webhook_event,
+};
+pub use pos::{ record_sale, get_sale, lookup_unit, ensure_unit_code_indexes };
+pub use returns::{
+    ensure_return_indexes,
+    get_return,
+    get_returns_queue,
+    return_sale_units,
+    request_order_return,
+    approve_return,
+    reject_return,
+};
+pub use inventory::{
+    ensure_inventory_indexes,
+    get_lot,
+    get_lots,
+    receive_lot,
+    update_lot,
+    write_off_units,
+};
+pub use expiry::{
+    get_expiry_report,
+    run_expiry_check,
+    spawn_expiry_check,
+};
+pub use reorder::{
+    ensure_stock_alert_indexes,
+    set_reorder_levels,
+    clear_reorder_levels,
+    get_low_stock,
+    spawn_stock_alert_check,
+};
+pub use movements::{
+    ensure_movement_indexes,
+    record_opening_balances,
+    get_movements,
+    reconcile_store,
+    spawn_reconciliation,
+};
+pub use counts::{
+    ensure_stock_count_indexes,
+    open_count,
+    get_count,
+    get_store_counts,
+    add_scans,
+    close_count,
+    approve_count,
+    cancel_count,
+};
+pub use purchasing::{
+    ensure_purchasing_indexes,
+    create_supplier,
+    get_supplier,
+    get_store_suppliers,
+    update_supplier,
+    delete_supplier,
+    create_purchase_order,
+    get_purchase_order,
+    get_store_purchase_orders,
+    update_purchase_order,
+    delete_purchase_order,
+    send_purchase_order,
+    close_purchase_order,
+    receive_purchase_order,
+};
+pub use valuation::{
+    get_valuation,
+    get_margin_report,
+};
+pub use transfers::{
+    ensure_stock_transfer_indexes,
+    request_transfer,
+    get_transfer,
+    get_store_transfers,
+    ship_transfer,
+    receive_transfer,
+    cancel_transfer,
+};
+pub use catalog::{
+    ensure_catalog_indexes,
+    create_catalog_item,
+    get_catalog_item,
+    get_catalog_items,
+    update_catalog_item,
+    archive_catalog_item,
+    restore_catalog_item,
+};
 
 use crate::prelude::*;
 use anyhow::Result;
@@ -17,4 +157,13 @@ pub async fn get_redis_conn(redis_pool: &deadpool_redis::Pool) -> Result<deadpoo
             Err(anyhow!("Redis connection could not be established."))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Whether a write failed for breaking a unique index.
+pub fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error)) => write_error.code == 11000,
+        mongodb::error::ErrorKind::Command(command_error) => command_error.code == 11000,
+        _ => false,
+    }
+}
@@ -0,0 +1,372 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::orders::commit;
+use crate::types::{
+    mongodb::{ inventory::{ Lot, Movement, MovementKind }, stores::StoreInfo },
+    requests::inventory::MovementQuery,
+    responses::{ DriftUnit, ReconciliationReport, StockDrift },
+};
+use mongodb::{ ClientSession, IndexModel, options::ReadConcern };
+use std::time::Duration;
+
+pub const MOVEMENTS_COLL: &str = "inventoryMovements";
+
+const PAGE_SIZE: i64 = 50;
+
+pub async fn ensure_movement_indexes(db: &mongodb::Database) -> Result<()> {
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+
+    movements_coll.create_index(IndexModel::builder().keys(doc! { "coll": 1, "item": 1, "at": -1 }).build()).await?;
+    movements_coll.create_index(IndexModel::builder().keys(doc! { "code": 1 }).build()).await?;
+    movements_coll.create_index(IndexModel::builder().keys(doc! { "reference": 1 }).build()).await?;
+    movements_coll.create_index(IndexModel::builder().keys(doc! { "coll": 1, "kind": 1, "at": -1 }).build()).await?;
+    movements_coll.create_index(IndexModel::builder().keys(doc! { "lot": 1, "at": -1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Units of a lot moved by the same operation.
+pub struct LotMovement<'a> {
+    pub kind: MovementKind,
+    pub coll: &'a str,
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub codes: &'a [bson::Bson],
+    pub by: Option<ObjectId>,
+    pub reason: Option<&'a str>,
+    pub reference: Option<ObjectId>,
+}
+
+/// Appends an entry to the inventory ledger for each unit moved. Must run in
+/// the transaction which moves them.
+pub async fn record_movements(db: &mongodb::Database, session: &mut ClientSession, moved: LotMovement<'_>) -> Result<()> {
+    if moved.codes.is_empty() {
+        return Ok(());
+    }
+
+    let cost = lot_cost(db, session, &moved).await?;
+
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    movements_coll.insert_many(to_movements(&moved, cost, bson::DateTime::now())).session(&mut *session).await?;
+
+    Ok(())
+}
+
+/// Cost price of the lot of some moved units. Lots taken out of their item
+/// altogether, like expired ones, keep the cost of their earlier entries.
+async fn lot_cost(db: &mongodb::Database, session: &mut ClientSession, moved: &LotMovement<'_>) -> Result<Option<f64>> {
+    let item_coll: Collection<Document> = db.collection(moved.coll);
+
+    let item = item_coll
+        .find_one(doc! { "_id": moved.item, "lot._id": moved.lot })
+        .projection(doc! { "lot.$": 1 })
+        .session(&mut *session)
+        .await?;
+
+    if let Some(lot) = item.as_ref().and_then(|item| item.get_array("lot").ok()).and_then(|lots| lots.first()).and_then(|lot| lot.as_document()) {
+        return Ok(Lot::from_doc(lot)?.cost_price);
+    }
+
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    let earlier = movements_coll
+        .find_one(doc! { "lot": moved.lot, "cost": { "$ne": null }})
+        .sort(doc! { "at": -1 })
+        .session(&mut *session)
+        .await?;
+
+    Ok(earlier.and_then(|movement| movement.cost))
+}
+
+fn to_movements(moved: &LotMovement, cost: Option<f64>, at: bson::DateTime) -> Vec<Movement> {
+    moved.codes.iter()
+        .map(|code| Movement {
+            id: ObjectId::new(),
+            coll: moved.coll.to_string(),
+            item: moved.item,
+            lot: moved.lot,
+            code: code.clone(),
+            kind: moved.kind,
+            quantity: moved.kind.quantity(),
+            by: moved.by,
+            reason: moved.reason.map(str::to_string),
+            reference: moved.reference,
+            cost,
+            at,
+        })
+        .collect()
+}
+
+/// Records the units of items stocked before the inventory ledger was kept,
+/// the ones without any entry, as their opening balance. Runs on startup,
+/// before anything can move units.
+pub async fn record_opening_balances(db: &mongodb::Database) -> Result<()> {
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    let at = bson::DateTime::now();
+    let mut count = 0;
+
+    for coll in stores::item_colls() {
+        let item_coll: Collection<Document> = db.collection(&coll);
+        let tracked = movements_coll.distinct("item", doc! { "coll": &coll }).await?;
+
+        let mut cursor = item_coll
+            .find(doc! { "_id": { "$nin": tracked }})
+            .projection(doc! { "lot": 1 })
+            .await?;
+
+        while let Some(item) = cursor.try_next().await? {
+            let Ok(item_id) = item.get_object_id("_id") else { continue };
+
+            let movements: Vec<Movement> = item.get_array("lot")
+                .map(|lots| lots.iter()
+                    .filter_map(|lot| lot.as_document())
+                    .filter_map(|lot| Lot::from_doc(lot).ok())
+                    .flat_map(|lot| to_movements(&LotMovement {
+                        kind: MovementKind::Opening,
+                        coll: &coll,
+                        item: item_id,
+                        lot: lot.id,
+                        codes: &lot.code,
+                        by: None,
+                        reason: None,
+                        reference: None,
+                    }, lot.cost_price, at))
+                    .collect())
+                .unwrap_or_default();
+
+            if !movements.is_empty() {
+                count += movements.len();
+                movements_coll.insert_many(movements).await?;
+            }
+        }
+    }
+
+    if count > 0 {
+        tracing::info!(target: "mongodb", "Opening balance of {} units recorded in the inventory ledger.", count);
+    }
+
+    Ok(())
+}
+
+/// Cost prices of some lots of a collection which still exist, for the reports
+/// to cost the entries recorded before the ledger kept costs with. The entries
+/// themselves are never changed.
+pub async fn get_lot_costs(db: &mongodb::Database, coll: &str, lots: &[ObjectId]) -> Result<HashMap<ObjectId, f64>> {
+    if lots.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let mut cursor = item_coll
+        .find(doc! { "lot": { "$elemMatch": { "_id": { "$in": lots }, "costPrice": { "$ne": null }}}})
+        .projection(doc! { "lot._id": 1, "lot.costPrice": 1 })
+        .await?;
+
+    let mut costs = HashMap::new();
+    while let Some(item) = cursor.try_next().await? {
+        let item_lots = item.get_array("lot").map(|lots| lots.iter().filter_map(|lot| lot.as_document())).into_iter().flatten();
+        for lot in item_lots {
+            let (Ok(lot_id), Some(cost)) = (lot.get_object_id("_id"), lot.get("costPrice").and_then(|cost| cost.as_f64())) else { continue };
+            if lots.contains(&lot_id) {
+                costs.insert(lot_id, cost);
+            }
+        }
+    }
+
+    Ok(costs)
+}
+
+/// Entries of the inventory ledger of a store's item collections, newest first.
+pub async fn get_movements(db: &mongodb::Database, store: &StoreInfo, query: &MovementQuery) -> Result<Vec<Movement>> {
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+
+    let mut filter = match &query.coll {
+        Some(coll) => doc! { "coll": coll },
+        None => doc! { "coll": { "$in": &store.item_colls }},
+    };
+    if let Some(item) = query.item {
+        filter.insert("item", item);
+    }
+    if let Some(lot) = query.lot {
+        filter.insert("lot", lot);
+    }
+    if let Some(code) = query.code {
+        filter.insert("code", code);
+    }
+    if let Some(kind) = query.kind {
+        filter.insert("kind", kind.as_str());
+    }
+    if let Some(reference) = query.reference {
+        filter.insert("reference", reference);
+    }
+
+    let mut at = doc! {};
+    if let Some(from) = query.from {
+        at.insert("$gte", bson::DateTime::from_millis(from.timestamp_millis()));
+    }
+    if let Some(to) = query.to {
+        at.insert("$lt", bson::DateTime::from_millis(to.timestamp_millis()));
+    }
+    if !at.is_empty() {
+        filter.insert("at", at);
+    }
+
+    let movements = movements_coll
+        .find(filter)
+        .sort(doc! { "at": -1, "_id": -1 })
+        .skip(query.page.unwrap_or(0) * PAGE_SIZE as u64)
+        .limit(PAGE_SIZE)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(movements)
+}
+
+/// Rebuilds the stock of every item of a store from the inventory ledger and
+/// compares it with the units in its lots.
+#[tracing::instrument(name = "Reconciling inventory", skip(db, store), fields(store = %store.name))]
+pub async fn reconcile_store(db: &mongodb::Database, store: &StoreInfo) -> Result<ReconciliationReport> {
+    let checked_at = bson::DateTime::now();
+    let mut items = 0;
+    let mut drift = Vec::new();
+
+    for coll in &store.item_colls {
+        let item_coll: Collection<Document> = db.collection(coll);
+        let ids: Vec<ObjectId> = item_coll
+            .distinct("_id", doc! {})
+            .await?
+            .into_iter()
+            .filter_map(|id| id.as_object_id())
+            .collect();
+
+        for item_id in ids {
+            items += 1;
+            if let Some(item_drift) = reconcile_item(db, coll, item_id).await? {
+                drift.push(item_drift);
+            }
+        }
+    }
+
+    Ok(ReconciliationReport { store: store.name.clone(), checked_at, items, drift })
+}
+
+/// Compares an item's lots with its ledger entries. Both are read from the same
+/// snapshot, so units moved meanwhile don't show up as drift.
+async fn reconcile_item(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> Result<Option<StockDrift>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().read_concern(ReadConcern::snapshot()).await?;
+
+    let result = async {
+        let Some(item) = item_coll
+            .find_one(doc! { "_id": item_id })
+            .projection(doc! { "name": 1, "lot": 1 })
+            .session(&mut session)
+            .await? else {
+            return Ok(None);
+        };
+
+        let lots: Vec<Lot> = item.get_array("lot")
+            .map(|lots| lots.iter()
+                .filter_map(|lot| lot.as_document())
+                .filter_map(|lot| Lot::from_doc(lot).ok())
+                .collect())
+            .unwrap_or_default();
+
+        // Net quantity of every unit of the item, by lot
+        let balances: Vec<Document> = movements_coll
+            .aggregate(vec![
+                doc! { "$match": { "coll": coll, "item": item_id }},
+                doc! { "$group": { "_id": { "lot": "$lot", "code": "$code" }, "balance": { "$sum": "$quantity" }}},
+            ])
+            .session(&mut session)
+            .await?
+            .stream(&mut session)
+            .try_collect()
+            .await?;
+
+        let mut ledger: HashMap<(ObjectId, String), (bson::Bson, i64)> = HashMap::new();
+        for balance in balances {
+            let Ok(key) = balance.get_document("_id") else { continue };
+            let (Ok(lot), Some(code)) = (key.get_object_id("lot"), key.get("code")) else { continue };
+            let quantity = balance.get_i64("balance").or_else(|_| balance.get_i32("balance").map(i64::from)).unwrap_or(0);
+            ledger.insert((lot, code.to_string()), (code.clone(), quantity));
+        }
+
+        let mut unrecorded = Vec::new();
+        let mut actual = 0;
+        for lot in &lots {
+            for code in &lot.code {
+                actual += 1;
+                let balance = ledger.remove(&(lot.id, code.to_string())).map(|(_, balance)| balance).unwrap_or(0);
+                if balance < 1 {
+                    unrecorded.push(DriftUnit { lot: lot.id, code: code.clone(), balance });
+                } else if balance > 1 {
+                    ledger.insert((lot.id, code.to_string()), (code.clone(), balance - 1));
+                }
+            }
+        }
+
+        // Units left are the ones the ledger has in stock but the lots don't
+        let mut missing: Vec<DriftUnit> = ledger.into_iter()
+            .filter(|(_, (_, balance))| *balance > 0)
+            .map(|((lot, _), (code, balance))| DriftUnit { lot, code, balance })
+            .collect();
+        missing.sort_by_key(|unit| unit.lot);
+
+        commit(&mut session).await?;
+
+        if missing.is_empty() && unrecorded.is_empty() {
+            return Ok(None);
+        }
+
+        let missing_units: i64 = missing.iter().map(|unit| unit.balance).sum();
+        let unrecorded_units = unrecorded.len() as i64;
+
+        Ok(Some(StockDrift {
+            coll: coll.to_string(),
+            item: item_id,
+            name: item.get_str("name").unwrap_or_default().to_string(),
+            expected: actual - unrecorded_units + missing_units,
+            actual,
+            missing,
+            unrecorded,
+        }))
+    }.await;
+
+    if result.is_err() {
+        let _ = session.abort_transaction().await;
+    }
+
+    result
+}
+
+/// Reconciles every store right away, then every `reconcile_interval_hours`,
+/// logging the items that drifted from the ledger.
+pub fn spawn_reconciliation(db: mongodb::Database, settings: &crate::settings::LedgerSettings) {
+    let check_interval = Duration::from_secs(settings.reconcile_interval_hours * 60 * 60);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            for store in stores::all() {
+                match reconcile_store(&db, &store).await {
+                    Ok(report) if report.drift.is_empty() => {}
+                    Ok(report) => {
+                        for item in &report.drift {
+                            tracing::warn!(
+                                target: "mongodb",
+                                "Item {} of `{}` drifted from the inventory ledger: {} units expected, {} in its lots.",
+                                item.item, item.coll, item.expected, item.actual,
+                            );
+                        }
+                    }
+                    Err(e) => tracing::error!(target: "mongodb", "Failed to reconcile the inventory of `{}`: {}", store.name, e),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,628 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{ CartOwner, get_item_info, movements::{ LotMovement, record_movements }};
+use crate::types::{
+    error,
+    mongodb::{ inventory::MovementKind, orders::{ Order, OrderEvent, OrderItem, OrderStatus, ReservedUnit }, stores::StoreInfo },
+};
+use mongodb::{
+    ClientSession,
+    IndexModel,
+    error::{ TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT },
+};
+use std::time::Duration;
+
+pub const ORDERS_COLL: &str = "orders";
+
+/// Times a transaction is retried when it conflicts with another one.
+const MAX_TRANSACTION_ATTEMPTS: u32 = 3;
+
+pub async fn ensure_order_indexes(db: &mongodb::Database) -> Result<()> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "status": 1, "expiresAt": 1 }).build()).await?;
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "user": 1, "createdAt": -1 }).build()).await?;
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "parent": 1 }).build()).await?;
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "store": 1, "status": 1, "createdAt": 1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Turns the cart of a client into a pending order, split into one sub-order per
+/// store. The unit codes of every item are taken from their lots in the same
+/// transaction that inserts the orders and empties the cart, so two buyers can
+/// never get the same unit. They stay reserved until the order is paid or its
+/// reservation expires.
+#[tracing::instrument(name = "Checking out cart", skip(db, redis_pool))]
+pub async fn checkout(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    user_id: ObjectId,
+    accept_price_changes: bool,
+) -> Result<Order> {
+    let settings = crate::settings::get_settings().expect("Failed to read settings.");
+
+    let cart = crate::database::cart::get_cart(db, redis_pool, CartOwner::User(user_id)).await?;
+    if cart.is_empty() {
+        bail!(error::Checkout::EmptyCart);
+    }
+
+    let mut items = Vec::with_capacity(cart.len());
+    for entry in cart {
+        let Some(item) = get_item_info(db, &entry.coll, entry.item).await? else {
+            bail!(error::Checkout::ItemNotFound(format!("Item {} of `{}` no longer exists.", entry.item, entry.coll)));
+        };
+
+        if !accept_price_changes && entry.price.is_some_and(|price| price != item.price) {
+            bail!(error::Checkout::PricesChanged);
+        }
+
+        let Some(total) = item.line_total(entry.quantity, entry.weight_kg) else {
+            bail!(error::Checkout::MissingWeight(item.name));
+        };
+
+        items.push(OrderItem {
+            store: stores::get_coll_store(&entry.coll).map(|store| store.name).unwrap_or_default(),
+            coll: entry.coll,
+            item: entry.item,
+            name: item.name,
+            quantity: entry.quantity,
+            price: item.price,
+            per_kg: item.per_kg,
+            weight_kg: if item.per_kg { entry.weight_kg } else { None },
+            total,
+            units: Vec::new(),
+        });
+    }
+
+    let now = bson::DateTime::now();
+    let order = Order {
+        id: ObjectId::new(),
+        user: user_id,
+        status: OrderStatus::Pending,
+        history: vec![OrderEvent { status: OrderStatus::Pending, at: now, by: Some(user_id), note: None }],
+        total: (items.iter().map(|item| item.total).sum::<f64>() * 100.0).round() / 100.0,
+        items,
+        created_at: now,
+        expires_at: Some(bson::DateTime::from_millis(
+            now.timestamp_millis() + settings.orders.reservation_minutes * 60 * 1000
+        )),
+        parent: None,
+        store: None,
+        sub_orders: Vec::new(),
+        refund_due: false,
+    };
+
+    let mut session = db.client().start_session().await?;
+    let mut attempt = 1;
+
+    loop {
+        session.start_transaction().await?;
+
+        let mut attempt_order = order.clone();
+        let result = match reserve_and_insert(db, &mut session, &mut attempt_order).await {
+            Ok(()) => commit(&mut session).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                tracing::info!(target: "mongodb", "Order {} created for user {}.", attempt_order.id, user_id);
+                return Ok(attempt_order);
+            }
+            Err(e) => {
+                let _ = session.abort_transaction().await;
+
+                if attempt < MAX_TRANSACTION_ATTEMPTS && has_error_label(&e, TRANSIENT_TRANSACTION_ERROR) {
+                    tracing::warn!(target: "mongodb", "Checkout transaction conflicted, retrying: {}", e);
+                    attempt += 1;
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+async fn reserve_and_insert(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    order: &mut Order,
+) -> Result<()> {
+    let now = bson::DateTime::now();
+    let (order_id, user_id) = (order.id, order.user);
+
+    for line in &mut order.items {
+        let item_coll: Collection<Document> = db.collection(&line.coll);
+
+        let Some(item) = item_coll.find_one(doc! { "_id": line.item }).session(&mut *session).await? else {
+            bail!(error::Checkout::ItemNotFound(format!("Item {} of `{}` no longer exists.", line.item, line.coll)));
+        };
+
+        let Some(picked) = utils::stock::pick_units(&item, line.quantity, now) else {
+            bail!(error::Checkout::OutOfStock {
+                name: line.name.clone(),
+                available: utils::stock::available_units(&utils::stock::lot_units(&item), now),
+            });
+        };
+
+        for (lot_id, codes) in picked {
+            // Only pull the codes if they're all still in the lot
+            let res = item_coll.update_one(
+                doc! {
+                    "_id": line.item,
+                    "lot": { "$elemMatch": { "_id": lot_id, "code": { "$all": &codes }}},
+                },
+                doc! { "$pull": { "lot.$[lot].code": { "$in": &codes }}},
+            )
+            .array_filters(vec![doc! { "lot._id": lot_id }])
+            .session(&mut *session)
+            .await?;
+
+            if res.modified_count != 1 {
+                bail!(error::Checkout::OutOfStock { name: line.name.clone(), available: 0 });
+            }
+
+            record_movements(db, session, LotMovement {
+                kind: MovementKind::Reserved,
+                coll: &line.coll,
+                item: line.item,
+                lot: lot_id,
+                codes: &codes,
+                by: Some(user_id),
+                reason: None,
+                reference: Some(order_id),
+            }).await?;
+
+            line.units.extend(codes.into_iter().map(|code| ReservedUnit { lot: lot_id, code }));
+        }
+    }
+
+    let sub_orders = split_by_store(order);
+    order.sub_orders = sub_orders.iter().map(|sub_order| sub_order.id).collect();
+
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+    orders_coll.insert_one(&*order).session(&mut *session).await?;
+    orders_coll.insert_many(&sub_orders).session(&mut *session).await?;
+
+    let users_coll: Collection<Document> = db.collection("user");
+    users_coll.update_one(
+        doc! { "_id": order.user },
+        doc! { "$set": { "client.cart": [] }},
+    )
+    .session(&mut *session)
+    .await?;
+
+    Ok(())
+}
+
+/// Splits an order into one sub-order per store of its items, so each store
+/// fulfils, refunds and reports its own part.
+fn split_by_store(order: &Order) -> Vec<Order> {
+    let mut sub_orders: Vec<Order> = Vec::new();
+
+    for line in &order.items {
+        match sub_orders.iter_mut().find(|sub_order| sub_order.items[0].store == line.store) {
+            Some(sub_order) => sub_order.items.push(line.clone()),
+            None => sub_orders.push(Order {
+                id: ObjectId::new(),
+                user: order.user,
+                status: order.status,
+                history: order.history.clone(),
+                items: vec![line.clone()],
+                total: 0.0,
+                created_at: order.created_at,
+                // Reservations lapse with the parent order
+                expires_at: None,
+                parent: Some(order.id),
+                store: stores::get_coll_store(&line.coll).map(|store| store.id),
+                sub_orders: Vec::new(),
+                refund_due: false,
+            }),
+        }
+    }
+
+    for sub_order in &mut sub_orders {
+        sub_order.total = (sub_order.items.iter().map(|item| item.total).sum::<f64>() * 100.0).round() / 100.0;
+    }
+
+    sub_orders
+}
+
+/// Returns the reserved units of an order's items to their lots.
+pub async fn release_units(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    order: &Order,
+    event: &OrderEvent,
+) -> Result<()> {
+    let items = &order.items;
+    for line in items {
+        let item_coll: Collection<Document> = db.collection(&line.coll);
+
+        let mut lots: HashMap<ObjectId, Vec<bson::Bson>> = HashMap::new();
+        for unit in &line.units {
+            lots.entry(unit.lot).or_default().push(unit.code.clone());
+        }
+
+        for (lot_id, codes) in lots {
+            let res = item_coll.update_one(
+                doc! { "_id": line.item, "lot._id": lot_id },
+                doc! { "$push": { "lot.$[lot].code": { "$each": &codes }}},
+            )
+            .array_filters(vec![doc! { "lot._id": lot_id }])
+            .session(&mut *session)
+            .await?;
+
+            // The lot was written off meanwhile, so the units can't go back to it
+            if res.modified_count != 1 {
+                tracing::warn!(target: "mongodb", "Lot {} of item {} no longer exists, {} units of order {} not restocked.", lot_id, line.item, codes.len(), order.id);
+                continue;
+            }
+
+            record_movements(db, session, LotMovement {
+                kind: MovementKind::Released,
+                coll: &line.coll,
+                item: line.item,
+                lot: lot_id,
+                codes: &codes,
+                by: event.by,
+                reason: Some(event.status.as_str()),
+                reference: Some(order.id),
+            }).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves an order to another status, recording who did it, and returns the
+/// updated order. Units of orders that are cancelled or expire before being
+/// completed go back to stock in the same transaction.
+///
+/// Orders split into sub-orders take them along when they're paid, cancelled,
+/// expire or are refunded, and otherwise follow their sub-orders, which are
+/// the ones stores fulfil.
+#[tracing::instrument(name = "Changing order status", skip(db))]
+pub async fn transition_order(
+    db: &mongodb::Database,
+    order_id: ObjectId,
+    next: OrderStatus,
+    by: Option<ObjectId>,
+    note: Option<String>,
+) -> Result<Order> {
+    let mut attempt = 1;
+
+    loop {
+        match try_transition_order(db, order_id, next, by, note.clone()).await {
+            Err(e) if attempt < MAX_TRANSACTION_ATTEMPTS && has_error_label(&e, TRANSIENT_TRANSACTION_ERROR) => {
+                tracing::warn!(target: "mongodb", "Order status change conflicted, retrying: {}", e);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn try_transition_order(
+    db: &mongodb::Database,
+    order_id: ObjectId,
+    next: OrderStatus,
+    by: Option<ObjectId>,
+    note: Option<String>,
+) -> Result<Order> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let Some(mut order) = orders_coll.find_one(doc! { "_id": order_id }).await? else {
+        bail!(error::Orders::NotFound);
+    };
+
+    if !order.status.can_become(next) {
+        bail!(error::Orders::IllegalTransition { from: order.status.to_string(), to: next.to_string() });
+    }
+
+    let sub_orders: Vec<Order> = if order.is_parent() {
+        orders_coll.find(doc! { "parent": order_id }).await?.try_collect().await?
+    } else {
+        Vec::new()
+    };
+    check_split_transition(&order, &sub_orders, next)?;
+
+    let event = OrderEvent { status: next, at: bson::DateTime::now(), by, note };
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        // Fails if another request changed the status since the order was read
+        set_order_status(db, &mut session, &order, &event).await?;
+
+        // A cancelled order owes its payments back until they're settled
+        let refund_due = match next {
+            OrderStatus::Cancelled => Some(doc! { "$set": { "refundDue": true }}),
+            OrderStatus::Refunded => Some(doc! { "$unset": { "refundDue": "" }}),
+            _ => None,
+        };
+        if let Some(update) = refund_due {
+            orders_coll.update_one(doc! { "_id": order_id }, update).session(&mut session).await?;
+        }
+
+        for sub_order in &sub_orders {
+            if cascades_to(sub_order.status, next) {
+                let sub_event = OrderEvent { note: Some("Changed with its parent order.".into()), ..event.clone() };
+                set_order_status(db, &mut session, sub_order, &sub_event).await?;
+            }
+        }
+
+        if let Some(parent_id) = order.parent {
+            roll_up_parent(db, &mut session, parent_id, event.at).await?;
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    tracing::info!(target: "mongodb", "Order {} went from `{}` to `{}`.", order_id, order.status, next);
+
+    order.status = next;
+    order.history.push(event);
+    order.expires_at = None;
+    order.refund_due = next == OrderStatus::Cancelled;
+
+    Ok(order)
+}
+
+/// Rejects the status changes that don't fit orders split into sub-orders.
+fn check_split_transition(order: &Order, sub_orders: &[Order], next: OrderStatus) -> Result<()> {
+    if order.is_parent() {
+        match next {
+            OrderStatus::Paid | OrderStatus::Expired => {}
+            OrderStatus::Cancelled => {
+                let fulfilled = sub_orders.iter().any(|sub_order|
+                    !sub_order.status.can_become(OrderStatus::Cancelled)
+                    && !matches!(sub_order.status, OrderStatus::Cancelled | OrderStatus::Refunded | OrderStatus::Expired)
+                );
+                if fulfilled {
+                    bail!(error::Orders::SubOrders(
+                        "Some sub-orders were already completed, so the others have to be cancelled one by one.".into()
+                    ));
+                }
+            }
+            OrderStatus::Refunded if order.status == OrderStatus::Cancelled => {}
+            _ => bail!(error::Orders::SubOrders(
+                "Orders are fulfilled and refunded through their sub-orders.".into()
+            )),
+        }
+    } else if order.parent.is_some() {
+        match next {
+            OrderStatus::Paid | OrderStatus::Expired => bail!(error::Orders::SubOrders(
+                "Sub-orders are paid through their parent order.".into()
+            )),
+            OrderStatus::Cancelled if order.status == OrderStatus::Pending => bail!(error::Orders::SubOrders(
+                "Unpaid orders are cancelled as a whole, through their parent order.".into()
+            )),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a sub-order follows its parent order to a status.
+fn cascades_to(current: OrderStatus, next: OrderStatus) -> bool {
+    match next {
+        // Only the parts cancelled along with the order are refunded along with it
+        OrderStatus::Refunded => current == OrderStatus::Cancelled,
+        _ => current.can_become(next),
+    }
+}
+
+/// Records a status change of an order, returning its units to stock if it's
+/// cancelled or expires. Orders split into sub-orders don't hold units themselves.
+async fn set_order_status(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    order: &Order,
+    event: &OrderEvent,
+) -> Result<()> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let mut update = doc! {
+        "$set": { "status": event.status.as_str() },
+        "$push": { "history": bson::to_bson(event)? },
+    };
+    if order.status == OrderStatus::Pending {
+        update.insert("$unset", doc! { "expiresAt": "" });
+    }
+
+    let res = orders_coll.update_one(
+        doc! { "_id": order.id, "status": order.status.as_str() },
+        update,
+    )
+    .session(&mut *session)
+    .await?;
+
+    if res.modified_count != 1 {
+        bail!(error::Orders::Changed);
+    }
+
+    let release = matches!(event.status, OrderStatus::Cancelled | OrderStatus::Expired) && order.status.holds_units();
+    if release && !order.is_parent() {
+        release_units(db, session, order, event).await?;
+    }
+
+    Ok(())
+}
+
+/// Updates the status of a parent order after one of its sub-orders changed.
+async fn roll_up_parent(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    parent_id: ObjectId,
+    at: bson::DateTime,
+) -> Result<()> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let statuses: Vec<OrderStatus> = orders_coll
+        .find(doc! { "parent": parent_id })
+        .session(&mut *session)
+        .await?
+        .stream(&mut *session)
+        .map_ok(|sub_order| sub_order.status)
+        .try_collect()
+        .await?;
+
+    let Some(status) = OrderStatus::roll_up(&statuses) else {
+        return Ok(());
+    };
+
+    let Some(parent) = orders_coll.find_one(doc! { "_id": parent_id }).session(&mut *session).await? else {
+        return Ok(());
+    };
+
+    // The parent is written even if its status stays the same, so sub-orders
+    // changing at the same time conflict instead of missing each other's change.
+    let mut update = doc! { "$set": { "updatedAt": at }};
+    if parent.status != status {
+        let event = OrderEvent { status, at, by: None, note: Some("Updated from its sub-orders.".into()) };
+        update = doc! {
+            "$set": { "status": status.as_str(), "updatedAt": at },
+            "$push": { "history": bson::to_bson(&event)? },
+        };
+    }
+    orders_coll.update_one(doc! { "_id": parent_id }, update).session(&mut *session).await?;
+
+    Ok(())
+}
+
+/// Expires the pending orders whose reservation lapsed, returning their units to stock.
+#[tracing::instrument(name = "Expiring order reservations", skip(db))]
+pub async fn expire_reservations(db: &mongodb::Database) -> Result<u64> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let expired: Vec<Order> = orders_coll
+        .find(doc! { "status": OrderStatus::Pending.as_str(), "expiresAt": { "$lte": bson::DateTime::now() }})
+        .projection(doc! { "_id": 1, "user": 1, "status": 1, "items": 1, "total": 1, "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut count = 0;
+
+    for order in expired {
+        match transition_order(db, order.id, OrderStatus::Expired, None, None).await {
+            Ok(_) => count += 1,
+            // Paid or cancelled since it was read
+            Err(e) if e.is::<error::Orders>() => {}
+            Err(e) => tracing::error!(target: "mongodb", "Failed to expire order {}: {}", order.id, e),
+        }
+    }
+
+    if count > 0 {
+        tracing::info!(target: "mongodb", "{} order reservations expired.", count);
+    }
+
+    Ok(count)
+}
+
+/// Records that the payments of a cancelled order were settled without a refund.
+pub async fn clear_refund_due(db: &mongodb::Database, order: &mut Order) -> Result<()> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    orders_coll.update_one(doc! { "_id": order.id }, doc! { "$unset": { "refundDue": "" }}).await?;
+    order.refund_due = false;
+
+    Ok(())
+}
+
+pub async fn get_order(db: &mongodb::Database, order_id: ObjectId) -> Result<Option<Order>> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+    Ok(orders_coll.find_one(doc! { "_id": order_id }).await?)
+}
+
+/// Returns a page of the orders of a user, newest first. Their sub-orders
+/// are left out, since they're listed in `subOrders`.
+pub async fn get_user_orders(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+    page: u64,
+    page_size: i64,
+) -> Result<Vec<Order>> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let orders = orders_coll
+        .find(doc! { "user": user_id, "parent": { "$exists": false }})
+        .sort(doc! { "createdAt": -1 })
+        .skip(page * page_size as u64)
+        .limit(page_size)
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(orders)
+}
+
+/// Returns the sub-orders of a store in some statuses, oldest first, so store
+/// employees handle them in the order they were placed. Orders placed before
+/// they were split by store are matched by their items.
+pub async fn get_store_order_queue(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    statuses: &[OrderStatus],
+) -> Result<Vec<Order>> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+
+    let statuses: Vec<&str> = statuses.iter().map(|status| status.as_str()).collect();
+
+    let orders = orders_coll
+        .find(doc! {
+            "status": { "$in": statuses },
+            "$or": [
+                { "store": store.id },
+                { "store": { "$exists": false }, "subOrders": { "$exists": false }, "items.coll": { "$in": &store.item_colls } },
+            ],
+        })
+        .sort(doc! { "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(orders)
+}
+
+pub fn spawn_reservation_expiry(db: mongodb::Database, settings: &crate::settings::OrderSettings) {
+    let check_interval = Duration::from_secs(settings.expiry_check_interval_seconds);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire_reservations(&db).await {
+                tracing::error!(target: "mongodb", "Failed to expire order reservations: {}", e);
+            }
+        }
+    });
+}
+
+/// Commits a transaction, retrying a few times while its result is unknown.
+pub async fn commit(session: &mut ClientSession) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < MAX_TRANSACTION_ATTEMPTS && e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+pub fn has_error_label(e: &anyhow::Error, label: &str) -> bool {
+    e.downcast_ref::<mongodb::error::Error>()
+        .is_some_and(|e| e.contains_label(label))
+}
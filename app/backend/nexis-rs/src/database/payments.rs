@@ -0,0 +1,494 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::transition_order;
+use crate::payments::{ PaymentProvider, ProviderResult, WebhookEvent, WebhookEventKind };
+use crate::types::{
+    error,
+    mongodb::{
+        orders::{ Order, OrderStatus },
+        payments::{ PaymentEvent, PaymentMethod, PaymentRecord, PaymentStatus },
+        stores::DaySale,
+    },
+};
+use mongodb::{ IndexModel, options::IndexOptions };
+
+const PAYMENTS_COLL: &str = "payments";
+
+pub async fn ensure_payment_indexes(db: &mongodb::Database) -> Result<()> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    payments_coll.create_index(IndexModel::builder().keys(doc! { "order": 1 }).build()).await?;
+    payments_coll.create_index(IndexModel::builder().keys(doc! { "sale": 1 }).build()).await?;
+    // Only one payment per order can be going through or have gone through
+    payments_coll.create_index(
+        IndexModel::builder()
+            .keys(doc! { "order": 1 })
+            .options(IndexOptions::builder()
+                .name("order_1_active".to_string())
+                .unique(true)
+                .partial_filter_expression(doc! {
+                    "order": { "$exists": true },
+                    "status": { "$in": active_statuses().as_slice() },
+                })
+                .build())
+            .build()
+    ).await?;
+    payments_coll.create_index(IndexModel::builder().keys(doc! { "provider": 1, "providerRef": 1 }).build()).await?;
+
+    Ok(())
+}
+
+pub async fn get_order_payments(db: &mongodb::Database, order_id: ObjectId) -> Result<Vec<PaymentRecord>> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let payments = payments_coll
+        .find(doc! { "order": order_id })
+        .sort(doc! { "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(payments)
+}
+
+/// Payment of a sale charged at the register through the payment provider,
+/// unless it was paid in cash.
+pub async fn get_sale_payment(db: &mongodb::Database, sale_id: ObjectId) -> Result<Option<PaymentRecord>> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let payment = payments_coll
+        .find_one(doc! {
+            "sale": sale_id,
+            "status": { "$nin": [
+                PaymentStatus::Voided.as_str(), PaymentStatus::Declined.as_str(), PaymentStatus::Failed.as_str(),
+            ]},
+        })
+        .await?;
+
+    Ok(payment)
+}
+
+/// Statuses of payments which are going through or went through, of which an
+/// order can have one.
+fn active_statuses() -> [&'static str; 3] {
+    [PaymentStatus::Pending.as_str(), PaymentStatus::Authorized.as_str(), PaymentStatus::Captured.as_str()]
+}
+
+/// Records a status change of a payment. Changes from a provider's callback are
+/// only recorded if the payment is still as it was read and the callback wasn't
+/// applied meanwhile. Returns whether the change was recorded.
+async fn set_payment_status(
+    db: &mongodb::Database,
+    payment: &mut PaymentRecord,
+    status: PaymentStatus,
+    amount: Option<f64>,
+    event_id: Option<String>,
+    reference: Option<ObjectId>,
+) -> Result<bool> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let mut filter = doc! { "_id": payment.id };
+    if let Some(event_id) = &event_id {
+        // Every change pushes an event, so their count tells if another one got in first
+        filter.insert("status", payment.status.as_str());
+        filter.insert("events", doc! { "$size": payment.events.len() as i64 });
+        filter.insert("events.eventId", doc! { "$ne": event_id });
+    }
+
+    let event = PaymentEvent { status, at: bson::DateTime::now(), amount, event_id, reference };
+
+    let mut set = doc! { "status": status.as_str(), "refunded": payment.refunded };
+    if let Some(provider_ref) = &payment.provider_ref {
+        set.insert("providerRef", provider_ref);
+    }
+
+    let result = payments_coll.update_one(
+        filter,
+        doc! { "$set": set, "$push": { "events": bson::to_bson(&event)? }},
+    ).await?;
+    if result.matched_count == 0 {
+        return Ok(false);
+    }
+
+    payment.status = status;
+    payment.events.push(event);
+
+    Ok(true)
+}
+
+fn check_provider_result(result: &ProviderResult) -> Result<()> {
+    if result.status == PaymentStatus::Declined {
+        bail!(error::Payments::Declined(result.message.clone().unwrap_or_else(|| "No reason given.".into())));
+    }
+    Ok(())
+}
+
+/// Pays a pending order through the payment provider. Card payments are authorized
+/// and captured right away, which marks the order as paid. Payments the provider
+/// confirms later, such as transfers, are left `pending` until its callback arrives.
+#[tracing::instrument(name = "Paying order", skip(db, provider, order), fields(order_id = %order.id))]
+pub async fn pay_order(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    order: &Order,
+    method: PaymentMethod,
+) -> Result<PaymentRecord> {
+    if order.status != OrderStatus::Pending {
+        bail!(error::Payments::OrderNotPayable(format!("It's `{}`.", order.status)));
+    }
+    if let Some(parent) = order.parent {
+        bail!(error::Payments::OrderNotPayable(format!("It's part of order {}, which is paid as a whole.", parent)));
+    }
+    if matches!(method, PaymentMethod::Cash(_)) {
+        bail!(error::Payments::UnsupportedMethod("cash".into()));
+    }
+
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let mut payment = PaymentRecord {
+        id: ObjectId::new(),
+        order: Some(order.id),
+        sale: None,
+        user: Some(order.user),
+        provider: provider.name().to_string(),
+        provider_ref: None,
+        amount: order.total,
+        method,
+        status: PaymentStatus::Pending,
+        refunded: 0.0,
+        events: Vec::new(),
+        created_at: bson::DateTime::now(),
+    };
+    // The unique index on active payments stops two from going through at once
+    if let Err(e) = payments_coll.insert_one(&payment).await {
+        if crate::database::is_duplicate_key(&e) {
+            bail!(error::Payments::AlreadyPaid);
+        }
+        return Err(e.into());
+    }
+    let amount = payment.amount;
+
+    let authorization = match provider.authorize(&payment.id.to_hex(), amount, &payment.method).await {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            set_payment_status(db, &mut payment, PaymentStatus::Failed, None, None, None).await?;
+            return Err(e);
+        }
+    };
+
+    payment.provider_ref = authorization.provider_ref.clone();
+    set_payment_status(db, &mut payment, authorization.status, Some(amount), None, None).await?;
+    check_provider_result(&authorization)?;
+
+    if authorization.status == PaymentStatus::Authorized {
+        let provider_ref = payment.provider_ref.clone().unwrap_or_default();
+        let capture = match provider.capture(&provider_ref, amount).await {
+            Ok(capture) => capture,
+            Err(e) => {
+                // Left authorized, the payment would block every retry for the order
+                if let Err(void_error) = settle_payment(db, provider, &mut payment).await {
+                    tracing::warn!(target: "backend", "Couldn't void payment {} after its capture failed: {}", payment.id, void_error);
+                    set_payment_status(db, &mut payment, PaymentStatus::Failed, None, None, None).await?;
+                }
+                return Err(e);
+            }
+        };
+        set_payment_status(db, &mut payment, capture.status, Some(amount), None, None).await?;
+
+        if capture.status == PaymentStatus::Captured {
+            mark_order_paid(db, provider, &mut payment).await?;
+        }
+    }
+
+    Ok(payment)
+}
+
+/// Charges a sale at the register through the payment provider, unless it's
+/// paid in cash. Cards are authorized and captured right away, while transfers
+/// are left `pending` until the provider's callback arrives. Runs before the
+/// sale's transaction commits, which settles the payment if it doesn't.
+#[tracing::instrument(name = "Charging register sale", skip(db, provider, sale), fields(sale_id = %sale.id))]
+pub async fn charge_sale(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    sale: &DaySale,
+) -> Result<Option<PaymentRecord>> {
+    if matches!(sale.payment.method, PaymentMethod::Cash(_)) {
+        return Ok(None);
+    }
+
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let mut payment = PaymentRecord {
+        id: ObjectId::new(),
+        order: None,
+        sale: Some(sale.id),
+        user: sale.client.user,
+        provider: provider.name().to_string(),
+        provider_ref: None,
+        amount: sale.payment.amount,
+        method: sale.payment.method.clone(),
+        status: PaymentStatus::Pending,
+        refunded: 0.0,
+        events: Vec::new(),
+        created_at: bson::DateTime::now(),
+    };
+    payments_coll.insert_one(&payment).await?;
+    let amount = payment.amount;
+
+    let authorization = match provider.authorize(&payment.id.to_hex(), amount, &payment.method).await {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            set_payment_status(db, &mut payment, PaymentStatus::Failed, None, None, None).await?;
+            return Err(e);
+        }
+    };
+
+    payment.provider_ref = authorization.provider_ref.clone();
+    set_payment_status(db, &mut payment, authorization.status, Some(amount), None, None).await?;
+    check_provider_result(&authorization)?;
+
+    if authorization.status == PaymentStatus::Authorized {
+        let provider_ref = payment.provider_ref.clone().unwrap_or_default();
+        let capture = match provider.capture(&provider_ref, amount).await {
+            Ok(capture) => capture,
+            Err(e) => {
+                settle_payment(db, provider, &mut payment).await?;
+                return Err(e);
+            }
+        };
+        set_payment_status(db, &mut payment, capture.status, Some(amount), None, None).await?;
+        check_provider_result(&capture)?;
+    }
+
+    Ok(Some(payment))
+}
+
+/// Marks the order of a captured payment as paid. If the order can't be paid
+/// anymore, because its reservation expired meanwhile, the payment is refunded.
+async fn mark_order_paid(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    payment: &mut PaymentRecord,
+) -> Result<()> {
+    // Sales at a register were recorded when they were charged
+    let Some(order_id) = payment.order else { return Ok(()) };
+    let note = Some(format!("Payment {} captured.", payment.id));
+
+    match transition_order(db, order_id, OrderStatus::Paid, None, note).await {
+        Ok(_) => {
+            crate::receipts::spawn_order_receipt_email(db.clone(), order_id);
+            Ok(())
+        }
+        Err(e) if e.is::<error::Orders>() => {
+            tracing::warn!(target: "backend", "Order {} can't be paid anymore ({}), refunding payment {}.", order_id, e, payment.id);
+            let amount = payment.amount;
+            refund_payment(db, provider, payment, amount, None).await?;
+            bail!(error::Payments::OrderNotPayable(e.to_string()))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Refunds part or all of a captured payment, for the sub-order or return
+/// given as reference.
+pub async fn refund_payment(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    payment: &mut PaymentRecord,
+    amount: f64,
+    reference: Option<ObjectId>,
+) -> Result<()> {
+    let provider_ref = payment.provider_ref.clone().ok_or(error::Payments::UnknownPayment)?;
+
+    let refund = provider.refund(&provider_ref, amount).await?;
+    check_provider_result(&refund)?;
+
+    record_refund(db, payment, amount, None, reference).await?;
+
+    Ok(())
+}
+
+async fn record_refund(
+    db: &mongodb::Database,
+    payment: &mut PaymentRecord,
+    amount: f64,
+    event_id: Option<String>,
+    reference: Option<ObjectId>,
+) -> Result<bool> {
+    let refunded = payment.refunded;
+    payment.refunded = ((payment.refunded + amount) * 100.0).round() / 100.0;
+
+    // Partial refunds keep the payment captured
+    let status = if payment.refunded >= payment.amount {
+        PaymentStatus::Refunded
+    } else {
+        PaymentStatus::Captured
+    };
+
+    let recorded = set_payment_status(db, payment, status, Some(amount), event_id, reference).await?;
+    if !recorded {
+        payment.refunded = refunded;
+    }
+
+    Ok(recorded)
+}
+
+/// Settles the payments of a cancelled order: authorizations and pending payments
+/// are voided, and captured ones are refunded. A cancelled sub-order only gets its
+/// own part refunded from the payment of its parent. Settling again after a
+/// provider error only finishes what's left. Returns the amount refunded in all.
+#[tracing::instrument(name = "Settling payments of cancelled order", skip(db, provider, order), fields(order_id = %order.id))]
+pub async fn settle_cancelled_order_payments(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    order: &Order,
+) -> Result<f64> {
+    if order.parent.is_some() {
+        return refund_sub_order(db, provider, order).await;
+    }
+
+    let mut refunded = 0.0;
+    for mut payment in get_order_payments(db, order.id).await? {
+        settle_payment(db, provider, &mut payment).await?;
+        refunded += payment.refunded;
+    }
+
+    Ok((refunded * 100.0).round() / 100.0)
+}
+
+/// Voids a payment going through, or refunds what's left of a captured one.
+/// Returns the amount refunded.
+pub async fn settle_payment(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    payment: &mut PaymentRecord,
+) -> Result<f64> {
+    let Some(provider_ref) = payment.provider_ref.clone() else { return Ok(0.0) };
+
+    match payment.status {
+        PaymentStatus::Pending | PaymentStatus::Authorized => {
+            let void = provider.void(&provider_ref).await?;
+            set_payment_status(db, payment, void.status, None, None, None).await?;
+            Ok(0.0)
+        }
+        PaymentStatus::Captured => {
+            let amount = ((payment.amount - payment.refunded) * 100.0).round() / 100.0;
+            refund_payment(db, provider, payment, amount, None).await?;
+            Ok(amount)
+        }
+        _ => Ok(0.0),
+    }
+}
+
+async fn refund_sub_order(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    sub_order: &Order,
+) -> Result<f64> {
+    let payments = get_order_payments(db, sub_order.payable_order()).await?;
+
+    // Retried settlements mustn't refund a sub-order twice
+    let refunded = payments.iter()
+        .flat_map(|payment| payment.events.iter())
+        .any(|event| event.reference == Some(sub_order.id));
+    if refunded {
+        return Ok(sub_order.total);
+    }
+
+    let payment = payments
+        .into_iter()
+        .find(|payment| payment.status == PaymentStatus::Captured && payment.amount - payment.refunded >= sub_order.total - 0.005);
+
+    let Some(mut payment) = payment else {
+        tracing::warn!(target: "backend", "Sub-order {} has no captured payment left to refund.", sub_order.id);
+        return Ok(0.0);
+    };
+
+    refund_payment(db, provider, &mut payment, sub_order.total, Some(sub_order.id)).await?;
+
+    Ok(sub_order.total)
+}
+
+/// Applies a verified callback of the payment provider. Callbacks are applied
+/// once, so providers retrying them is harmless.
+#[tracing::instrument(name = "Applying payment callback", skip(db, provider))]
+pub async fn apply_webhook_event(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    event: WebhookEvent,
+) -> Result<()> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let Some(mut payment) = payments_coll
+        .find_one(doc! { "provider": provider.name(), "providerRef": &event.provider_ref })
+        .await? else {
+        bail!(error::Payments::UnknownPayment);
+    };
+
+    if payment.events.iter().any(|applied| applied.event_id.as_deref() == Some(event.id.as_str())) {
+        tracing::info!(target: "backend", "Payment callback {} was already applied.", event.id);
+        return Ok(());
+    }
+
+    match event.kind {
+        WebhookEventKind::Captured => {
+            if !matches!(payment.status, PaymentStatus::Pending | PaymentStatus::Authorized) {
+                tracing::warn!(target: "backend", "Ignoring capture of payment {}, which is `{}`.", payment.id, payment.status.as_str());
+                return Ok(());
+            }
+            if !set_payment_status(db, &mut payment, PaymentStatus::Captured, Some(event.amount), Some(event.id.clone()), None).await? {
+                return callback_not_applied(db, &payment, &event.id).await;
+            }
+            if let Err(e) = mark_order_paid(db, provider, &mut payment).await {
+                if !e.is::<error::Payments>() {
+                    return Err(e);
+                }
+            }
+        }
+        WebhookEventKind::Failed => {
+            if matches!(payment.status, PaymentStatus::Pending | PaymentStatus::Authorized) {
+                if !set_payment_status(db, &mut payment, PaymentStatus::Failed, None, Some(event.id.clone()), None).await? {
+                    return callback_not_applied(db, &payment, &event.id).await;
+                }
+                if let Some(sale_id) = payment.sale {
+                    tracing::warn!(target: "backend", "Payment {} of sale {} failed after the sale was recorded.", payment.id, sale_id);
+                }
+            }
+        }
+        WebhookEventKind::Refunded => {
+            // Only money which was taken can be refunded
+            if payment.status != PaymentStatus::Captured {
+                tracing::warn!(target: "backend", "Ignoring refund of payment {}, which is `{}`.", payment.id, payment.status.as_str());
+                return Ok(());
+            }
+            if !record_refund(db, &mut payment, event.amount, Some(event.id.clone()), None).await? {
+                return callback_not_applied(db, &payment, &event.id).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a callback whose change wasn't recorded because the payment changed
+/// since it was read. If the same callback got in first, it's already applied.
+/// Otherwise the provider is asked to retry it against the payment as it is now.
+async fn callback_not_applied(db: &mongodb::Database, payment: &PaymentRecord, event_id: &str) -> Result<()> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+
+    let applied = payments_coll
+        .count_documents(doc! { "_id": payment.id, "events.eventId": event_id })
+        .await?;
+    if applied > 0 {
+        tracing::info!(target: "backend", "Payment callback {} was already applied.", event_id);
+        return Ok(());
+    }
+
+    bail!("Payment {} changed while applying callback {}.", payment.id, event_id)
+}
+
+pub async fn get_payment(db: &mongodb::Database, payment_id: ObjectId) -> Result<Option<PaymentRecord>> {
+    let payments_coll: Collection<PaymentRecord> = db.collection(PAYMENTS_COLL);
+    Ok(payments_coll.find_one(doc! { "_id": payment_id }).await?)
+}
@@ -0,0 +1,435 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    ItemInfo,
+    charge_sale,
+    settle_payment,
+    movements::{ LotMovement, record_movements },
+    orders::{ commit, has_error_label },
+};
+use crate::payments::PaymentProvider;
+use crate::types::{
+    error,
+    mongodb::{
+        inventory::MovementKind,
+        orders::{ Order, OrderStatus },
+        payments::PaymentMethod,
+        stores::{ DaySale, SalePayment, SoldUnit, StoreInfo },
+    },
+    requests::pos::{ NewSale, ScannedUnit },
+    responses::{ UnitItem, UnitLookup, UnitLot, UnitStatus },
+};
+use futures_util::future::try_join_all;
+use mongodb::{ ClientSession, IndexModel, error::TRANSIENT_TRANSACTION_ERROR };
+
+/// Times a sale transaction is retried when it conflicts with another one.
+const MAX_SALE_ATTEMPTS: u32 = 3;
+
+/// Charges a sale at the register of a store. Every scanned unit is priced and
+/// pulled from its lot in the same transaction that appends the sale to the
+/// store's `daySales`, so a unit can't be sold twice. Sales paid by card or
+/// transfer are charged through the payment provider before the transaction
+/// commits, and the payment is voided or refunded if it doesn't.
+#[tracing::instrument(name = "Recording POS sale", skip(db, provider, sale), fields(store = %sale.store, terminal = %sale.terminal))]
+pub async fn record_sale(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    cashier: ObjectId,
+    store: &StoreInfo,
+    sale: NewSale,
+) -> Result<DaySale> {
+    if sale.units.is_empty() {
+        bail!(error::Pos::EmptySale);
+    }
+    if sale.terminal.trim().is_empty() {
+        bail!(error::Pos::Invalid("The terminal id is required.".into()));
+    }
+    if matches!(sale.payment.method, PaymentMethod::Cash(false)) {
+        bail!(error::Pos::Invalid("Cash payments must be `{ cash: true }`.".into()));
+    }
+
+    let mut scanned = HashSet::new();
+    for unit in &sale.units {
+        if !scanned.insert(unit.code) {
+            bail!(error::Pos::DuplicateCode(unit.code.to_hex()));
+        }
+    }
+
+    if let Some(user) = sale.client.user {
+        if !utils::user_has_role(db, user, utils::Role::Client).await? {
+            bail!(error::Pos::Invalid(format!("User {} isn't a client.", user)));
+        }
+    }
+
+    let mut session = db.client().start_session().await?;
+    let mut attempt = 1;
+
+    loop {
+        session.start_transaction().await?;
+
+        let mut payment = None;
+        let result = async {
+            let day_sale = sell_units(db, &mut session, cashier, store, &sale).await?;
+            payment = charge_sale(db, provider, &day_sale).await?;
+            commit(&mut session).await?;
+            Ok(day_sale)
+        }.await;
+
+        match result {
+            Ok(day_sale) => {
+                tracing::info!(target: "mongodb", "Sale {} recorded at `{}` by {}.", day_sale.id, store.name, cashier);
+                return Ok(day_sale);
+            }
+            Err(e) => {
+                let _ = session.abort_transaction().await;
+
+                if let Some(mut payment) = payment {
+                    if let Err(e) = settle_payment(db, provider, &mut payment).await {
+                        tracing::error!(target: "backend", "Failed to settle payment {} of a sale which wasn't recorded: {}", payment.id, e);
+                    }
+                }
+
+                if attempt < MAX_SALE_ATTEMPTS && has_error_label(&e, TRANSIENT_TRANSACTION_ERROR) {
+                    tracing::warn!(target: "mongodb", "Sale transaction conflicted, retrying: {}", e);
+                    attempt += 1;
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+async fn sell_units(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    cashier: ObjectId,
+    store: &StoreInfo,
+    sale: &NewSale,
+) -> Result<DaySale> {
+    let now = bson::DateTime::now();
+    let sale_id = ObjectId::new();
+    let mut sold = Vec::with_capacity(sale.units.len());
+
+    for unit in &sale.units {
+        sold.push(sell_unit(db, session, store, unit, (sale_id, cashier), now).await?);
+    }
+
+    let amount = (sold.iter().map(|unit| unit.price).sum::<f64>() * 100.0).round() / 100.0;
+    if let Some(tendered) = sale.payment.tendered {
+        if tendered < amount {
+            bail!(error::Pos::Invalid(format!("{:.2} were tendered, but the sale is {:.2}.", tendered, amount)));
+        }
+    }
+
+    let day_sale = DaySale {
+        id: sale_id,
+        payment: SalePayment {
+            amount,
+            method: sale.payment.method.clone(),
+        },
+        client: sale.client.clone(),
+        item: sold,
+        cashier,
+        terminal: sale.terminal.clone(),
+        timestamp: now,
+    };
+
+    let stores_coll: Collection<Document> = db.collection("store");
+    stores_coll.update_one(
+        doc! { "_id": store.id },
+        doc! { "$push": { "daySales": bson::to_bson(&day_sale)? }},
+    )
+    .session(&mut *session)
+    .await?;
+
+    Ok(day_sale)
+}
+
+/// Finds the item holding a scanned unit in a lot held by the store, either of
+/// its own item collections or transferred to it from another store, prices it
+/// and pulls its code from the lot, recording it in the inventory ledger as
+/// sold by the cashier in the sale.
+async fn sell_unit(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    store: &StoreInfo,
+    unit: &ScannedUnit,
+    (sale_id, cashier): (ObjectId, ObjectId),
+    now: bson::DateTime,
+) -> Result<SoldUnit> {
+    let other_colls: Vec<String> = stores::item_colls().into_iter().filter(|coll| !store.item_colls.contains(coll)).collect();
+
+    for coll in store.item_colls.iter().chain(&other_colls) {
+        let item_coll: Collection<Document> = db.collection(coll);
+
+        let Some(item) = item_coll
+            .find_one(held_unit_filter(store, coll, unit.code))
+            .session(&mut *session)
+            .await? else {
+            continue;
+        };
+
+        let item_id = item.get_object_id("_id")?;
+        let info = ItemInfo::from_doc(&item, now)?;
+        if item.get_bool("archived").unwrap_or(false) {
+            bail!(error::Pos::Invalid(format!("`{}` is no longer sold.", info.name)));
+        }
+
+        let Some(lot) = item.get_array("lot")?.iter()
+            .filter_map(|lot| lot.as_document())
+            .find(|lot| lot.get_array("code").is_ok_and(|codes| codes.contains(&bson::Bson::ObjectId(unit.code)))) else {
+            bail!(error::Pos::UnitUnavailable(unit.code.to_hex()));
+        };
+        let lot_id = lot.get_object_id("_id")?;
+
+        if !utils::stock::lot_is_sellable(lot, now) {
+            bail!(error::Pos::Expired(unit.code.to_hex(), info.name));
+        }
+
+        let Some(price) = info.line_total(1, unit.weight_kg) else {
+            bail!(error::Pos::MissingWeight(info.name));
+        };
+        if unit.weight_kg.is_some_and(|weight_kg| !(weight_kg.is_finite() && weight_kg > 0.0)) {
+            bail!(error::Pos::Invalid("The weight must be greater than 0.".into()));
+        }
+
+        let res = item_coll.update_one(
+            doc! { "_id": item_id, "lot.code": unit.code },
+            doc! { "$pull": { "lot.$[lot].code": unit.code }},
+        )
+        .array_filters(vec![doc! { "lot._id": lot_id }])
+        .session(&mut *session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Pos::UnitUnavailable(unit.code.to_hex()));
+        }
+
+        record_movements(db, session, LotMovement {
+            kind: MovementKind::Sold,
+            coll,
+            item: item_id,
+            lot: lot_id,
+            codes: &[bson::Bson::ObjectId(unit.code)],
+            by: Some(cashier),
+            reason: None,
+            reference: Some(sale_id),
+        }).await?;
+
+        return Ok(SoldUnit {
+            coll: coll.clone(),
+            item: item_id,
+            lot: lot_id,
+            code: unit.code,
+            name: info.name,
+            price,
+            weight_kg: if info.per_kg { unit.weight_kg } else { None },
+        });
+    }
+
+    bail!(error::Pos::UnitUnavailable(unit.code.to_hex()))
+}
+
+/// Matches the item with a unit in a lot held by a store: a lot of one of its
+/// own item collections which wasn't transferred elsewhere, or a lot of
+/// another store's collection transferred to it.
+fn held_unit_filter(store: &StoreInfo, coll: &str, code: ObjectId) -> Document {
+    let holder = if store.item_colls.iter().any(|own| own == coll) {
+        bson::Bson::Document(doc! { "$exists": false })
+    } else {
+        bson::Bson::ObjectId(store.id)
+    };
+
+    doc! { "lot": { "$elemMatch": { "code": code, "store": holder }}}
+}
+
+/// Indexes the unit codes of every item collection in the store registry,
+/// and the ones recorded by sales and orders, so scanned codes are found
+/// without scanning whole collections. Sales are also indexed by id.
+pub async fn ensure_unit_code_indexes(db: &mongodb::Database) -> Result<()> {
+    for coll in stores::item_colls() {
+        let item_coll: Collection<Document> = db.collection(&coll);
+        item_coll.create_index(IndexModel::builder().keys(doc! { "lot.code": 1 }).build()).await?;
+    }
+
+    let stores_coll: Collection<Document> = db.collection("store");
+    stores_coll.create_index(IndexModel::builder().keys(doc! { "daySales.item.code": 1 }).build()).await?;
+    stores_coll.create_index(IndexModel::builder().keys(doc! { "daySales._id": 1 }).build()).await?;
+
+    let orders_coll: Collection<Document> = db.collection(crate::database::orders::ORDERS_COLL);
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "items.units.code": 1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Finds the item and lot of a unit code. Units still in their lot are looked
+/// up in every item collection at once. Otherwise, the sale or order that took
+/// the unit tells where it came from. Returns `None` if the code is unknown.
+#[tracing::instrument(name = "Looking up unit code", skip(db))]
+pub async fn lookup_unit(db: &mongodb::Database, code: ObjectId) -> Result<Option<UnitLookup>> {
+    let now = bson::DateTime::now();
+
+    let found = try_join_all(stores::item_colls().into_iter().map(|coll| async move {
+        let item_coll: Collection<Document> = db.collection(&coll);
+        let item = item_coll
+            .find_one(doc! { "lot.code": code })
+            .projection(doc! { "name": 1, "price": 1, "pricePerKg": 1, "lot.$": 1 })
+            .await?;
+        Ok::<_, anyhow::Error>(item.map(|item| (coll, item)))
+    })).await?;
+
+    if let Some((coll, item)) = found.into_iter().flatten().next() {
+        let lot = item.get_array("lot")?.iter()
+            .filter_map(|lot| lot.as_document())
+            .next()
+            .cloned()
+            .unwrap_or_default();
+
+        let status = if utils::stock::lot_is_sellable(&lot, now) { UnitStatus::InStock } else { UnitStatus::Expired };
+        return Ok(Some(build_lookup(code, status, coll, &item, &lot, None, None)?));
+    }
+
+    let (sale, order) = futures_util::try_join!(find_sold_unit(db, code), find_ordered_unit(db, code))?;
+
+    let (status, coll, item_id, lot_id, sale_id, order_id) = match (sale, order) {
+        (Some((coll, item_id, lot_id, sale_id)), _) =>
+            (UnitStatus::Sold, coll, item_id, lot_id, sale_id, None),
+        (None, Some((order, coll, item_id, lot_id))) => {
+            let status = if order.status.holds_units() { UnitStatus::Reserved } else { UnitStatus::Sold };
+            (status, coll, item_id, lot_id, None, Some(order.id))
+        }
+        (None, None) => return Ok(None),
+    };
+
+    let item_coll: Collection<Document> = db.collection(&coll);
+    let Some(item) = item_coll
+        .find_one(doc! { "_id": item_id })
+        .projection(doc! { "name": 1, "price": 1, "pricePerKg": 1, "lot": { "$elemMatch": { "_id": lot_id }}})
+        .await? else {
+        tracing::warn!(target: "mongodb", "Unit {} was taken from item {} of `{}`, which no longer exists.", code, item_id, coll);
+        return Ok(None);
+    };
+
+    let lot = item.get_array("lot").ok()
+        .and_then(|lots| lots.iter().filter_map(|lot| lot.as_document()).next().cloned())
+        .unwrap_or_else(|| doc! { "_id": lot_id });
+
+    Ok(Some(build_lookup(code, status, coll, &item, &lot, sale_id, order_id)?))
+}
+
+/// Finds the sale of a store's `daySales` that took a unit.
+/// Returns its collection, item, lot and the sale id, if it has one.
+async fn find_sold_unit(
+    db: &mongodb::Database,
+    code: ObjectId,
+) -> Result<Option<(String, ObjectId, ObjectId, Option<ObjectId>)>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let Some(store) = stores_coll
+        .find_one(doc! { "daySales.item.code": code })
+        .projection(doc! { "daySales.$": 1 })
+        .await? else {
+        return Ok(None);
+    };
+
+    let Some(sale) = store.get_array("daySales")?.iter().filter_map(|sale| sale.as_document()).next() else {
+        return Ok(None);
+    };
+
+    let unit = sale.get_array("item")?.iter()
+        .filter_map(|unit| unit.as_document())
+        .find(|unit| unit.get_object_id("code").is_ok_and(|unit_code| unit_code == code));
+
+    match unit {
+        Some(unit) => Ok(Some((
+            unit.get_str("coll")?.to_string(),
+            unit.get_object_id("_id")?,
+            unit.get_object_id("lot")?,
+            sale.get_object_id("_id").ok(),
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Finds the latest order that took a unit.
+/// Returns it with the unit's collection, item and lot.
+async fn find_ordered_unit(
+    db: &mongodb::Database,
+    code: ObjectId,
+) -> Result<Option<(Order, String, ObjectId, ObjectId)>> {
+    let orders_coll: Collection<Order> = db.collection(crate::database::orders::ORDERS_COLL);
+
+    // Units of cancelled and expired orders went back to their lots, and the
+    // ones of orders split by store are held by their sub-orders
+    let Some(order) = orders_coll
+        .find_one(doc! {
+            "items.units.code": code,
+            "subOrders": { "$exists": false },
+            "status": { "$nin": [OrderStatus::Cancelled.as_str(), OrderStatus::Expired.as_str()] },
+        })
+        .sort(doc! { "createdAt": -1 })
+        .await? else {
+        return Ok(None);
+    };
+
+    let unit = order.items.iter().find_map(|line| line.units.iter()
+        .find(|unit| unit.code == bson::Bson::ObjectId(code))
+        .map(|unit| (line.coll.clone(), line.item, unit.lot)));
+
+    Ok(unit.map(|(coll, item, lot)| (order, coll, item, lot)))
+}
+
+fn build_lookup(
+    code: ObjectId,
+    status: UnitStatus,
+    coll: String,
+    item: &Document,
+    lot: &Document,
+    sale: Option<ObjectId>,
+    order: Option<ObjectId>,
+) -> Result<UnitLookup> {
+    let info = ItemInfo::from_doc(item, bson::DateTime::now())?;
+
+    Ok(UnitLookup {
+        code,
+        status,
+        sellable: status == UnitStatus::InStock,
+        store: lot.get_object_id("store").ok()
+            .and_then(|store_id| stores::get_store(&store_id))
+            .or_else(|| stores::get_coll_store(&coll))
+            .map(|store| store.name)
+            .unwrap_or_default(),
+        coll,
+        item: UnitItem {
+            id: item.get_object_id("_id")?,
+            name: info.name,
+            price: info.price,
+            per_kg: info.per_kg,
+        },
+        lot: UnitLot {
+            id: lot.get_object_id("_id")?,
+            enter_date: utils::stock::lot_enter_date(lot),
+            expiry: utils::stock::get_date(lot, "expiry"),
+        },
+        sale,
+        order,
+    })
+}
+
+/// Finds a sale of the `daySales` of any store. Returns it with the id of its store.
+pub async fn get_sale(db: &mongodb::Database, sale_id: ObjectId) -> Result<Option<(ObjectId, DaySale)>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let Some(store) = stores_coll
+        .find_one(doc! { "daySales._id": sale_id })
+        .projection(doc! { "_id": 1, "daySales.$": 1 })
+        .await? else {
+        return Ok(None);
+    };
+
+    match store.get_array("daySales")?.first() {
+        Some(sale) => Ok(Some((store.get_object_id("_id")?, bson::from_bson(sale.clone())?))),
+        None => Ok(None),
+    }
+}
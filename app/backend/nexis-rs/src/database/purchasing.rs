@@ -0,0 +1,512 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    inventory::{ build_lot, push_lot, refresh_projection },
+    orders::commit,
+};
+use crate::types::{
+    error,
+    mongodb::{
+        purchasing::{ PurchaseOrder, PurchaseOrderLine, PurchaseOrderReceipt, PurchaseOrderStatus, Supplier },
+        stores::StoreInfo,
+    },
+    requests::{
+        inventory::NewLot,
+        purchasing::{
+            NewPurchaseOrder,
+            NewPurchaseOrderLine,
+            NewSupplier,
+            PurchaseOrderDelivery,
+            PurchaseOrderQuery,
+            PurchaseOrderUpdate,
+            SupplierUpdate,
+        },
+    },
+};
+use chrono::{ DateTime, Utc };
+use mongodb::{ IndexModel, options::ReturnDocument };
+
+pub const SUPPLIERS_COLL: &str = "suppliers";
+pub const PURCHASE_ORDERS_COLL: &str = "purchaseOrders";
+
+/// Most lines a purchase order can have.
+const MAX_LINES: usize = 200;
+
+pub async fn ensure_purchasing_indexes(db: &mongodb::Database) -> Result<()> {
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+
+    suppliers_coll.create_index(IndexModel::builder().keys(doc! { "store": 1, "name": 1 }).build()).await?;
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "store": 1, "status": 1, "createdAt": -1 }).build()).await?;
+    orders_coll.create_index(IndexModel::builder().keys(doc! { "supplier": 1 }).build()).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Adding supplier", skip(db, store), fields(store = %store.name))]
+pub async fn create_supplier(db: &mongodb::Database, store: &StoreInfo, new_supplier: NewSupplier) -> Result<Supplier> {
+    let name = new_supplier.name.trim();
+    if name.is_empty() {
+        bail!(error::Purchasing::InvalidSupplier("The name can't be empty.".into()));
+    }
+    check_email(new_supplier.email.as_deref())?;
+
+    let supplier = Supplier {
+        id: ObjectId::new(),
+        store: store.id,
+        name: name.to_string(),
+        email: new_supplier.email,
+        phone: new_supplier.phone,
+        address: new_supplier.address,
+        notes: new_supplier.notes,
+        archived: false,
+        created_at: bson::DateTime::now(),
+    };
+
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+    suppliers_coll.insert_one(&supplier).await?;
+
+    tracing::info!(target: "mongodb", "Supplier {} of `{}` added.", supplier.id, store.name);
+
+    Ok(supplier)
+}
+
+pub async fn get_supplier(db: &mongodb::Database, supplier_id: ObjectId) -> Result<Option<Supplier>> {
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+    Ok(suppliers_coll.find_one(doc! { "_id": supplier_id }).await?)
+}
+
+/// Returns the suppliers of a store by name, leaving out the archived ones unless asked for.
+pub async fn get_store_suppliers(db: &mongodb::Database, store: &StoreInfo, archived: bool) -> Result<Vec<Supplier>> {
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+
+    let mut filter = doc! { "store": store.id };
+    if !archived {
+        filter.insert("archived", doc! { "$ne": true });
+    }
+
+    let suppliers = suppliers_coll
+        .find(filter)
+        .sort(doc! { "name": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(suppliers)
+}
+
+#[tracing::instrument(name = "Editing supplier", skip(db))]
+pub async fn update_supplier(db: &mongodb::Database, supplier_id: ObjectId, changes: SupplierUpdate) -> Result<Supplier> {
+    let mut set = doc! {};
+    if let Some(name) = changes.name {
+        let name = name.trim();
+        if name.is_empty() {
+            bail!(error::Purchasing::InvalidSupplier("The name can't be empty.".into()));
+        }
+        set.insert("name", name);
+    }
+    check_email(changes.email.as_deref())?;
+    for (field, value) in [("email", changes.email), ("phone", changes.phone), ("address", changes.address), ("notes", changes.notes)] {
+        if let Some(value) = value {
+            set.insert(field, value);
+        }
+    }
+    if set.is_empty() {
+        bail!(error::Purchasing::InvalidSupplier("Nothing to change.".into()));
+    }
+
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+    let supplier = suppliers_coll.find_one_and_update(doc! { "_id": supplier_id }, doc! { "$set": set })
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or(error::Purchasing::SupplierNotFound)?;
+
+    Ok(supplier)
+}
+
+/// Deletes a supplier, or archives it if it has purchase orders, so they can
+/// still tell who they were placed with. Returns whether it was deleted.
+#[tracing::instrument(name = "Deleting supplier", skip(db))]
+pub async fn delete_supplier(db: &mongodb::Database, supplier_id: ObjectId) -> Result<bool> {
+    let suppliers_coll: Collection<Supplier> = db.collection(SUPPLIERS_COLL);
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+
+    if orders_coll.find_one(doc! { "supplier": supplier_id }).projection(doc! { "_id": 1 }).await?.is_some() {
+        let res = suppliers_coll.update_one(doc! { "_id": supplier_id }, doc! { "$set": { "archived": true }}).await?;
+        if res.matched_count != 1 {
+            bail!(error::Purchasing::SupplierNotFound);
+        }
+        tracing::info!(target: "mongodb", "Supplier {} archived.", supplier_id);
+        return Ok(false);
+    }
+
+    let res = suppliers_coll.delete_one(doc! { "_id": supplier_id }).await?;
+    if res.deleted_count != 1 {
+        bail!(error::Purchasing::SupplierNotFound);
+    }
+    tracing::info!(target: "mongodb", "Supplier {} deleted.", supplier_id);
+
+    Ok(true)
+}
+
+/// Creates a draft purchase order of a store.
+#[tracing::instrument(name = "Creating purchase order", skip(db, store, new_order), fields(store = %store.name))]
+pub async fn create_purchase_order(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    by: ObjectId,
+    new_order: NewPurchaseOrder,
+) -> Result<PurchaseOrder> {
+    check_supplier(db, store, new_order.supplier).await?;
+    let lines = build_lines(db, store, new_order.lines).await?;
+
+    let order = PurchaseOrder {
+        id: ObjectId::new(),
+        store: store.id,
+        supplier: new_order.supplier,
+        status: PurchaseOrderStatus::Draft,
+        total: order_total(&lines),
+        lines,
+        receipts: Vec::new(),
+        expected_at: new_order.expected_at.map(to_bson_date),
+        notes: new_order.notes,
+        created_by: by,
+        created_at: bson::DateTime::now(),
+        sent_at: None,
+        received_at: None,
+        closed_at: None,
+    };
+
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+    orders_coll.insert_one(&order).await?;
+
+    tracing::info!(target: "mongodb", "Purchase order {} of `{}` created.", order.id, store.name);
+
+    Ok(order)
+}
+
+pub async fn get_purchase_order(db: &mongodb::Database, order_id: ObjectId) -> Result<Option<PurchaseOrder>> {
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+    Ok(orders_coll.find_one(doc! { "_id": order_id }).await?)
+}
+
+/// Returns the purchase orders of a store, newest first.
+pub async fn get_store_purchase_orders(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    query: &PurchaseOrderQuery,
+) -> Result<Vec<PurchaseOrder>> {
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+
+    let mut filter = doc! { "store": store.id };
+    if let Some(status) = query.status {
+        filter.insert("status", status.as_str());
+    }
+    if let Some(supplier) = query.supplier {
+        filter.insert("supplier", supplier);
+    }
+
+    let orders = orders_coll
+        .find(filter)
+        .sort(doc! { "createdAt": -1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(orders)
+}
+
+/// Edits a purchase order which is still a draft.
+#[tracing::instrument(name = "Editing purchase order", skip(db, store, changes), fields(store = %store.name))]
+pub async fn update_purchase_order(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    order_id: ObjectId,
+    changes: PurchaseOrderUpdate,
+) -> Result<PurchaseOrder> {
+    let mut set = doc! {};
+    if let Some(supplier) = changes.supplier {
+        check_supplier(db, store, supplier).await?;
+        set.insert("supplier", supplier);
+    }
+    if let Some(lines) = changes.lines {
+        let lines = build_lines(db, store, lines).await?;
+        set.insert("total", order_total(&lines));
+        set.insert("lines", bson::to_bson(&lines)?);
+    }
+    if let Some(expected_at) = changes.expected_at {
+        set.insert("expectedAt", to_bson_date(expected_at));
+    }
+    if let Some(notes) = changes.notes {
+        set.insert("notes", notes);
+    }
+    if set.is_empty() {
+        bail!(error::Purchasing::Invalid("Nothing to change.".into()));
+    }
+
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+    let order = orders_coll.find_one_and_update(
+        doc! { "_id": order_id, "status": PurchaseOrderStatus::Draft.as_str() },
+        doc! { "$set": set },
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match order {
+        Some(order) => Ok(order),
+        None => Err(state_error(db, order_id).await),
+    }
+}
+
+/// Deletes a purchase order which is still a draft.
+#[tracing::instrument(name = "Deleting purchase order", skip(db))]
+pub async fn delete_purchase_order(db: &mongodb::Database, order_id: ObjectId) -> Result<()> {
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+
+    let res = orders_coll.delete_one(doc! { "_id": order_id, "status": PurchaseOrderStatus::Draft.as_str() }).await?;
+    if res.deleted_count != 1 {
+        return Err(state_error(db, order_id).await);
+    }
+
+    tracing::info!(target: "mongodb", "Purchase order {} deleted.", order_id);
+
+    Ok(())
+}
+
+/// Marks a draft purchase order as sent to its supplier, after which it can be received.
+#[tracing::instrument(name = "Sending purchase order", skip(db))]
+pub async fn send_purchase_order(db: &mongodb::Database, order_id: ObjectId) -> Result<PurchaseOrder> {
+    transition(
+        db,
+        order_id,
+        &[PurchaseOrderStatus::Draft],
+        doc! { "status": PurchaseOrderStatus::Sent.as_str(), "sentAt": bson::DateTime::now() },
+    ).await
+}
+
+/// Marks a purchase order as done, leaving any units not received yet out.
+#[tracing::instrument(name = "Closing purchase order", skip(db))]
+pub async fn close_purchase_order(db: &mongodb::Database, order_id: ObjectId) -> Result<PurchaseOrder> {
+    transition(
+        db,
+        order_id,
+        &[PurchaseOrderStatus::Sent, PurchaseOrderStatus::PartiallyReceived, PurchaseOrderStatus::Received],
+        doc! { "status": PurchaseOrderStatus::Closed.as_str(), "closedAt": bson::DateTime::now() },
+    ).await
+}
+
+/// Receives a delivery of a purchase order: a lot is added to the item of each
+/// delivered line, at the line's cost price, and recorded in the inventory
+/// ledger referencing the order. The order is received once every line is.
+#[tracing::instrument(name = "Receiving purchase order", skip(db, delivery))]
+pub async fn receive_purchase_order(
+    db: &mongodb::Database,
+    order_id: ObjectId,
+    by: ObjectId,
+    delivery: PurchaseOrderDelivery,
+) -> Result<PurchaseOrder> {
+    let Some(order) = get_purchase_order(db, order_id).await? else {
+        bail!(error::Purchasing::OrderNotFound);
+    };
+    if !matches!(order.status, PurchaseOrderStatus::Sent | PurchaseOrderStatus::PartiallyReceived) {
+        bail!(error::Purchasing::InvalidState(order.status.as_str().into()));
+    }
+    if delivery.lines.is_empty() {
+        bail!(error::Purchasing::Invalid("The delivery has no lines.".into()));
+    }
+
+    let now = bson::DateTime::now();
+    let mut delivered: HashMap<ObjectId, u32> = HashMap::new();
+    let mut lots = Vec::new();
+    let mut receipts = Vec::new();
+
+    for received in &delivery.lines {
+        let Some(line) = order.lines.iter().find(|line| line.id == received.line) else {
+            bail!(error::Purchasing::LineNotFound(received.line.to_hex()));
+        };
+
+        let total = delivered.entry(line.id).or_default();
+        *total = total.saturating_add(received.quantity);
+        let remaining = line.quantity.saturating_sub(line.received);
+        if *total > remaining {
+            bail!(error::Purchasing::OverReceived { line: line.id.to_hex(), remaining });
+        }
+
+        let lot = build_lot(&NewLot {
+            quantity: received.quantity,
+            enter_date: received.enter_date,
+            expiry: received.expiry,
+            cost_price: Some(line.cost_price),
+        })?;
+
+        receipts.push(PurchaseOrderReceipt { line: line.id, lot: lot.id, quantity: received.quantity, by, at: now });
+        lots.push((line, lot));
+    }
+
+    let fully_received = order.lines.iter()
+        .all(|line| line.received + delivered.get(&line.id).copied().unwrap_or(0) >= line.quantity);
+
+    let mut set = doc! {};
+    if fully_received {
+        set.insert("status", PurchaseOrderStatus::Received.as_str());
+        set.insert("receivedAt", now);
+    } else {
+        set.insert("status", PurchaseOrderStatus::PartiallyReceived.as_str());
+    }
+
+    let mut inc = doc! {};
+    let mut array_filters = Vec::new();
+    for (i, (line_id, quantity)) in delivered.iter().enumerate() {
+        inc.insert(format!("lines.$[line{}].received", i), *quantity as i64);
+        array_filters.push(doc! { format!("line{}._id", i): line_id });
+    }
+
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        // Another delivery received meanwhile changes the receipts
+        let res = orders_coll.update_one(
+            doc! {
+                "_id": order_id,
+                "status": order.status.as_str(),
+                "receipts": { "$size": order.receipts.len() as i64 },
+            },
+            doc! {
+                "$inc": inc,
+                "$set": set,
+                "$push": { "receipts": { "$each": bson::to_bson(&receipts)? }},
+            },
+        )
+        .array_filters(array_filters)
+        .session(&mut session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Purchasing::Changed);
+        }
+
+        for (line, lot) in &lots {
+            push_lot(db, &mut session, &line.coll, line.item, by, lot, Some(order_id)).await?;
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    let items: HashSet<(&str, ObjectId)> = lots.iter().map(|(line, _)| (line.coll.as_str(), line.item)).collect();
+    for (coll, item_id) in items {
+        refresh_projection(db, coll, item_id).await;
+    }
+
+    tracing::info!(target: "mongodb", "{} lots received for purchase order {}.", lots.len(), order_id);
+
+    get_purchase_order(db, order_id).await?.ok_or_else(|| anyhow!(error::Purchasing::OrderNotFound))
+}
+
+async fn transition(
+    db: &mongodb::Database,
+    order_id: ObjectId,
+    from: &[PurchaseOrderStatus],
+    set: Document,
+) -> Result<PurchaseOrder> {
+    let orders_coll: Collection<PurchaseOrder> = db.collection(PURCHASE_ORDERS_COLL);
+    let from: Vec<&str> = from.iter().map(|status| status.as_str()).collect();
+
+    let order = orders_coll.find_one_and_update(
+        doc! { "_id": order_id, "status": { "$in": from }},
+        doc! { "$set": set },
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match order {
+        Some(order) => {
+            tracing::info!(target: "mongodb", "Purchase order {} is now `{}`.", order_id, order.status.as_str());
+            Ok(order)
+        }
+        None => Err(state_error(db, order_id).await),
+    }
+}
+
+/// Checks that a supplier can be ordered from by a store.
+async fn check_supplier(db: &mongodb::Database, store: &StoreInfo, supplier_id: ObjectId) -> Result<()> {
+    match get_supplier(db, supplier_id).await? {
+        Some(supplier) if supplier.store != store.id => bail!(error::Purchasing::SupplierNotFound),
+        Some(supplier) if supplier.archived => bail!(error::Purchasing::Invalid(format!("`{}` is archived.", supplier.name))),
+        Some(_) => Ok(()),
+        None => bail!(error::Purchasing::SupplierNotFound),
+    }
+}
+
+/// Checks the lines of a purchase order, which must be of items the store sells.
+async fn build_lines(db: &mongodb::Database, store: &StoreInfo, lines: Vec<NewPurchaseOrderLine>) -> Result<Vec<PurchaseOrderLine>> {
+    if lines.is_empty() || lines.len() > MAX_LINES {
+        bail!(error::Purchasing::Invalid(format!("A purchase order must have between 1 and {} lines.", MAX_LINES)));
+    }
+
+    let mut built = Vec::with_capacity(lines.len());
+    for line in lines {
+        if !store.item_colls.contains(&line.coll) {
+            bail!(error::Purchasing::Invalid(format!("`{}` doesn't sell `{}`.", store.name, line.coll)));
+        }
+        if line.quantity == 0 {
+            bail!(error::Purchasing::Invalid("The quantity of a line can't be 0.".into()));
+        }
+        if !line.cost_price.is_finite() || line.cost_price < 0.0 {
+            bail!(error::Purchasing::Invalid("The cost price can't be negative.".into()));
+        }
+
+        let item_coll: Collection<Document> = db.collection(&line.coll);
+        let Some(item) = item_coll.find_one(doc! { "_id": line.item }).projection(doc! { "name": 1, "archived": 1 }).await? else {
+            bail!(error::Purchasing::Invalid(format!("Item {} of `{}` not found.", line.item, line.coll)));
+        };
+        if item.get_bool("archived").unwrap_or(false) {
+            bail!(error::Purchasing::Invalid(format!("`{}` is archived.", item.get_str("name").unwrap_or_default())));
+        }
+
+        built.push(PurchaseOrderLine {
+            id: ObjectId::new(),
+            name: item.get_str("name").unwrap_or_default().to_string(),
+            coll: line.coll,
+            item: line.item,
+            quantity: line.quantity,
+            received: 0,
+            cost_price: line.cost_price,
+            expected_at: line.expected_at.map(to_bson_date),
+        });
+    }
+
+    Ok(built)
+}
+
+fn order_total(lines: &[PurchaseOrderLine]) -> f64 {
+    let total: f64 = lines.iter().map(|line| line.cost_price * line.quantity as f64).sum();
+    (total * 100.0).round() / 100.0
+}
+
+fn check_email(email: Option<&str>) -> Result<()> {
+    if email.is_some_and(|email| !email.contains('@')) {
+        bail!(error::Purchasing::InvalidSupplier("Invalid email.".into()));
+    }
+    Ok(())
+}
+
+fn to_bson_date(date: DateTime<Utc>) -> bson::DateTime {
+    bson::DateTime::from_millis(date.timestamp_millis())
+}
+
+/// Why a purchase order couldn't be updated: it doesn't exist, or isn't in the right status.
+async fn state_error(db: &mongodb::Database, order_id: ObjectId) -> anyhow::Error {
+    match get_purchase_order(db, order_id).await {
+        Ok(Some(order)) => anyhow!(error::Purchasing::InvalidState(order.status.as_str().into())),
+        Ok(None) => anyhow!(error::Purchasing::OrderNotFound),
+        Err(e) => e,
+    }
+}
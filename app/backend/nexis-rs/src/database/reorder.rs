@@ -0,0 +1,263 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::settings::StockAlertSettings;
+use crate::types::{
+    error,
+    mongodb::{ inventory::{ ReorderLevels, StockAlert, StockAlertStatus }, stores::StoreInfo },
+    requests::inventory::ReorderUpdate,
+    responses::LowStockItem,
+};
+use mongodb::{ IndexModel, options::IndexOptions };
+use std::time::Duration;
+
+pub const STOCK_ALERTS_COLL: &str = "stockAlerts";
+
+pub async fn ensure_stock_alert_indexes(db: &mongodb::Database) -> Result<()> {
+    let alerts_coll: Collection<StockAlert> = db.collection(STOCK_ALERTS_COLL);
+
+    // Only one open alert per item
+    alerts_coll.create_index(
+        IndexModel::builder()
+            .keys(doc! { "coll": 1, "item": 1 })
+            .options(IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "status": "open" })
+                .build())
+            .build()
+    ).await?;
+    alerts_coll.create_index(IndexModel::builder().keys(doc! { "store": 1, "status": 1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Gives an item its own reorder levels, instead of its collection's defaults.
+#[tracing::instrument(name = "Setting reorder levels", skip(db))]
+pub async fn set_reorder_levels(
+    db: &mongodb::Database,
+    coll: &str,
+    item_id: ObjectId,
+    levels: ReorderUpdate,
+) -> Result<ReorderLevels> {
+    if levels.point < 0 {
+        bail!(error::Inventory::InvalidReorder("The reorder point can't be negative.".into()));
+    }
+    if levels.target <= levels.point {
+        bail!(error::Inventory::InvalidReorder("The target must be above the reorder point.".into()));
+    }
+
+    let levels = ReorderLevels { point: levels.point, target: levels.target };
+
+    let item_coll: Collection<Document> = db.collection(coll);
+    let res = item_coll.update_one(
+        doc! { "_id": item_id },
+        doc! { "$set": { "reorder": bson::to_bson(&levels)? }},
+    ).await?;
+
+    if res.matched_count != 1 {
+        bail!(error::Inventory::ItemNotFound);
+    }
+
+    tracing::info!(target: "mongodb", "Reorder levels of item {} of `{}` set.", item_id, coll);
+
+    Ok(levels)
+}
+
+/// Makes an item use its collection's default reorder levels again.
+#[tracing::instrument(name = "Clearing reorder levels", skip(db))]
+pub async fn clear_reorder_levels(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> Result<()> {
+    let item_coll: Collection<Document> = db.collection(coll);
+    let res = item_coll.update_one(doc! { "_id": item_id }, doc! { "$unset": { "reorder": "" }}).await?;
+
+    if res.matched_count != 1 {
+        bail!(error::Inventory::ItemNotFound);
+    }
+
+    tracing::info!(target: "mongodb", "Reorder levels of item {} of `{}` cleared.", item_id, coll);
+
+    Ok(())
+}
+
+/// Items of a store whose sellable units are at or under their reorder point,
+/// the emptiest first, with the alert raised for each of them, if any.
+pub async fn get_low_stock(
+    db: &mongodb::Database,
+    store: &StoreInfo,
+    settings: &StockAlertSettings,
+) -> Result<Vec<LowStockItem>> {
+    let now = bson::DateTime::now();
+    let mut items = Vec::new();
+
+    for coll in &store.item_colls {
+        let item_coll: Collection<Document> = db.collection(coll);
+        let defaults = settings.reorder(coll);
+
+        let mut cursor = item_coll
+            .find(doc! { "archived": { "$ne": true }})
+            .projection(doc! { "name": 1, "lot.code": 1, "lot.expiry": 1, "reorder": 1 })
+            .await?;
+
+        while let Some(item) = cursor.try_next().await? {
+            let (Ok(item_id), Ok(name)) = (item.get_object_id("_id"), item.get_str("name")) else { continue };
+
+            let own = item.get_document("reorder").ok()
+                .and_then(|levels| bson::from_document::<ReorderLevels>(levels.clone()).ok());
+            let reorder = own.unwrap_or(defaults);
+
+            let units = utils::stock::available_units(&utils::stock::lot_units(&item), now);
+            if units > reorder.point {
+                continue;
+            }
+
+            items.push(LowStockItem {
+                coll: coll.clone(),
+                item: item_id,
+                name: name.to_string(),
+                units,
+                reorder,
+                own_levels: own.is_some(),
+                to_order: (reorder.target - units).max(0),
+                alerted_at: None,
+            });
+        }
+    }
+
+    let alerts_coll: Collection<StockAlert> = db.collection(STOCK_ALERTS_COLL);
+    let alerts: HashMap<ObjectId, bson::DateTime> = alerts_coll
+        .find(doc! { "store": store.id, "status": "open" })
+        .await?
+        .try_collect::<Vec<StockAlert>>()
+        .await?
+        .into_iter()
+        .map(|alert| (alert.item, alert.raised_at))
+        .collect();
+
+    for item in &mut items {
+        item.alerted_at = alerts.get(&item.item).copied();
+    }
+
+    items.sort_by_key(|item| (item.units - item.reorder.point, item.units));
+
+    Ok(items)
+}
+
+/// Checks the stock of every store: raises an alert for each item which dropped
+/// to its reorder point, and resolves the alerts of restocked items. Returns the
+/// alerts staff should be emailed about, by store: new ones, and the ones not
+/// notified within `renotify_hours`.
+#[tracing::instrument(name = "Checking stock levels", skip(db, settings))]
+pub async fn run_stock_alert_check(
+    db: &mongodb::Database,
+    settings: &StockAlertSettings,
+) -> Result<Vec<(StoreInfo, Vec<StockAlert>)>> {
+    let alerts_coll: Collection<StockAlert> = db.collection(STOCK_ALERTS_COLL);
+    let now = bson::DateTime::now();
+    let renotify_before = bson::DateTime::from_millis(now.timestamp_millis() - settings.renotify_hours * 60 * 60 * 1000);
+
+    let mut notifications = Vec::new();
+
+    for store in stores::all() {
+        let low_stock = get_low_stock(db, &store, settings).await?;
+        let mut to_notify = Vec::new();
+
+        for item in &low_stock {
+            let existing = alerts_coll.find_one_and_update(
+                doc! { "coll": &item.coll, "item": item.item, "status": "open" },
+                doc! { "$set": { "units": item.units, "reorder": bson::to_bson(&item.reorder)? }},
+            )
+            .return_document(mongodb::options::ReturnDocument::After)
+            .await?;
+
+            let alert = match existing {
+                Some(alert) if alert.notified_at.is_some_and(|notified_at| notified_at > renotify_before) => continue,
+                Some(alert) => alert,
+                None => {
+                    let alert = StockAlert {
+                        id: ObjectId::new(),
+                        store: store.id,
+                        coll: item.coll.clone(),
+                        item: item.item,
+                        name: item.name.clone(),
+                        status: StockAlertStatus::Open,
+                        units: item.units,
+                        reorder: item.reorder,
+                        raised_at: now,
+                        notified_at: None,
+                        resolved_at: None,
+                    };
+                    match alerts_coll.insert_one(&alert).await {
+                        Ok(_) => {}
+                        // Raised by another instance in the meantime
+                        Err(e) if crate::database::is_duplicate_key(&e) => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                    tracing::info!(target: "mongodb", "Low stock alert raised for item {} of `{}` with {} units.", item.item, item.coll, item.units);
+                    alert
+                }
+            };
+
+            to_notify.push(alert);
+        }
+
+        // Items back over their reorder point, or gone altogether
+        let low_items: Vec<ObjectId> = low_stock.iter().map(|item| item.item).collect();
+        let res = alerts_coll.update_many(
+            doc! { "store": store.id, "status": "open", "item": { "$nin": low_items }},
+            doc! { "$set": { "status": "resolved", "resolvedAt": now }},
+        ).await?;
+        if res.modified_count > 0 {
+            tracing::info!(target: "mongodb", "{} low stock alerts of `{}` resolved.", res.modified_count, store.name);
+        }
+
+        if !to_notify.is_empty() {
+            notifications.push((store, to_notify));
+        }
+    }
+
+    Ok(notifications)
+}
+
+/// Records that staff were emailed about some alerts, so they aren't again
+/// until `renotify_hours` pass.
+pub async fn mark_alerts_notified(db: &mongodb::Database, alerts: &[StockAlert]) -> Result<()> {
+    let alerts_coll: Collection<StockAlert> = db.collection(STOCK_ALERTS_COLL);
+    let ids: Vec<ObjectId> = alerts.iter().map(|alert| alert.id).collect();
+
+    alerts_coll.update_many(
+        doc! { "_id": { "$in": ids }},
+        doc! { "$set": { "notifiedAt": bson::DateTime::now() }},
+    ).await?;
+
+    Ok(())
+}
+
+/// Runs the stock check right away, then every `check_interval_minutes`.
+pub fn spawn_stock_alert_check(db: mongodb::Database, settings: &StockAlertSettings) {
+    let settings = settings.clone();
+    let check_interval = Duration::from_secs(settings.check_interval_minutes * 60);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            let notifications = match run_stock_alert_check(&db, &settings).await {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    tracing::error!(target: "mongodb", "Failed to check stock levels: {}", e);
+                    continue;
+                }
+            };
+
+            if !settings.email_staff {
+                continue;
+            }
+            for (store, alerts) in notifications {
+                match crate::alerts::email_low_stock(&db, &store, &alerts).await {
+                    Ok(()) => if let Err(e) = mark_alerts_notified(&db, &alerts).await {
+                        tracing::error!(target: "mongodb", "Failed to mark low stock alerts as notified: {}", e);
+                    },
+                    Err(e) => tracing::error!(target: "backend", "Failed to email the low stock alerts of `{}`: {}", store.name, e),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,537 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    get_order_payments,
+    get_sale_payment,
+    refund_payment,
+    transition_order,
+    movements::{ LotMovement, record_movements },
+    orders::commit,
+};
+use crate::payments::PaymentProvider;
+use crate::types::{
+    error,
+    mongodb::{
+        inventory::MovementKind,
+        orders::{ Order, OrderStatus },
+        payments::{ PaymentRecord, PaymentStatus },
+        returns::{ ReturnRecord, ReturnStatus, ReturnedUnit, UnitCondition },
+        stores::StoreInfo,
+    },
+};
+use mongodb::{ ClientSession, IndexModel };
+
+pub const RETURNS_COLL: &str = "returns";
+
+pub async fn ensure_return_indexes(db: &mongodb::Database) -> Result<()> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    returns_coll.create_index(IndexModel::builder().keys(doc! { "sale": 1, "units.code": 1 }).build()).await?;
+    returns_coll.create_index(IndexModel::builder().keys(doc! { "order": 1, "units.code": 1 }).build()).await?;
+    returns_coll.create_index(IndexModel::builder().keys(doc! { "status": 1, "units.coll": 1, "createdAt": 1 }).build()).await?;
+
+    Ok(())
+}
+
+pub async fn get_return(db: &mongodb::Database, return_id: ObjectId) -> Result<Option<ReturnRecord>> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+    Ok(returns_coll.find_one(doc! { "_id": return_id }).await?)
+}
+
+/// Returns of any of some item collections with a status, oldest first.
+pub async fn get_returns_queue(
+    db: &mongodb::Database,
+    colls: &[String],
+    status: ReturnStatus,
+) -> Result<Vec<ReturnRecord>> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let returns = returns_coll
+        .find(doc! { "status": status.as_str(), "units.coll": { "$in": colls }})
+        .sort(doc! { "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(returns)
+}
+
+/// Takes back units sold at the register of a store. The units go back to
+/// inventory in the same transaction that records the return. Cash is given
+/// back at the register, while sales paid through the payment provider are
+/// refunded from their payment afterwards. If that refund fails, the return
+/// stays `approved` and approving it retries the refund.
+#[tracing::instrument(name = "Returning sold units", skip(db, provider, store, codes, reason), fields(store = %store.name))]
+#[allow(clippy::too_many_arguments)]
+pub async fn return_sale_units(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    employee: ObjectId,
+    store: &StoreInfo,
+    sale_id: ObjectId,
+    codes: &[ObjectId],
+    condition: UnitCondition,
+    reason: Option<String>,
+) -> Result<ReturnRecord> {
+    let sale = match crate::database::get_sale(db, sale_id).await? {
+        Some((store_id, sale)) if store_id == store.id => sale,
+        _ => bail!(error::Returns::SaleNotFound),
+    };
+
+    let mut units = Vec::with_capacity(codes.len());
+    for code in unique_codes(codes)? {
+        let Some(unit) = sale.item.iter().find(|unit| unit.code == code) else {
+            bail!(error::Returns::UnitNotSold(code.to_hex()));
+        };
+        check_window(&unit.coll, sale.timestamp)?;
+
+        units.push(ReturnedUnit {
+            coll: unit.coll.clone(),
+            item: unit.item,
+            lot: unit.lot,
+            code: bson::Bson::ObjectId(code),
+            name: unit.name.clone(),
+            price: unit.price,
+        });
+    }
+
+    let amount = total(&units);
+    let payment = get_sale_payment(db, sale_id).await?;
+    if let Some(payment) = &payment {
+        if payment.status != PaymentStatus::Captured || payment.amount - payment.refunded < amount - 0.005 {
+            bail!(error::Returns::NotReturnable("The sale has no captured payment left to refund.".into()));
+        }
+    }
+
+    let now = bson::DateTime::now();
+    let mut record = ReturnRecord {
+        id: ObjectId::new(),
+        store: Some(store.id),
+        sale: Some(sale_id),
+        order: None,
+        user: sale.client.user,
+        amount,
+        units,
+        // Refunded at the register, unless it's refunded through the provider below
+        status: if payment.is_some() { ReturnStatus::Approved } else { ReturnStatus::Refunded },
+        condition: Some(condition),
+        reason,
+        rejection_reason: None,
+        refund_method: Some(sale.payment.method),
+        payment: None,
+        requested_by: employee,
+        processed_by: Some(employee),
+        created_at: now,
+        processed_at: Some(now),
+    };
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        check_not_returned(db, &mut session, doc! { "sale": sale_id }, &record).await?;
+
+        let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+        returns_coll.insert_one(&record).session(&mut session).await?;
+        restock_units(db, &mut session, &record, condition, employee, now).await?;
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    tracing::info!(target: "mongodb", "Return {} of sale {} processed by {}.", record.id, sale_id, employee);
+
+    if let Some(mut payment) = payment {
+        refund_return(db, provider, &mut record, &mut payment).await?;
+    }
+
+    Ok(record)
+}
+
+/// Refunds an approved return from a payment through the payment provider.
+async fn refund_return(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    record: &mut ReturnRecord,
+    payment: &mut PaymentRecord,
+) -> Result<()> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    refund_payment(db, provider, payment, record.amount, Some(record.id)).await?;
+
+    returns_coll.update_one(
+        doc! { "_id": record.id },
+        doc! { "$set": {
+            "status": ReturnStatus::Refunded.as_str(),
+            "payment": payment.id,
+            "refundType": bson::to_bson(&payment.method)?,
+        }},
+    ).await?;
+
+    record.status = ReturnStatus::Refunded;
+    record.payment = Some(payment.id);
+    record.refund_method = Some(payment.method.clone());
+
+    Ok(())
+}
+
+/// Records a customer's request to return units of a completed order.
+/// Nothing goes back to inventory until the store approves it.
+#[tracing::instrument(name = "Requesting order return", skip(db, order, codes, reason), fields(order_id = %order.id))]
+pub async fn request_order_return(
+    db: &mongodb::Database,
+    order: &Order,
+    codes: &[ObjectId],
+    reason: Option<String>,
+) -> Result<ReturnRecord> {
+    if order.is_parent() {
+        bail!(error::Returns::NotReturnable("Units are returned through the sub-order of their store.".into()));
+    }
+    if order.status != OrderStatus::Completed {
+        bail!(error::Returns::NotReturnable(format!("Only completed orders can be returned, and this one is `{}`.", order.status)));
+    }
+
+    let completed_at = order.history.iter()
+        .rev()
+        .find(|event| event.status == OrderStatus::Completed)
+        .map(|event| event.at)
+        .unwrap_or(order.created_at);
+
+    let mut units = Vec::with_capacity(codes.len());
+    for code in unique_codes(codes)? {
+        let bson_code = bson::Bson::ObjectId(code);
+        let Some((line, unit)) = order.items.iter()
+            .find_map(|line| line.units.iter().find(|unit| unit.code == bson_code).map(|unit| (line, unit))) else {
+            bail!(error::Returns::UnitNotSold(code.to_hex()));
+        };
+        check_window(&line.coll, completed_at)?;
+
+        units.push(ReturnedUnit {
+            coll: line.coll.clone(),
+            item: line.item,
+            lot: unit.lot,
+            code: bson_code,
+            name: line.name.clone(),
+            price: ((line.total / line.quantity.max(1) as f64) * 100.0).round() / 100.0,
+        });
+    }
+
+    let record = ReturnRecord {
+        id: ObjectId::new(),
+        store: None,
+        sale: None,
+        order: Some(order.id),
+        user: Some(order.user),
+        amount: total(&units),
+        units,
+        status: ReturnStatus::Requested,
+        condition: None,
+        reason,
+        rejection_reason: None,
+        refund_method: None,
+        payment: None,
+        requested_by: order.user,
+        processed_by: None,
+        created_at: bson::DateTime::now(),
+        processed_at: None,
+    };
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        check_not_returned(db, &mut session, doc! { "order": order.id }, &record).await?;
+
+        let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+        returns_coll.insert_one(&record).session(&mut session).await?;
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    Ok(record)
+}
+
+/// Approves a requested order return: its units go back to inventory and the
+/// amount is refunded from the order's payment through the payment provider.
+/// If the refund fails, the return stays `approved` and approving it again
+/// retries the refund, as for returns at a register. Once every unit of an
+/// order is returned, it's `refunded`.
+#[tracing::instrument(name = "Approving order return", skip(db, provider))]
+pub async fn approve_return(
+    db: &mongodb::Database,
+    provider: &dyn PaymentProvider,
+    return_id: ObjectId,
+    staff: ObjectId,
+    condition: UnitCondition,
+) -> Result<ReturnRecord> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let mut record = get_return(db, return_id).await?.ok_or(error::Returns::NotFound)?;
+    if !matches!(record.status, ReturnStatus::Requested | ReturnStatus::Approved) {
+        bail!(error::Returns::InvalidState(record.status.as_str().into()));
+    }
+
+    let Some(order_id) = record.order else {
+        // Returns at a register are only left `approved` by a failed refund
+        let payment = match record.sale {
+            Some(sale_id) if record.status == ReturnStatus::Approved => get_sale_payment(db, sale_id).await?,
+            _ => None,
+        };
+        let Some(mut payment) = payment else {
+            bail!(error::Returns::InvalidState(record.status.as_str().into()));
+        };
+        tracing::info!(target: "backend", "Retrying refund of return {}.", return_id);
+        refund_return(db, provider, &mut record, &mut payment).await?;
+        return Ok(record);
+    };
+
+    let payable_order = match crate::database::get_order(db, order_id).await? {
+        Some(order) => order.payable_order(),
+        None => order_id,
+    };
+    let payment = get_order_payments(db, payable_order).await?
+        .into_iter()
+        .find(|payment| payment.status == PaymentStatus::Captured && payment.amount - payment.refunded >= record.amount - 0.005);
+    let Some(mut payment) = payment else {
+        bail!(error::Returns::NotReturnable("The order has no captured payment left to refund.".into()));
+    };
+
+    match record.status {
+        ReturnStatus::Requested => {
+            let now = bson::DateTime::now();
+
+            let mut session = db.client().start_session().await?;
+            session.start_transaction().await?;
+
+            let result = async {
+                // Fails if the return was processed since it was read
+                let res = returns_coll.update_one(
+                    doc! { "_id": return_id, "status": ReturnStatus::Requested.as_str() },
+                    doc! { "$set": {
+                        "status": ReturnStatus::Approved.as_str(),
+                        "condition": bson::to_bson(&condition)?,
+                        "processedBy": staff,
+                        "processedAt": now,
+                    }},
+                )
+                .session(&mut session)
+                .await?;
+
+                if res.modified_count != 1 {
+                    bail!(error::Returns::InvalidState("being processed".into()));
+                }
+
+                check_not_returned(db, &mut session, doc! { "order": order_id }, &record).await?;
+                restock_units(db, &mut session, &record, condition, staff, now).await?;
+                commit(&mut session).await
+            }.await;
+
+            if let Err(e) = result {
+                let _ = session.abort_transaction().await;
+                return Err(e);
+            }
+
+            record.status = ReturnStatus::Approved;
+            record.condition = Some(condition);
+            record.processed_by = Some(staff);
+            record.processed_at = Some(now);
+        }
+        _ => tracing::info!(target: "backend", "Retrying refund of return {}.", return_id),
+    }
+
+    refund_return(db, provider, &mut record, &mut payment).await?;
+    refund_order_if_fully_returned(db, order_id, staff).await?;
+
+    Ok(record)
+}
+
+#[tracing::instrument(name = "Rejecting order return", skip(db))]
+pub async fn reject_return(
+    db: &mongodb::Database,
+    return_id: ObjectId,
+    staff: ObjectId,
+    reason: Option<String>,
+) -> Result<ReturnRecord> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let mut set = doc! {
+        "status": ReturnStatus::Rejected.as_str(),
+        "processedBy": staff,
+        "processedAt": bson::DateTime::now(),
+    };
+    if let Some(reason) = reason {
+        set.insert("rejectionReason", reason);
+    }
+
+    let record = returns_coll.find_one_and_update(
+        doc! { "_id": return_id, "status": ReturnStatus::Requested.as_str() },
+        doc! { "$set": set },
+    )
+    .return_document(mongodb::options::ReturnDocument::After)
+    .await?;
+
+    match record {
+        Some(record) => Ok(record),
+        None => match get_return(db, return_id).await? {
+            Some(record) => bail!(error::Returns::InvalidState(record.status.as_str().into())),
+            None => bail!(error::Returns::NotFound),
+        },
+    }
+}
+
+fn unique_codes(codes: &[ObjectId]) -> Result<Vec<ObjectId>> {
+    if codes.is_empty() {
+        bail!(error::Returns::Empty);
+    }
+
+    let mut unique = Vec::with_capacity(codes.len());
+    for code in codes {
+        if !unique.contains(code) {
+            unique.push(*code);
+        }
+    }
+
+    Ok(unique)
+}
+
+fn check_window(coll: &str, sold_at: bson::DateTime) -> Result<()> {
+    let settings = crate::settings::get_settings().expect("Failed to read settings.");
+
+    let window_ms = settings.returns.window_days(coll) * 24 * 60 * 60 * 1000;
+    if bson::DateTime::now().timestamp_millis() - sold_at.timestamp_millis() > window_ms {
+        bail!(error::Returns::WindowClosed(coll.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Fails if any of the units of a return was already returned, or is in another
+/// pending return, from the same sale or order. Run within the transaction
+/// recording the return, though it's `restock_units` that stops two returns
+/// running at once from both putting a unit back.
+async fn check_not_returned(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    source: Document,
+    record: &ReturnRecord,
+) -> Result<()> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let codes: Vec<&bson::Bson> = record.units.iter().map(|unit| &unit.code).collect();
+
+    let mut filter = source;
+    filter.insert("_id", doc! { "$ne": record.id });
+    filter.insert("units.code", doc! { "$in": codes });
+    filter.insert("status", doc! { "$ne": ReturnStatus::Rejected.as_str() });
+
+    if let Some(returned) = returns_coll.find_one(filter).session(session).await? {
+        let code = returned.units.iter()
+            .find(|returned| record.units.iter().any(|unit| unit.code == returned.code))
+            .map(|unit| unit.code.to_string())
+            .unwrap_or_default();
+        bail!(error::Returns::AlreadyReturned(code));
+    }
+
+    Ok(())
+}
+
+/// Puts returned units back in inventory. Resellable units go back to their
+/// lot, unless it no longer exists. Damaged ones, and the ones without a lot,
+/// are kept in the `quarantine` of their item, which isn't counted as stock.
+/// Fails if a unit is already back in its item, as when two returns of it run
+/// at once.
+async fn restock_units(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    record: &ReturnRecord,
+    condition: UnitCondition,
+    staff: ObjectId,
+    now: bson::DateTime,
+) -> Result<()> {
+    for unit in &record.units {
+        let item_coll: Collection<Document> = db.collection(&unit.coll);
+
+        if condition == UnitCondition::Resellable {
+            let res = item_coll.update_one(
+                doc! { "_id": unit.item, "lot": { "$elemMatch": { "_id": unit.lot, "code": { "$ne": &unit.code }}}},
+                doc! { "$push": { "lot.$[lot].code": &unit.code }},
+            )
+            .array_filters(vec![doc! { "lot._id": unit.lot }])
+            .session(&mut *session)
+            .await?;
+
+            if res.modified_count == 1 {
+                record_movements(db, session, LotMovement {
+                    kind: MovementKind::Returned,
+                    coll: &unit.coll,
+                    item: unit.item,
+                    lot: unit.lot,
+                    codes: std::slice::from_ref(&unit.code),
+                    by: Some(staff),
+                    reason: record.reason.as_deref(),
+                    reference: Some(record.id),
+                }).await?;
+                continue;
+            }
+            if item_coll.find_one(doc! { "_id": unit.item, "lot.code": &unit.code }).session(&mut *session).await?.is_some() {
+                bail!(error::Returns::AlreadyReturned(unit.code.to_string()));
+            }
+            tracing::warn!(target: "mongodb", "Lot {} of item {} no longer exists, quarantining unit {}.", unit.lot, unit.item, unit.code);
+        }
+
+        let res = item_coll.update_one(
+            doc! { "_id": unit.item, "quarantine.code": { "$ne": &unit.code }},
+            doc! { "$push": { "quarantine": {
+                "code": &unit.code,
+                "lot": unit.lot,
+                "return": record.id,
+                "condition": bson::to_bson(&condition)?,
+                "returnedAt": now,
+            }}},
+        )
+        .session(&mut *session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Returns::AlreadyReturned(unit.code.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Marks an order as refunded once every one of its units was returned.
+async fn refund_order_if_fully_returned(db: &mongodb::Database, order_id: ObjectId, staff: ObjectId) -> Result<()> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let Some(order) = crate::database::get_order(db, order_id).await? else {
+        return Ok(());
+    };
+
+    let mut returned = HashSet::new();
+    let mut cursor = returns_coll.find(doc! { "order": order_id, "status": ReturnStatus::Refunded.as_str() }).await?;
+    while let Some(record) = cursor.try_next().await? {
+        returned.extend(record.units.into_iter().map(|unit| unit.code.to_string()));
+    }
+
+    let fully_returned = order.items.iter()
+        .flat_map(|line| &line.units)
+        .all(|unit| returned.contains(&unit.code.to_string()));
+
+    if fully_returned && order.status.can_become(OrderStatus::Refunded) {
+        transition_order(db, order_id, OrderStatus::Refunded, Some(staff), Some("Every unit was returned.".into())).await?;
+    }
+
+    Ok(())
+}
+
+fn total(units: &[ReturnedUnit]) -> f64 {
+    (units.iter().map(|unit| unit.price).sum::<f64>() * 100.0).round() / 100.0
+}
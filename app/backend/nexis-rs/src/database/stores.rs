@@ -0,0 +1,142 @@
+use crate::prelude::*;
+use anyhow::Result;
+use types::{ mongodb::stores::StoreInfo, requests::stores::{ NewStore, StoreUpdate }};
+
+#[tracing::instrument(
+    name = "Inserting new store into DB",
+    skip(db, new_store),
+    fields(new_store_name = %new_store.name)
+)]
+pub async fn insert_store(
+    db: &mongodb::Database,
+    new_store: NewStore,
+) -> Result<ObjectId> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let candidate = StoreInfo {
+        id: ObjectId::new(),
+        name: new_store.name.clone(),
+        item_colls: new_store.item_colls.clone(),
+    };
+
+    let mut registry = stores::get_db_stores(db).await?;
+    registry.push(candidate.clone());
+    stores::validate(&registry)?;
+
+    let mut store = doc! {
+        "_id": candidate.id,
+        "name": new_store.name,
+        "itemColls": new_store.item_colls,
+        "daySales": [],
+        "owner": [],
+        "employee": [],
+    };
+    if let Some(num) = new_store.num {
+        store.insert("num", num as i32);
+    }
+    if let Some(floor) = new_store.floor {
+        store.insert("floor", floor as i32);
+    }
+
+    stores_coll.insert_one(store).await?;
+
+    tracing::info!(target: "mongodb", "Store created successfully {}.", candidate.id);
+
+    Ok(candidate.id)
+}
+
+#[tracing::instrument(name = "Updating store in DB", skip(db, update))]
+pub async fn update_store(
+    db: &mongodb::Database,
+    store_id: ObjectId,
+    update: StoreUpdate,
+) -> Result<Option<StoreInfo>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let mut registry = stores::get_db_stores(db).await?;
+    let Some(store) = registry.iter_mut().find(|store| store.id == store_id) else {
+        return Ok(None);
+    };
+
+    if let Some(name) = update.name {
+        store.name = name;
+    }
+    if let Some(item_colls) = update.item_colls {
+        store.item_colls = item_colls;
+    }
+    let store = store.clone();
+
+    stores::validate(&registry)?;
+
+    stores_coll.update_one(
+        doc! { "_id": store_id },
+        doc! { "$set": { "name": &store.name, "itemColls": &store.item_colls }},
+    ).await?;
+
+    Ok(Some(store))
+}
+
+/// Returns the stores where a user works, either as an employee or as an owner.
+pub async fn get_staff_stores(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+) -> Result<Vec<StoreInfo>> {
+    let stores_coll: Collection<StoreInfo> = db.collection("store");
+
+    let stores = stores_coll
+        .find(doc! { "$or": [{ "employee": user_id }, { "owner.owner": user_id }]})
+        .projection(doc! { "_id": 1, "name": 1, "itemColls": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(stores)
+}
+
+/// Returns the email and name of everyone working at a store, employees and owners alike.
+pub async fn get_store_staff_contacts(
+    db: &mongodb::Database,
+    store_id: ObjectId,
+) -> Result<Vec<(String, String)>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+    let users_coll: Collection<Document> = db.collection("user");
+
+    let Some(store) = stores_coll
+        .find_one(doc! { "_id": store_id })
+        .projection(doc! { "employee": 1, "owner": 1 })
+        .await? else {
+        return Ok(Vec::new());
+    };
+
+    let mut staff: Vec<ObjectId> = store.get_array("employee")
+        .map(|employees| employees.iter().filter_map(|employee| employee.as_object_id()).collect())
+        .unwrap_or_default();
+    staff.extend(store.get_array("owner")
+        .map(|owners| owners.iter()
+            .filter_map(|owner| owner.as_document())
+            .filter_map(|owner| owner.get_object_id("owner").ok())
+            .collect::<Vec<_>>())
+        .unwrap_or_default());
+
+    let contacts = users_coll
+        .find(doc! { "_id": { "$in": staff }, "isActive": true })
+        .projection(doc! { "email": 1, "name": 1 })
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .into_iter()
+        .filter_map(|user| Some((
+            user.get_str("email").ok()?.to_string(),
+            user.get_str("name").unwrap_or_default().to_string(),
+        )))
+        .collect();
+
+    Ok(contacts)
+}
+
+/// Whether a user is one of the owners of a store.
+pub async fn is_store_owner(db: &mongodb::Database, store_id: ObjectId, user_id: ObjectId) -> Result<bool> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    Ok(stores_coll.find_one(doc! { "_id": store_id, "owner.owner": user_id }).projection(doc! { "_id": 1 }).await?.is_some())
+}
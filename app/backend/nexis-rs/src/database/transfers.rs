@@ -0,0 +1,486 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    inventory::refresh_projection,
+    movements::{ LotMovement, record_movements },
+    orders::commit,
+};
+use crate::types::{
+    error,
+    mongodb::{
+        inventory::{ Lot, MovementKind, StockTransfer, TransferLine, TransferStatus, TransferredLot },
+        stores::StoreInfo,
+    },
+    requests::inventory::{ NewTransfer, TransferDirection, TransferQuery, TransferReceipt, TransferShipment },
+};
+use mongodb::{ ClientSession, IndexModel, options::ReturnDocument };
+
+pub const STOCK_TRANSFERS_COLL: &str = "stockTransfers";
+
+/// Item collections whose lots a store held at some point, each with a filter on
+/// the `lot` of inventory movements matching the lots it held: those of its own
+/// collections, except the ones received by other stores, and the ones of other
+/// stores' collections received by it. Transfers receive units into new lots,
+/// so a lot is only ever held by one store.
+pub async fn get_held_lots(db: &mongodb::Database, store: &StoreInfo) -> Result<Vec<(String, Document)>> {
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    let mut away: HashMap<String, Vec<ObjectId>> = HashMap::new();
+    let mut here: HashMap<String, Vec<ObjectId>> = HashMap::new();
+
+    let mut cursor = transfers_coll.find(doc! {
+        "status": TransferStatus::Received.as_str(),
+        "$or": [{ "to": store.id }, { "lines.coll": { "$in": &store.item_colls }}],
+    }).await?;
+    while let Some(transfer) = cursor.try_next().await? {
+        for line in transfer.lines {
+            let received = line.lots.iter().filter_map(|lot| lot.received_lot);
+            match (store.item_colls.contains(&line.coll), transfer.to == store.id) {
+                (true, false) => away.entry(line.coll).or_default().extend(received),
+                (false, true) => here.entry(line.coll).or_default().extend(received),
+                _ => {}
+            }
+        }
+    }
+
+    let mut held: Vec<(String, Document)> = store.item_colls.iter()
+        .map(|coll| (coll.clone(), doc! { "$nin": away.remove(coll).unwrap_or_default() }))
+        .collect();
+    held.extend(here.into_iter().map(|(coll, lots)| (coll, doc! { "$in": lots })));
+
+    Ok(held)
+}
+
+/// Most lines a transfer can have.
+const MAX_TRANSFER_LINES: usize = 200;
+
+pub async fn ensure_stock_transfer_indexes(db: &mongodb::Database) -> Result<()> {
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    transfers_coll.create_index(IndexModel::builder().keys(doc! { "from": 1, "status": 1, "requestedAt": -1 }).build()).await?;
+    transfers_coll.create_index(IndexModel::builder().keys(doc! { "to": 1, "status": 1, "requestedAt": -1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Asks another store for units of some items.
+#[tracing::instrument(name = "Requesting stock transfer", skip(db, to, new_transfer), fields(to = %to.name))]
+pub async fn request_transfer(
+    db: &mongodb::Database,
+    to: &StoreInfo,
+    by: ObjectId,
+    new_transfer: NewTransfer,
+) -> Result<StockTransfer> {
+    let Some(from) = stores::get_store(&new_transfer.from) else {
+        bail!(error::Inventory::InvalidTransfer(format!("Store {} not found.", new_transfer.from)));
+    };
+    if from.id == to.id {
+        bail!(error::Inventory::InvalidTransfer("A store can't transfer units to itself.".into()));
+    }
+    if new_transfer.lines.is_empty() || new_transfer.lines.len() > MAX_TRANSFER_LINES {
+        bail!(error::Inventory::InvalidTransfer(format!("A transfer must have between 1 and {} lines.", MAX_TRANSFER_LINES)));
+    }
+
+    let mut lines = Vec::with_capacity(new_transfer.lines.len());
+    for line in new_transfer.lines {
+        if !stores::is_item_coll(&line.coll) {
+            bail!(error::Inventory::InvalidTransfer(format!("`{}` isn't an item collection.", line.coll)));
+        }
+        if line.quantity == 0 {
+            bail!(error::Inventory::InvalidTransfer("The quantity of a line can't be 0.".into()));
+        }
+
+        let item_coll: Collection<Document> = db.collection(&line.coll);
+        let Some(item) = item_coll.find_one(doc! { "_id": line.item }).projection(doc! { "name": 1, "archived": 1 }).await? else {
+            bail!(error::Inventory::InvalidTransfer(format!("Item {} of `{}` not found.", line.item, line.coll)));
+        };
+        if item.get_bool("archived").unwrap_or(false) {
+            bail!(error::Inventory::InvalidTransfer(format!("`{}` is archived.", item.get_str("name").unwrap_or_default())));
+        }
+
+        lines.push(TransferLine {
+            id: ObjectId::new(),
+            name: item.get_str("name").unwrap_or_default().to_string(),
+            coll: line.coll,
+            item: line.item,
+            quantity: line.quantity,
+            lots: Vec::new(),
+        });
+    }
+
+    let transfer = StockTransfer {
+        id: ObjectId::new(),
+        from: from.id,
+        to: to.id,
+        status: TransferStatus::Requested,
+        lines,
+        notes: new_transfer.notes,
+        lost: Vec::new(),
+        requested_by: by,
+        requested_at: bson::DateTime::now(),
+        shipped_by: None,
+        shipped_at: None,
+        received_by: None,
+        received_at: None,
+        cancelled_by: None,
+        cancelled_at: None,
+    };
+
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+    transfers_coll.insert_one(&transfer).await?;
+
+    tracing::info!(target: "mongodb", "Stock transfer {} from `{}` to `{}` requested.", transfer.id, from.name, to.name);
+
+    Ok(transfer)
+}
+
+pub async fn get_transfer(db: &mongodb::Database, transfer_id: ObjectId) -> Result<Option<StockTransfer>> {
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+    Ok(transfers_coll.find_one(doc! { "_id": transfer_id }).await?)
+}
+
+/// Returns the transfers sent or received by a store, newest first.
+pub async fn get_store_transfers(db: &mongodb::Database, store: &StoreInfo, query: &TransferQuery) -> Result<Vec<StockTransfer>> {
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    let mut filter = match query.direction {
+        Some(TransferDirection::Incoming) => doc! { "to": store.id },
+        Some(TransferDirection::Outgoing) => doc! { "from": store.id },
+        None => doc! { "$or": [{ "from": store.id }, { "to": store.id }] },
+    };
+    if let Some(status) = query.status {
+        filter.insert("status", status.as_str());
+    }
+
+    let transfers = transfers_coll
+        .find(filter)
+        .sort(doc! { "requestedAt": -1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(transfers)
+}
+
+/// Ships the units of a transfer: every scanned code is pulled from its lot at
+/// the sending store and recorded in the inventory ledger as transferred out.
+/// Lines can be shipped short, or not at all.
+#[tracing::instrument(name = "Shipping stock transfer", skip(db, shipment))]
+pub async fn ship_transfer(
+    db: &mongodb::Database,
+    transfer_id: ObjectId,
+    by: ObjectId,
+    shipment: TransferShipment,
+) -> Result<StockTransfer> {
+    let Some(mut transfer) = get_transfer(db, transfer_id).await? else {
+        bail!(error::Inventory::TransferNotFound);
+    };
+    if transfer.status != TransferStatus::Requested {
+        bail!(error::Inventory::TransferState(transfer.status.as_str().into()));
+    }
+    let Some(from) = stores::get_store(&transfer.from) else {
+        bail!(error::Inventory::InvalidTransfer("The sending store no longer exists.".into()));
+    };
+
+    let mut scanned = HashSet::new();
+    let mut lines = HashSet::new();
+    for shipped in &shipment.lines {
+        if !lines.insert(shipped.line) {
+            bail!(error::Inventory::InvalidTransfer(format!("Line {} was shipped twice.", shipped.line)));
+        }
+        let Some(line) = transfer.lines.iter().find(|line| line.id == shipped.line) else {
+            bail!(error::Inventory::InvalidTransfer(format!("Line {} isn't in this transfer.", shipped.line)));
+        };
+        if shipped.codes.len() > line.quantity as usize {
+            bail!(error::Inventory::InvalidTransfer(format!("Only {} units of `{}` were requested.", line.quantity, line.name)));
+        }
+        for code in &shipped.codes {
+            if !scanned.insert(*code) {
+                bail!(error::Inventory::InvalidTransfer(format!("Unit {} was scanned twice.", code)));
+            }
+        }
+    }
+    if scanned.is_empty() {
+        bail!(error::Inventory::InvalidTransfer("No units were shipped.".into()));
+    }
+
+    let now = bson::DateTime::now();
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        for shipped in shipment.lines.iter().filter(|shipped| !shipped.codes.is_empty()) {
+            let Some(line) = transfer.lines.iter_mut().find(|line| line.id == shipped.line) else { continue };
+            line.lots = ship_units(db, &mut session, &from, line, &shipped.codes, (transfer_id, by), now).await?;
+        }
+
+        let res = transfers_coll.update_one(
+            doc! { "_id": transfer_id, "status": TransferStatus::Requested.as_str() },
+            doc! { "$set": {
+                "status": TransferStatus::Shipped.as_str(),
+                "lines": bson::to_bson(&transfer.lines)?,
+                "shippedBy": by,
+                "shippedAt": now,
+            }},
+        )
+        .session(&mut session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Inventory::TransferState("being updated".into()));
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    for line in transfer.lines.iter().filter(|line| !line.lots.is_empty()) {
+        refresh_projection(db, &line.coll, line.item).await;
+    }
+
+    tracing::info!(target: "mongodb", "Stock transfer {} shipped with {} units.", transfer_id, scanned.len());
+
+    get_transfer(db, transfer_id).await?.ok_or_else(|| anyhow!(error::Inventory::TransferNotFound))
+}
+
+/// Pulls the shipped units of a line from the lots of the sending store.
+async fn ship_units(
+    db: &mongodb::Database,
+    session: &mut ClientSession,
+    from: &StoreInfo,
+    line: &TransferLine,
+    codes: &[ObjectId],
+    (transfer_id, by): (ObjectId, ObjectId),
+    now: bson::DateTime,
+) -> Result<Vec<TransferredLot>> {
+    let item_coll: Collection<Document> = db.collection(&line.coll);
+    let own = from.item_colls.contains(&line.coll);
+
+    let Some(item) = item_coll.find_one(doc! { "_id": line.item }).projection(doc! { "lot": 1 }).session(&mut *session).await? else {
+        bail!(error::Inventory::ItemNotFound);
+    };
+
+    let lots: Vec<Lot> = item.get_array("lot")
+        .map(|lots| lots.iter()
+            .filter_map(|lot| lot.as_document())
+            .filter_map(|lot| Lot::from_doc(lot).ok())
+            .filter(|lot| if own { lot.store.is_none() } else { lot.store == Some(from.id) })
+            .collect())
+        .unwrap_or_default();
+
+    let mut shipped: Vec<TransferredLot> = Vec::new();
+    for code in codes {
+        let Some(lot) = lots.iter().find(|lot| lot.code.contains(&bson::Bson::ObjectId(*code))) else {
+            bail!(error::Inventory::InvalidTransfer(format!("Unit {} of `{}` isn't in stock at `{}`.", code, line.name, from.name)));
+        };
+        if lot.expiry.is_some_and(|expiry| expiry <= now) {
+            bail!(error::Inventory::InvalidTransfer(format!("Unit {} of `{}` is expired.", code, line.name)));
+        }
+
+        match shipped.iter_mut().find(|shipped| shipped.lot == lot.id) {
+            Some(shipped) => shipped.codes.push(*code),
+            None => shipped.push(TransferredLot {
+                lot: lot.id,
+                enter_date: lot.enter_date,
+                expiry: lot.expiry,
+                cost_price: lot.cost_price,
+                codes: vec![*code],
+                received_lot: None,
+            }),
+        }
+    }
+
+    for lot in &shipped {
+        let codes: Vec<bson::Bson> = lot.codes.iter().map(|code| bson::Bson::ObjectId(*code)).collect();
+
+        // Only pull the codes if they're all still in the lot
+        let res = item_coll.update_one(
+            doc! {
+                "_id": line.item,
+                "lot": { "$elemMatch": { "_id": lot.lot, "code": { "$all": &codes }}},
+            },
+            doc! { "$pull": { "lot.$[lot].code": { "$in": &codes }}},
+        )
+        .array_filters(vec![doc! { "lot._id": lot.lot }])
+        .session(&mut *session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Inventory::Changed);
+        }
+
+        record_movements(db, session, LotMovement {
+            kind: MovementKind::TransferredOut,
+            coll: &line.coll,
+            item: line.item,
+            lot: lot.lot,
+            codes: &codes,
+            by: Some(by),
+            reason: None,
+            reference: Some(transfer_id),
+        }).await?;
+    }
+
+    Ok(shipped)
+}
+
+/// Receives a shipped transfer: the units of each lot shipped go into a new lot
+/// of the item, with the same dates and cost price, held by the receiving store.
+/// Units reported as lost stay out of stock.
+#[tracing::instrument(name = "Receiving stock transfer", skip(db, receipt))]
+pub async fn receive_transfer(
+    db: &mongodb::Database,
+    transfer_id: ObjectId,
+    by: ObjectId,
+    receipt: TransferReceipt,
+) -> Result<StockTransfer> {
+    let Some(mut transfer) = get_transfer(db, transfer_id).await? else {
+        bail!(error::Inventory::TransferNotFound);
+    };
+    if transfer.status != TransferStatus::Shipped {
+        bail!(error::Inventory::TransferState(transfer.status.as_str().into()));
+    }
+    let Some(to) = stores::get_store(&transfer.to) else {
+        bail!(error::Inventory::InvalidTransfer("The receiving store no longer exists.".into()));
+    };
+
+    let shipped: HashSet<ObjectId> = transfer.lines.iter()
+        .flat_map(|line| line.lots.iter().flat_map(|lot| lot.codes.iter().copied()))
+        .collect();
+    if let Some(code) = receipt.lost.iter().find(|code| !shipped.contains(code)) {
+        bail!(error::Inventory::InvalidTransfer(format!("Unit {} wasn't shipped by this transfer.", code)));
+    }
+    let lost: HashSet<ObjectId> = receipt.lost.iter().copied().collect();
+
+    let now = bson::DateTime::now();
+
+    // Lots received by the store, by line
+    let mut received: Vec<(usize, Lot)> = Vec::new();
+    for (i, line) in transfer.lines.iter_mut().enumerate() {
+        for shipped_lot in &mut line.lots {
+            let codes: Vec<bson::Bson> = shipped_lot.codes.iter()
+                .filter(|code| !lost.contains(code))
+                .map(|code| bson::Bson::ObjectId(*code))
+                .collect();
+            if codes.is_empty() {
+                continue;
+            }
+
+            let lot = Lot {
+                id: ObjectId::new(),
+                enter_date: shipped_lot.enter_date.or(Some(now)),
+                expiry: shipped_lot.expiry,
+                cost_price: shipped_lot.cost_price,
+                // Units back at the store selling the item are part of its stock again
+                store: if to.item_colls.contains(&line.coll) { None } else { Some(to.id) },
+                code: codes,
+            };
+            shipped_lot.received_lot = Some(lot.id);
+            received.push((i, lot));
+        }
+    }
+
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    let mut session = db.client().start_session().await?;
+    session.start_transaction().await?;
+
+    let result = async {
+        let res = transfers_coll.update_one(
+            doc! { "_id": transfer_id, "status": TransferStatus::Shipped.as_str() },
+            doc! { "$set": {
+                "status": TransferStatus::Received.as_str(),
+                "lines": bson::to_bson(&transfer.lines)?,
+                "lost": &receipt.lost,
+                "receivedBy": by,
+                "receivedAt": now,
+            }},
+        )
+        .session(&mut session)
+        .await?;
+
+        if res.modified_count != 1 {
+            bail!(error::Inventory::TransferState("being updated".into()));
+        }
+
+        for (i, lot) in &received {
+            let line = &transfer.lines[*i];
+            let item_coll: Collection<Document> = db.collection(&line.coll);
+
+            let res = item_coll.update_one(
+                doc! { "_id": line.item },
+                doc! { "$push": { "lot": bson::to_bson(lot)? }},
+            )
+            .session(&mut session)
+            .await?;
+
+            if res.matched_count != 1 {
+                bail!(error::Inventory::ItemNotFound);
+            }
+
+            record_movements(db, &mut session, LotMovement {
+                kind: MovementKind::TransferredIn,
+                coll: &line.coll,
+                item: line.item,
+                lot: lot.id,
+                codes: &lot.code,
+                by: Some(by),
+                reason: None,
+                reference: Some(transfer_id),
+            }).await?;
+        }
+
+        commit(&mut session).await
+    }.await;
+
+    if let Err(e) = result {
+        let _ = session.abort_transaction().await;
+        return Err(e);
+    }
+
+    for line in transfer.lines.iter().filter(|line| !line.lots.is_empty()) {
+        refresh_projection(db, &line.coll, line.item).await;
+    }
+
+    if lost.is_empty() {
+        tracing::info!(target: "mongodb", "Stock transfer {} received at `{}`.", transfer_id, to.name);
+    } else {
+        tracing::warn!(target: "mongodb", "Stock transfer {} received at `{}` with {} units lost.", transfer_id, to.name, lost.len());
+    }
+
+    get_transfer(db, transfer_id).await?.ok_or_else(|| anyhow!(error::Inventory::TransferNotFound))
+}
+
+/// Cancels a transfer which wasn't shipped yet.
+#[tracing::instrument(name = "Cancelling stock transfer", skip(db))]
+pub async fn cancel_transfer(db: &mongodb::Database, transfer_id: ObjectId, by: ObjectId) -> Result<StockTransfer> {
+    let transfers_coll: Collection<StockTransfer> = db.collection(STOCK_TRANSFERS_COLL);
+
+    let transfer = transfers_coll.find_one_and_update(
+        doc! { "_id": transfer_id, "status": TransferStatus::Requested.as_str() },
+        doc! { "$set": {
+            "status": TransferStatus::Cancelled.as_str(),
+            "cancelledBy": by,
+            "cancelledAt": bson::DateTime::now(),
+        }},
+    )
+    .return_document(ReturnDocument::After)
+    .await?;
+
+    match transfer {
+        Some(transfer) => {
+            tracing::info!(target: "mongodb", "Stock transfer {} cancelled.", transfer_id);
+            Ok(transfer)
+        }
+        None => match get_transfer(db, transfer_id).await? {
+            Some(transfer) => bail!(error::Inventory::TransferState(transfer.status.as_str().into())),
+            None => bail!(error::Inventory::TransferNotFound),
+        },
+    }
+}
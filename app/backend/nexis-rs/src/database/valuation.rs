@@ -0,0 +1,469 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::database::{
+    movements::{ MOVEMENTS_COLL, get_lot_costs },
+    transfers::get_held_lots,
+    orders::ORDERS_COLL,
+    returns::RETURNS_COLL,
+};
+use crate::types::{
+    error,
+    mongodb::{
+        inventory::{ Movement, MovementKind },
+        orders::{ Order, OrderStatus },
+        returns::{ ReturnRecord, ReturnStatus },
+        stores::{ DaySale, StoreInfo },
+    },
+    requests::inventory::{ MarginQuery, ValuationMethod, ValuationQuery },
+    responses::{ ItemMargin, ItemValuation, MarginReport, ValuationReport },
+};
+
+/// Days covered by a margin report without a date range.
+const DEFAULT_MARGIN_DAYS: i64 = 30;
+
+/// Values the units a store had in stock at some time, rebuilt from the
+/// inventory ledger. With FIFO the units left of every item are valued against
+/// the cost layers it received, newest first, as if the oldest units had always
+/// left first. With weighted average they're valued at the average cost of
+/// every unit of the item received until then.
+#[tracing::instrument(name = "Valuing inventory", skip(db, store), fields(store = %store.name))]
+pub async fn get_valuation(db: &mongodb::Database, store: &StoreInfo, query: &ValuationQuery) -> Result<ValuationReport> {
+    let method = query.method.unwrap_or_default();
+    let at = query.at.map(|at| bson::DateTime::from_millis(at.timestamp_millis())).unwrap_or_else(bson::DateTime::now);
+
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    let mut items = Vec::new();
+
+    for (coll, lots) in &get_held_lots(db, store).await? {
+        // Units in stock by item
+        let in_stock: Vec<Document> = movements_coll
+            .aggregate(vec![
+                doc! { "$match": { "coll": coll, "lot": lots, "at": { "$lt": at }}},
+                doc! { "$group": {
+                    "_id": { "item": "$item", "lot": "$lot", "code": "$code" },
+                    "balance": { "$sum": "$quantity" },
+                }},
+                doc! { "$match": { "balance": { "$gt": 0 }}},
+                doc! { "$group": { "_id": "$_id.item", "units": { "$sum": "$balance" }}},
+            ])
+            .await?
+            .try_collect()
+            .await?;
+
+        if in_stock.is_empty() {
+            continue;
+        }
+
+        let cost_layers = get_cost_layers(db, coll, lots, at).await?;
+
+        let ids: Vec<ObjectId> = in_stock.iter().filter_map(|item| item.get_object_id("_id").ok()).collect();
+        let names = get_item_names(db, coll, &ids).await?;
+
+        for item in in_stock {
+            let Ok(item_id) = item.get_object_id("_id") else { continue };
+            let units = get_number(&item, "units") as i64;
+
+            let (value, uncosted_units) = match method {
+                ValuationMethod::Fifo => value_fifo(units, cost_layers.get(&item_id).map(Vec::as_slice).unwrap_or_default()),
+                ValuationMethod::WeightedAverage => match cost_layers.get(&item_id).and_then(|layers| average_cost(layers)) {
+                    Some(average) => (average * units as f64, 0),
+                    None => (0.0, units),
+                },
+            };
+            let costed_units = units - uncosted_units;
+
+            items.push(ItemValuation {
+                coll: coll.clone(),
+                item: item_id,
+                name: names.get(&item_id).cloned().unwrap_or_default(),
+                units,
+                value: round_money(value),
+                unit_cost: (costed_units > 0).then(|| round_money(value / costed_units as f64)),
+                uncosted_units,
+            });
+        }
+    }
+
+    items.sort_by(|a, b| b.value.total_cmp(&a.value));
+
+    Ok(ValuationReport {
+        store: store.name.clone(),
+        method,
+        at,
+        units: items.iter().map(|item| item.units).sum(),
+        value: round_money(items.iter().map(|item| item.value).sum()),
+        uncosted_units: items.iter().map(|item| item.uncosted_units).sum(),
+        items,
+    })
+}
+
+/// A number of units received of an item, and what each cost if it's known.
+struct CostLayer {
+    units: i64,
+    cost: Option<f64>,
+}
+
+/// Values units against the cost layers of their item, newest first. Units
+/// beyond the layers, or taken from ones without a cost, are left uncosted.
+fn value_fifo(units: i64, layers: &[CostLayer]) -> (f64, i64) {
+    let mut left = units;
+    let mut value = 0.0;
+    let mut uncosted_units = 0;
+
+    for layer in layers {
+        if left == 0 {
+            break;
+        }
+        let taken = layer.units.min(left);
+        match layer.cost {
+            Some(cost) => value += cost * taken as f64,
+            None => uncosted_units += taken,
+        }
+        left -= taken;
+    }
+
+    (value, uncosted_units + left)
+}
+
+/// Average cost of the units of an item received in some layers, if any had a cost.
+fn average_cost(layers: &[CostLayer]) -> Option<f64> {
+    let (cost, units) = layers.iter()
+        .filter_map(|layer| Some((layer.cost? * layer.units as f64, layer.units)))
+        .fold((0.0, 0), |(cost, units), (layer_cost, layer_units)| (cost + layer_cost, units + layer_units));
+    (units > 0).then(|| cost / units as f64)
+}
+
+/// Units received into some lots of every item of a collection until some
+/// time, by lot, newest first. Entries recorded before the ledger kept costs
+/// are costed at the cost price of their lot, if it still has one.
+async fn get_cost_layers(
+    db: &mongodb::Database,
+    coll: &str,
+    lots: &Document,
+    at: bson::DateTime,
+) -> Result<HashMap<ObjectId, Vec<CostLayer>>> {
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+
+    let mut cursor = movements_coll
+        .aggregate(vec![
+            doc! { "$match": {
+                "coll": coll,
+                "lot": lots,
+                "kind": { "$in": [
+                    MovementKind::Opening.as_str(), MovementKind::Received.as_str(), MovementKind::TransferredIn.as_str(),
+                ]},
+                "at": { "$lt": at },
+            }},
+            doc! { "$group": {
+                "_id": { "item": "$item", "lot": "$lot" },
+                "units": { "$sum": "$quantity" },
+                "cost": { "$max": "$cost" },
+                "at": { "$max": "$at" },
+            }},
+            doc! { "$sort": { "at": -1, "_id.lot": -1 }},
+        ])
+        .await?;
+
+    let mut received = Vec::new();
+    while let Some(layer) = cursor.try_next().await? {
+        let Ok(id) = layer.get_document("_id") else { continue };
+        let (Ok(item_id), Ok(lot_id)) = (id.get_object_id("item"), id.get_object_id("lot")) else { continue };
+        let cost = match layer.get("cost") {
+            None | Some(bson::Bson::Null) => None,
+            Some(_) => Some(get_number(&layer, "cost")),
+        };
+        received.push((item_id, lot_id, CostLayer { units: get_number(&layer, "units") as i64, cost }));
+    }
+
+    let uncosted: Vec<ObjectId> = received.iter().filter(|(_, _, layer)| layer.cost.is_none()).map(|(_, lot, _)| *lot).collect();
+    let lot_costs = get_lot_costs(db, coll, &uncosted).await?;
+
+    let mut layers: HashMap<ObjectId, Vec<CostLayer>> = HashMap::new();
+    for (item_id, lot_id, mut layer) in received {
+        layer.cost = layer.cost.or_else(|| lot_costs.get(&lot_id).copied());
+        layers.entry(item_id).or_default().push(layer);
+    }
+
+    Ok(layers)
+}
+
+/// Revenue, cost of goods sold and gross margin of a store over a date range.
+/// Register sales count when they're charged, and orders when they're paid,
+/// unless they were cancelled or refunded since. Approved returns take their
+/// refund off the revenue, and the cost of the units restocked off the cost of
+/// goods sold. The cost of a unit is the one of its lot when it left stock, as
+/// recorded in the inventory ledger.
+#[tracing::instrument(name = "Computing gross margin", skip(db, store), fields(store = %store.name))]
+pub async fn get_margin_report(db: &mongodb::Database, store: &StoreInfo, query: &MarginQuery) -> Result<MarginReport> {
+    let to = query.to.map(|to| bson::DateTime::from_millis(to.timestamp_millis())).unwrap_or_else(bson::DateTime::now);
+    let from = query.from
+        .map(|from| bson::DateTime::from_millis(from.timestamp_millis()))
+        .unwrap_or_else(|| bson::DateTime::from_millis(to.timestamp_millis() - DEFAULT_MARGIN_DAYS * 24 * 60 * 60 * 1000));
+
+    if from >= to {
+        bail!(error::Inventory::Invalid("The start of the range must be before its end.".into()));
+    }
+
+    let mut items: HashMap<(String, ObjectId), ItemMargin> = HashMap::new();
+
+    // Register sales
+    let sales = get_day_sales(db, store, from, to).await?;
+    let costs = get_unit_costs(db, MovementKind::Sold, sales.iter().map(|sale| sale.id).collect()).await?;
+    for sale in &sales {
+        for unit in &sale.item {
+            let line = item_margin(&mut items, &unit.coll, unit.item, &unit.name);
+            line.units += 1;
+            line.revenue += unit.price;
+            match costs.get(&(sale.id, bson::Bson::ObjectId(unit.code).to_string())) {
+                Some(cost) => line.cogs += cost,
+                None => line.uncosted_units += 1,
+            }
+        }
+    }
+
+    // Orders paid, whose units were taken out of stock when they were reserved
+    let orders = get_paid_orders(db, store, from, to).await?;
+    let costs = get_unit_costs(db, MovementKind::Reserved, orders.iter().map(|order| order.parent.unwrap_or(order.id)).collect()).await?;
+    for order in &orders {
+        let reference = order.parent.unwrap_or(order.id);
+        for order_item in &order.items {
+            let line = item_margin(&mut items, &order_item.coll, order_item.item, &order_item.name);
+            line.units += order_item.units.len() as i64;
+            line.revenue += order_item.total;
+            for unit in &order_item.units {
+                match costs.get(&(reference, unit.code.to_string())) {
+                    Some(cost) => line.cogs += cost,
+                    None => line.uncosted_units += 1,
+                }
+            }
+        }
+    }
+
+    // Returns, whose restocked units are worth their cost again
+    let returns = get_processed_returns(db, store, from, to).await?;
+    let costs = get_unit_costs(db, MovementKind::Returned, returns.iter().map(|record| record.id).collect()).await?;
+    for record in &returns {
+        for unit in record.units.iter().filter(|unit| store.item_colls.contains(&unit.coll)) {
+            let line = item_margin(&mut items, &unit.coll, unit.item, &unit.name);
+            line.units -= 1;
+            line.revenue -= unit.price;
+            if let Some(cost) = costs.get(&(record.id, unit.code.to_string())) {
+                line.cogs -= cost;
+            }
+        }
+    }
+
+    let mut items: Vec<ItemMargin> = items.into_values()
+        .map(|mut item| {
+            item.revenue = round_money(item.revenue);
+            item.cogs = round_money(item.cogs);
+            item.margin = round_money(item.revenue - item.cogs);
+            item
+        })
+        .collect();
+    items.sort_by(|a, b| b.margin.total_cmp(&a.margin));
+
+    let revenue = round_money(items.iter().map(|item| item.revenue).sum());
+    let cogs = round_money(items.iter().map(|item| item.cogs).sum());
+    let gross_margin = round_money(revenue - cogs);
+
+    Ok(MarginReport {
+        store: store.name.clone(),
+        from,
+        to,
+        revenue,
+        cogs,
+        gross_margin,
+        margin_percent: (revenue > 0.0).then(|| (gross_margin / revenue * 10_000.0).round() / 100.0),
+        uncosted_units: items.iter().map(|item| item.uncosted_units).sum(),
+        items,
+    })
+}
+
+fn item_margin<'a>(
+    items: &'a mut HashMap<(String, ObjectId), ItemMargin>,
+    coll: &str,
+    item: ObjectId,
+    name: &str,
+) -> &'a mut ItemMargin {
+    items.entry((coll.to_string(), item)).or_insert_with(|| ItemMargin {
+        coll: coll.to_string(),
+        item,
+        name: name.to_string(),
+        units: 0,
+        revenue: 0.0,
+        cogs: 0.0,
+        margin: 0.0,
+        uncosted_units: 0,
+    })
+}
+
+async fn get_day_sales(db: &mongodb::Database, store: &StoreInfo, from: bson::DateTime, to: bson::DateTime) -> Result<Vec<DaySale>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let sales: Vec<Document> = stores_coll
+        .aggregate(vec![
+            doc! { "$match": { "_id": store.id }},
+            doc! { "$unwind": "$daySales" },
+            doc! { "$match": { "daySales.timestamp": { "$gte": from, "$lt": to }}},
+            doc! { "$replaceRoot": { "newRoot": "$daySales" }},
+        ])
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(sales.into_iter().map(bson::from_document).collect::<Result<_, _>>()?)
+}
+
+/// The store's parts of the orders paid within the range, which weren't cancelled or refunded since.
+async fn get_paid_orders(db: &mongodb::Database, store: &StoreInfo, from: bson::DateTime, to: bson::DateTime) -> Result<Vec<Order>> {
+    let orders_coll: Collection<Order> = db.collection(ORDERS_COLL);
+    let sold: Vec<&str> = [OrderStatus::Paid, OrderStatus::Preparing, OrderStatus::Ready, OrderStatus::Completed]
+        .iter()
+        .map(|status| status.as_str())
+        .collect();
+
+    let orders = orders_coll
+        .find(doc! {
+            "store": store.id,
+            "status": { "$in": sold },
+            "history": { "$elemMatch": { "status": OrderStatus::Paid.as_str(), "at": { "$gte": from, "$lt": to }}},
+        })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(orders)
+}
+
+async fn get_processed_returns(db: &mongodb::Database, store: &StoreInfo, from: bson::DateTime, to: bson::DateTime) -> Result<Vec<ReturnRecord>> {
+    let returns_coll: Collection<ReturnRecord> = db.collection(RETURNS_COLL);
+
+    let returns = returns_coll
+        .find(doc! {
+            "status": { "$in": [ReturnStatus::Approved.as_str(), ReturnStatus::Refunded.as_str()] },
+            "processedAt": { "$gte": from, "$lt": to },
+            "units.coll": { "$in": &store.item_colls },
+        })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(returns)
+}
+
+/// Cost of the units some sales, orders or returns moved, by reference and unit
+/// code. Entries recorded before the ledger kept costs are costed at the cost
+/// price of their lot, if it still has one.
+async fn get_unit_costs(
+    db: &mongodb::Database,
+    kind: MovementKind,
+    references: Vec<ObjectId>,
+) -> Result<HashMap<(ObjectId, String), f64>> {
+    if references.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let movements_coll: Collection<Movement> = db.collection(MOVEMENTS_COLL);
+    let mut cursor = movements_coll
+        .find(doc! { "kind": kind.as_str(), "reference": { "$in": references }})
+        .await?;
+
+    let mut costs = HashMap::new();
+    let mut uncosted: HashMap<String, Vec<Movement>> = HashMap::new();
+    while let Some(movement) = cursor.try_next().await? {
+        let Some(reference) = movement.reference else { continue };
+        match movement.cost {
+            Some(cost) => { costs.insert((reference, movement.code.to_string()), cost); }
+            None => uncosted.entry(movement.coll.clone()).or_default().push(movement),
+        }
+    }
+
+    for (coll, movements) in uncosted {
+        let lots: Vec<ObjectId> = movements.iter().map(|movement| movement.lot).collect();
+        let lot_costs = get_lot_costs(db, &coll, &lots).await?;
+        for movement in movements {
+            if let (Some(reference), Some(cost)) = (movement.reference, lot_costs.get(&movement.lot)) {
+                costs.insert((reference, movement.code.to_string()), *cost);
+            }
+        }
+    }
+
+    Ok(costs)
+}
+
+async fn get_item_names(db: &mongodb::Database, coll: &str, ids: &[ObjectId]) -> Result<HashMap<ObjectId, String>> {
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    let items: Vec<Document> = item_coll
+        .find(doc! { "_id": { "$in": ids }})
+        .projection(doc! { "name": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(items.iter()
+        .filter_map(|item| Some((item.get_object_id("_id").ok()?, item.get_str("name").ok()?.to_string())))
+        .collect())
+}
+
+/// Numbers summed by an aggregation can come back as any numeric type.
+fn get_number(doc: &Document, key: &str) -> f64 {
+    match doc.get(key) {
+        Some(bson::Bson::Double(number)) => *number,
+        Some(bson::Bson::Int32(number)) => *number as f64,
+        Some(bson::Bson::Int64(number)) => *number as f64,
+        _ => 0.0,
+    }
+}
+
+fn round_money(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(units: i64, cost: Option<f64>) -> CostLayer {
+        CostLayer { units, cost }
+    }
+
+    #[test]
+    fn fifo_takes_the_newest_layers_first() {
+        let layers = [layer(10, Some(3.0)), layer(10, Some(2.0)), layer(10, Some(1.0))];
+
+        assert_eq!(value_fifo(5, &layers), (15.0, 0));
+        assert_eq!(value_fifo(10, &layers), (30.0, 0));
+        assert_eq!(value_fifo(15, &layers), (40.0, 0));
+        assert_eq!(value_fifo(30, &layers), (60.0, 0));
+    }
+
+    #[test]
+    fn fifo_leaves_units_beyond_the_layers_uncosted() {
+        let layers = [layer(4, Some(2.5))];
+
+        assert_eq!(value_fifo(6, &layers), (10.0, 2));
+        assert_eq!(value_fifo(3, &[]), (0.0, 3));
+        assert_eq!(value_fifo(0, &layers), (0.0, 0));
+    }
+
+    #[test]
+    fn fifo_counts_units_of_layers_without_a_cost() {
+        let layers = [layer(2, Some(5.0)), layer(3, None), layer(4, Some(1.0))];
+
+        assert_eq!(value_fifo(4, &layers), (10.0, 2));
+        assert_eq!(value_fifo(9, &layers), (14.0, 3));
+        assert_eq!(value_fifo(12, &layers), (14.0, 6));
+    }
+
+    #[test]
+    fn average_cost_weighs_layers_by_units() {
+        let layers = [layer(1, Some(10.0)), layer(3, Some(2.0)), layer(5, None)];
+
+        assert_eq!(average_cost(&layers), Some(4.0));
+        assert_eq!(average_cost(&[layer(5, None)]), None);
+        assert_eq!(average_cost(&[]), None);
+    }
+}
@@ -6,6 +6,12 @@ pub mod utils;
 pub mod types;
 pub mod database;
 pub mod prelude;
+pub mod search;
+pub mod stores;
+pub mod payments;
+pub mod receipts;
+pub mod alerts;
+pub mod catalog;
 
 use once_cell::sync::Lazy;
 use std::{path::Path, fs};
@@ -32,5 +38,6 @@ fn load_templates_from_directory(env: &mut Environment<'static>, dir: &Path) ->
 pub static ENV: Lazy<minijinja::Environment<'static>> = Lazy::new(|| {
     let mut env = Environment::new();
     load_templates_from_directory(&mut env, Path::new("templates")).unwrap();
+    env.add_filter("money", |amount: f64| format!("{:.2}", amount));
     env
 });
\ No newline at end of file
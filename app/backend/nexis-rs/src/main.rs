@@ -1,9 +1,30 @@
+use futures_util::StreamExt;
+use nexis_rs::types::requests::catalog::CatalogFormat;
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+
+const USAGE: &str = "\
+Usage:
+    nexis-rs
+    nexis-rs import <collection> <file> [--format csv|ndjson] [--dry-run]
+    nexis-rs export <collection> [--format csv|ndjson] [--archived] [--out <file>]";
+
+/// Size of the chunks files are imported in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
 
     let settings = nexis_rs::settings::get_settings().expect("Failed to read settings.");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        return match command.as_str() {
+            "import" | "export" => catalog_command(settings, &args).await,
+            _ => Err(usage_error(format!("Unknown command `{}`.", command))),
+        };
+    }
+
     let base_url = settings.application.base_url.clone();
 
     let subscriber = nexis_rs::telemetry::get_subscriber(settings.clone().debug);
@@ -18,4 +39,93 @@ async fn main() -> std::io::Result<()> {
     application.run_until_stopped().await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Imports a file of items into a collection, or exports one, without starting
+/// the server. Exports are written to stdout unless given a file.
+async fn catalog_command(settings: nexis_rs::settings::Settings, args: &[String]) -> std::io::Result<()> {
+    let mut positional = Vec::new();
+    let mut format = None;
+    let mut dry_run = false;
+    let mut archived = false;
+    let mut out = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--format" => format = Some(match rest.next().map(String::as_str) {
+                Some("csv") => CatalogFormat::Csv,
+                Some("ndjson") => CatalogFormat::Ndjson,
+                _ => return Err(usage_error("The format is `csv` or `ndjson`.")),
+            }),
+            "--dry-run" => dry_run = true,
+            "--archived" => archived = true,
+            "--out" => out = Some(rest.next().ok_or_else(|| usage_error("Missing the file to export to."))?.clone()),
+            flag if flag.starts_with("--") => return Err(usage_error(format!("Unknown option `{}`.", flag))),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let db = nexis_rs::startup::connect_database(&settings).await;
+    nexis_rs::stores::load(&db, &settings).await.map_err(std::io::Error::other)?;
+
+    match (args[0].as_str(), positional.as_slice()) {
+        ("import", [coll, file]) => {
+            check_coll(coll)?;
+            nexis_rs::database::ensure_catalog_indexes(&db).await.map_err(std::io::Error::other)?;
+            let format = format.unwrap_or(
+                if file.ends_with(".ndjson") || file.ends_with(".jsonl") { CatalogFormat::Ndjson } else { CatalogFormat::Csv }
+            );
+
+            let file = tokio::fs::File::open(file).await?;
+            let chunks = futures_util::stream::unfold(file, |mut file| async move {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                match file.read(&mut chunk).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        Some((Ok(chunk), file))
+                    }
+                    Err(e) => Some((Err(e), file)),
+                }
+            });
+
+            let report = nexis_rs::catalog::import_items(&db, coll, format, dry_run, None, Box::pin(chunks)).await
+                .map_err(std::io::Error::other)?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+
+            if report.failed > 0 {
+                return Err(std::io::Error::other(format!("{} rows failed.", report.failed)));
+            }
+            Ok(())
+        }
+        ("export", [coll]) => {
+            check_coll(coll)?;
+            let format = format.unwrap_or_default();
+
+            let mut rows = nexis_rs::catalog::export_items(&db, coll, format, archived).await
+                .map_err(std::io::Error::other)?;
+            let mut writer: Box<dyn tokio::io::AsyncWrite + Unpin> = match out {
+                Some(out) => Box::new(tokio::fs::File::create(out).await?),
+                None => Box::new(tokio::io::stdout()),
+            };
+
+            while let Some(row) = rows.next().await {
+                writer.write_all(&row.map_err(std::io::Error::other)?).await?;
+            }
+            writer.flush().await
+        }
+        _ => Err(usage_error("Wrong arguments.")),
+    }
+}
+
+fn check_coll(coll: &str) -> std::io::Result<()> {
+    if !nexis_rs::stores::is_item_coll(coll) {
+        return Err(usage_error(format!("Unknown item collection `{}`.", coll)));
+    }
+    Ok(())
+}
+
+fn usage_error(msg: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{}\n\n{}", msg, USAGE))
+}
@@ -0,0 +1,73 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::{ error, mongodb::payments::{ PaymentMethod, PaymentStatus }};
+use super::{ PaymentProvider, ProviderResult, WebhookEvent, WebhookEventKind };
+use sha2::{ Digest, Sha256 };
+
+/// Local payment gateway for development and tests. It keeps no state, and its
+/// answers only depend on its inputs:
+/// * Amounts ending in `.51` are declined for insufficient funds.
+/// * Amounts ending in `.52` fail as if the gateway were unreachable.
+/// * Cards are authorized right away, while transfers stay pending until a
+///   `payment.captured` or `payment.failed` callback arrives.
+pub struct MockProvider;
+
+impl MockProvider {
+    fn reference(key: &str) -> String {
+        format!("mock_{}", &hex::encode(Sha256::digest(key.as_bytes()))[..24])
+    }
+
+    fn cents(amount: f64) -> i64 {
+        (amount * 100.0).round() as i64 % 100
+    }
+
+    /// Builds the callback the mock gateway sends once a pending payment settles.
+    pub fn settle_event(provider_ref: &str, amount: f64, succeeded: bool) -> WebhookEvent {
+        let kind = if succeeded { WebhookEventKind::Captured } else { WebhookEventKind::Failed };
+        WebhookEvent {
+            id: Self::reference(&format!("{}:{:?}", provider_ref, kind)).replacen("mock_", "evt_", 1),
+            kind,
+            provider_ref: provider_ref.to_string(),
+            amount,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PaymentProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn authorize(&self, key: &str, amount: f64, method: &PaymentMethod) -> Result<ProviderResult> {
+        match Self::cents(amount) {
+            51 => return Ok(ProviderResult {
+                status: PaymentStatus::Declined,
+                provider_ref: None,
+                message: Some("Insufficient funds.".to_string()),
+            }),
+            52 => bail!(error::Payments::ProviderError("The mock gateway is unreachable.".into())),
+            _ => {}
+        }
+
+        let status = match method {
+            PaymentMethod::Card(_) => PaymentStatus::Authorized,
+            PaymentMethod::Transfer { .. } => PaymentStatus::Pending,
+            PaymentMethod::Cash(_) => bail!(error::Payments::UnsupportedMethod("cash".into())),
+        };
+
+        Ok(ProviderResult { status, provider_ref: Some(Self::reference(key)), message: None })
+    }
+
+    async fn capture(&self, provider_ref: &str, _amount: f64) -> Result<ProviderResult> {
+        Ok(ProviderResult { status: PaymentStatus::Captured, provider_ref: Some(provider_ref.to_string()), message: None })
+    }
+
+    async fn refund(&self, provider_ref: &str, _amount: f64) -> Result<ProviderResult> {
+        Ok(ProviderResult { status: PaymentStatus::Refunded, provider_ref: Some(provider_ref.to_string()), message: None })
+    }
+
+    async fn void(&self, provider_ref: &str) -> Result<ProviderResult> {
+        Ok(ProviderResult { status: PaymentStatus::Voided, provider_ref: Some(provider_ref.to_string()), message: None })
+    }
+}
@@ -0,0 +1,47 @@
+pub mod mock;
+pub mod webhooks;
+
+pub use mock::MockProvider;
+pub use webhooks::{ WebhookEvent, WebhookEventKind, SIGNATURE_HEADER, sign_payload, verify_signature, process_callback };
+
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::mongodb::payments::{ PaymentMethod, PaymentStatus };
+use std::sync::Arc;
+
+/// What a payment provider answered to an operation.
+#[derive(Debug, Clone)]
+pub struct ProviderResult {
+    pub status: PaymentStatus,
+    /// Reference of the payment at the provider, set by `authorize`.
+    pub provider_ref: Option<String>,
+    /// Reason given by the provider when a payment is declined.
+    pub message: Option<String>,
+}
+
+/// A payment gateway. Amounts are charged in two steps: `authorize` reserves them
+/// and `capture` collects them, and `void` releases an authorization that won't
+/// be captured. Providers that confirm payments later, such as bank transfers,
+/// answer `authorize` with a `Pending` status and report the outcome through a
+/// signed webhook callback.
+#[async_trait::async_trait]
+pub trait PaymentProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `key` identifies the payment, so retrying an authorization never charges twice.
+    async fn authorize(&self, key: &str, amount: f64, method: &PaymentMethod) -> Result<ProviderResult>;
+
+    async fn capture(&self, provider_ref: &str, amount: f64) -> Result<ProviderResult>;
+
+    async fn refund(&self, provider_ref: &str, amount: f64) -> Result<ProviderResult>;
+
+    async fn void(&self, provider_ref: &str) -> Result<ProviderResult>;
+}
+
+/// Builds the payment provider named in the settings.
+pub fn build_provider(settings: &crate::settings::PaymentSettings) -> Result<Arc<dyn PaymentProvider>> {
+    match settings.provider.as_str() {
+        "mock" => Ok(Arc::new(MockProvider)),
+        other => bail!("`{}` is not a supported payment provider. Use `mock`.", other),
+    }
+}
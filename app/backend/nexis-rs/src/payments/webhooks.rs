@@ -0,0 +1,100 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::error;
+use hmac::{ Hmac, Mac };
+use sha2::Sha256;
+
+/// Header carrying the signature of a provider callback, as `t=<unix timestamp>,v1=<hex HMAC>`.
+pub const SIGNATURE_HEADER: &str = "X-Nexis-Signature";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WebhookEventKind {
+    #[serde(rename = "payment.captured")]
+    Captured,
+    #[serde(rename = "payment.failed")]
+    Failed,
+    #[serde(rename = "payment.refunded")]
+    Refunded,
+}
+
+/// Callback sent by a payment provider when a payment changes on its side.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: WebhookEventKind,
+    #[serde(rename = "providerRef")]
+    pub provider_ref: String,
+    pub amount: f64,
+}
+
+fn payload_mac(secret: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Signs a callback body, returning the value of the signature header.
+/// The timestamp is signed along with the body so old callbacks can't be replayed.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let signature = hex::encode(payload_mac(secret, timestamp, body).finalize().into_bytes());
+    format!("t={},v1={}", timestamp, signature)
+}
+
+/// Checks the signature header of a callback body, and that it was signed
+/// at most `tolerance_seconds` away from `now`.
+pub fn verify_signature(
+    secret: &str,
+    header: &str,
+    body: &[u8],
+    tolerance_seconds: i64,
+    now: i64,
+) -> Result<()> {
+    let mut timestamp = None;
+    let mut signature = None;
+
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signature = hex::decode(value).ok(),
+            _ => {}
+        }
+    }
+
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        bail!(error::Payments::InvalidSignature("Malformed signature header.".into()));
+    };
+
+    if (now - timestamp).abs() > tolerance_seconds {
+        bail!(error::Payments::InvalidSignature("Signature timestamp out of tolerance.".into()));
+    }
+
+    payload_mac(secret, timestamp, body)
+        .verify_slice(&signature)
+        .map_err(|_| error::Payments::InvalidSignature("Signature mismatch.".into()))?;
+
+    Ok(())
+}
+
+/// Verifies and applies a provider callback.
+pub async fn process_callback(
+    db: &mongodb::Database,
+    provider: &dyn super::PaymentProvider,
+    settings: &crate::settings::PaymentSettings,
+    signature_header: &str,
+    body: &[u8],
+) -> Result<()> {
+    verify_signature(
+        &settings.webhook_secret,
+        signature_header,
+        body,
+        settings.webhook_tolerance_seconds,
+        chrono::Utc::now().timestamp(),
+    )?;
+
+    let event: WebhookEvent = serde_json::from_slice(body)?;
+
+    crate::database::apply_webhook_event(db, provider, event).await
+}
@@ -27,8 +27,4 @@ pub use uuid::Uuid;
 pub use once_cell::sync::Lazy;
 pub use futures_util::TryStreamExt;
 pub use std::collections::{ HashMap, HashSet };
-pub use crate::{ types, utils };
-
-pub static ITEM_COLLS: Lazy<Vec<&str>> = Lazy::new(|| vec![
-    "clothes", "food", "libraryItem", "tech", "techCpu", "techGpu", "techKeyboard", "techOther"
-]);
\ No newline at end of file
+pub use crate::{ types, utils, stores };
\ No newline at end of file
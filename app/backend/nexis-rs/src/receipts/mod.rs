@@ -0,0 +1,373 @@
+mod pdf;
+
+pub use pdf::render_pdf;
+
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::mongodb::{
+    orders::Order,
+    payments::{ CardType, PaymentMethod, PaymentStatus },
+    stores::DaySale,
+};
+
+/// Receipt of an online order or a sale charged at a register, with its lines
+/// grouped by store. Prices already include taxes, which are broken down.
+#[derive(Serialize, Debug, Clone)]
+pub struct Receipt {
+    pub number: String,
+    pub kind: ReceiptKind,
+    pub issued_at: String,
+    pub customer: Option<String>,
+    pub sections: Vec<ReceiptSection>,
+    pub subtotal: f64,
+    pub tax_name: String,
+    pub tax_rate: f64,
+    pub tax: f64,
+    pub total: f64,
+    pub payment: String,
+    pub terminal: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptKind {
+    Order,
+    Sale,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReceiptSection {
+    pub store: ReceiptStore,
+    pub lines: Vec<ReceiptLine>,
+    pub total: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReceiptStore {
+    pub name: String,
+    pub num: Option<i64>,
+    pub floor: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ReceiptLine {
+    pub name: String,
+    pub quantity: u32,
+    pub weight_kg: Option<f64>,
+    pub unit_price: f64,
+    pub total: f64,
+}
+
+impl Receipt {
+    fn new(
+        number: ObjectId,
+        kind: ReceiptKind,
+        issued_at: bson::DateTime,
+        customer: Option<String>,
+        sections: Vec<ReceiptSection>,
+        payment: String,
+        terminal: Option<String>,
+    ) -> Self {
+        let settings = crate::settings::get_settings().expect("Failed to read settings.");
+
+        let total = round_cents(sections.iter().map(|section| section.total).sum());
+        let subtotal = round_cents(total / (1.0 + settings.receipts.tax_rate));
+
+        Receipt {
+            number: number.to_hex(),
+            kind,
+            issued_at: chrono::DateTime::from_timestamp_millis(issued_at.timestamp_millis())
+                .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default(),
+            customer,
+            sections,
+            subtotal,
+            tax_name: settings.receipts.tax_name,
+            tax_rate: (settings.receipts.tax_rate * 10000.0).round() / 100.0,
+            tax: round_cents(total - subtotal),
+            total,
+            payment,
+            terminal,
+        }
+    }
+
+    /// Plain text version of the receipt, used for emails and PDFs.
+    pub fn to_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("{} {}", match self.kind { ReceiptKind::Order => "Order", ReceiptKind::Sale => "Sale" }, self.number),
+            format!("Date: {}", self.issued_at),
+        ];
+        if let Some(customer) = &self.customer {
+            lines.push(format!("Customer: {}", customer));
+        }
+        if let Some(terminal) = &self.terminal {
+            lines.push(format!("Terminal: {}", terminal));
+        }
+
+        for section in &self.sections {
+            lines.push(String::new());
+            lines.push(section.store.describe());
+            for line in &section.lines {
+                let amount = match line.weight_kg {
+                    Some(weight_kg) => format!("{:.3} kg", weight_kg),
+                    None => format!("{} x {:.2}", line.quantity, line.unit_price),
+                };
+                lines.push(format!("  {:<40} {:>16} {:>10.2}", truncate(&line.name, 40), amount, line.total));
+            }
+        }
+
+        lines.push(String::new());
+        lines.push(format!("{:<58} {:>10.2}", "Subtotal", self.subtotal));
+        lines.push(format!("{:<58} {:>10.2}", format!("{} ({}%)", self.tax_name, self.tax_rate), self.tax));
+        lines.push(format!("{:<58} {:>10.2}", "Total", self.total));
+        lines.push(format!("Paid with: {}", self.payment));
+
+        lines
+    }
+}
+
+impl ReceiptStore {
+    fn describe(&self) -> String {
+        let mut description = self.name.clone();
+        if let Some(num) = self.num {
+            description.push_str(&format!(" - Store #{}", num));
+        }
+        if let Some(floor) = self.floor {
+            description.push_str(&format!(", floor {}", floor));
+        }
+        description
+    }
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        format!("{}...", text.chars().take(max - 3).collect::<String>())
+    }
+}
+
+pub fn describe_payment_method(method: &PaymentMethod) -> String {
+    match method {
+        PaymentMethod::Cash(_) => "Cash".to_string(),
+        PaymentMethod::Card(card) => format!("Card ({})", match card {
+            CardType::Visa => "Visa",
+            CardType::Mastercard => "Mastercard",
+            CardType::Amex => "American Express",
+        }),
+        PaymentMethod::Transfer { bank, ref_num } => format!("Transfer ({}, ref. {})", bank, ref_num),
+    }
+}
+
+/// Reads the name, number and floor of some stores by name.
+async fn get_receipt_stores(db: &mongodb::Database, names: &[String]) -> Result<HashMap<String, ReceiptStore>> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    let mut cursor = stores_coll
+        .find(doc! { "name": { "$in": names }})
+        .projection(doc! { "name": 1, "num": 1, "floor": 1 })
+        .await?;
+
+    let number = |store: &Document, key: &str| match store.get(key) {
+        Some(bson::Bson::Int32(value)) => Some(*value as i64),
+        Some(bson::Bson::Int64(value)) => Some(*value),
+        Some(bson::Bson::Double(value)) => Some(*value as i64),
+        _ => None,
+    };
+
+    let mut stores = HashMap::new();
+    while let Some(store) = cursor.try_next().await? {
+        let name = store.get_str("name")?.to_string();
+        stores.insert(name.clone(), ReceiptStore {
+            num: number(&store, "num"),
+            floor: number(&store, "floor"),
+            name,
+        });
+    }
+
+    Ok(stores)
+}
+
+async fn get_user_contact(db: &mongodb::Database, user_id: ObjectId) -> Result<Option<(String, String)>> {
+    let users_coll: Collection<Document> = db.collection("user");
+
+    let Some(user) = users_coll
+        .find_one(doc! { "_id": user_id })
+        .projection(doc! { "email": 1, "name": 1 })
+        .await? else {
+        return Ok(None);
+    };
+
+    Ok(Some((user.get_str("email")?.to_string(), user.get_str("name").unwrap_or_default().to_string())))
+}
+
+pub async fn build_order_receipt(db: &mongodb::Database, order: &Order) -> Result<Receipt> {
+    let mut names: Vec<String> = Vec::new();
+    for line in &order.items {
+        if !names.contains(&line.store) {
+            names.push(line.store.clone());
+        }
+    }
+    let stores = get_receipt_stores(db, &names).await?;
+
+    let sections = names.into_iter()
+        .map(|name| {
+            let lines: Vec<ReceiptLine> = order.items.iter()
+                .filter(|line| line.store == name)
+                .map(|line| ReceiptLine {
+                    name: line.name.clone(),
+                    quantity: line.quantity,
+                    weight_kg: line.weight_kg,
+                    unit_price: line.price,
+                    total: line.total,
+                })
+                .collect();
+
+            ReceiptSection {
+                total: round_cents(lines.iter().map(|line| line.total).sum()),
+                store: stores.get(&name).cloned().unwrap_or(ReceiptStore { name, ..Default::default() }),
+                lines,
+            }
+        })
+        .collect();
+
+    let payment = crate::database::get_order_payments(db, order.payable_order()).await?
+        .into_iter()
+        .rev()
+        .find(|payment| matches!(payment.status, PaymentStatus::Captured | PaymentStatus::Refunded))
+        .map(|payment| describe_payment_method(&payment.method))
+        .unwrap_or_else(|| "Not paid yet".to_string());
+
+    let customer = get_user_contact(db, order.user).await?.map(|(_, name)| name);
+
+    Ok(Receipt::new(order.id, ReceiptKind::Order, order.created_at, customer, sections, payment, None))
+}
+
+pub async fn build_sale_receipt(db: &mongodb::Database, store_id: ObjectId, sale: &DaySale) -> Result<Receipt> {
+    let store_name = stores::get_store(&store_id).map(|store| store.name).unwrap_or_default();
+    let store = get_receipt_stores(db, std::slice::from_ref(&store_name)).await?
+        .remove(&store_name)
+        .unwrap_or(ReceiptStore { name: store_name, ..Default::default() });
+
+    // Every unit is scanned on its own, so the units of an item are added up
+    let mut lines: Vec<(ObjectId, ReceiptLine)> = Vec::new();
+    for unit in &sale.item {
+        match lines.iter_mut().find(|(item, _)| *item == unit.item) {
+            Some((_, line)) => {
+                line.quantity += 1;
+                line.weight_kg = line.weight_kg.map(|weight_kg| weight_kg + unit.weight_kg.unwrap_or_default());
+                line.total = round_cents(line.total + unit.price);
+                line.unit_price = round_cents(line.total / line.quantity as f64);
+            }
+            None => lines.push((unit.item, ReceiptLine {
+                name: unit.name.clone(),
+                quantity: 1,
+                weight_kg: unit.weight_kg,
+                unit_price: unit.price,
+                total: unit.price,
+            })),
+        }
+    }
+    let lines: Vec<ReceiptLine> = lines.into_iter().map(|(_, line)| line).collect();
+
+    let customer = match (&sale.client.name, sale.client.user) {
+        (Some(name), _) => Some(name.clone()),
+        (None, Some(user)) => get_user_contact(db, user).await?.map(|(_, name)| name),
+        (None, None) => None,
+    };
+
+    let section = ReceiptSection {
+        total: round_cents(lines.iter().map(|line| line.total).sum()),
+        store,
+        lines,
+    };
+
+    Ok(Receipt::new(
+        sale.id,
+        ReceiptKind::Sale,
+        sale.timestamp,
+        customer,
+        vec![section],
+        describe_payment_method(&sale.payment.method),
+        Some(sale.terminal.clone()),
+    ))
+}
+
+pub fn render_html(receipt: &Receipt) -> Result<String> {
+    let template = crate::ENV.get_template("receipt.html")?;
+    Ok(template.render(minijinja::context! { receipt => receipt })?)
+}
+
+/// Emails a receipt, with its plain text version as the alternative body.
+pub async fn send_receipt(receipt: &Receipt, email: String, name: String) -> Result<()> {
+    let subject = match receipt.kind {
+        ReceiptKind::Order => format!("Receipt of your order {}", receipt.number),
+        ReceiptKind::Sale => format!("Receipt of your purchase {}", receipt.number),
+    };
+
+    utils::emails::send_email(None, email, name, subject, render_html(receipt)?, receipt.to_lines().join("\n"))
+        .await
+        .map_err(|e| anyhow!(e))
+}
+
+/// Emails the receipt of an order to its client.
+pub async fn email_order_receipt(db: &mongodb::Database, order: &Order) -> Result<()> {
+    let Some((email, name)) = get_user_contact(db, order.user).await? else {
+        bail!("User {} not found.", order.user);
+    };
+
+    send_receipt(&build_order_receipt(db, order).await?, email, name).await
+}
+
+/// Emails the receipt of a sale to an address, or to its client if the buyer is a
+/// registered user. Returns `false` if there's nowhere to send it.
+pub async fn email_sale_receipt(
+    db: &mongodb::Database,
+    store_id: ObjectId,
+    sale: &DaySale,
+    email: Option<String>,
+) -> Result<bool> {
+    let contact = match (email, sale.client.user) {
+        (Some(email), _) => Some((email, sale.client.name.clone().unwrap_or_default())),
+        (None, Some(user)) => get_user_contact(db, user).await?,
+        (None, None) => None,
+    };
+    let Some((email, name)) = contact else {
+        return Ok(false);
+    };
+
+    send_receipt(&build_sale_receipt(db, store_id, sale).await?, email, name).await?;
+
+    Ok(true)
+}
+
+/// Emails the receipt of an order in the background, once it's paid.
+pub fn spawn_order_receipt_email(db: mongodb::Database, order_id: ObjectId) {
+    tokio::spawn(async move {
+        let result = match crate::database::get_order(&db, order_id).await {
+            Ok(Some(order)) => email_order_receipt(&db, &order).await,
+            Ok(None) => Err(anyhow!("Order not found.")),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!(target: "backend", "Failed to email the receipt of order {}: {}", order_id, e);
+        }
+    });
+}
+
+/// Emails the receipt of a sale in the background, if the buyer is a registered user.
+pub fn spawn_sale_receipt_email(db: mongodb::Database, store_id: ObjectId, sale: DaySale) {
+    if sale.client.user.is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = email_sale_receipt(&db, store_id, &sale, None).await {
+            tracing::error!(target: "backend", "Failed to email the receipt of sale {}: {}", sale.id, e);
+        }
+    });
+}
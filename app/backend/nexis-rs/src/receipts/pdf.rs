@@ -0,0 +1,81 @@
+use anyhow::Result;
+use lopdf::{ content::{ Content, Operation }, dictionary, Document, Object, Stream };
+use super::Receipt;
+
+/// A4, in points.
+const PAGE_WIDTH: i64 = 595;
+const PAGE_HEIGHT: i64 = 842;
+const MARGIN: i64 = 40;
+const FONT_SIZE: i64 = 9;
+const LINE_HEIGHT: i64 = 12;
+
+/// Renders the plain text version of a receipt as a PDF, in a monospaced
+/// font so its columns stay aligned.
+pub fn render_pdf(receipt: &Receipt) -> Result<Vec<u8>> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Courier",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let lines_per_page = ((PAGE_HEIGHT - 2 * MARGIN) / LINE_HEIGHT) as usize;
+    let mut page_ids = Vec::new();
+
+    for page_lines in receipt.to_lines().chunks(lines_per_page) {
+        let mut operations = vec![
+            Operation::new("BT", vec![]),
+            Operation::new("Tf", vec!["F1".into(), FONT_SIZE.into()]),
+            Operation::new("TL", vec![LINE_HEIGHT.into()]),
+            Operation::new("Td", vec![MARGIN.into(), (PAGE_HEIGHT - MARGIN).into()]),
+        ];
+        for line in page_lines {
+            operations.push(Operation::new("Tj", vec![Object::string_literal(win_ansi(line))]));
+            operations.push(Operation::new("T*", vec![]));
+        }
+        operations.push(Operation::new("ET", vec![]));
+
+        let content_id = doc.add_object(Stream::new(dictionary! {}, Content { operations }.encode()?));
+        page_ids.push(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        }));
+    }
+
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+        "Type" => "Pages",
+        "Count" => page_ids.len() as i64,
+        "Kids" => page_ids.into_iter().map(Object::from).collect::<Vec<_>>(),
+        "Resources" => resources_id,
+        "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+    }));
+
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+
+    let mut pdf = Vec::new();
+    doc.save_to(&mut pdf)?;
+
+    Ok(pdf)
+}
+
+/// Encodes text for the standard PDF fonts, replacing what they can't show.
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ 0x20..=0x7e | code @ 0xa0..=0xff => code as u8,
+            _ => b'?',
+        })
+        .collect()
+}
@@ -0,0 +1,19 @@
+mod reindex;
+mod search;
+mod payments;
+mod stores;
+
+use actix_web::web;
+
+pub fn admin_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .service(reindex::reindex_items)
+            .service(stores::list_stores)
+            .service(stores::create_store)
+            .service(stores::edit_store)
+            .service(search::top_search_queries)
+            .service(search::zero_result_search_queries)
+            .service(payments::mock_settle_payment)
+    );
+}
@@ -0,0 +1,61 @@
+use crate::prelude::*;
+use crate::payments::{ MockProvider, PaymentProvider, process_callback, sign_payload };
+use crate::routes::payments::payment_error_response;
+use crate::settings::PaymentSettings;
+use crate::types::{ ErrorResponse, requests::payments::MockSettlement };
+use crate::utils::{ Role, authorize, auth_error_response };
+
+/// Makes the mock gateway send the callback settling a pending payment, such
+/// as a transfer. The callback is signed and goes through the same verification
+/// as the ones received at the webhook.
+#[tracing::instrument(name = "Settling mock payment", skip(req, body, db, redis_pool, provider, settings))]
+#[actix_web::post("/payments/{id}/mock-settle")]
+pub async fn mock_settle_payment(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<MockSettlement>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+    settings: web::Data<PaymentSettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing mock payment settlement.");
+
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    if provider.name() != "mock" {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "The payment provider isn't the mock one.".to_string() });
+    }
+
+    let Ok(payment_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid payment id.".to_string() });
+    };
+
+    let payment = match crate::database::get_payment(&db, payment_id).await {
+        Ok(Some(payment)) => payment,
+        Ok(None) => return HttpResponse::NotFound().json(ErrorResponse { error: "Payment not found.".to_string() }),
+        Err(e) => return payment_error_response(e),
+    };
+
+    let Some(provider_ref) = payment.provider_ref else {
+        return HttpResponse::Conflict().json(ErrorResponse { error: "The payment never reached the provider.".to_string() });
+    };
+
+    let event = MockProvider::settle_event(&provider_ref, payment.amount, body.succeeded);
+    let callback = match serde_json::to_vec(&event) {
+        Ok(callback) => callback,
+        Err(e) => return payment_error_response(e.into()),
+    };
+    let signature = sign_payload(&settings.webhook_secret, chrono::Utc::now().timestamp(), &callback);
+
+    if let Err(e) = process_callback(&db, provider.get_ref(), &settings, &signature, &callback).await {
+        return payment_error_response(e);
+    }
+
+    match crate::database::get_payment(&db, payment_id).await {
+        Ok(payment) => HttpResponse::Ok().json(payment),
+        Err(e) => payment_error_response(e),
+    }
+}
@@ -0,0 +1,36 @@
+use crate::prelude::*;
+use crate::search::{ SearchIndex, reconcile_items };
+use crate::utils::{ Role, authorize, auth_error_response };
+
+#[tracing::instrument(
+    name = "Reindexing items",
+    skip(req, db, redis_pool, search_index)
+)]
+#[actix_web::post("/items/reindex")]
+pub async fn reindex_items(
+    req: HttpRequest,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    search_index: web::Data<SearchIndex>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing items reindex.");
+
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    let report = match reconcile_items(&db).await {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::error!(target: "mongodb", "Failed to reconcile items projection: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if let Err(e) = search_index.rebuild(&db).await {
+        tracing::error!(target: "backend", "Failed to rebuild search index: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(report)
+}
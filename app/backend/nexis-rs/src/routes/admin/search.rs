@@ -0,0 +1,71 @@
+use crate::prelude::*;
+use crate::search::{ QueryKind, analytics::top_queries };
+use crate::utils::{ Role, authorize, auth_error_response };
+
+#[derive(Deserialize, Debug)]
+pub struct QueryStatsParams {
+    kind: Option<QueryKind>,
+    days: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[tracing::instrument(
+    name = "Getting top search queries",
+    skip(req, db, redis_pool)
+)]
+#[actix_web::get("/search/top")]
+pub async fn top_search_queries(
+    req: HttpRequest,
+    parameters: web::Query<QueryStatsParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing top search queries.");
+
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    query_stats_response(&db, &parameters, false).await
+}
+
+#[tracing::instrument(
+    name = "Getting zero-result search queries",
+    skip(req, db, redis_pool)
+)]
+#[actix_web::get("/search/zero-results")]
+pub async fn zero_result_search_queries(
+    req: HttpRequest,
+    parameters: web::Query<QueryStatsParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing zero-result search queries.");
+
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    query_stats_response(&db, &parameters, true).await
+}
+
+async fn query_stats_response(
+    db: &mongodb::Database,
+    parameters: &QueryStatsParams,
+    zero_results: bool,
+) -> HttpResponse {
+    const MAX_QUERIES: i64 = 100;
+
+    let kind = parameters.kind.unwrap_or(QueryKind::Search);
+    let days = parameters.days.unwrap_or(7).clamp(1, 365);
+    let limit = parameters.limit.unwrap_or(20).clamp(1, MAX_QUERIES);
+    let since = bson::DateTime::from_millis(bson::DateTime::now().timestamp_millis() - days * 86_400_000);
+
+    match top_queries(db, kind, since, zero_results, limit).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => {
+            tracing::error!(target: "mongodb", "Failed to get search query stats: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::prelude::*;
+use crate::database::{ insert_store, update_store };
+use crate::types::{ ErrorResponse, error, requests::stores::{ NewStore, StoreUpdate }};
+use crate::utils::{ Role, authorize, auth_error_response };
+
+#[tracing::instrument(name = "Listing stores", skip(req, db, redis_pool))]
+#[actix_web::get("/stores")]
+pub async fn list_stores(
+    req: HttpRequest,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    HttpResponse::Ok().json(stores::all())
+}
+
+#[tracing::instrument(
+    name = "Creating a store",
+    skip(req, new_store, db, redis_pool),
+    fields(new_store_name = %new_store.name)
+)]
+#[actix_web::post("/stores")]
+pub async fn create_store(
+    req: HttpRequest,
+    new_store: web::Json<NewStore>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    let store_id = match insert_store(&db, new_store.into_inner()).await {
+        Ok(id) => id,
+        Err(e) => return store_error_response(e),
+    };
+
+    if let Err(e) = reload_registry(&db).await {
+        tracing::error!(target: "backend", "Failed to reload store registry: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Created().json(stores::get_store(&store_id))
+}
+
+#[tracing::instrument(name = "Editing a store", skip(req, update, db, redis_pool))]
+#[actix_web::patch("/stores/{id}")]
+pub async fn edit_store(
+    req: HttpRequest,
+    path: web::Path<String>,
+    update: web::Json<StoreUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    if let Err(e) = authorize(&req, Some(Role::Admin), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    let Ok(store_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid store id.".to_string() });
+    };
+
+    let store = match update_store(&db, store_id, update.into_inner()).await {
+        Ok(Some(store)) => store,
+        Ok(None) => return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() }),
+        Err(e) => return store_error_response(e),
+    };
+
+    if let Err(e) = reload_registry(&db).await {
+        tracing::error!(target: "backend", "Failed to reload store registry: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(store)
+}
+
+/// Reloads the cached registry, indexes the unit codes of new item collections,
+/// and reprojects every item in the background since the store of their
+/// collections may have changed.
+async fn reload_registry(db: &mongodb::Database) -> anyhow::Result<()> {
+    stores::reload(db).await?;
+    crate::database::ensure_unit_code_indexes(db).await?;
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        if let Err(e) = crate::search::reconcile_items(&db).await {
+            tracing::error!(target: "backend", "Failed to reconcile items projection: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+fn store_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(error::Stores::InvalidRegistry(msg)) = e.downcast_ref::<error::Stores>() {
+        HttpResponse::BadRequest().json(ErrorResponse { error: msg.clone() })
+    } else {
+        tracing::error!(target: "mongodb", "Failed to save store: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::prelude::*;
+use crate::database::{ self, CartOwner };
+use crate::types::{ ErrorResponse, error, requests::cart::{ NewCartItem, CartQuantity }};
+use crate::utils::{ Role, auth_error_response };
+
+/// Cookie identifying the redis cart of an anonymous shopper.
+pub const GUEST_CART_COOKIE: &str = "guest_cart";
+
+pub fn cart_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/cart")
+            .service(get_cart)
+            .service(add_item)
+            .service(set_item_quantity)
+            .service(remove_item)
+            .service(clear_cart)
+    );
+}
+
+#[tracing::instrument(name = "Getting cart", skip(req, db, redis_pool))]
+#[actix_web::get("")]
+pub async fn get_cart(
+    req: HttpRequest,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing cart.");
+
+    let owner = match get_cart_owner(&req, &db, &redis_pool).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return HttpResponse::Ok().json(crate::types::responses::CartResponse {
+            items: Vec::new(),
+            total: 0.0,
+            price_changed: false,
+        }),
+        Err(response) => return response,
+    };
+
+    cart_response(&db, &redis_pool, owner, None).await
+}
+
+#[tracing::instrument(
+    name = "Adding item to cart",
+    skip(req, new_item, db, redis_pool),
+    fields(item = %new_item.item, quantity = ?new_item.quantity)
+)]
+#[actix_web::post("")]
+pub async fn add_item(
+    req: HttpRequest,
+    new_item: web::Json<NewCartItem>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing cart item addition.");
+
+    // Anonymous shoppers adding their first item get a new guest cart
+    let (owner, new_guest_id) = match get_cart_owner(&req, &db, &redis_pool).await {
+        Ok(Some(owner)) => (owner, None),
+        Ok(None) => {
+            let guest_id = Uuid::new_v4();
+            (CartOwner::Guest(guest_id), Some(guest_id))
+        }
+        Err(response) => return response,
+    };
+
+    let NewCartItem { coll, item, quantity, weight_kg } = new_item.into_inner();
+
+    if let Err(e) = database::add_to_cart(&db, &redis_pool, owner, &coll, item, quantity.unwrap_or(1), weight_kg).await {
+        return cart_error_response(e);
+    }
+
+    cart_response(&db, &redis_pool, owner, new_guest_id).await
+}
+
+#[tracing::instrument(name = "Setting cart item quantity", skip(req, body, db, redis_pool))]
+#[actix_web::patch("/{item}")]
+pub async fn set_item_quantity(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CartQuantity>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing cart item quantity.");
+
+    let Ok(item_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() });
+    };
+
+    let owner = match get_cart_owner(&req, &db, &redis_pool).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return cart_error_response(anyhow!(error::Cart::NotInCart)),
+        Err(response) => return response,
+    };
+
+    if let Err(e) = database::set_cart_quantity(&db, &redis_pool, owner, item_id, body.quantity, body.weight_kg).await {
+        return cart_error_response(e);
+    }
+
+    cart_response(&db, &redis_pool, owner, None).await
+}
+
+#[tracing::instrument(name = "Removing item from cart", skip(req, db, redis_pool))]
+#[actix_web::delete("/{item}")]
+pub async fn remove_item(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing cart item removal.");
+
+    let Ok(item_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() });
+    };
+
+    let owner = match get_cart_owner(&req, &db, &redis_pool).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return cart_error_response(anyhow!(error::Cart::NotInCart)),
+        Err(response) => return response,
+    };
+
+    if let Err(e) = database::remove_from_cart(&db, &redis_pool, owner, item_id).await {
+        return cart_error_response(e);
+    }
+
+    cart_response(&db, &redis_pool, owner, None).await
+}
+
+#[tracing::instrument(name = "Clearing cart", skip(req, db, redis_pool))]
+#[actix_web::delete("")]
+pub async fn clear_cart(
+    req: HttpRequest,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing cart clearing.");
+
+    let owner = match get_cart_owner(&req, &db, &redis_pool).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return HttpResponse::NoContent().finish(),
+        Err(response) => return response,
+    };
+
+    if let Err(e) = database::save_cart(&db, &redis_pool, owner, &[]).await {
+        return cart_error_response(e);
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+/// Gets the owner of the cart of a request: the logged in client, or the
+/// anonymous shopper of the guest cart cookie. Returns `None` if the request
+/// has neither, and the error response if the session is invalid or the
+/// logged in user isn't a client.
+async fn get_cart_owner(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+) -> Result<Option<CartOwner>, HttpResponse> {
+    match utils::get_session_user_id(req, db, redis_pool).await {
+        Ok(Some(user_id)) => match utils::user_has_role(db, user_id, Role::Client).await {
+            Ok(true) => Ok(Some(CartOwner::User(user_id))),
+            Ok(false) => Err(auth_error_response(anyhow!(error::Auth::Forbidden(
+                format!("This action requires the `{}` role.", Role::Client.as_str())
+            )))),
+            Err(e) => Err(auth_error_response(e)),
+        },
+        Ok(None) => Ok(req.cookie(GUEST_CART_COOKIE)
+            .and_then(|cookie| Uuid::parse_str(cookie.value()).ok())
+            .map(CartOwner::Guest)),
+        Err(e) => Err(auth_error_response(e)),
+    }
+}
+
+/// Responds with the current cart, setting the guest cart cookie if a guest cart was just created.
+async fn cart_response(
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    owner: CartOwner,
+    new_guest_id: Option<Uuid>,
+) -> HttpResponse {
+    let cart = match database::get_cart_response(db, redis_pool, owner).await {
+        Ok(cart) => cart,
+        Err(e) => return cart_error_response(e),
+    };
+
+    let mut response = HttpResponse::Ok();
+
+    if let Some(guest_id) = new_guest_id {
+        let settings = crate::settings::get_settings().expect("Failed to read settings.");
+        let cookie = Cookie::build(GUEST_CART_COOKIE, guest_id.to_string())
+            .path("/")
+            .http_only(true)
+            .max_age(actix_web::cookie::time::Duration::days(settings.cart.guest_cart_expiration_days as i64))
+            .finish();
+        response.cookie(cookie);
+    }
+
+    response.json(cart)
+}
+
+fn cart_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Cart>() {
+        match e {
+            error::Cart::ItemNotFound(msg) =>
+                HttpResponse::NotFound().json(ErrorResponse { error: msg.clone() }),
+            error::Cart::NotInCart =>
+                HttpResponse::NotFound().json(ErrorResponse { error: "Item not in cart.".to_string() }),
+            error::Cart::OutOfStock { .. } =>
+                HttpResponse::Conflict().json(ErrorResponse { error: e.to_string() }),
+            error::Cart::InvalidQuantity(msg) =>
+                HttpResponse::BadRequest().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else if e.is::<error::Redis>() {
+        tracing::error!(target: "redis", "Failed to access guest cart: {}", e);
+        HttpResponse::InternalServerError().finish()
+    } else {
+        tracing::error!(target: "backend", "Failed to access cart: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
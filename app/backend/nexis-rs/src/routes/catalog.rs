@@ -0,0 +1,282 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    error,
+    requests::catalog::{
+        CatalogExportParams, CatalogImportParams, CatalogItemUpdate, CatalogItemVersion, NewCatalogItem,
+    },
+};
+use crate::utils::{ authorize, auth_error_response };
+
+pub fn catalog_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/catalog")
+            .service(create_catalog_item)
+            .service(list_catalog_items)
+            .service(import_catalog_items)
+            .service(export_catalog_items)
+            .service(get_catalog_item)
+            .service(update_catalog_item)
+            .service(archive_catalog_item)
+            .service(restore_catalog_item)
+    );
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ItemPath {
+    coll: String,
+    id: String,
+}
+
+#[tracing::instrument(name = "Creating catalog item", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{coll}", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn create_catalog_item(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewCatalogItem>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item creation.");
+
+    let coll = path.into_inner();
+    let user_id = match authorize_coll(&req, &db, &redis_pool, &coll).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match crate::database::create_catalog_item(&db, &coll, user_id, body.into_inner()).await {
+        Ok(item) => HttpResponse::Created().json(item),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogListParams {
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    page: u64,
+}
+
+#[tracing::instrument(name = "Listing catalog items", skip(req, db, redis_pool))]
+#[actix_web::get("/{coll}")]
+pub async fn list_catalog_items(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<CatalogListParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item list.");
+
+    let coll = path.into_inner();
+    if let Err(response) = authorize_coll(&req, &db, &redis_pool, &coll).await {
+        return response;
+    }
+
+    match crate::database::get_catalog_items(&db, &coll, parameters.archived, parameters.page).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Importing catalog items", skip(req, payload, db, redis_pool))]
+#[actix_web::post("/{coll}/import")]
+pub async fn import_catalog_items(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<CatalogImportParams>,
+    payload: web::Payload,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item import.");
+
+    let coll = path.into_inner();
+    let user_id = match authorize_coll(&req, &db, &redis_pool, &coll).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match crate::catalog::import_items(&db, &coll, parameters.format, parameters.dry_run, Some(user_id), payload).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Exporting catalog items", skip(req, db, redis_pool))]
+#[actix_web::get("/{coll}/export")]
+pub async fn export_catalog_items(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<CatalogExportParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item export.");
+
+    let coll = path.into_inner();
+    if let Err(response) = authorize_coll(&req, &db, &redis_pool, &coll).await {
+        return response;
+    }
+
+    match crate::catalog::export_items(&db, &coll, parameters.format, parameters.archived).await {
+        Ok(rows) => HttpResponse::Ok()
+            .content_type(parameters.format.content_type())
+            .insert_header((
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", coll, parameters.format.extension()),
+            ))
+            .streaming(rows),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting catalog item", skip(req, db, redis_pool))]
+#[actix_web::get("/{coll}/{id}")]
+pub async fn get_catalog_item(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item.");
+
+    let (_, item_id) = match authorize_item(&req, &db, &redis_pool, &path).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::get_catalog_item(&db, &path.coll, item_id).await {
+        Ok(Some(item)) => HttpResponse::Ok().json(item),
+        Ok(None) => catalog_error_response(anyhow!(error::Catalog::ItemNotFound)),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Updating catalog item", skip(req, body, db, redis_pool))]
+#[actix_web::put("/{coll}/{id}")]
+pub async fn update_catalog_item(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    body: web::Json<CatalogItemUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item update.");
+
+    let (user_id, item_id) = match authorize_item(&req, &db, &redis_pool, &path).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::update_catalog_item(&db, &path.coll, item_id, user_id, body.into_inner()).await {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Archiving catalog item", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{coll}/{id}/archive")]
+pub async fn archive_catalog_item(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    body: web::Json<CatalogItemVersion>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item archiving.");
+
+    let (user_id, item_id) = match authorize_item(&req, &db, &redis_pool, &path).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::archive_catalog_item(&db, &path.coll, item_id, user_id, body.version).await {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Restoring catalog item", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{coll}/{id}/restore")]
+pub async fn restore_catalog_item(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    body: web::Json<CatalogItemVersion>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing catalog item restoring.");
+
+    let (user_id, item_id) = match authorize_item(&req, &db, &redis_pool, &path).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::restore_catalog_item(&db, &path.coll, item_id, user_id, body.version).await {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => catalog_error_response(e),
+    }
+}
+
+/// Checks that the user works at the store selling an item collection, or is an admin.
+async fn authorize_coll(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    coll: &str,
+) -> Result<ObjectId, HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Some(store) = stores::get_coll_store(coll) else {
+        return Err(HttpResponse::NotFound().json(ErrorResponse { error: format!("Unknown item collection `{}`.", coll) }));
+    };
+
+    if !utils::is_store_staff(db, user_id, &[store.id]).await.map_err(catalog_error_response)? {
+        return Err(catalog_error_response(anyhow!(error::Catalog::Forbidden(
+            format!("You don't work at `{}`.", store.name)
+        ))));
+    }
+
+    Ok(user_id)
+}
+
+/// Same as `authorize_coll`, also parsing the item id. Returns the user and item ids.
+async fn authorize_item(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    path: &ItemPath,
+) -> Result<(ObjectId, ObjectId), HttpResponse> {
+    let user_id = authorize_coll(req, db, redis_pool, &path.coll).await?;
+
+    let Ok(item_id) = ObjectId::parse_str(&path.id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() }));
+    };
+
+    Ok((user_id, item_id))
+}
+
+fn catalog_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Catalog>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Catalog::ItemNotFound => HttpResponse::NotFound().json(error),
+            error::Catalog::Invalid(_) | error::Catalog::InvalidFile(_) => HttpResponse::BadRequest().json(error),
+            error::Catalog::Changed(_) | error::Catalog::Archived => HttpResponse::Conflict().json(error),
+            error::Catalog::Forbidden(msg) => HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else if let Some(e) = e.downcast_ref::<error::Inventory>() {
+        // Lots a new item starts with
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Inventory::ItemNotFound => HttpResponse::NotFound().json(error),
+            _ => HttpResponse::BadRequest().json(error),
+        }
+    } else {
+        tracing::error!(target: "mongodb", "Failed to access the catalog: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
@@ -1,7 +1,9 @@
-use types::responses::{ ItemSuggestion, ItemResult };
+use types::responses::{ ItemSuggestion, ItemResult, SearchSuggestions, TrendingSearches };
 
 use crate::prelude::*;
-use crate::types::{ mongodb::Item, responses };
+use crate::search::{ SearchIndex, QueryKind, SearchLogEntry, escape_regex, log_query, normalize_query };
+use crate::settings::SearchSettings;
+use crate::types::mongodb::Item;
 
 #[derive(Deserialize, Debug)]
 pub struct SearchSuggestionParams {
@@ -10,55 +12,35 @@ pub struct SearchSuggestionParams {
 
 #[tracing::instrument(
     name = "Getting search suggestions",
-    skip(db)
+    skip(req, search_index, db, redis_pool)
 )]
 #[actix_web::get("/search-suggestions")]
 pub async fn search_suggestions(
+    req: HttpRequest,
     parameters: web::Query<SearchSuggestionParams>,
+    search_index: web::Data<SearchIndex>,
     db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
 ) -> HttpResponse {
     tracing::info!(target: "backend", "Accessing search suggestions.");
 
-    const MAX_SUGGEST: i32 = 8;
+    const MAX_SUGGEST: usize = 8;
 
-    let items_coll: Collection<Item> = db.collection("items");
-
-    let input = &parameters.input;
-
-    let mut cursor = items_coll.aggregate(vec![
-        doc! { "$match": { "$text": { "$search": input, "$caseSensitive": false }}},
-        doc! { "$sample": { "size": MAX_SUGGEST }},
-    ])
-    .await.unwrap();
-
-    let mut suggestions: Vec<responses::ItemSuggestion> = Vec::new();
-    let mut added_ids: HashSet<ObjectId> = HashSet::new();
-
-    while let Ok(Some(doc)) = cursor.try_next().await {
-        let item: Item = bson::from_document(doc).unwrap();
-        if added_ids.insert(item.id) {
-            suggestions.push(ItemSuggestion::from(item))
-        }
-    }
-
-    if suggestions.len() < MAX_SUGGEST as usize {
-        let max_suggest_rem: i32 = MAX_SUGGEST - suggestions.len() as i32;
+    let suggestions = search_index.suggest(&parameters.input, MAX_SUGGEST);
 
-        let mut cursor = items_coll.aggregate(vec![
-            doc! { "$match": { "name": { "$regex": input, "$options": "i" }}},
-            doc! { "$sample": { "size": max_suggest_rem }}
-        ])
-        .await.unwrap();
+    log_query(&db, SearchLogEntry {
+        query: normalize_query(&parameters.input),
+        kind: QueryKind::Suggestions,
+        filters: doc! {},
+        results: suggestions.items.len() as i64,
+        user: get_logged_user_id(&req, &db, &redis_pool).await,
+        timestamp: bson::DateTime::now(),
+    });
 
-        while let Ok(Some(doc)) = cursor.try_next().await {
-            let item: Item = bson::from_document(doc).unwrap();
-            if added_ids.insert(item.id) {
-                suggestions.push(ItemSuggestion::from(item))
-            }
-        }
-    }
-
-    HttpResponse::Ok().json(suggestions)
+    HttpResponse::Ok().json(SearchSuggestions {
+        suggestions: suggestions.items.into_iter().map(ItemSuggestion::from).collect(),
+        did_you_mean: suggestions.did_you_mean,
+    })
 }
 
 #[derive(Deserialize, Debug)]
@@ -69,16 +51,20 @@ pub struct SearchParams {
     min_price: Option<i32>,
     #[serde(rename = "max-price", skip_serializing_if = "Option::is_none")]
     max_price: Option<i32>,
+    #[serde(rename = "in-stock", alias = "inStock", default)]
+    in_stock: bool,
 }
 
 #[tracing::instrument(
     name = "Getting search results",
-    skip(db)
+    skip(req, db, redis_pool)
 )]
 #[actix_web::get("/search")]
 pub async fn search(
+    req: HttpRequest,
     parameters: web::Query<SearchParams>,
     db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
 ) -> HttpResponse {
     tracing::info!(target: "backend", "Accessing search.");
 
@@ -88,9 +74,10 @@ pub async fn search(
     let page = &parameters.page;
     let min_price: Option<i32> = parameters.min_price;
     let max_price: Option<i32> = parameters.max_price;
+    let in_stock = parameters.in_stock;
     let skip = page * MAX_RESULTS;
 
-    let search_aggregate = build_search_pipeline(input, min_price, max_price, skip, MAX_RESULTS, true).await;
+    let search_aggregate = build_search_pipeline(input, min_price, max_price, in_stock, skip, MAX_RESULTS, true).await;
         
     let items_coll: Collection<Item> = db.collection("items");
 
@@ -106,7 +93,7 @@ pub async fn search(
     }
 
     if results.len() < MAX_RESULTS as usize {
-        let search_aggregate = build_search_pipeline(input, min_price, max_price, skip, MAX_RESULTS, false).await;
+        let search_aggregate = build_search_pipeline(input, min_price, max_price, in_stock, skip, MAX_RESULTS, false).await;
 
         let mut cursor = items_coll.aggregate(search_aggregate).await.expect("Item aggregate failed");
         while let Ok(Some(doc)) = cursor.try_next().await {
@@ -118,13 +105,77 @@ pub async fn search(
 
     }
 
+    // Only the first page is logged, so paging through the results of a query doesn't count it again
+    if *page == 0 {
+        let mut filters = doc! { "inStock": in_stock };
+        if let Some(min_price) = min_price {
+            filters.insert("minPrice", min_price);
+        }
+        if let Some(max_price) = max_price {
+            filters.insert("maxPrice", max_price);
+        }
+
+        log_query(&db, SearchLogEntry {
+            query: normalize_query(input),
+            kind: QueryKind::Search,
+            filters,
+            results: results.len() as i64,
+            user: get_logged_user_id(&req, &db, &redis_pool).await,
+            timestamp: bson::DateTime::now(),
+        });
+    }
+
     HttpResponse::Ok().json(results)
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TrendingParams {
+    limit: Option<i64>,
+}
+
+#[tracing::instrument(
+    name = "Getting trending searches",
+    skip(db, search_settings)
+)]
+#[actix_web::get("/search/trending")]
+pub async fn trending_searches(
+    parameters: web::Query<TrendingParams>,
+    db: web::Data<mongodb::Database>,
+    search_settings: web::Data<SearchSettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing trending searches.");
+
+    const MAX_TRENDING: i64 = 20;
+
+    let limit = parameters.limit.unwrap_or(8).clamp(1, MAX_TRENDING);
+    let since = bson::DateTime::from_millis(
+        bson::DateTime::now().timestamp_millis() - search_settings.trending_window_hours * 3_600_000
+    );
+
+    match crate::search::analytics::trending_queries(&db, since, limit).await {
+        Ok(queries) => HttpResponse::Ok().json(TrendingSearches { trending: queries }),
+        Err(e) => {
+            tracing::error!(target: "mongodb", "Failed to get trending searches: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Returns the id of the logged in user, if any. Failing to verify the
+/// session doesn't prevent searching, so any error is ignored.
+async fn get_logged_user_id(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+) -> Option<ObjectId> {
+    utils::get_session_user_id(req, db, redis_pool).await.ok().flatten()
+}
+
 async fn build_search_pipeline(
     input: &str,
     min_price: Option<i32>,
     max_price: Option<i32>,
+    in_stock: bool,
     skip: i32,
     limit: i32,
     use_text_search: bool
@@ -139,17 +190,23 @@ async fn build_search_pipeline(
     let mut text_or_regex_match = if use_text_search {
         doc! { "$text": { "$search": input, "$caseSensitive": false }}
     } else {
-        doc! { "name": { "$regex": input, "$options": "i" }}
+        doc! { "name": { "$regex": escape_regex(input), "$options": "i" }}
     };
 
     text_or_regex_match.extend(price_match);
 
-    let pipeline = vec![
+    let mut pipeline = vec![
         doc! { "$match": text_or_regex_match },
-        doc! { "$skip": skip },
-        doc! { "$limit": limit },
+        doc! { "$addFields": { "stock": utils::stock::stock_expr() }},
     ];
 
+    if in_stock {
+        pipeline.push(doc! { "$match": { "stock": { "$gt": 0 }}});
+    }
+
+    pipeline.push(doc! { "$skip": skip });
+    pipeline.push(doc! { "$limit": limit });
+
     tracing::debug!(target: "backend", "Search pipeline: {:#?}", pipeline);
 
     pipeline
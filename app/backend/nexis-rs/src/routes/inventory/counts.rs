@@ -0,0 +1,215 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    error,
+    mongodb::inventory::{ StockCount, StockCountStatus },
+    requests::inventory::{
+        CountApproval,
+        CountScans,
+        NewStockCount,
+    },
+};
+use crate::utils::{ authorize, auth_error_response };
+use super::{ authorize_store, authorize_store_owner, inventory_error_response };
+
+#[tracing::instrument(name = "Opening stock count", skip(req, body, db, redis_pool))]
+#[actix_web::post("/stores/{id}/counts", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn open_stock_count(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewStockCount>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count opening.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::open_count(&db, &store, user_id, body.into_inner()).await {
+        Ok(count) => HttpResponse::Created().json(count),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CountListParams {
+    status: Option<StockCountStatus>,
+}
+
+#[tracing::instrument(name = "Listing stock counts", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/counts")]
+pub async fn list_stock_counts(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<CountListParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count list.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_store_counts(&db, &store, parameters.status).await {
+        Ok(counts) => HttpResponse::Ok().json(counts),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting stock count", skip(req, db, redis_pool))]
+#[actix_web::get("/counts/{id}")]
+pub async fn get_stock_count(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count.");
+
+    match authorize_count(&req, &db, &redis_pool, &path.into_inner(), false).await {
+        Ok((_, count)) => HttpResponse::Ok().json(count),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Scanning stock count units", skip(req, body, db, redis_pool))]
+#[actix_web::post("/counts/{id}/scans")]
+pub async fn scan_units(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CountScans>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count scans.");
+
+    let count = match authorize_count(&req, &db, &redis_pool, &path.into_inner(), false).await {
+        Ok((_, count)) => count,
+        Err(response) => return response,
+    };
+
+    match crate::database::add_scans(&db, count.id, &body.codes).await {
+        Ok(count) => HttpResponse::Ok().json(count),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Closing stock count", skip(req, db, redis_pool))]
+#[actix_web::post("/counts/{id}/close")]
+pub async fn close_stock_count(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count closing.");
+
+    let (user_id, count) = match authorize_count(&req, &db, &redis_pool, &path.into_inner(), false).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::close_count(&db, count.id, user_id).await {
+        Ok(count) => HttpResponse::Ok().json(count),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Approving stock count", skip(req, body, db, redis_pool))]
+#[actix_web::post("/counts/{id}/approve", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn approve_stock_count(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: Option<web::Json<CountApproval>>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count approval.");
+
+    let (user_id, count) = match authorize_count(&req, &db, &redis_pool, &path.into_inner(), true).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    let approval = body.map(|body| body.into_inner()).unwrap_or_default();
+    match crate::database::approve_count(&db, count.id, user_id, approval).await {
+        Ok(count) => HttpResponse::Ok().json(count),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Cancelling stock count", skip(req, db, redis_pool))]
+#[actix_web::post("/counts/{id}/cancel")]
+pub async fn cancel_stock_count(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock count cancelling.");
+
+    let count = match authorize_count(&req, &db, &redis_pool, &path.into_inner(), true).await {
+        Ok((_, count)) => count,
+        Err(response) => return response,
+    };
+
+    match crate::database::cancel_count(&db, count.id).await {
+        Ok(count) => HttpResponse::Ok().json(count),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+/// Checks that the user works at the store of a stock count, or is an admin.
+/// Managing it further, like approving it, takes an owner of the store or an
+/// admin. Returns the user id and the count.
+async fn authorize_count(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    count_id: &str,
+    manager: bool,
+) -> Result<(ObjectId, StockCount), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Ok(count_id) = ObjectId::parse_str(count_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid stock count id.".to_string() }));
+    };
+    let count = match crate::database::get_count(db, count_id).await {
+        Ok(Some(count)) => count,
+        Ok(None) => return Err(inventory_error_response(anyhow!(error::Inventory::CountNotFound))),
+        Err(e) => return Err(inventory_error_response(e)),
+    };
+    let Some(store) = stores::get_store(&count.store) else {
+        return Err(inventory_error_response(anyhow!(error::Inventory::CountNotFound)));
+    };
+
+    if !manager {
+        authorize_store(db, user_id, &store).await?;
+        return Ok((user_id, count));
+    }
+
+    authorize_store_owner(db, user_id, &store).await?;
+
+    Ok((user_id, count))
+}
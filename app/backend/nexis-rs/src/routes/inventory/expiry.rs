@@ -0,0 +1,48 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+};
+use crate::settings::ExpirySettings;
+use crate::utils::{ authorize, auth_error_response };
+use super::{ authorize_store, inventory_error_response };
+
+#[derive(Deserialize, Debug)]
+pub struct ExpiringLotsQuery {
+    days: Option<i64>,
+}
+
+#[tracing::instrument(name = "Listing expiring lots", skip(req, db, redis_pool, settings))]
+#[actix_web::get("/stores/{id}/expiring-lots")]
+pub async fn expiring_lots(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ExpiringLotsQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    settings: web::Data<ExpirySettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing expiring lots.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    let days = query.days.unwrap_or(settings.warning_days);
+    if !(0..=365).contains(&days) {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "The days must be between 0 and 365.".to_string() });
+    }
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_expiry_report(&db, &store, days).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => inventory_error_response(e),
+    }
+}
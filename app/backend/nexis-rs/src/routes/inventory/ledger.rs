@@ -0,0 +1,70 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    requests::inventory::MovementQuery,
+};
+use crate::utils::{ authorize, auth_error_response };
+use super::{ authorize_store, inventory_error_response };
+
+#[tracing::instrument(name = "Listing inventory movements", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/movements")]
+pub async fn list_movements(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<MovementQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing inventory movements.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    if query.coll.as_ref().is_some_and(|coll| !store.item_colls.contains(coll)) {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: format!("`{}` doesn't sell that collection.", store.name) });
+    }
+
+    match crate::database::get_movements(&db, &store, &query).await {
+        Ok(movements) => HttpResponse::Ok().json(movements),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Reconciling inventory", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/reconciliation")]
+pub async fn reconcile_inventory(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing inventory reconciliation.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::reconcile_store(&db, &store).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => inventory_error_response(e),
+    }
+}
@@ -0,0 +1,129 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    error,
+    requests::inventory::{
+        LotUpdate,
+        NewLot,
+        NewWriteOff,
+    },
+};
+use crate::utils::{ authorize, auth_error_response };
+use super::{ ItemPath, LotPath, authorize_inventory, authorize_store, inventory_error_response };
+
+#[tracing::instrument(name = "Listing lots", skip(req, db, redis_pool))]
+#[actix_web::get("/{coll}/{id}/lots")]
+pub async fn list_lots(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing item lots.");
+
+    let (_, item_id) = match authorize_inventory(&req, &db, &redis_pool, &path.coll, &path.id).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::get_lots(&db, &path.coll, item_id).await {
+        Ok(Some(lots)) => HttpResponse::Ok().json(lots),
+        Ok(None) => inventory_error_response(anyhow!(error::Inventory::ItemNotFound)),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Receiving lot", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{coll}/{id}/lots", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn receive_lot(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    body: web::Json<NewLot>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing lot receiving.");
+
+    let (user_id, item_id) = match authorize_inventory(&req, &db, &redis_pool, &path.coll, &path.id).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::receive_lot(&db, &path.coll, item_id, user_id, body.into_inner()).await {
+        Ok(lot) => HttpResponse::Created().json(lot),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Editing lot", skip(req, body, db, redis_pool))]
+#[actix_web::patch("/{coll}/{id}/lots/{lot}")]
+pub async fn update_lot(
+    req: HttpRequest,
+    path: web::Path<LotPath>,
+    body: web::Json<LotUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing lot edit.");
+
+    let (_, item_id, lot_id) = match authorize_lot(&req, &db, &redis_pool, &path).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::update_lot(&db, &path.coll, item_id, lot_id, body.into_inner()).await {
+        Ok(lot) => HttpResponse::Ok().json(lot),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Writing off units", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{coll}/{id}/lots/{lot}/write-offs", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn write_off_units(
+    req: HttpRequest,
+    path: web::Path<LotPath>,
+    body: web::Json<NewWriteOff>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing unit write-off.");
+
+    let (user_id, item_id, lot_id) = match authorize_lot(&req, &db, &redis_pool, &path).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::write_off_units(&db, &path.coll, item_id, lot_id, user_id, body.into_inner()).await {
+        Ok(write_off) => HttpResponse::Created().json(write_off),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+/// Checks that the user works at the store holding a lot, which is the one
+/// selling its item unless the lot was transferred, or is an admin. Returns the
+/// user, item and lot ids.
+async fn authorize_lot(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    path: &LotPath,
+) -> Result<(ObjectId, ObjectId, ObjectId), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Some(home) = stores::get_coll_store(&path.coll) else {
+        return Err(inventory_error_response(anyhow!(error::Inventory::ItemNotFound)));
+    };
+    let Ok(item_id) = ObjectId::parse_str(&path.id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() }));
+    };
+    let Ok(lot_id) = ObjectId::parse_str(&path.lot) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid lot id.".to_string() }));
+    };
+
+    let lot = crate::database::get_lot(db, &path.coll, item_id, lot_id).await.map_err(inventory_error_response)?;
+    let holder = lot.store.and_then(|store| stores::get_store(&store)).unwrap_or(home);
+
+    authorize_store(db, user_id, &holder).await?;
+
+    Ok((user_id, item_id, lot_id))
+}
@@ -0,0 +1,126 @@
+mod lots;
+mod reorder;
+mod expiry;
+mod ledger;
+mod valuation;
+mod counts;
+mod transfers;
+
+use crate::prelude::*;
+use crate::types::{ ErrorResponse, error, mongodb::stores::StoreInfo };
+use crate::utils::{ authorize, auth_error_response };
+
+pub fn inventory_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/inventory")
+            .service(lots::list_lots)
+            .service(lots::receive_lot)
+            .service(lots::update_lot)
+            .service(lots::write_off_units)
+            .service(reorder::set_reorder_levels)
+            .service(reorder::clear_reorder_levels)
+            .service(counts::get_stock_count)
+            .service(counts::scan_units)
+            .service(counts::close_stock_count)
+            .service(counts::approve_stock_count)
+            .service(counts::cancel_stock_count)
+            .service(transfers::get_stock_transfer)
+            .service(transfers::ship_stock_transfer)
+            .service(transfers::receive_stock_transfer)
+            .service(transfers::cancel_stock_transfer)
+    )
+    .service(expiry::expiring_lots)
+    .service(reorder::low_stock)
+    .service(ledger::list_movements)
+    .service(ledger::reconcile_inventory)
+    .service(valuation::inventory_valuation)
+    .service(valuation::gross_margin)
+    .service(counts::open_stock_count)
+    .service(counts::list_stock_counts)
+    .service(transfers::request_stock_transfer)
+    .service(transfers::list_stock_transfers);
+}
+
+#[derive(Deserialize, Debug)]
+struct ItemPath {
+    coll: String,
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct LotPath {
+    coll: String,
+    id: String,
+    lot: String,
+}
+
+/// Checks that the user owns a store, or is an admin.
+async fn authorize_store_owner(db: &mongodb::Database, user_id: ObjectId, store: &StoreInfo) -> Result<(), HttpResponse> {
+    if !utils::is_store_manager(db, user_id, store.id).await.map_err(inventory_error_response)? {
+        return Err(inventory_error_response(anyhow!(error::Inventory::Forbidden(
+            format!("Only the owners of `{}` can do this.", store.name)
+        ))));
+    }
+
+    Ok(())
+}
+
+/// Checks that the user works at the store selling an item collection, or is
+/// an admin. Returns the user and item ids.
+async fn authorize_inventory(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    coll: &str,
+    item_id: &str,
+) -> Result<(ObjectId, ObjectId), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Some(store) = stores::get_coll_store(coll) else {
+        return Err(inventory_error_response(anyhow!(error::Inventory::ItemNotFound)));
+    };
+    let Ok(item_id) = ObjectId::parse_str(item_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() }));
+    };
+
+    authorize_store(db, user_id, &store).await?;
+
+    Ok((user_id, item_id))
+}
+
+/// Checks that the user works at a store, or is an admin.
+async fn authorize_store(db: &mongodb::Database, user_id: ObjectId, store: &StoreInfo) -> Result<(), HttpResponse> {
+    if !utils::is_store_staff(db, user_id, &[store.id]).await.map_err(inventory_error_response)? {
+        return Err(inventory_error_response(anyhow!(error::Inventory::Forbidden(
+            format!("You don't work at `{}`.", store.name)
+        ))));
+    }
+
+    Ok(())
+}
+
+fn inventory_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Inventory>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Inventory::ItemNotFound
+            | error::Inventory::LotNotFound
+            | error::Inventory::CountNotFound
+            | error::Inventory::TransferNotFound => HttpResponse::NotFound().json(error),
+            error::Inventory::Invalid(_)
+            | error::Inventory::InvalidReorder(_)
+            | error::Inventory::InvalidCount(_)
+            | error::Inventory::InvalidTransfer(_)
+            | error::Inventory::UnitNotInLot(_) => HttpResponse::BadRequest().json(error),
+            error::Inventory::NotEnoughUnits(_)
+            | error::Inventory::Changed
+            | error::Inventory::CountInProgress
+            | error::Inventory::CountState(_)
+            | error::Inventory::TransferState(_) => HttpResponse::Conflict().json(error),
+            error::Inventory::Forbidden(msg) => HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else {
+        tracing::error!(target: "mongodb", "Failed to access inventory: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
@@ -0,0 +1,83 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    requests::inventory::ReorderUpdate,
+};
+use crate::settings::StockAlertSettings;
+use crate::utils::{ authorize, auth_error_response };
+use super::{ ItemPath, authorize_inventory, authorize_store, inventory_error_response };
+
+#[tracing::instrument(name = "Setting reorder levels", skip(req, body, db, redis_pool))]
+#[actix_web::put("/{coll}/{id}/reorder")]
+pub async fn set_reorder_levels(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    body: web::Json<ReorderUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing reorder levels.");
+
+    let (_, item_id) = match authorize_inventory(&req, &db, &redis_pool, &path.coll, &path.id).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::set_reorder_levels(&db, &path.coll, item_id, body.into_inner()).await {
+        Ok(levels) => HttpResponse::Ok().json(levels),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Clearing reorder levels", skip(req, db, redis_pool, settings))]
+#[actix_web::delete("/{coll}/{id}/reorder")]
+pub async fn clear_reorder_levels(
+    req: HttpRequest,
+    path: web::Path<ItemPath>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    settings: web::Data<StockAlertSettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing reorder levels.");
+
+    let (_, item_id) = match authorize_inventory(&req, &db, &redis_pool, &path.coll, &path.id).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    // Returns the collection's defaults, which apply from now on
+    match crate::database::clear_reorder_levels(&db, &path.coll, item_id).await {
+        Ok(()) => HttpResponse::Ok().json(settings.reorder(&path.coll)),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Listing low stock", skip(req, db, redis_pool, settings))]
+#[actix_web::get("/stores/{id}/low-stock")]
+pub async fn low_stock(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    settings: web::Data<StockAlertSettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing low stock report.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_low_stock(&db, &store, &settings).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => inventory_error_response(e),
+    }
+}
@@ -0,0 +1,202 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    error,
+    mongodb::{ inventory::StockTransfer, stores::StoreInfo },
+    requests::inventory::{
+        NewTransfer,
+        TransferQuery,
+        TransferReceipt,
+        TransferShipment,
+    },
+};
+use crate::utils::{ authorize, auth_error_response };
+use super::{ authorize_store, inventory_error_response };
+
+#[tracing::instrument(name = "Requesting stock transfer", skip(req, body, db, redis_pool))]
+#[actix_web::post("/stores/{id}/transfers", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn request_stock_transfer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewTransfer>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer request.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::request_transfer(&db, &store, user_id, body.into_inner()).await {
+        Ok(transfer) => HttpResponse::Created().json(transfer),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Listing stock transfers", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/transfers")]
+pub async fn list_stock_transfers(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<TransferQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer list.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_store_transfers(&db, &store, &parameters).await {
+        Ok(transfers) => HttpResponse::Ok().json(transfers),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting stock transfer", skip(req, db, redis_pool))]
+#[actix_web::get("/transfers/{id}")]
+pub async fn get_stock_transfer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer.");
+
+    match authorize_transfer(&req, &db, &redis_pool, &path.into_inner(), TransferParty::Either).await {
+        Ok((_, transfer)) => HttpResponse::Ok().json(transfer),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Shipping stock transfer", skip(req, body, db, redis_pool))]
+#[actix_web::post("/transfers/{id}/ship", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn ship_stock_transfer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<TransferShipment>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer shipping.");
+
+    let (user_id, transfer) = match authorize_transfer(&req, &db, &redis_pool, &path.into_inner(), TransferParty::Sender).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::ship_transfer(&db, transfer.id, user_id, body.into_inner()).await {
+        Ok(transfer) => HttpResponse::Ok().json(transfer),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Receiving stock transfer", skip(req, body, db, redis_pool))]
+#[actix_web::post("/transfers/{id}/receive", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn receive_stock_transfer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<TransferReceipt>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer receiving.");
+
+    let (user_id, transfer) = match authorize_transfer(&req, &db, &redis_pool, &path.into_inner(), TransferParty::Receiver).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::receive_transfer(&db, transfer.id, user_id, body.into_inner()).await {
+        Ok(transfer) => HttpResponse::Ok().json(transfer),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Cancelling stock transfer", skip(req, db, redis_pool))]
+#[actix_web::post("/transfers/{id}/cancel")]
+pub async fn cancel_stock_transfer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing stock transfer cancelling.");
+
+    let (user_id, transfer) = match authorize_transfer(&req, &db, &redis_pool, &path.into_inner(), TransferParty::Either).await {
+        Ok(authorized) => authorized,
+        Err(response) => return response,
+    };
+
+    match crate::database::cancel_transfer(&db, transfer.id, user_id).await {
+        Ok(transfer) => HttpResponse::Ok().json(transfer),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+/// Which of the stores of a transfer the user must work at.
+enum TransferParty {
+    Sender,
+    Receiver,
+    Either,
+}
+
+/// Checks that the user works at the sending or receiving store of a transfer,
+/// or is an admin. Returns the user id and the transfer.
+async fn authorize_transfer(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    transfer_id: &str,
+    party: TransferParty,
+) -> Result<(ObjectId, StockTransfer), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Ok(transfer_id) = ObjectId::parse_str(transfer_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid stock transfer id.".to_string() }));
+    };
+    let transfer = match crate::database::get_transfer(db, transfer_id).await {
+        Ok(Some(transfer)) => transfer,
+        Ok(None) => return Err(inventory_error_response(anyhow!(error::Inventory::TransferNotFound))),
+        Err(e) => return Err(inventory_error_response(e)),
+    };
+
+    let stores: Vec<StoreInfo> = match party {
+        TransferParty::Sender => vec![transfer.from],
+        TransferParty::Receiver => vec![transfer.to],
+        TransferParty::Either => vec![transfer.from, transfer.to],
+    }
+    .iter()
+    .filter_map(stores::get_store)
+    .collect();
+
+    let mut denied = None;
+    for store in &stores {
+        match authorize_store(db, user_id, store).await {
+            Ok(()) => return Ok((user_id, transfer)),
+            Err(response) => denied = Some(response),
+        }
+    }
+
+    Err(denied.unwrap_or_else(|| inventory_error_response(anyhow!(error::Inventory::TransferNotFound))))
+}
@@ -0,0 +1,67 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    requests::inventory::{ MarginQuery, ValuationQuery },
+};
+use crate::utils::{ authorize, auth_error_response };
+use super::{ authorize_store_owner, inventory_error_response };
+
+#[tracing::instrument(name = "Getting inventory valuation", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/valuation")]
+pub async fn inventory_valuation(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<ValuationQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing inventory valuation.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store_owner(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_valuation(&db, &store, &parameters).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => inventory_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting gross margin", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/margin")]
+pub async fn gross_margin(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<MarginQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing gross margin report.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    if let Err(response) = authorize_store_owner(&db, user_id, &store).await {
+        return response;
+    }
+
+    match crate::database::get_margin_report(&db, &store, &parameters).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => inventory_error_response(e),
+    }
+}
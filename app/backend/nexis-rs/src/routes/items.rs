@@ -0,0 +1,63 @@
+use crate::prelude::*;
+use crate::types::ErrorResponse;
+
+#[tracing::instrument(
+    name = "Getting item detail",
+    skip(db)
+)]
+#[actix_web::get("/items/{coll}/{id}")]
+pub async fn item_detail(
+    path: web::Path<(String, String)>,
+    db: web::Data<mongodb::Database>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing item detail.");
+
+    let (coll, id) = path.into_inner();
+
+    let Some(store) = stores::get_coll_store(&coll) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: format!("Unknown item collection `{}`.", coll) });
+    };
+
+    let Ok(id) = ObjectId::parse_str(&id) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid item id.".to_string() });
+    };
+
+    let item_coll: Collection<Document> = db.collection(&coll);
+    let mut item = match item_coll.find_one(doc! { "_id": id }).await {
+        Ok(Some(item)) => item,
+        Ok(None) => return HttpResponse::NotFound().json(ErrorResponse { error: "Item not found.".to_string() }),
+        Err(e) => {
+            tracing::error!(target: "mongodb", "Failed to get item: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let now = bson::DateTime::now();
+    let stock = utils::stock::available_units(&utils::stock::lot_units(&item), now);
+
+    // Replace the unit codes of each lot with their amount, since the codes
+    // themselves are only meaningful to store employees, as is what the lot cost.
+    // Lots transferred to other stores aren't sold here.
+    if let Ok(lots) = item.get_array_mut("lot") {
+        lots.retain(|lot| lot.as_document().is_some_and(utils::stock::lot_is_home));
+        for lot in lots.iter_mut().filter_map(|lot| lot.as_document_mut()) {
+            let units = lot.get_array("code").map(|codes| codes.len() as i64).unwrap_or(0);
+            let sellable = utils::stock::lot_is_sellable(lot, now);
+            lot.remove("code");
+            lot.remove("costPrice");
+            lot.insert("units", units);
+            lot.insert("sellable", sellable);
+        }
+    }
+
+    // Reorder levels and who edited the item are only for the staff
+    for key in ["reorder", "createdBy", "updatedBy", "archivedBy"] {
+        item.remove(key);
+    }
+
+    item.insert("store", store.name);
+    item.insert("coll", coll);
+    item.insert("stock", stock);
+
+    HttpResponse::Ok().json(item)
+}
@@ -1,7 +1,28 @@
 mod health;
 mod users;
 mod common;
+mod admin;
+mod items;
+mod cart;
+mod orders;
+pub mod payments;
+mod pos;
+pub mod returns;
+mod receipts;
+mod inventory;
+mod purchasing;
+mod catalog;
 
 pub use health::health_check;
 pub use users::auth_routes_config;
-pub use common::{ search_suggestions, search };
\ No newline at end of file
+pub use admin::admin_routes_config;
+pub use common::{ search_suggestions, search, trending_searches };
+pub use items::item_detail;
+pub use cart::{ cart_routes_config, GUEST_CART_COOKIE };
+pub use orders::orders_routes_config;
+pub use payments::payments_routes_config;
+pub use pos::pos_routes_config;
+pub use returns::returns_routes_config;
+pub use inventory::inventory_routes_config;
+pub use purchasing::purchasing_routes_config;
+pub use catalog::catalog_routes_config;
@@ -0,0 +1,420 @@
+use crate::prelude::*;
+use crate::payments::PaymentProvider;
+use crate::routes::receipts::{ ReceiptParams, receipt_response };
+use crate::types::{
+    ErrorResponse,
+    SuccessResponse,
+    error,
+    mongodb::orders::{ Order, OrderStatus },
+    requests::{ orders::{ CheckoutRequest, StatusChange }, returns::OrderReturnRequest },
+};
+use crate::utils::{ Role, authorize, auth_error_response };
+
+pub fn orders_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/orders")
+            .service(checkout)
+            .service(list_orders)
+            .service(get_order)
+            .service(change_order_status)
+            .service(super::payments::pay_order)
+            .service(request_return)
+            .service(order_receipt)
+            .service(resend_order_receipt)
+    )
+    .service(store_order_queue);
+}
+
+#[tracing::instrument(name = "Checking out", skip(req, body, db, redis_pool))]
+#[actix_web::post("/checkout", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn checkout(
+    req: HttpRequest,
+    body: Option<web::Json<CheckoutRequest>>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing checkout.");
+
+    let user_id = match authorize(&req, Some(Role::Client), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let accept_price_changes = body.map(|body| body.accept_price_changes).unwrap_or_default();
+
+    match crate::database::checkout(&db, &redis_pool, user_id, accept_price_changes).await {
+        Ok(order) => HttpResponse::Created().json(order),
+        Err(e) => checkout_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OrderListParams {
+    page: Option<u64>,
+}
+
+#[tracing::instrument(name = "Listing orders", skip(req, db, redis_pool))]
+#[actix_web::get("")]
+pub async fn list_orders(
+    req: HttpRequest,
+    parameters: web::Query<OrderListParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order list.");
+
+    const PAGE_SIZE: i64 = 20;
+
+    let user_id = match authorize(&req, Some(Role::Client), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    match crate::database::get_user_orders(&db, user_id, parameters.page.unwrap_or(0), PAGE_SIZE).await {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(e) => order_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting order", skip(req, db, redis_pool))]
+#[actix_web::get("/{id}")]
+pub async fn get_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    match get_accessible_order(&db, user_id, &path.into_inner()).await {
+        Ok((order, _)) => HttpResponse::Ok().json(order),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Changing order status", skip(req, change, db, redis_pool, provider))]
+#[actix_web::post("/{id}/status", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn change_order_status(
+    req: HttpRequest,
+    path: web::Path<String>,
+    change: web::Json<StatusChange>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order status change.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let (order, access) = match get_accessible_order(&db, user_id, &path.into_inner()).await {
+        Ok(order) => order,
+        Err(response) => return response,
+    };
+
+    let StatusChange { status, note } = change.into_inner();
+
+    // Cancelling again retries settling the payments of a cancelled order
+    let retry = order.status == OrderStatus::Cancelled && status == OrderStatus::Cancelled && order.refund_due;
+
+    if !retry && !order.status.can_become(status) {
+        return order_error_response(anyhow!(error::Orders::IllegalTransition {
+            from: order.status.to_string(),
+            to: status.to_string(),
+        }));
+    }
+
+    if !retry && !access.may_change_status(order.status, status) {
+        return order_error_response(anyhow!(error::Orders::Forbidden(
+            format!("You can't mark this order as `{}`.", status)
+        )));
+    }
+
+    let mut order = if retry {
+        order
+    } else {
+        match crate::database::transition_order(&db, order.id, status, Some(user_id), note).await {
+            Ok(order) => order,
+            Err(e) => return order_error_response(e),
+        }
+    };
+
+    if order.status == OrderStatus::Paid {
+        crate::receipts::spawn_order_receipt_email(db.get_ref().clone(), order.id);
+    }
+    if order.status != OrderStatus::Cancelled {
+        return HttpResponse::Ok().json(order);
+    }
+
+    // Give the money of a cancelled order back. If the provider fails, the order
+    // keeps `refundDue` until cancelling it again settles its payments
+    let refunded = match crate::database::settle_cancelled_order_payments(&db, provider.get_ref(), &order).await {
+        Ok(refunded) => refunded,
+        Err(e) => return super::payments::payment_error_response(e),
+    };
+    if refunded <= 0.0 {
+        return match crate::database::clear_refund_due(&db, &mut order).await {
+            Ok(()) => HttpResponse::Ok().json(order),
+            Err(e) => order_error_response(e),
+        };
+    }
+
+    let note = Some(format!("Refunded {:.2} on cancellation.", refunded));
+    match crate::database::transition_order(&db, order.id, OrderStatus::Refunded, None, note).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => order_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Requesting order return", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{id}/returns")]
+pub async fn request_return(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<OrderReturnRequest>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order return request.");
+
+    let user_id = match authorize(&req, Some(Role::Client), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let order = match get_accessible_order(&db, user_id, &path.into_inner()).await {
+        Ok((order, access)) if access.owner => order,
+        Ok(_) => return order_error_response(anyhow!(error::Orders::NotFound)),
+        Err(response) => return response,
+    };
+
+    let OrderReturnRequest { codes, reason } = body.into_inner();
+
+    match crate::database::request_order_return(&db, &order, &codes, reason).await {
+        Ok(record) => HttpResponse::Created().json(record),
+        Err(e) => super::returns::return_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting order receipt", skip(req, db, redis_pool))]
+#[actix_web::get("/{id}/receipt")]
+pub async fn order_receipt(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<ReceiptParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order receipt.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let order = match get_accessible_order(&db, user_id, &path.into_inner()).await {
+        Ok((order, _)) => order,
+        Err(response) => return response,
+    };
+
+    receipt_response(
+        crate::receipts::build_order_receipt(&db, &order).await,
+        parameters.format.unwrap_or_default(),
+    )
+}
+
+#[tracing::instrument(name = "Resending order receipt", skip(req, db, redis_pool))]
+#[actix_web::post("/{id}/receipt/resend")]
+pub async fn resend_order_receipt(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order receipt resend.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let order = match get_accessible_order(&db, user_id, &path.into_inner()).await {
+        Ok((order, _)) => order,
+        Err(response) => return response,
+    };
+
+    match crate::receipts::email_order_receipt(&db, &order).await {
+        Ok(()) => HttpResponse::Accepted().json(SuccessResponse { message: "Receipt sent to the client's email.".to_string() }),
+        Err(e) => {
+            tracing::error!(target: "backend", "Failed to resend the receipt of order {}: {}", order.id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QueueParams {
+    /// Comma-separated statuses.
+    status: Option<String>,
+}
+
+#[tracing::instrument(name = "Getting store order queue", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/orders")]
+pub async fn store_order_queue(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<QueueParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing store order queue.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Some(store) = ObjectId::parse_str(path.into_inner()).ok().and_then(|id| stores::get_store(&id)) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    let statuses: Vec<OrderStatus> = match &parameters.status {
+        Some(statuses) => match statuses.split(',')
+            .map(|status| serde_json::from_value(serde_json::Value::String(status.trim().to_string())))
+            .collect::<Result<Vec<OrderStatus>, _>>()
+        {
+            Ok(statuses) => statuses,
+            Err(_) => return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid order status.".to_string() }),
+        },
+        None => vec![OrderStatus::Paid, OrderStatus::Preparing, OrderStatus::Ready],
+    };
+
+    match utils::is_store_staff(&db, user_id, &[store.id]).await {
+        Ok(true) => {}
+        Ok(false) => return order_error_response(anyhow!(error::Orders::Forbidden(
+            format!("You don't work at `{}`.", store.name)
+        ))),
+        Err(e) => return order_error_response(e),
+    }
+
+    match crate::database::get_store_order_queue(&db, &store, &statuses).await {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(e) => order_error_response(e),
+    }
+}
+
+/// How a user is related to an order.
+struct OrderAccess {
+    owner: bool,
+    /// Works at the store of a sub-order, or at one with items in an order
+    /// placed before orders were split by store.
+    staff: bool,
+    admin: bool,
+}
+
+impl OrderAccess {
+    fn may_change_status(&self, current: OrderStatus, next: OrderStatus) -> bool {
+        match next {
+            // Customers can only cancel orders that aren't being prepared yet
+            OrderStatus::Cancelled =>
+                self.staff || self.admin || (self.owner && matches!(current, OrderStatus::Pending | OrderStatus::Paid)),
+            OrderStatus::Preparing | OrderStatus::Ready | OrderStatus::Completed =>
+                self.staff || self.admin,
+            // Only reached through the payment provider, so that the order and
+            // its payments always agree: `paid` once a payment is captured, and
+            // `refunded` once the money is given back by a return or a cancellation
+            OrderStatus::Paid | OrderStatus::Refunded | OrderStatus::Pending | OrderStatus::Expired => false,
+        }
+    }
+}
+
+/// Gets an order the user may see: their own, a sub-order of a store they
+/// work at, or any if they're an admin. Other orders are reported as not found.
+async fn get_accessible_order(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+    order_id: &str,
+) -> Result<(Order, OrderAccess), HttpResponse> {
+    let not_found = || order_error_response(anyhow!(error::Orders::NotFound));
+
+    let Ok(order_id) = ObjectId::parse_str(order_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid order id.".to_string() }));
+    };
+
+    let order = match crate::database::get_order(db, order_id).await {
+        Ok(Some(order)) => order,
+        Ok(None) => return Err(not_found()),
+        Err(e) => return Err(order_error_response(e)),
+    };
+
+    let staff_stores: Vec<ObjectId> = crate::database::get_staff_stores(db, user_id).await
+        .map_err(order_error_response)?
+        .into_iter()
+        .map(|store| store.id)
+        .collect();
+    let admin = utils::user_has_role(db, user_id, Role::Admin).await.map_err(order_error_response)?;
+
+    let access = OrderAccess {
+        owner: order.user == user_id,
+        staff: !order.is_parent() && order_stores(&order).iter().any(|store| staff_stores.contains(store)),
+        admin,
+    };
+
+    if access.owner || access.staff || access.admin {
+        Ok((order, access))
+    } else {
+        Err(not_found())
+    }
+}
+
+/// `_id`s of the stores fulfilling an order. Orders placed before they were
+/// split by store are matched to their stores through their items' collections.
+fn order_stores(order: &Order) -> Vec<ObjectId> {
+    if let Some(store) = order.store {
+        return vec![store];
+    }
+
+    order.items.iter()
+        .filter_map(|item| stores::get_coll_store(&item.coll).map(|store| store.id))
+        .collect()
+}
+
+fn order_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Orders>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Orders::NotFound => HttpResponse::NotFound().json(error),
+            error::Orders::IllegalTransition { .. }
+            | error::Orders::Changed
+            | error::Orders::SubOrders(_) => HttpResponse::Conflict().json(error),
+            error::Orders::Forbidden(msg) => HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else {
+        tracing::error!(target: "mongodb", "Failed to access order: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
+
+fn checkout_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Checkout>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Checkout::EmptyCart | error::Checkout::MissingWeight(_) =>
+                HttpResponse::BadRequest().json(error),
+            error::Checkout::ItemNotFound(_) =>
+                HttpResponse::NotFound().json(error),
+            error::Checkout::PricesChanged | error::Checkout::OutOfStock { .. } =>
+                HttpResponse::Conflict().json(error),
+        }
+    } else {
+        tracing::error!(target: "mongodb", "Failed to check out: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
@@ -0,0 +1,96 @@
+use crate::prelude::*;
+use crate::payments::{ PaymentProvider, SIGNATURE_HEADER, process_callback };
+use crate::settings::PaymentSettings;
+use crate::types::{
+    ErrorResponse,
+    error,
+    mongodb::payments::PaymentStatus,
+    requests::payments::PayOrder,
+};
+use crate::utils::{ Role, authorize, auth_error_response };
+
+pub fn payments_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/payments")
+            .service(payment_webhook)
+    );
+}
+
+#[tracing::instrument(name = "Paying order", skip(req, body, db, redis_pool, provider))]
+#[actix_web::post("/{id}/pay", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn pay_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PayOrder>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing order payment.");
+
+    let user_id = match authorize(&req, Some(Role::Client), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Ok(order_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid order id.".to_string() });
+    };
+
+    let order = match crate::database::get_order(&db, order_id).await {
+        Ok(Some(order)) if order.user == user_id => order,
+        Ok(_) => return HttpResponse::NotFound().json(ErrorResponse { error: "Order not found.".to_string() }),
+        Err(e) => return payment_error_response(e),
+    };
+
+    match crate::database::pay_order(&db, provider.get_ref(), &order, body.into_inner().method).await {
+        Ok(payment) if payment.status == PaymentStatus::Pending => HttpResponse::Accepted().json(payment),
+        Ok(payment) => HttpResponse::Ok().json(payment),
+        Err(e) => payment_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Receiving payment callback", skip(req, body, db, provider, settings))]
+#[actix_web::post("/webhooks/{provider}", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn payment_webhook(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    db: web::Data<mongodb::Database>,
+    provider: web::Data<dyn PaymentProvider>,
+    settings: web::Data<PaymentSettings>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing payment webhook.");
+
+    if path.into_inner() != provider.name() {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Unknown payment provider.".to_string() });
+    }
+
+    let Some(signature) = req.headers().get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok()) else {
+        return HttpResponse::Unauthorized().json(ErrorResponse { error: "Signature header missing.".to_string() });
+    };
+
+    match process_callback(&db, provider.get_ref(), &settings, signature, &body).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) if e.is::<serde_json::Error>() =>
+            HttpResponse::BadRequest().json(ErrorResponse { error: format!("Invalid callback: {}", e) }),
+        Err(e) => payment_error_response(e),
+    }
+}
+
+pub fn payment_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Payments>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Payments::OrderNotPayable(_) | error::Payments::AlreadyPaid => HttpResponse::Conflict().json(error),
+            error::Payments::UnsupportedMethod(_) => HttpResponse::BadRequest().json(error),
+            error::Payments::Declined(_) => HttpResponse::PaymentRequired().json(error),
+            error::Payments::ProviderError(_) => HttpResponse::BadGateway().json(error),
+            error::Payments::InvalidSignature(_) => HttpResponse::Unauthorized().json(error),
+            error::Payments::UnknownPayment => HttpResponse::NotFound().json(error),
+        }
+    } else {
+        tracing::error!(target: "backend", "Failed to process payment: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
@@ -0,0 +1,242 @@
+use crate::prelude::*;
+use crate::payments::PaymentProvider;
+use crate::routes::receipts::{ ReceiptParams, receipt_response };
+use crate::types::{
+    ErrorResponse,
+    SuccessResponse,
+    error,
+    mongodb::payments::PaymentMethod,
+    mongodb::stores::DaySale,
+    requests::{ pos::NewSale, receipts::ResendReceipt, returns::SaleReturn },
+    responses::PosSale,
+};
+use crate::utils::{ Role, authorize, auth_error_response };
+
+pub fn pos_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/pos")
+            .service(record_sale)
+            .service(lookup_code)
+            .service(return_units)
+            .service(sale_receipt)
+            .service(resend_sale_receipt)
+    );
+}
+
+#[tracing::instrument(name = "Charging POS sale", skip(req, body, db, redis_pool, provider))]
+#[actix_web::post("/sales", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn record_sale(
+    req: HttpRequest,
+    body: web::Json<NewSale>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing POS sale.");
+
+    let cashier = match authorize(&req, Some(Role::Employee), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let sale = body.into_inner();
+
+    let store = match crate::database::get_staff_stores(&db, cashier).await {
+        Ok(stores) => stores.into_iter().find(|store| store.id == sale.store),
+        Err(e) => return pos_error_response(e),
+    };
+    let Some(store) = store else {
+        return pos_error_response(anyhow!(error::Pos::Forbidden("You don't work at this store.".into())));
+    };
+
+    let tendered = match sale.payment.method {
+        PaymentMethod::Cash(_) => sale.payment.tendered,
+        _ => None,
+    };
+
+    match crate::database::record_sale(&db, provider.get_ref(), cashier, &store, sale).await {
+        Ok(sale) => {
+            crate::receipts::spawn_sale_receipt_email(db.get_ref().clone(), store.id, sale.clone());
+            HttpResponse::Created().json(PosSale {
+                change: tendered.map(|tendered| ((tendered - sale.payment.amount) * 100.0).round() / 100.0),
+                sale,
+            })
+        }
+        Err(e) => pos_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Looking up unit code", skip(req, db, redis_pool))]
+#[actix_web::get("/codes/{code}")]
+pub async fn lookup_code(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing unit code lookup.");
+
+    if let Err(e) = authorize(&req, Some(Role::Employee), &db, &redis_pool).await {
+        return auth_error_response(e);
+    }
+
+    let Ok(code) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid unit code.".to_string() });
+    };
+
+    match crate::database::lookup_unit(&db, code).await {
+        Ok(Some(unit)) => HttpResponse::Ok().json(unit),
+        Ok(None) => HttpResponse::NotFound().json(ErrorResponse { error: "Unknown unit code.".to_string() }),
+        Err(e) => pos_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Returning units at POS", skip(req, body, db, redis_pool, provider))]
+#[actix_web::post("/returns", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn return_units(
+    req: HttpRequest,
+    body: web::Json<SaleReturn>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing POS return.");
+
+    let employee = match authorize(&req, Some(Role::Employee), &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let SaleReturn { store, sale, codes, condition, reason } = body.into_inner();
+
+    let store = match crate::database::get_staff_stores(&db, employee).await {
+        Ok(stores) => stores.into_iter().find(|staff_store| staff_store.id == store),
+        Err(e) => return pos_error_response(e),
+    };
+    let Some(store) = store else {
+        return pos_error_response(anyhow!(error::Pos::Forbidden("You don't work at this store.".into())));
+    };
+
+    match crate::database::return_sale_units(&db, provider.get_ref(), employee, &store, sale, &codes, condition, reason).await {
+        Ok(record) => HttpResponse::Created().json(record),
+        Err(e) => super::returns::return_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting sale receipt", skip(req, db, redis_pool))]
+#[actix_web::get("/sales/{id}/receipt")]
+pub async fn sale_receipt(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<ReceiptParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing sale receipt.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let (store_id, sale, _) = match get_accessible_sale(&db, user_id, &path.into_inner()).await {
+        Ok(sale) => sale,
+        Err(response) => return response,
+    };
+
+    receipt_response(
+        crate::receipts::build_sale_receipt(&db, store_id, &sale).await,
+        parameters.format.unwrap_or_default(),
+    )
+}
+
+#[tracing::instrument(name = "Resending sale receipt", skip(req, body, db, redis_pool))]
+#[actix_web::post("/sales/{id}/receipt/resend")]
+pub async fn resend_sale_receipt(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: Option<web::Json<ResendReceipt>>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing sale receipt resend.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let (store_id, sale, staff) = match get_accessible_sale(&db, user_id, &path.into_inner()).await {
+        Ok(sale) => sale,
+        Err(response) => return response,
+    };
+
+    let email = body.and_then(|body| body.into_inner().email);
+    if let Some(email) = &email {
+        if !staff {
+            return pos_error_response(anyhow!(error::Pos::Forbidden("Only the store can send receipts to other addresses.".into())));
+        }
+        if email.parse::<lettre::Address>().is_err() {
+            return pos_error_response(anyhow!(error::Pos::Invalid("Invalid email address.".into())));
+        }
+    }
+
+    match crate::receipts::email_sale_receipt(&db, store_id, &sale, email).await {
+        Ok(true) => HttpResponse::Accepted().json(SuccessResponse { message: "Receipt sent.".to_string() }),
+        Ok(false) => pos_error_response(anyhow!(error::Pos::Invalid(
+            "The buyer isn't a registered user, so an email address is required.".into()
+        ))),
+        Err(e) => {
+            tracing::error!(target: "backend", "Failed to resend the receipt of sale {}: {}", sale.id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Gets a sale the user may see: one of a store they work at, any if they're
+/// an admin, or one they made as a registered client. Other sales are reported
+/// as not found. Returns it with its store id and whether the user is staff.
+async fn get_accessible_sale(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+    sale_id: &str,
+) -> Result<(ObjectId, DaySale, bool), HttpResponse> {
+    let not_found = || HttpResponse::NotFound().json(ErrorResponse { error: "Sale not found.".to_string() });
+
+    let Ok(sale_id) = ObjectId::parse_str(sale_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid sale id.".to_string() }));
+    };
+
+    let (store_id, sale) = match crate::database::get_sale(db, sale_id).await {
+        Ok(Some(sale)) => sale,
+        Ok(None) => return Err(not_found()),
+        Err(e) => return Err(pos_error_response(e)),
+    };
+
+    let staff = utils::is_store_staff(db, user_id, &[store_id]).await.map_err(pos_error_response)?;
+
+    if staff || sale.client.user == Some(user_id) {
+        Ok((store_id, sale, staff))
+    } else {
+        Err(not_found())
+    }
+}
+
+pub fn pos_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Pos>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Pos::Forbidden(_) => HttpResponse::Forbidden().json(error),
+            error::Pos::UnitUnavailable(_) | error::Pos::Expired(..) => HttpResponse::Conflict().json(error),
+            error::Pos::EmptySale
+            | error::Pos::DuplicateCode(_)
+            | error::Pos::MissingWeight(_)
+            | error::Pos::Invalid(_) => HttpResponse::BadRequest().json(error),
+        }
+    } else if e.is::<error::Payments>() {
+        super::payments::payment_error_response(e)
+    } else {
+        tracing::error!(target: "backend", "Failed to process POS request: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
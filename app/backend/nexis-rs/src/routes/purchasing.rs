@@ -0,0 +1,420 @@
+use crate::prelude::*;
+use crate::types::{
+    ErrorResponse,
+    error,
+    mongodb::{ purchasing::{ PurchaseOrder, Supplier }, stores::StoreInfo },
+    requests::purchasing::{
+        NewPurchaseOrder,
+        NewSupplier,
+        PurchaseOrderDelivery,
+        PurchaseOrderQuery,
+        PurchaseOrderUpdate,
+        SupplierUpdate,
+    },
+};
+use crate::utils::{ authorize, auth_error_response };
+
+pub fn purchasing_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/suppliers")
+            .service(get_supplier)
+            .service(update_supplier)
+            .service(delete_supplier)
+    )
+    .service(
+        web::scope("/purchase-orders")
+            .service(get_purchase_order)
+            .service(update_purchase_order)
+            .service(delete_purchase_order)
+            .service(send_purchase_order)
+            .service(receive_purchase_order)
+            .service(close_purchase_order)
+    )
+    .service(create_supplier)
+    .service(list_suppliers)
+    .service(create_purchase_order)
+    .service(list_purchase_orders);
+}
+
+#[tracing::instrument(name = "Adding supplier", skip(req, body, db, redis_pool))]
+#[actix_web::post("/stores/{id}/suppliers")]
+pub async fn create_supplier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewSupplier>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing supplier creation.");
+
+    let store = match authorize_store_path(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, store)) => store,
+        Err(response) => return response,
+    };
+
+    match crate::database::create_supplier(&db, &store, body.into_inner()).await {
+        Ok(supplier) => HttpResponse::Created().json(supplier),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SupplierListParams {
+    #[serde(default)]
+    archived: bool,
+}
+
+#[tracing::instrument(name = "Listing suppliers", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/suppliers")]
+pub async fn list_suppliers(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<SupplierListParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing supplier list.");
+
+    let store = match authorize_store_path(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, store)) => store,
+        Err(response) => return response,
+    };
+
+    match crate::database::get_store_suppliers(&db, &store, parameters.archived).await {
+        Ok(suppliers) => HttpResponse::Ok().json(suppliers),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting supplier", skip(req, db, redis_pool))]
+#[actix_web::get("/{id}")]
+pub async fn get_supplier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing supplier.");
+
+    match authorize_supplier(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok(supplier) => HttpResponse::Ok().json(supplier),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Editing supplier", skip(req, body, db, redis_pool))]
+#[actix_web::patch("/{id}")]
+pub async fn update_supplier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<SupplierUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing supplier editing.");
+
+    let supplier = match authorize_supplier(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok(supplier) => supplier,
+        Err(response) => return response,
+    };
+
+    match crate::database::update_supplier(&db, supplier.id, body.into_inner()).await {
+        Ok(supplier) => HttpResponse::Ok().json(supplier),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Deleting supplier", skip(req, db, redis_pool))]
+#[actix_web::delete("/{id}")]
+pub async fn delete_supplier(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing supplier deletion.");
+
+    let supplier = match authorize_supplier(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok(supplier) => supplier,
+        Err(response) => return response,
+    };
+
+    match crate::database::delete_supplier(&db, supplier.id).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Creating purchase order", skip(req, body, db, redis_pool))]
+#[actix_web::post("/stores/{id}/purchase-orders", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn create_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<NewPurchaseOrder>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order creation.");
+
+    let (user_id, store) = match authorize_store_path(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok(access) => access,
+        Err(response) => return response,
+    };
+
+    match crate::database::create_purchase_order(&db, &store, user_id, body.into_inner()).await {
+        Ok(order) => HttpResponse::Created().json(order),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Listing purchase orders", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/purchase-orders")]
+pub async fn list_purchase_orders(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<PurchaseOrderQuery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order list.");
+
+    let store = match authorize_store_path(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, store)) => store,
+        Err(response) => return response,
+    };
+
+    match crate::database::get_store_purchase_orders(&db, &store, &parameters).await {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Getting purchase order", skip(req, db, redis_pool))]
+#[actix_web::get("/{id}")]
+pub async fn get_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order.");
+
+    match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, order, _)) => HttpResponse::Ok().json(order),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Editing purchase order", skip(req, body, db, redis_pool))]
+#[actix_web::patch("/{id}")]
+pub async fn update_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PurchaseOrderUpdate>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order editing.");
+
+    let (order, store) = match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, order, store)) => (order, store),
+        Err(response) => return response,
+    };
+
+    match crate::database::update_purchase_order(&db, &store, order.id, body.into_inner()).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Deleting purchase order", skip(req, db, redis_pool))]
+#[actix_web::delete("/{id}")]
+pub async fn delete_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order deletion.");
+
+    let order = match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, order, _)) => order,
+        Err(response) => return response,
+    };
+
+    match crate::database::delete_purchase_order(&db, order.id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Sending purchase order", skip(req, db, redis_pool))]
+#[actix_web::post("/{id}/send")]
+pub async fn send_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order sending.");
+
+    let order = match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, order, _)) => order,
+        Err(response) => return response,
+    };
+
+    match crate::database::send_purchase_order(&db, order.id).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Receiving purchase order", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{id}/receive", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn receive_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<PurchaseOrderDelivery>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order receiving.");
+
+    let (user_id, order) = match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((user_id, order, _)) => (user_id, order),
+        Err(response) => return response,
+    };
+
+    match crate::database::receive_purchase_order(&db, order.id, user_id, body.into_inner()).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Closing purchase order", skip(req, db, redis_pool))]
+#[actix_web::post("/{id}/close")]
+pub async fn close_purchase_order(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing purchase order closing.");
+
+    let order = match authorize_purchase_order(&req, &db, &redis_pool, &path.into_inner()).await {
+        Ok((_, order, _)) => order,
+        Err(response) => return response,
+    };
+
+    match crate::database::close_purchase_order(&db, order.id).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => purchasing_error_response(e),
+    }
+}
+
+/// Checks that the user owns the store of the path, or is an admin. Returns
+/// the user id and the store.
+async fn authorize_store_path(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    store_id: &str,
+) -> Result<(ObjectId, StoreInfo), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Some(store) = ObjectId::parse_str(store_id).ok().and_then(|id| stores::get_store(&id)) else {
+        return Err(HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() }));
+    };
+
+    authorize_store_owner(db, user_id, &store).await?;
+
+    Ok((user_id, store))
+}
+
+/// Checks that the user owns the store of a supplier, or is an admin.
+async fn authorize_supplier(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    supplier_id: &str,
+) -> Result<Supplier, HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Ok(supplier_id) = ObjectId::parse_str(supplier_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid supplier id.".to_string() }));
+    };
+    let supplier = match crate::database::get_supplier(db, supplier_id).await {
+        Ok(Some(supplier)) => supplier,
+        Ok(None) => return Err(purchasing_error_response(anyhow!(error::Purchasing::SupplierNotFound))),
+        Err(e) => return Err(purchasing_error_response(e)),
+    };
+    let Some(store) = stores::get_store(&supplier.store) else {
+        return Err(purchasing_error_response(anyhow!(error::Purchasing::SupplierNotFound)));
+    };
+
+    authorize_store_owner(db, user_id, &store).await?;
+
+    Ok(supplier)
+}
+
+/// Checks that the user owns the store of a purchase order, or is an admin.
+/// Returns the user id, the order and its store.
+async fn authorize_purchase_order(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+    order_id: &str,
+) -> Result<(ObjectId, PurchaseOrder, StoreInfo), HttpResponse> {
+    let user_id = authorize(req, None, db, redis_pool).await.map_err(auth_error_response)?;
+
+    let Ok(order_id) = ObjectId::parse_str(order_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid purchase order id.".to_string() }));
+    };
+    let order = match crate::database::get_purchase_order(db, order_id).await {
+        Ok(Some(order)) => order,
+        Ok(None) => return Err(purchasing_error_response(anyhow!(error::Purchasing::OrderNotFound))),
+        Err(e) => return Err(purchasing_error_response(e)),
+    };
+    let Some(store) = stores::get_store(&order.store) else {
+        return Err(purchasing_error_response(anyhow!(error::Purchasing::OrderNotFound)));
+    };
+
+    authorize_store_owner(db, user_id, &store).await?;
+
+    Ok((user_id, order, store))
+}
+
+async fn authorize_store_owner(db: &mongodb::Database, user_id: ObjectId, store: &StoreInfo) -> Result<(), HttpResponse> {
+    if !utils::is_store_manager(db, user_id, store.id).await.map_err(purchasing_error_response)? {
+        return Err(purchasing_error_response(anyhow!(error::Purchasing::Forbidden(
+            format!("Only the owners of `{}` can do this.", store.name)
+        ))));
+    }
+
+    Ok(())
+}
+
+fn purchasing_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Purchasing>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Purchasing::SupplierNotFound | error::Purchasing::OrderNotFound => HttpResponse::NotFound().json(error),
+            error::Purchasing::LineNotFound(_)
+            | error::Purchasing::InvalidSupplier(_)
+            | error::Purchasing::Invalid(_)
+            | error::Purchasing::OverReceived { .. } => HttpResponse::BadRequest().json(error),
+            error::Purchasing::InvalidState(_) | error::Purchasing::Changed => HttpResponse::Conflict().json(error),
+            error::Purchasing::Forbidden(msg) => HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else if let Some(e) = e.downcast_ref::<error::Inventory>() {
+        // Lots received for a delivery
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Inventory::ItemNotFound => HttpResponse::NotFound().json(error),
+            _ => HttpResponse::BadRequest().json(error),
+        }
+    } else {
+        tracing::error!(target: "mongodb", "Failed to access purchasing: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
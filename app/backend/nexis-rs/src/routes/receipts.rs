@@ -0,0 +1,40 @@
+use crate::prelude::*;
+use crate::receipts::{ Receipt, render_html, render_pdf };
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptFormat {
+    #[default]
+    Html,
+    Pdf,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReceiptParams {
+    pub format: Option<ReceiptFormat>,
+}
+
+/// Renders a receipt as an HTML page or a downloadable PDF.
+pub fn receipt_response(receipt: anyhow::Result<Receipt>, format: ReceiptFormat) -> HttpResponse {
+    let rendered = receipt.and_then(|receipt| match format {
+        ReceiptFormat::Html => render_html(&receipt).map(|html| (receipt, html.into_bytes())),
+        ReceiptFormat::Pdf => render_pdf(&receipt).map(|pdf| (receipt, pdf)),
+    });
+
+    match rendered {
+        Ok((_, html)) if format == ReceiptFormat::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(html),
+        Ok((receipt, pdf)) => HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header((
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"receipt-{}.pdf\"", receipt.number),
+            ))
+            .body(pdf),
+        Err(e) => {
+            tracing::error!(target: "backend", "Failed to render receipt: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
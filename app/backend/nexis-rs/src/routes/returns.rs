@@ -0,0 +1,194 @@
+use crate::prelude::*;
+use crate::payments::PaymentProvider;
+use crate::types::{
+    ErrorResponse,
+    error,
+    mongodb::returns::{ ReturnRecord, ReturnStatus },
+    requests::returns::{ ReturnApproval, ReturnRejection },
+};
+use crate::utils::{ authorize, auth_error_response };
+
+pub fn returns_routes_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/returns")
+            .service(get_return)
+            .service(approve_return)
+            .service(reject_return)
+    )
+    .service(store_returns_queue);
+}
+
+#[tracing::instrument(name = "Getting return", skip(req, db, redis_pool))]
+#[actix_web::get("/{id}")]
+pub async fn get_return(
+    req: HttpRequest,
+    path: web::Path<String>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing return.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    match get_accessible_return(&db, user_id, &path.into_inner()).await {
+        Ok((record, _)) => HttpResponse::Ok().json(record),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "Approving return", skip(req, body, db, redis_pool, provider))]
+#[actix_web::post("/{id}/approve", wrap = "actix_web::middleware::from_fn(utils::idempotency)")]
+pub async fn approve_return(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<ReturnApproval>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+    provider: web::Data<dyn PaymentProvider>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing return approval.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let record = match get_accessible_return(&db, user_id, &path.into_inner()).await {
+        Ok((record, true)) => record,
+        Ok(_) => return return_error_response(anyhow!(error::Returns::Forbidden("Only the store can approve returns.".into()))),
+        Err(response) => return response,
+    };
+
+    match crate::database::approve_return(&db, provider.get_ref(), record.id, user_id, body.condition).await {
+        Ok(record) => HttpResponse::Ok().json(record),
+        Err(e) => return_error_response(e),
+    }
+}
+
+#[tracing::instrument(name = "Rejecting return", skip(req, body, db, redis_pool))]
+#[actix_web::post("/{id}/reject")]
+pub async fn reject_return(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: Option<web::Json<ReturnRejection>>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing return rejection.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let record = match get_accessible_return(&db, user_id, &path.into_inner()).await {
+        Ok((record, true)) => record,
+        Ok(_) => return return_error_response(anyhow!(error::Returns::Forbidden("Only the store can reject returns.".into()))),
+        Err(response) => return response,
+    };
+
+    let reason = body.and_then(|body| body.into_inner().reason);
+
+    match crate::database::reject_return(&db, record.id, user_id, reason).await {
+        Ok(record) => HttpResponse::Ok().json(record),
+        Err(e) => return_error_response(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReturnsQueueParams {
+    status: Option<ReturnStatus>,
+}
+
+#[tracing::instrument(name = "Getting store returns queue", skip(req, db, redis_pool))]
+#[actix_web::get("/stores/{id}/returns")]
+pub async fn store_returns_queue(
+    req: HttpRequest,
+    path: web::Path<String>,
+    parameters: web::Query<ReturnsQueueParams>,
+    db: web::Data<mongodb::Database>,
+    redis_pool: web::Data<deadpool_redis::Pool>,
+) -> HttpResponse {
+    tracing::info!(target: "backend", "Accessing store returns queue.");
+
+    let user_id = match authorize(&req, None, &db, &redis_pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return auth_error_response(e),
+    };
+
+    let Ok(store_id) = ObjectId::parse_str(path.into_inner()) else {
+        return HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid store id.".to_string() });
+    };
+    let Some(store) = stores::get_store(&store_id) else {
+        return HttpResponse::NotFound().json(ErrorResponse { error: "Store not found.".to_string() });
+    };
+
+    match utils::is_store_staff(&db, user_id, &[store.id]).await {
+        Ok(true) => {}
+        Ok(false) => return return_error_response(anyhow!(error::Returns::Forbidden("You don't work at this store.".into()))),
+        Err(e) => return return_error_response(e),
+    }
+
+    let status = parameters.status.unwrap_or(ReturnStatus::Requested);
+
+    match crate::database::get_returns_queue(&db, &store.item_colls, status).await {
+        Ok(returns) => HttpResponse::Ok().json(returns),
+        Err(e) => return_error_response(e),
+    }
+}
+
+/// Gets a return the user may see: one they requested, one with units of a
+/// store they work at, or any if they're an admin. Other returns are reported
+/// as not found. Also tells whether the user may process it.
+async fn get_accessible_return(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+    return_id: &str,
+) -> Result<(ReturnRecord, bool), HttpResponse> {
+    let Ok(return_id) = ObjectId::parse_str(return_id) else {
+        return Err(HttpResponse::BadRequest().json(ErrorResponse { error: "Invalid return id.".to_string() }));
+    };
+
+    let record = match crate::database::get_return(db, return_id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return Err(return_error_response(anyhow!(error::Returns::NotFound))),
+        Err(e) => return Err(return_error_response(e)),
+    };
+
+    let mut stores: Vec<ObjectId> = record.units.iter()
+        .filter_map(|unit| stores::get_coll_store(&unit.coll))
+        .map(|store| store.id)
+        .collect();
+    stores.extend(record.store);
+
+    let staff = utils::is_store_staff(db, user_id, &stores).await.map_err(return_error_response)?;
+
+    if staff || record.user == Some(user_id) || record.requested_by == user_id {
+        Ok((record, staff))
+    } else {
+        Err(return_error_response(anyhow!(error::Returns::NotFound)))
+    }
+}
+
+pub fn return_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Returns>() {
+        let error = ErrorResponse { error: e.to_string() };
+        match e {
+            error::Returns::NotFound | error::Returns::SaleNotFound => HttpResponse::NotFound().json(error),
+            error::Returns::Empty | error::Returns::UnitNotSold(_) => HttpResponse::BadRequest().json(error),
+            error::Returns::AlreadyReturned(_)
+            | error::Returns::WindowClosed(_)
+            | error::Returns::NotReturnable(_)
+            | error::Returns::InvalidState(_) => HttpResponse::Conflict().json(error),
+            error::Returns::Forbidden(msg) => HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else if e.is::<error::Payments>() {
+        super::payments::payment_error_response(e)
+    } else {
+        tracing::error!(target: "backend", "Failed to process return: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
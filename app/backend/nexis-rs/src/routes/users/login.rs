@@ -2,7 +2,7 @@ use crate::prelude::*;
 use anyhow::Result;
 use types::{ User, LoginUser };
 
-const USER_NOT_FOUND_MSG: &'static str = "A user with these details does not exist. If you registered with these details, ensure you activated your account by clicking on the link sent to your e-mail address.";
+const USER_NOT_FOUND_MSG: &str = "A user with these details does not exist. If you registered with these details, ensure you activated your account by clicking on the link sent to your e-mail address.";
 
 #[tracing::instrument(
     name = "Logging a user in",
@@ -23,7 +23,7 @@ async fn login_user(
 
     if req.cookie("session_uuid").is_some() {
         let sss_uuid_token = req.cookie("session_uuid").unwrap().value().to_string();
-        if let Ok(_) = utils::verify_session_token(sss_uuid_token, &db, &redis_pool).await {
+        if utils::verify_session_token(sss_uuid_token, &db, &redis_pool).await.is_ok() {
             return HttpResponse::Ok().json("You are already logged in.");
         }
     }
@@ -63,8 +63,27 @@ async fn login_user(
                         cookie
                     };
 
-                    HttpResponse::Ok()
-                        .cookie(session_cookie)
+                    let mut response = HttpResponse::Ok();
+                    response.cookie(session_cookie);
+
+                    // Move the items the user added to the cart before logging in into their own cart
+                    if let Some(guest_id) = req.cookie(crate::routes::GUEST_CART_COOKIE)
+                        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok())
+                    {
+                        match crate::database::merge_guest_cart(&db, &redis_pool, guest_id, db_user.id).await {
+                            Ok(()) => {
+                                let mut clear_cookie = Cookie::build(crate::routes::GUEST_CART_COOKIE, "")
+                                    .path("/")
+                                    .http_only(true)
+                                    .finish();
+                                clear_cookie.make_removal();
+                                response.cookie(clear_cookie);
+                            }
+                            Err(e) => tracing::error!(target: "backend", "Failed to merge guest cart: {}", e),
+                        }
+                    }
+
+                    response
                         .json(types::UserResponse {
                             email: db_user.email,
                             name: db_user.name,
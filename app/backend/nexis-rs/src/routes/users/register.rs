@@ -27,7 +27,7 @@ pub async fn register_user(
     tracing::event!(target: "backend", tracing::Level::INFO, "Reached /users/register");
 
     // Ensure the redis server is up before attempting to register a user.
-    if let Err(_) = get_redis_conn(&redis_pool).await {
+    if get_redis_conn(&redis_pool).await.is_err() {
         return HttpResponse::InternalServerError().json("Your account cannot be registered at the moment.")
     };
 
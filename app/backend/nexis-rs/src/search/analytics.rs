@@ -0,0 +1,151 @@
+use crate::prelude::*;
+use mongodb::IndexModel;
+
+const SEARCH_LOG_COLL: &str = "searchLog";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryKind {
+    Search,
+    Suggestions,
+}
+
+/// A query made through `/search` or `/search-suggestions`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchLogEntry {
+    pub query: String,
+    pub kind: QueryKind,
+    #[serde(default)]
+    pub filters: Document,
+    pub results: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<ObjectId>,
+    pub timestamp: bson::DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryStats {
+    #[serde(rename = "_id")]
+    pub query: String,
+    pub count: i64,
+    #[serde(rename = "avgResults")]
+    pub avg_results: f64,
+    #[serde(rename = "lastSeen")]
+    pub last_seen: bson::DateTime,
+}
+
+/// Normalizes a query so different spellings of the same search are counted together.
+pub fn normalize_query(input: &str) -> String {
+    super::tokenize(input).join(" ")
+}
+
+/// Creates the capped collection where queries are logged, if it doesn't exist yet.
+/// Once it's full, the oldest entries are overwritten.
+#[tracing::instrument(name = "Preparing search log", skip(db, settings))]
+pub async fn ensure_search_log(
+    db: &mongodb::Database,
+    settings: &crate::settings::SearchSettings,
+) -> anyhow::Result<()> {
+    if !db.list_collection_names().await?.iter().any(|name| name == SEARCH_LOG_COLL) {
+        db.create_collection(SEARCH_LOG_COLL)
+            .capped(true)
+            .size(settings.log_size_bytes)
+            .await?;
+        tracing::info!(target: "mongodb", "Created capped `{}` collection.", SEARCH_LOG_COLL);
+    }
+
+    let log_coll: Collection<SearchLogEntry> = db.collection(SEARCH_LOG_COLL);
+    log_coll.create_index(IndexModel::builder().keys(doc! { "kind": 1, "timestamp": -1 }).build()).await?;
+
+    Ok(())
+}
+
+/// Logs a query in the background, so the response isn't delayed by the insert.
+/// Empty queries aren't logged.
+pub fn log_query(db: &mongodb::Database, entry: SearchLogEntry) {
+    if entry.query.is_empty() {
+        return;
+    }
+
+    let log_coll: Collection<SearchLogEntry> = db.collection(SEARCH_LOG_COLL);
+    tokio::spawn(async move {
+        if let Err(e) = log_coll.insert_one(entry).await {
+            tracing::warn!(target: "mongodb", "Failed to log search query: {}", e);
+        }
+    });
+}
+
+/// Returns the most frequent queries of a kind made since `since`.
+/// If `zero_results` is set, only the queries which found nothing are counted.
+pub async fn top_queries(
+    db: &mongodb::Database,
+    kind: QueryKind,
+    since: bson::DateTime,
+    zero_results: bool,
+    limit: i64,
+) -> anyhow::Result<Vec<QueryStats>> {
+    let log_coll: Collection<SearchLogEntry> = db.collection(SEARCH_LOG_COLL);
+
+    let mut query_match = doc! {
+        "kind": bson::to_bson(&kind)?,
+        "timestamp": { "$gte": since },
+    };
+    if zero_results {
+        query_match.insert("results", 0);
+    }
+
+    let pipeline = vec![
+        doc! { "$match": query_match },
+        doc! { "$group": {
+            "_id": "$query",
+            "count": { "$sum": 1 },
+            "avgResults": { "$avg": "$results" },
+            "lastSeen": { "$max": "$timestamp" },
+        }},
+        doc! { "$sort": { "count": -1, "lastSeen": -1 }},
+        doc! { "$limit": limit },
+    ];
+
+    let stats = log_coll
+        .aggregate(pipeline)
+        .with_type::<QueryStats>()
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(stats)
+}
+
+/// Returns the searches that found results and were made by the most
+/// distinct shoppers since `since`. Anonymous searches count once each.
+pub async fn trending_queries(
+    db: &mongodb::Database,
+    since: bson::DateTime,
+    limit: i64,
+) -> anyhow::Result<Vec<String>> {
+    let log_coll: Collection<SearchLogEntry> = db.collection(SEARCH_LOG_COLL);
+
+    let pipeline = vec![
+        doc! { "$match": {
+            "kind": bson::to_bson(&QueryKind::Search)?,
+            "timestamp": { "$gte": since },
+            "results": { "$gt": 0 },
+        }},
+        doc! { "$group": {
+            "_id": "$query",
+            "users": { "$addToSet": { "$ifNull": ["$user", "$_id"] }},
+            "lastSeen": { "$max": "$timestamp" },
+        }},
+        doc! { "$project": { "searchers": { "$size": "$users" }, "lastSeen": 1 }},
+        doc! { "$sort": { "searchers": -1, "lastSeen": -1 }},
+        doc! { "$limit": limit },
+    ];
+
+    let mut cursor = log_coll.aggregate(pipeline).await?;
+    let mut queries = Vec::new();
+    while let Some(doc) = cursor.try_next().await? {
+        queries.push(doc.get_str("_id")?.to_string());
+    }
+
+    Ok(queries)
+}
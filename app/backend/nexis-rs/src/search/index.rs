@@ -0,0 +1,265 @@
+use crate::prelude::*;
+use crate::types::mongodb::Item;
+use std::{ collections::BTreeSet, sync::RwLock };
+
+/// Maximum number of prefix completions considered for the last query token.
+const MAX_PREFIX_TERMS: usize = 64;
+
+/// In-process inverted index over the `items` collection.
+/// Item names are split into lowercase terms, and every term is also indexed by
+/// its trigrams so that misspelled query terms can be matched by edit distance.
+pub struct SearchIndex {
+    data: RwLock<IndexData>,
+}
+
+#[derive(Default)]
+struct IndexData {
+    items: HashMap<ObjectId, Item>,
+    postings: HashMap<String, HashSet<ObjectId>>,
+    trigrams: HashMap<String, HashSet<String>>,
+    terms: BTreeSet<String>,
+}
+
+pub struct Suggestions {
+    pub items: Vec<Item>,
+    pub did_you_mean: Option<String>,
+}
+
+/// How a query token matched an indexed term.
+#[derive(Clone, Copy)]
+enum TermMatch {
+    Exact,
+    Prefix,
+    Fuzzy(usize),
+}
+
+impl TermMatch {
+    fn score(self) -> f64 {
+        match self {
+            TermMatch::Exact => 1.0,
+            TermMatch::Prefix => 0.8,
+            TermMatch::Fuzzy(distance) => 0.6 - 0.15 * distance as f64,
+        }
+    }
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        SearchIndex { data: RwLock::new(IndexData::default()) }
+    }
+}
+
+impl SearchIndex {
+    /// Builds a new index containing every document in the `items` collection.
+    #[tracing::instrument(name = "Building search index", skip(db))]
+    pub async fn build(db: &mongodb::Database) -> anyhow::Result<Self> {
+        let index = SearchIndex::default();
+        index.rebuild(db).await?;
+        Ok(index)
+    }
+
+    /// Reloads the whole `items` collection and swaps it in place of the current index.
+    pub async fn rebuild(&self, db: &mongodb::Database) -> anyhow::Result<()> {
+        let items_coll: Collection<Item> = db.collection("items");
+        let mut cursor = items_coll.find(doc! {}).await?;
+
+        let mut data = IndexData::default();
+        while let Some(item) = cursor.try_next().await? {
+            data.insert(item);
+        }
+
+        let len = data.items.len();
+        *self.data.write().expect("Search index lock poisoned") = data;
+
+        tracing::info!(target: "backend", "Search index built with {} items.", len);
+
+        Ok(())
+    }
+
+    pub fn upsert(&self, item: Item) {
+        let mut data = self.data.write().expect("Search index lock poisoned");
+        data.remove(&item.id);
+        data.insert(item);
+    }
+
+    pub fn remove(&self, id: &ObjectId) {
+        self.data.write().expect("Search index lock poisoned").remove(id);
+    }
+
+    /// Returns up to `limit` items matching `input`, tolerating typos and
+    /// treating the last token as an incomplete word. If some tokens matched
+    /// no term exactly, a corrected query is returned as `did_you_mean`.
+    pub fn suggest(&self, input: &str, limit: usize) -> Suggestions {
+        let tokens = tokenize(input);
+        let data = self.data.read().expect("Search index lock poisoned");
+
+        if tokens.is_empty() {
+            return Suggestions { items: Vec::new(), did_you_mean: None };
+        }
+
+        let mut scores: Option<HashMap<ObjectId, f64>> = None;
+        let mut corrected: Vec<String> = Vec::with_capacity(tokens.len());
+        let mut was_corrected = false;
+
+        for (i, token) in tokens.iter().enumerate() {
+            let is_last = i == tokens.len() - 1;
+            let matches = data.match_token(token, is_last);
+
+            // Use the closest, most frequent fuzzy term as the correction for
+            // tokens that don't exist in the index as a word or a prefix.
+            let is_known = matches.iter().any(|(_, m)| matches!(m, TermMatch::Exact | TermMatch::Prefix));
+            let correction = matches.iter()
+                .filter_map(|(term, m)| match m {
+                    TermMatch::Fuzzy(distance) => Some((term, *distance)),
+                    _ => None,
+                })
+                .min_by_key(|(term, distance)| (*distance, std::cmp::Reverse(data.postings[*term].len())));
+
+            match correction {
+                Some((term, _)) if !is_known => {
+                    corrected.push(term.clone());
+                    was_corrected = true;
+                }
+                _ => corrected.push(token.clone()),
+            }
+
+            let mut token_scores: HashMap<ObjectId, f64> = HashMap::new();
+            for (term, term_match) in &matches {
+                for id in &data.postings[term] {
+                    let score = token_scores.entry(*id).or_insert(0.0);
+                    *score = score.max(term_match.score());
+                }
+            }
+
+            // Every token has to match for an item to be suggested.
+            scores = Some(match scores {
+                None => token_scores,
+                Some(prev) => prev.into_iter()
+                    .filter_map(|(id, score)| token_scores.get(&id).map(|s| (id, score + s)))
+                    .collect(),
+            });
+        }
+
+        let mut ranked: Vec<(ObjectId, f64)> = scores.unwrap_or_default().into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| {
+            b.total_cmp(a).then_with(|| data.items[a_id].name.cmp(&data.items[b_id].name))
+        });
+
+        Suggestions {
+            items: ranked.into_iter()
+                .take(limit)
+                .map(|(id, _)| data.items[&id].clone())
+                .collect(),
+            did_you_mean: if was_corrected { Some(corrected.join(" ")) } else { None },
+        }
+    }
+}
+
+impl IndexData {
+    fn insert(&mut self, item: Item) {
+        for term in tokenize(&item.name) {
+            if !self.postings.contains_key(&term) {
+                for trigram in trigrams(&term) {
+                    self.trigrams.entry(trigram).or_default().insert(term.clone());
+                }
+                self.terms.insert(term.clone());
+            }
+            self.postings.entry(term).or_default().insert(item.id);
+        }
+        self.items.insert(item.id, item);
+    }
+
+    fn remove(&mut self, id: &ObjectId) {
+        let Some(item) = self.items.remove(id) else { return };
+
+        for term in tokenize(&item.name) {
+            let Some(ids) = self.postings.get_mut(&term) else { continue };
+            ids.remove(id);
+
+            if ids.is_empty() {
+                self.postings.remove(&term);
+                self.terms.remove(&term);
+                for trigram in trigrams(&term) {
+                    if let Some(terms) = self.trigrams.get_mut(&trigram) {
+                        terms.remove(&term);
+                        if terms.is_empty() {
+                            self.trigrams.remove(&trigram);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds the indexed terms that match a query token exactly, as a prefix
+    /// (only for the last token), or within the allowed edit distance.
+    fn match_token(&self, token: &str, is_last: bool) -> Vec<(String, TermMatch)> {
+        let mut matches: HashMap<String, TermMatch> = HashMap::new();
+
+        if self.postings.contains_key(token) {
+            matches.insert(token.to_string(), TermMatch::Exact);
+        }
+
+        if is_last {
+            for term in self.terms.range(token.to_string()..)
+                .take_while(|term| term.starts_with(token))
+                .take(MAX_PREFIX_TERMS)
+            {
+                matches.entry(term.clone()).or_insert(TermMatch::Prefix);
+            }
+        }
+
+        let max_distance = max_edit_distance(token);
+        if max_distance > 0 {
+            let token_trigrams = trigrams(token);
+            let mut shared: HashMap<&String, usize> = HashMap::new();
+            for trigram in &token_trigrams {
+                for term in self.trigrams.get(trigram).into_iter().flatten() {
+                    *shared.entry(term).or_insert(0) += 1;
+                }
+            }
+
+            // A substitution breaks at most three trigrams, and a transposition
+            // four, so terms sharing fewer than that can't be within the
+            // allowed distance.
+            let min_shared = token_trigrams.len().saturating_sub(4 * max_distance).max(1);
+            for (term, count) in shared {
+                if count < min_shared || matches.contains_key(term) {
+                    continue;
+                }
+                let distance = strsim::damerau_levenshtein(token, term);
+                if distance <= max_distance {
+                    matches.insert(term.clone(), TermMatch::Fuzzy(distance));
+                }
+            }
+        }
+
+        matches.into_iter().collect()
+    }
+}
+
+fn max_edit_distance(token: &str) -> usize {
+    match token.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// Splits a string into lowercase alphanumeric terms.
+pub fn tokenize(input: &str) -> Vec<String> {
+    input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+fn trigrams(term: &str) -> Vec<String> {
+    let chars: Vec<char> = std::iter::once('$')
+        .chain(term.chars())
+        .chain(std::iter::once('$'))
+        .collect();
+
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
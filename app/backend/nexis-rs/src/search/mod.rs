@@ -0,0 +1,95 @@
+pub mod analytics;
+pub mod index;
+pub mod projection;
+
+pub use analytics::{ QueryKind, SearchLogEntry, ensure_search_log, log_query, normalize_query };
+pub use index::{ SearchIndex, Suggestions, tokenize };
+pub use projection::{ reconcile_items, refresh_item, spawn_items_sync, ReconcileReport };
+
+use crate::prelude::*;
+use crate::types::mongodb::Item;
+use mongodb::{
+    change_stream::event::OperationType,
+    options::FullDocumentType,
+};
+use std::time::Duration;
+
+/// Keeps the search index in sync with the `items` collection.
+/// Changes are applied as they happen through a change stream, and the whole
+/// index is periodically rebuilt in case the stream is unavailable or missed events.
+pub fn spawn_index_maintenance(
+    db: mongodb::Database,
+    index: web::Data<SearchIndex>,
+    settings: &crate::settings::SearchSettings,
+) {
+    let rebuild_interval = Duration::from_secs(settings.rebuild_interval_seconds);
+    let retry_delay = Duration::from_secs(settings.watch_retry_seconds);
+
+    {
+        let db = db.clone();
+        let index = index.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = watch_items(&db, &index).await {
+                    tracing::warn!(target: "mongodb", "Items change stream closed: {}. Retrying in {:?}.", e, retry_delay);
+                }
+                tokio::time::sleep(retry_delay).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(rebuild_interval);
+        // The first tick completes immediately, and the index was just built.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = index.rebuild(&db).await {
+                tracing::error!(target: "backend", "Failed to rebuild search index: {}", e);
+            }
+        }
+    });
+}
+
+/// Escapes every regex metacharacter so user input can be matched literally by `$regex`.
+pub fn escape_regex(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' | '#' | '-' | '/') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+async fn watch_items(db: &mongodb::Database, index: &SearchIndex) -> anyhow::Result<()> {
+    let items_coll: Collection<Item> = db.collection("items");
+    let mut stream = items_coll
+        .watch()
+        .full_document(FullDocumentType::UpdateLookup)
+        .await?;
+
+    tracing::info!(target: "mongodb", "Watching the items collection for search index updates.");
+
+    while let Some(event) = stream.try_next().await? {
+        match event.operation_type {
+            OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                if let Some(item) = event.full_document {
+                    index.upsert(item);
+                }
+            }
+            OperationType::Delete => {
+                if let Some(Ok(id)) = event.document_key.as_ref().map(|key| key.get_object_id("_id")) {
+                    index.remove(&id);
+                }
+            }
+            OperationType::Drop | OperationType::DropDatabase | OperationType::Invalidate => {
+                bail!("the items collection was dropped");
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
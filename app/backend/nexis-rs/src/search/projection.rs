@@ -0,0 +1,203 @@
+use crate::prelude::*;
+use crate::types::mongodb::Item;
+use mongodb::{
+    change_stream::event::OperationType,
+    options::FullDocumentType,
+};
+use std::time::Duration;
+
+#[derive(Debug, Serialize)]
+pub struct ReconcileReport {
+    pub upserted: u64,
+    pub removed: u64,
+}
+
+/// Builds the denormalized `items` document for a document of an item collection.
+pub fn project_item(coll: &str, doc: &Document) -> anyhow::Result<Item> {
+    let price = doc.get_f64("price")
+        .or_else(|_| doc.get_f64("pricePerKg"))
+        .map_err(|_| anyhow!("Item has neither `price` nor `pricePerKg`"))?;
+
+    let store = stores::get_coll_store(coll)
+        .map(|store| store.name)
+        .unwrap_or_default();
+
+    let lot_units = utils::stock::lot_units(doc);
+    let stock = utils::stock::available_units(&lot_units, bson::DateTime::now());
+
+    Ok(Item {
+        id: doc.get_object_id("_id")?,
+        name: doc.get_str("name")?.to_string(),
+        price,
+        coll: coll.to_string(),
+        store,
+        brand: doc.get_str("brand").ok().map(|brand| brand.to_string()),
+        stock,
+        lot_units,
+    })
+}
+
+/// Archived items are kept in their collection, but out of the search results.
+fn is_archived(doc: &Document) -> bool {
+    doc.get_bool("archived").unwrap_or(false)
+}
+
+/// Writes the projection of an item, stamped with `syncedAt` so that a running
+/// reconciliation keeps it.
+async fn upsert_projection(items_coll: &Collection<Item>, item: &Item) -> anyhow::Result<()> {
+    let mut projection = bson::to_document(item)?;
+    projection.insert("syncedAt", bson::DateTime::now());
+
+    items_coll
+        .clone_with_type::<Document>()
+        .replace_one(doc! { "_id": item.id }, projection)
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+/// Projects an item into `items` right away, instead of waiting for the change
+/// stream, so stock changes show up in the next search. Removes the projection
+/// if the item no longer exists or was archived.
+pub async fn refresh_item(db: &mongodb::Database, coll: &str, item_id: ObjectId) -> anyhow::Result<()> {
+    let items_coll: Collection<Item> = db.collection("items");
+    let item_coll: Collection<Document> = db.collection(coll);
+
+    match item_coll.find_one(doc! { "_id": item_id }).await? {
+        Some(doc) if !is_archived(&doc) => upsert_projection(&items_coll, &project_item(coll, &doc)?).await?,
+        _ => {
+            items_coll.delete_one(doc! { "_id": item_id }).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the projection of every item collection into `items`,
+/// removing the projections whose source document no longer exists or was archived.
+#[tracing::instrument(name = "Reconciling items projection", skip(db))]
+pub async fn reconcile_items(db: &mongodb::Database) -> anyhow::Result<ReconcileReport> {
+    let items_coll: Collection<Item> = db.collection("items");
+
+    let item_colls = stores::item_colls();
+    let mut upserted = 0;
+    let mut removed = 0;
+
+    for coll_name in &item_colls {
+        let started = bson::DateTime::now();
+
+        let coll: Collection<Document> = db.collection(coll_name);
+        let mut cursor = coll.find(doc! { "archived": { "$ne": true }}).await?;
+
+        while let Some(doc) = cursor.try_next().await? {
+            match project_item(coll_name, &doc) {
+                Ok(item) => {
+                    upsert_projection(&items_coll, &item).await?;
+                    upserted += 1;
+                }
+                Err(e) => tracing::warn!(target: "backend", "Skipping item {:?} of `{}`: {}", doc.get("_id"), coll_name, e),
+            }
+        }
+
+        // Whatever wasn't written since the collection was read is gone from it,
+        // while the change stream's writes meanwhile are newer and stay
+        removed += items_coll
+            .delete_many(doc! { "coll": coll_name, "syncedAt": { "$not": { "$gte": started }}})
+            .await?
+            .deleted_count;
+    }
+
+    // Collections no longer sold by any store
+    removed += items_coll
+        .delete_many(doc! { "coll": { "$nin": &item_colls }})
+        .await?
+        .deleted_count;
+
+    tracing::info!(target: "backend", "Items projection reconciled: {} upserted, {} removed.", upserted, removed);
+
+    Ok(ReconcileReport { upserted, removed })
+}
+
+/// Keeps `items` in sync with the item collections. Changes are projected as they
+/// happen through a change stream, and a full reconciliation runs periodically
+/// to catch anything the stream missed.
+pub fn spawn_items_sync(db: mongodb::Database, settings: &crate::settings::SearchSettings) {
+    let reconcile_interval = Duration::from_secs(settings.reconcile_interval_seconds);
+    let retry_delay = Duration::from_secs(settings.watch_retry_seconds);
+
+    {
+        let db = db.clone();
+        tokio::spawn(async move {
+            loop {
+                match watch_item_colls(&db).await {
+                    // The store registry changed, so the stream is opened again at once
+                    Ok(()) => continue,
+                    Err(e) => tracing::warn!(target: "mongodb", "Item collections change stream closed: {}. Retrying in {:?}.", e, retry_delay),
+                }
+                tokio::time::sleep(retry_delay).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(reconcile_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = reconcile_items(&db).await {
+                tracing::error!(target: "backend", "Failed to reconcile items projection: {}", e);
+            }
+        }
+    });
+}
+
+/// Projects the changes of the item collections until the stream fails or ends,
+/// or the store registry changes and the stream must watch other collections.
+async fn watch_item_colls(db: &mongodb::Database) -> anyhow::Result<()> {
+    let items_coll: Collection<Item> = db.collection("items");
+
+    let mut reloads = stores::subscribe();
+    reloads.borrow_and_update();
+    let item_colls = stores::item_colls();
+
+    let mut stream = db
+        .watch()
+        .pipeline([doc! { "$match": { "ns.coll": { "$in": &item_colls }}}])
+        .full_document(FullDocumentType::UpdateLookup)
+        .await?;
+
+    tracing::info!(target: "mongodb", "Watching the item collections {:?} for projection updates.", item_colls);
+
+    loop {
+        let event = tokio::select! {
+            event = stream.try_next() => event?,
+            _ = reloads.changed() => {
+                tracing::info!(target: "mongodb", "Store registry reloaded, watching its item collections again.");
+                return Ok(());
+            }
+        };
+        let Some(event) = event else { break };
+        let Some(coll) = event.ns.as_ref().and_then(|ns| ns.coll.clone()) else { continue };
+
+        match event.operation_type {
+            OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                let Some(doc) = event.full_document else { continue };
+                if is_archived(&doc) {
+                    items_coll.delete_one(doc! { "_id": doc.get_object_id("_id")? }).await?;
+                    continue;
+                }
+                match project_item(&coll, &doc) {
+                    Ok(item) => upsert_projection(&items_coll, &item).await?,
+                    Err(e) => tracing::warn!(target: "backend", "Skipping item {:?} of `{}`: {}", doc.get("_id"), coll, e),
+                }
+            }
+            OperationType::Delete => {
+                if let Some(Ok(id)) = event.document_key.as_ref().map(|key| key.get_object_id("_id")) {
+                    items_coll.delete_one(doc! { "_id": id }).await?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bail!("The stream ended.")
+}
@@ -1,5 +1,7 @@
 // src/settings.rs
 
+use crate::types::mongodb::inventory::ReorderLevels;
+
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub application: ApplicationSettings,
@@ -8,9 +10,135 @@ pub struct Settings {
     pub redis: RedisSettings,
     pub secret: Secret,
     pub email: EmailSettings,
+    pub search: SearchSettings,
+    pub stores: Vec<StoreSettings>,
+    pub cart: CartSettings,
+    pub orders: OrderSettings,
+    pub payments: PaymentSettings,
+    pub returns: ReturnSettings,
+    pub receipts: ReceiptSettings,
+    pub idempotency: IdempotencySettings,
+    pub expiry: ExpirySettings,
+    pub stock_alerts: StockAlertSettings,
+    pub ledger: LedgerSettings,
     pub frontend_url: String,
 }
 
+/// Default item collections of a store, used to seed the store registry.
+#[derive(serde::Deserialize, Clone)]
+pub struct StoreSettings {
+    pub name: String,
+    pub item_colls: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CartSettings {
+    pub guest_cart_expiration_days: u64,
+    pub max_quantity: u32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OrderSettings {
+    pub reservation_minutes: i64,
+    pub expiry_check_interval_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PaymentSettings {
+    pub provider: String,
+    pub webhook_secret: String,
+    pub webhook_tolerance_seconds: i64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ReturnSettings {
+    pub window_days: i64,
+    /// Return windows of item collections which differ from `window_days`.
+    #[serde(default)]
+    pub coll_window_days: std::collections::HashMap<String, i64>,
+}
+
+impl ReturnSettings {
+    /// Days a unit of an item collection can be returned after it was sold.
+    pub fn window_days(&self, coll: &str) -> i64 {
+        self.coll_window_days.get(coll).copied().unwrap_or(self.window_days)
+    }
+}
+
+/// Taxes shown on receipts. Prices already include them.
+#[derive(serde::Deserialize, Clone)]
+pub struct ReceiptSettings {
+    pub tax_name: String,
+    pub tax_rate: f64,
+}
+
+/// How `Idempotency-Key` headers are honored.
+#[derive(serde::Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// How long the response of a key is replayed.
+    pub ttl_seconds: u64,
+    /// How long a key stays locked by a request which never finished.
+    pub lock_seconds: u64,
+    /// How long retries wait for the first request before getting a conflict.
+    pub wait_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ExpirySettings {
+    pub check_interval_hours: u64,
+    /// Lots expiring within this many days are reported as expiring soon.
+    pub warning_days: i64,
+    #[serde(default)]
+    pub markdowns: Vec<MarkdownRule>,
+    /// Whether store staff get the report of each check by email.
+    pub email_staff: bool,
+}
+
+impl ExpirySettings {
+    /// Discount of an item whose next lot expires in `days_left` days:
+    /// the largest one of the rules covering it, if any.
+    pub fn markdown(&self, days_left: f64) -> Option<f64> {
+        self.markdowns.iter()
+            .filter(|rule| days_left <= rule.within_days as f64)
+            .map(|rule| rule.discount)
+            .reduce(f64::max)
+    }
+}
+
+/// Discount, between 0 and 1, of items with a lot expiring within some days.
+#[derive(serde::Deserialize, Clone)]
+pub struct MarkdownRule {
+    pub within_days: i64,
+    pub discount: f64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct StockAlertSettings {
+    pub check_interval_minutes: u64,
+    /// Reorder levels of items without their own.
+    pub reorder: ReorderLevels,
+    /// Reorder levels of item collections which differ from `reorder`.
+    #[serde(default)]
+    pub coll_reorder: std::collections::HashMap<String, ReorderLevels>,
+    /// Staff are emailed again about an item still low after this many hours.
+    pub renotify_hours: i64,
+    /// Whether store staff get new alerts by email.
+    pub email_staff: bool,
+}
+
+impl StockAlertSettings {
+    /// Default reorder levels of the items of a collection.
+    pub fn reorder(&self, coll: &str) -> ReorderLevels {
+        self.coll_reorder.get(coll).copied().unwrap_or(self.reorder)
+    }
+}
+
+/// How the inventory ledger is checked against the lots.
+#[derive(serde::Deserialize, Clone)]
+pub struct LedgerSettings {
+    pub reconcile_interval_hours: u64,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct Secret {
     pub secret_key: String,
@@ -26,6 +154,15 @@ pub struct EmailSettings {
     pub host_user_password: String,
 }
 
+#[derive(serde::Deserialize, Clone)]
+pub struct SearchSettings {
+    pub rebuild_interval_seconds: u64,
+    pub reconcile_interval_seconds: u64,
+    pub watch_retry_seconds: u64,
+    pub log_size_bytes: u64,
+    pub trending_window_hours: i64,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
     pub port: u16,
@@ -15,17 +15,9 @@ impl Application {
         settings: crate::settings::Settings,
         db: Option<mongodb::Database>,
     ) -> Result<Self, std::io::Error> {
-        let db = if let Some(db) = db {
-            db
-        } else if settings.debug {
-            get_mongodb_database(&settings.database).await.expect("")
-        } else {
-            let db_uri = std::env::var("DATABASE_URI").expect("Failed to get DATABASE_URI.");
-            // TODO: Handle errors with match
-            let options = ClientOptions::parse(db_uri).resolver_config(ResolverConfig::cloudflare()).await.expect("Failed to get client options");
-            let client = mongodb::Client::with_options(options).expect("Failed to get database client");
-            let db = client.database(&settings.database.database_name);
-            db
+        let db = match db {
+            Some(db) => db,
+            None => connect_database(&settings).await,
         };
 
         let port = settings.application.port;
@@ -43,6 +35,20 @@ impl Application {
     }
 }
 
+/// Connects to the database of the settings in debug mode, or to the one at
+/// `DATABASE_URI` otherwise.
+pub async fn connect_database(settings: &crate::settings::Settings) -> mongodb::Database {
+    if settings.debug {
+        get_mongodb_database(&settings.database).await.expect("")
+    } else {
+        let db_uri = std::env::var("DATABASE_URI").expect("Failed to get DATABASE_URI.");
+        // TODO: Handle errors with match
+        let options = ClientOptions::parse(db_uri).resolver_config(ResolverConfig::cloudflare()).await.expect("Failed to get client options");
+        let client = mongodb::Client::with_options(options).expect("Failed to get database client");
+        client.database(&settings.database.database_name)
+    }
+}
+
 pub async fn get_mongodb_database(
     settings: &crate::settings::DatabaseSettings,
 ) -> Result<mongodb::Database, mongodb::error::Error> {
@@ -57,6 +63,53 @@ async fn run(
     db: mongodb::Database,
     settings: crate::settings::Settings,
 ) -> Result<actix_web::dev::Server, std::io::Error> {
+    // Stores and the item collections they sell
+    crate::stores::load(&db, &settings).await.expect("Failed to load the store registry.");
+
+    // In-memory search index, kept in sync with the `items` collection
+    let search_index = actix_web::web::Data::new(
+        crate::search::SearchIndex::build(&db).await.expect("Failed to build the search index.")
+    );
+    crate::search::spawn_index_maintenance(db.clone(), search_index.clone(), &settings.search);
+    crate::search::spawn_items_sync(db.clone(), &settings.search);
+    crate::search::ensure_search_log(&db, &settings.search).await.expect("Failed to prepare the search log.");
+
+    // Unit codes scanned at the registers
+    crate::database::ensure_unit_code_indexes(&db).await.expect("Failed to create the unit code indexes.");
+
+    // External SKUs the catalog is imported by
+    crate::database::ensure_catalog_indexes(&db).await.expect("Failed to create the catalog indexes.");
+
+    // Ledger of every unit entering or leaving a lot, started before any job can move units
+    crate::database::ensure_movement_indexes(&db).await.expect("Failed to create the inventory movement indexes.");
+    crate::database::record_opening_balances(&db).await.expect("Failed to record the opening balances of the inventory ledger.");
+    crate::database::ensure_stock_count_indexes(&db).await.expect("Failed to create the stock count indexes.");
+    crate::database::ensure_stock_transfer_indexes(&db).await.expect("Failed to create the stock transfer indexes.");
+    crate::database::ensure_purchasing_indexes(&db).await.expect("Failed to create the purchasing indexes.");
+
+    // Orders, and the job returning the units of unpaid ones to stock
+    crate::database::ensure_order_indexes(&db).await.expect("Failed to create the order indexes.");
+    crate::database::spawn_reservation_expiry(db.clone(), &settings.orders);
+
+    // Payment provider
+    crate::database::ensure_payment_indexes(&db).await.expect("Failed to create the payment indexes.");
+    let payment_provider: actix_web::web::Data<dyn crate::payments::PaymentProvider> = actix_web::web::Data::from(
+        crate::payments::build_provider(&settings.payments).expect("Failed to build the payment provider.")
+    );
+    crate::database::ensure_return_indexes(&db).await.expect("Failed to create the return indexes.");
+    crate::database::ensure_inventory_indexes(&db).await.expect("Failed to create the inventory indexes.");
+    let payment_settings = actix_web::web::Data::new(settings.payments.clone());
+
+    // Job writing off expired lots and marking down the ones about to expire
+    crate::database::spawn_expiry_check(db.clone(), &settings.expiry);
+
+    // Job raising low stock alerts for items at their reorder point
+    crate::database::ensure_stock_alert_indexes(&db).await.expect("Failed to create the stock alert indexes.");
+    crate::database::spawn_stock_alert_check(db.clone(), &settings.stock_alerts);
+
+    // Job checking the lots against the inventory ledger
+    crate::database::spawn_reconciliation(db.clone(), &settings.ledger);
+
     // Database connection application state
     let db = actix_web::web::Data::new(db);
 
@@ -67,29 +120,61 @@ async fn run(
         .expect("Cannot create deadpool redis.");
     let redis_pool_data = actix_web::web::Data::new(redis_pool);
 
+    let search_settings = actix_web::web::Data::new(settings.search.clone());
+    let idempotency_settings = actix_web::web::Data::new(settings.idempotency.clone());
+    let expiry_settings = actix_web::web::Data::new(settings.expiry.clone());
+    let stock_alert_settings = actix_web::web::Data::new(settings.stock_alerts.clone());
+
     let server = actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .wrap(
             actix_cors::Cors::default()
                 .allowed_origin(&settings.frontend_url)
-                .allowed_methods(vec!["GET", "POST", "PATCH", "DELETE", "OPTIONS"])
+                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
                 .allowed_headers(vec![
                     actix_web::http::header::AUTHORIZATION,
                     actix_web::http::header::ACCEPT,
                 ])
                 .allowed_header(actix_web::http::header::CONTENT_TYPE)
-                .expose_headers(&[actix_web::http::header::CONTENT_DISPOSITION])
+                .allowed_header(crate::utils::idempotency::IDEMPOTENCY_KEY_HEADER)
+                .expose_headers(&[
+                    actix_web::http::header::CONTENT_DISPOSITION,
+                    actix_web::http::header::HeaderName::from_static("idempotent-replayed"),
+                ])
                 .supports_credentials()
                 .max_age(3600),
             )
             .service(crate::routes::health_check)
             .service(crate::routes::search_suggestions)
+            .service(crate::routes::trending_searches)
             .service(crate::routes::search)
+            .service(crate::routes::item_detail)
             .configure(crate::routes::auth_routes_config)
+            .configure(crate::routes::admin_routes_config)
+            .configure(crate::routes::cart_routes_config)
+            .configure(crate::routes::orders_routes_config)
+            .configure(crate::routes::payments_routes_config)
+            .configure(crate::routes::pos_routes_config)
+            .configure(crate::routes::returns_routes_config)
+            .configure(crate::routes::inventory_routes_config)
+            .configure(crate::routes::purchasing_routes_config)
+            .configure(crate::routes::catalog_routes_config)
             // Add database pool to application state
             .app_data(db.clone())
             // Add redis pool to application state
             .app_data(redis_pool_data.clone())
+            // Add search index to application state
+            .app_data(search_index.clone())
+            .app_data(search_settings.clone())
+            // Add payment provider to application state
+            .app_data(payment_provider.clone())
+            .app_data(payment_settings.clone())
+            // Add idempotency key settings to application state
+            .app_data(idempotency_settings.clone())
+            // Add lot expiry settings to application state
+            .app_data(expiry_settings.clone())
+            // Add reorder level settings to application state
+            .app_data(stock_alert_settings.clone())
             .wrap(middleware::NormalizePath::trim())
     });
 
@@ -0,0 +1,153 @@
+// src/stores.rs
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::mongodb::stores::StoreInfo;
+use std::sync::RwLock;
+
+/// Registry of the mall's stores and the item collections each of them sells.
+/// It's loaded from the `store` collection at startup, seeded from the settings
+/// for stores that don't list their item collections yet, and cached in memory
+/// until an admin edits it.
+static REGISTRY: Lazy<RwLock<Vec<StoreInfo>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Bumped every time the registry is reloaded, for watchers of the item
+/// collections to follow it.
+static RELOADS: Lazy<tokio::sync::watch::Sender<u64>> = Lazy::new(|| tokio::sync::watch::Sender::new(0));
+
+/// Collections which can never hold store items.
+const RESERVED_COLLS: [&str; 3] = ["items", "store", "user"];
+
+/// Loads the registry from the `store` collection, seeding it from the
+/// settings first, and validates it before replacing the cached one.
+#[tracing::instrument(name = "Loading store registry", skip(db, settings))]
+pub async fn load(db: &mongodb::Database, settings: &crate::settings::Settings) -> Result<()> {
+    let stores_coll: Collection<Document> = db.collection("store");
+
+    for store in &settings.stores {
+        let existing = stores_coll.find_one(doc! { "name": &store.name }).await?;
+
+        match existing {
+            Some(existing) if existing.get_array("itemColls").is_ok() => {}
+            Some(existing) => {
+                stores_coll.update_one(
+                    doc! { "_id": existing.get_object_id("_id")? },
+                    doc! { "$set": { "itemColls": &store.item_colls }},
+                ).await?;
+                tracing::info!(target: "mongodb", "Seeded item collections of store `{}`.", store.name);
+            }
+            None => {
+                stores_coll.insert_one(doc! {
+                    "name": &store.name,
+                    "itemColls": &store.item_colls,
+                    "daySales": [],
+                    "owner": [],
+                    "employee": [],
+                }).await?;
+                tracing::info!(target: "mongodb", "Created store `{}` from settings.", store.name);
+            }
+        }
+    }
+
+    reload(db).await
+}
+
+/// Reloads the registry from the `store` collection.
+pub async fn reload(db: &mongodb::Database) -> Result<()> {
+    let stores = get_db_stores(db).await?;
+    validate(&stores)?;
+
+    tracing::info!(
+        target: "backend",
+        "Store registry loaded: {}",
+        stores.iter().map(|store| format!("{} {:?}", store.name, store.item_colls)).collect::<Vec<_>>().join(", ")
+    );
+
+    *REGISTRY.write().expect("Store registry lock poisoned") = stores;
+    RELOADS.send_modify(|reloads| *reloads += 1);
+
+    Ok(())
+}
+
+/// Notified when the registry is reloaded.
+pub fn subscribe() -> tokio::sync::watch::Receiver<u64> {
+    RELOADS.subscribe()
+}
+
+pub async fn get_db_stores(db: &mongodb::Database) -> Result<Vec<StoreInfo>> {
+    let stores_coll: Collection<StoreInfo> = db.collection("store");
+
+    let stores = stores_coll
+        .find(doc! {})
+        .projection(doc! { "_id": 1, "name": 1, "itemColls": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(stores)
+}
+
+/// Ensures store names are unique and every item collection is a valid
+/// collection name sold by a single store.
+pub fn validate(stores: &[StoreInfo]) -> Result<()> {
+    let mut names: HashSet<&str> = HashSet::new();
+    let mut colls: HashMap<&str, &str> = HashMap::new();
+
+    for store in stores {
+        if store.name.trim().is_empty() {
+            bail!(types::error::Stores::InvalidRegistry(format!("Store {} has no name.", store.id)));
+        }
+        if !names.insert(store.name.as_str()) {
+            bail!(types::error::Stores::InvalidRegistry(format!("Store name `{}` is repeated.", store.name)));
+        }
+
+        for coll in &store.item_colls {
+            if coll.is_empty()
+                || coll.starts_with("system.")
+                || coll.contains(['$', '\0'])
+                || RESERVED_COLLS.contains(&coll.as_str())
+            {
+                bail!(types::error::Stores::InvalidRegistry(format!(
+                    "`{}` of store `{}` is not a valid item collection name.", coll, store.name
+                )));
+            }
+            if let Some(other) = colls.insert(coll.as_str(), store.name.as_str()) {
+                bail!(types::error::Stores::InvalidRegistry(format!(
+                    "Item collection `{}` belongs to both `{}` and `{}`.", coll, other, store.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn all() -> Vec<StoreInfo> {
+    REGISTRY.read().expect("Store registry lock poisoned").clone()
+}
+
+pub fn get_store(id: &ObjectId) -> Option<StoreInfo> {
+    REGISTRY.read().expect("Store registry lock poisoned")
+        .iter()
+        .find(|store| &store.id == id)
+        .cloned()
+}
+
+/// Returns the store selling the items of a collection.
+pub fn get_coll_store(coll: &str) -> Option<StoreInfo> {
+    REGISTRY.read().expect("Store registry lock poisoned")
+        .iter()
+        .find(|store| store.item_colls.iter().any(|c| c == coll))
+        .cloned()
+}
+
+/// Returns every item collection of every store.
+pub fn item_colls() -> Vec<String> {
+    REGISTRY.read().expect("Store registry lock poisoned")
+        .iter()
+        .flat_map(|store| store.item_colls.iter().cloned())
+        .collect()
+}
+
+pub fn is_item_coll(coll: &str) -> bool {
+    get_coll_store(coll).is_some()
+}
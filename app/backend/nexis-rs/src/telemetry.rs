@@ -1,6 +1,7 @@
 use tracing_subscriber::layer::SubscriberExt;
 
 pub fn get_subscriber(debug: bool) -> impl tracing::Subscriber + Send + Sync {
+    #[allow(clippy::if_same_then_else)]
     let env_filter = if debug {
         "debug,h2=info,actix_server=off,hickory_resolver=off,hickory_proto=off".to_string()
     } else {
@@ -10,11 +11,9 @@ pub fn get_subscriber(debug: bool) -> impl tracing::Subscriber + Send + Sync {
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(env_filter));
 
     let stdout_log = tracing_subscriber::fmt::layer();
-    let subscriber = tracing_subscriber::Registry::default()
+    tracing_subscriber::Registry::default()
         .with(env_filter)
-        .with(stdout_log);
-
-    subscriber
+        .with(stdout_log)
 }
 
 pub fn init_subscriber(subscriber: impl tracing::Subscriber + Send + Sync) {
@@ -0,0 +1,358 @@
+use crate::prelude::*;
+
+/// A lot of units of an item, as kept in its `lot` array. Lots made by the mock
+/// generator may store `enter_date` instead of `enterDate`, and dates as strings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<bson::DateTime>,
+    /// Only food lots expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<bson::DateTime>,
+    /// What a unit of the lot cost the store.
+    #[serde(rename = "costPrice", skip_serializing_if = "Option::is_none")]
+    pub cost_price: Option<f64>,
+    /// Store holding the units, if they were transferred to one other than the
+    /// store selling the item.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<ObjectId>,
+    pub code: Vec<bson::Bson>,
+}
+
+impl Lot {
+    pub fn from_doc(lot: &Document) -> anyhow::Result<Self> {
+        Ok(Lot {
+            id: lot.get_object_id("_id")?,
+            enter_date: utils::stock::lot_enter_date(lot),
+            expiry: utils::stock::get_date(lot, "expiry"),
+            cost_price: lot.get("costPrice").and_then(|cost| cost.as_f64()),
+            store: lot.get_object_id("store").ok(),
+            code: lot.get_array("code").cloned().unwrap_or_default(),
+        })
+    }
+}
+
+/// Units taken out of stock without being sold, such as damaged, spoiled or lost ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WriteOff {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub coll: String,
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub codes: Vec<bson::Bson>,
+    pub reason: String,
+    /// Store holding the lot, if it was transferred to one other than the store selling the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<ObjectId>,
+    /// User who wrote the units off, or `None` if the backend did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<ObjectId>,
+    pub at: bson::DateTime,
+}
+
+/// Price cut of an item while one of its lots is about to expire. The item's
+/// price is the discounted one, and goes back to `regularPrice` once the lot is gone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Markdown {
+    pub discount: f64,
+    #[serde(rename = "regularPrice")]
+    pub regular_price: f64,
+    pub lot: ObjectId,
+    pub expiry: bson::DateTime,
+    pub at: bson::DateTime,
+}
+
+/// When to restock an item: once its sellable units drop to `point`, enough
+/// should be ordered to get back to `target`. Kept in the item's `reorder`
+/// field, or taken from the defaults of its collection.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReorderLevels {
+    pub point: i64,
+    pub target: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StockAlertStatus {
+    Open,
+    Resolved,
+}
+
+/// Raised once an item drops to its reorder point, and resolved once it's
+/// restocked. Only one alert of an item is open at a time, so staff are
+/// emailed when it's raised instead of on every check.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StockAlert {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub store: ObjectId,
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub status: StockAlertStatus,
+    /// Sellable units at the last check.
+    pub units: i64,
+    pub reorder: ReorderLevels,
+    #[serde(rename = "raisedAt")]
+    pub raised_at: bson::DateTime,
+    #[serde(rename = "notifiedAt", skip_serializing_if = "Option::is_none")]
+    pub notified_at: Option<bson::DateTime>,
+    #[serde(rename = "resolvedAt", skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<bson::DateTime>,
+}
+
+/// Why a unit entered or left its lot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum MovementKind {
+    /// In the lot before the ledger was kept.
+    Opening,
+    Received,
+    /// Reserved by an online order.
+    Reserved,
+    /// Back from an order cancelled or expired before being completed.
+    Released,
+    /// Sold at a register.
+    Sold,
+    /// Returned in a condition to be sold again.
+    Returned,
+    WrittenOff,
+    /// Written off by the expiry check.
+    Expired,
+    /// Not found by a stock count.
+    Missing,
+    /// Found by a stock count after it left its lot.
+    Found,
+    /// Shipped to another store.
+    TransferredOut,
+    /// Received from another store.
+    TransferredIn,
+}
+
+impl MovementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Opening => "opening",
+            Self::Received => "received",
+            Self::Reserved => "reserved",
+            Self::Released => "released",
+            Self::Sold => "sold",
+            Self::Returned => "returned",
+            Self::WrittenOff => "writtenOff",
+            Self::Expired => "expired",
+            Self::Missing => "missing",
+            Self::Found => "found",
+            Self::TransferredOut => "transferredOut",
+            Self::TransferredIn => "transferredIn",
+        }
+    }
+
+    /// 1 if the unit entered its lot, -1 if it left it.
+    pub fn quantity(&self) -> i64 {
+        match self {
+            Self::Opening | Self::Received | Self::Released | Self::Returned | Self::Found | Self::TransferredIn => 1,
+            Self::Reserved | Self::Sold | Self::WrittenOff | Self::Expired | Self::Missing | Self::TransferredOut => -1,
+        }
+    }
+}
+
+/// An entry of the inventory ledger: a unit entering (`quantity` 1) or leaving
+/// (`quantity` -1) a lot. Entries are only ever appended, in the same
+/// transaction as the change to the lot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Movement {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub coll: String,
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub code: bson::Bson,
+    pub kind: MovementKind,
+    pub quantity: i64,
+    /// User who moved the unit, or `None` if the backend or a client did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Order, sale, return or write-off that moved the unit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<ObjectId>,
+    /// Cost price of the unit's lot when it moved, if the lot has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    pub at: bson::DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StockCountStatus {
+    /// Units are being scanned.
+    Open,
+    /// Scanning is over, and the variance awaits a manager's approval.
+    Closed,
+    /// The lots were adjusted to the variance.
+    Approved,
+    Cancelled,
+}
+
+impl StockCountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Closed => "closed",
+            Self::Approved => "approved",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// A physical count of the units of a store, or of one of its item collections.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StockCount {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub store: ObjectId,
+    /// Item collections being counted.
+    pub colls: Vec<String>,
+    pub status: StockCountStatus,
+    /// Unit codes scanned so far, each once.
+    #[serde(default)]
+    pub scanned: Vec<ObjectId>,
+    #[serde(rename = "openedBy")]
+    pub opened_by: ObjectId,
+    #[serde(rename = "openedAt")]
+    pub opened_at: bson::DateTime,
+    #[serde(rename = "closedBy", skip_serializing_if = "Option::is_none")]
+    pub closed_by: Option<ObjectId>,
+    #[serde(rename = "closedAt", skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<bson::DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variance: Option<CountVariance>,
+    #[serde(rename = "approvedBy", skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<ObjectId>,
+    #[serde(rename = "approvedAt", skip_serializing_if = "Option::is_none")]
+    pub approved_at: Option<bson::DateTime>,
+    /// Units whose adjustment the manager left out when approving.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<ObjectId>,
+}
+
+/// Differences between the scanned units and the lots, as of when a count closed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CountVariance {
+    /// Units in the lots of the counted collections.
+    pub expected: usize,
+    pub scanned: usize,
+    /// In a lot, but not scanned.
+    pub missing: Vec<CountedUnit>,
+    /// Scanned, but no longer in a lot, such as written off units.
+    pub found: Vec<CountedUnit>,
+    /// Scanned, but sold at a register since, so they're left to be sorted out by hand.
+    #[serde(default)]
+    pub sold: Vec<CountedUnit>,
+    /// Scanned codes which were never in a lot of the counted collections.
+    pub unknown: Vec<ObjectId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CountedUnit {
+    pub coll: String,
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub code: ObjectId,
+    /// How a found unit last left its lot.
+    #[serde(rename = "lastMovement", skip_serializing_if = "Option::is_none")]
+    pub last_movement: Option<MovementKind>,
+    #[serde(rename = "lastMovementAt", skip_serializing_if = "Option::is_none")]
+    pub last_movement_at: Option<bson::DateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    /// Asked for by the receiving store, waiting for the sending one.
+    Requested,
+    /// Units out of the sending store's lots, on their way.
+    Shipped,
+    Received,
+    Cancelled,
+}
+
+impl TransferStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Requested => "requested",
+            Self::Shipped => "shipped",
+            Self::Received => "received",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Units of a lot shipped by a transfer. They're received as a new lot held
+/// by the receiving store, with the same dates and cost price.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferredLot {
+    /// Lot of the sending store they were taken from.
+    pub lot: ObjectId,
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<bson::DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<bson::DateTime>,
+    #[serde(rename = "costPrice", skip_serializing_if = "Option::is_none")]
+    pub cost_price: Option<f64>,
+    pub codes: Vec<ObjectId>,
+    /// Lot of the receiving store they were put in.
+    #[serde(rename = "receivedLot", skip_serializing_if = "Option::is_none")]
+    pub received_lot: Option<ObjectId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferLine {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    /// Units requested.
+    pub quantity: u32,
+    /// Units shipped, by the lot they were taken from.
+    #[serde(default)]
+    pub lots: Vec<TransferredLot>,
+}
+
+/// Units of items moving from one store to another. The units are out of
+/// every lot while in transit, so neither store can sell them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StockTransfer {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub from: ObjectId,
+    pub to: ObjectId,
+    pub status: TransferStatus,
+    pub lines: Vec<TransferLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Shipped units which never arrived.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lost: Vec<ObjectId>,
+    #[serde(rename = "requestedBy")]
+    pub requested_by: ObjectId,
+    #[serde(rename = "requestedAt")]
+    pub requested_at: bson::DateTime,
+    #[serde(rename = "shippedBy", skip_serializing_if = "Option::is_none")]
+    pub shipped_by: Option<ObjectId>,
+    #[serde(rename = "shippedAt", skip_serializing_if = "Option::is_none")]
+    pub shipped_at: Option<bson::DateTime>,
+    #[serde(rename = "receivedBy", skip_serializing_if = "Option::is_none")]
+    pub received_by: Option<ObjectId>,
+    #[serde(rename = "receivedAt", skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<bson::DateTime>,
+    #[serde(rename = "cancelledBy", skip_serializing_if = "Option::is_none")]
+    pub cancelled_by: Option<ObjectId>,
+    #[serde(rename = "cancelledAt", skip_serializing_if = "Option::is_none")]
+    pub cancelled_at: Option<bson::DateTime>,
+}
@@ -1,10 +1,29 @@
 use crate::prelude::*;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Denormalized item from the `items` collection, projected from the item collections.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Item {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub name: String,
     pub price: f64,
     pub coll: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    /// Units available when the projection was last updated. Search queries
+    /// recompute it from `lot_units`, since food lots expire over time.
+    #[serde(default)]
+    pub stock: i64,
+    #[serde(rename = "lotUnits", default)]
+    pub lot_units: Vec<LotUnits>,
+}
+
+/// Amount of units left in a lot, and when they expire if it's a food lot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LotUnits {
+    pub units: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<bson::DateTime>,
+}
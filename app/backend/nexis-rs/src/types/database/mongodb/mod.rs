@@ -1,4 +1,10 @@
 pub mod users;
 pub mod items;
+pub mod stores;
+pub mod orders;
+pub mod payments;
+pub mod returns;
+pub mod inventory;
+pub mod purchasing;
 
 pub use items::Item;
\ No newline at end of file
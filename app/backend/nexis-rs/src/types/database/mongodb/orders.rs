@@ -0,0 +1,161 @@
+use crate::prelude::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum OrderStatus {
+    /// Units are reserved until `expiresAt`, waiting for the payment.
+    Pending,
+    Paid,
+    Preparing,
+    Ready,
+    Completed,
+    Cancelled,
+    Refunded,
+    /// The payment never arrived and the reserved units went back to stock.
+    Expired,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Preparing => "preparing",
+            OrderStatus::Ready => "ready",
+            OrderStatus::Completed => "completed",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+            OrderStatus::Expired => "expired",
+        }
+    }
+
+    /// Statuses an order can move to from this one.
+    pub fn next_statuses(&self) -> &'static [OrderStatus] {
+        use OrderStatus::*;
+        match self {
+            Pending => &[Paid, Cancelled, Expired],
+            Paid => &[Preparing, Cancelled],
+            Preparing => &[Ready, Cancelled],
+            Ready => &[Completed, Cancelled],
+            Completed => &[Refunded],
+            Cancelled => &[Refunded],
+            Refunded | Expired => &[],
+        }
+    }
+
+    pub fn can_become(&self, next: OrderStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+
+    /// Whether the order still holds its reserved units, which go back to stock if it's cancelled.
+    pub fn holds_units(&self) -> bool {
+        matches!(self, OrderStatus::Pending | OrderStatus::Paid | OrderStatus::Preparing | OrderStatus::Ready)
+    }
+
+    /// Status of a parent order given the ones of its sub-orders: the least
+    /// advanced of the ones still being fulfilled, or how they all ended.
+    pub fn roll_up(statuses: &[OrderStatus]) -> Option<OrderStatus> {
+        use OrderStatus::*;
+
+        if statuses.is_empty() {
+            return None;
+        }
+        if statuses.iter().all(|status| *status == Expired) {
+            return Some(Expired);
+        }
+
+        let active: Vec<OrderStatus> = statuses.iter()
+            .copied()
+            .filter(|status| !matches!(status, Cancelled | Refunded | Expired))
+            .collect();
+
+        if active.is_empty() {
+            return Some(if statuses.iter().all(|status| *status == Refunded) { Refunded } else { Cancelled });
+        }
+
+        [Pending, Paid, Preparing, Ready, Completed].into_iter().find(|status| active.contains(status))
+    }
+}
+
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A status change of an order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderEvent {
+    pub status: OrderStatus,
+    pub at: bson::DateTime,
+    /// User who made the change, or `None` if the backend did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A unit code taken from a lot of an item.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReservedUnit {
+    pub lot: ObjectId,
+    pub code: bson::Bson,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderItem {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub store: String,
+    pub quantity: u32,
+    /// Price of a unit, or of a kilogram for items sold by `pricePerKg`.
+    pub price: f64,
+    #[serde(rename = "perKg")]
+    pub per_kg: bool,
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+    pub total: f64,
+    pub units: Vec<ReservedUnit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Order {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    pub status: OrderStatus,
+    #[serde(default)]
+    pub history: Vec<OrderEvent>,
+    pub items: Vec<OrderItem>,
+    pub total: f64,
+    #[serde(rename = "createdAt")]
+    pub created_at: bson::DateTime,
+    /// When the reservation of a pending order lapses.
+    #[serde(rename = "expiresAt", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<bson::DateTime>,
+    /// The order a sub-order was split from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ObjectId>,
+    /// `_id` of the store fulfilling a sub-order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<ObjectId>,
+    /// Per-store parts of an order, which their stores fulfil on their own.
+    #[serde(rename = "subOrders", default, skip_serializing_if = "Vec::is_empty")]
+    pub sub_orders: Vec<ObjectId>,
+    /// Set when the order is cancelled, until its payments are voided or refunded.
+    #[serde(rename = "refundDue", default, skip_serializing_if = "std::ops::Not::not")]
+    pub refund_due: bool,
+}
+
+impl Order {
+    /// Whether the order was split into sub-orders, which hold its units.
+    pub fn is_parent(&self) -> bool {
+        !self.sub_orders.is_empty()
+    }
+
+    /// The order holding the payments of this one.
+    pub fn payable_order(&self) -> ObjectId {
+        self.parent.unwrap_or(self.id)
+    }
+}
@@ -0,0 +1,96 @@
+use crate::prelude::*;
+
+/// How a payment was made, in the same shape as the `type` of the payments in `daySales`:
+/// `{ cash: true }`, `{ card: "visa" }` or `{ transfer: { bank: "bofa", refNum: 1234 }}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PaymentMethod {
+    #[serde(rename = "cash")]
+    Cash(bool),
+    #[serde(rename = "card")]
+    Card(CardType),
+    #[serde(rename = "transfer")]
+    Transfer {
+        bank: String,
+        #[serde(rename = "refNum")]
+        ref_num: i64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CardType {
+    Visa,
+    Mastercard,
+    Amex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PaymentStatus {
+    /// Waiting for the provider to confirm it, such as a bank transfer.
+    Pending,
+    Authorized,
+    Captured,
+    /// Fully refunded. Partial refunds keep the payment `captured`.
+    Refunded,
+    Voided,
+    Declined,
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Authorized => "authorized",
+            PaymentStatus::Captured => "captured",
+            PaymentStatus::Refunded => "refunded",
+            PaymentStatus::Voided => "voided",
+            PaymentStatus::Declined => "declined",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaymentEvent {
+    pub status: PaymentStatus,
+    pub at: bson::DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<f64>,
+    /// Id of the provider callback that caused the change, so it's only applied once.
+    #[serde(rename = "eventId", skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    /// Sub-order or return a refund was made for, so it's only made once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<ObjectId>,
+}
+
+/// A payment made through a payment provider, linked to an order or to a sale
+/// at the register of a store.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaymentRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<ObjectId>,
+    /// Sale of the store's `daySales` paid at the register.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sale: Option<ObjectId>,
+    /// Client paying, if known. Buyers at a register may not be registered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<ObjectId>,
+    pub provider: String,
+    #[serde(rename = "providerRef", skip_serializing_if = "Option::is_none")]
+    pub provider_ref: Option<String>,
+    pub amount: f64,
+    #[serde(rename = "type")]
+    pub method: PaymentMethod,
+    pub status: PaymentStatus,
+    #[serde(default)]
+    pub refunded: f64,
+    #[serde(default)]
+    pub events: Vec<PaymentEvent>,
+    #[serde(rename = "createdAt")]
+    pub created_at: bson::DateTime,
+}
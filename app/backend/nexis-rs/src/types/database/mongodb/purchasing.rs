@@ -0,0 +1,105 @@
+use crate::prelude::*;
+
+/// Someone a store buys stock from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Supplier {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub store: ObjectId,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Deleted suppliers with purchase orders are archived instead, and can't
+    /// be ordered from anymore.
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: bson::DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PurchaseOrderStatus {
+    /// Still being written, and the only status it can be edited or deleted in.
+    Draft,
+    Sent,
+    PartiallyReceived,
+    Received,
+    /// Nothing else will be received, even if some lines are short.
+    Closed,
+}
+
+impl PurchaseOrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PurchaseOrderStatus::Draft => "draft",
+            PurchaseOrderStatus::Sent => "sent",
+            PurchaseOrderStatus::PartiallyReceived => "partiallyReceived",
+            PurchaseOrderStatus::Received => "received",
+            PurchaseOrderStatus::Closed => "closed",
+        }
+    }
+}
+
+/// Units of an item ordered from a supplier.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PurchaseOrderLine {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub quantity: u32,
+    /// Units received so far, over one or more deliveries.
+    pub received: u32,
+    /// What a unit costs the store, given to the lots received for the line.
+    #[serde(rename = "costPrice")]
+    pub cost_price: f64,
+    /// Defaults to the expected date of the order.
+    #[serde(rename = "expectedAt", skip_serializing_if = "Option::is_none")]
+    pub expected_at: Option<bson::DateTime>,
+}
+
+/// A lot received for a line of a purchase order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PurchaseOrderReceipt {
+    pub line: ObjectId,
+    pub lot: ObjectId,
+    pub quantity: u32,
+    pub by: ObjectId,
+    pub at: bson::DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PurchaseOrder {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub store: ObjectId,
+    pub supplier: ObjectId,
+    pub status: PurchaseOrderStatus,
+    pub lines: Vec<PurchaseOrderLine>,
+    #[serde(default)]
+    pub receipts: Vec<PurchaseOrderReceipt>,
+    #[serde(rename = "expectedAt", skip_serializing_if = "Option::is_none")]
+    pub expected_at: Option<bson::DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Cost of every unit ordered.
+    pub total: f64,
+    #[serde(rename = "createdBy")]
+    pub created_by: ObjectId,
+    #[serde(rename = "createdAt")]
+    pub created_at: bson::DateTime,
+    #[serde(rename = "sentAt", skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<bson::DateTime>,
+    #[serde(rename = "receivedAt", skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<bson::DateTime>,
+    #[serde(rename = "closedAt", skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<bson::DateTime>,
+}
@@ -0,0 +1,84 @@
+use crate::prelude::*;
+use super::payments::PaymentMethod;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReturnStatus {
+    /// Asked for by a customer, waiting for the store.
+    Requested,
+    Rejected,
+    /// Units back in inventory, but the refund didn't go through yet.
+    Approved,
+    Refunded,
+}
+
+impl ReturnStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnStatus::Requested => "requested",
+            ReturnStatus::Rejected => "rejected",
+            ReturnStatus::Approved => "approved",
+            ReturnStatus::Refunded => "refunded",
+        }
+    }
+}
+
+/// State of a returned unit. Resellable units go back to their lot, while
+/// damaged ones are kept in the `quarantine` of their item, out of stock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitCondition {
+    Resellable,
+    Damaged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReturnedUnit {
+    pub coll: String,
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub code: bson::Bson,
+    pub name: String,
+    /// Amount refunded for the unit.
+    pub price: f64,
+}
+
+/// Return of units sold at a register, referencing the sale of the store's
+/// `daySales`, or of units bought through an order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReturnRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sale: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<ObjectId>,
+    /// Client who bought the units, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<ObjectId>,
+    pub units: Vec<ReturnedUnit>,
+    pub amount: f64,
+    pub status: ReturnStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<UnitCondition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(rename = "rejectionReason", skip_serializing_if = "Option::is_none")]
+    pub rejection_reason: Option<String>,
+    /// How the money was given back: the method of the sale, or the
+    /// payment refunded through the payment provider.
+    #[serde(rename = "refundType", skip_serializing_if = "Option::is_none")]
+    pub refund_method: Option<PaymentMethod>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment: Option<ObjectId>,
+    #[serde(rename = "requestedBy")]
+    pub requested_by: ObjectId,
+    #[serde(rename = "processedBy", skip_serializing_if = "Option::is_none")]
+    pub processed_by: Option<ObjectId>,
+    #[serde(rename = "createdAt")]
+    pub created_at: bson::DateTime,
+    #[serde(rename = "processedAt", skip_serializing_if = "Option::is_none")]
+    pub processed_at: Option<bson::DateTime>,
+}
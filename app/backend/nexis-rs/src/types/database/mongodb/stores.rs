@@ -0,0 +1,56 @@
+use crate::prelude::*;
+use super::payments::PaymentMethod;
+
+/// Entry of the store registry: a store and the item collections it sells.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoreInfo {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    #[serde(rename = "itemColls", default)]
+    pub item_colls: Vec<String>,
+}
+
+/// A sale charged at the register of a store, as kept in its `daySales`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaySale {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub payment: SalePayment,
+    pub client: SaleClient,
+    pub item: Vec<SoldUnit>,
+    /// Employee who charged the sale.
+    pub cashier: ObjectId,
+    pub terminal: String,
+    pub timestamp: bson::DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SalePayment {
+    pub amount: f64,
+    #[serde(rename = "type")]
+    pub method: PaymentMethod,
+}
+
+/// Buyer of a sale: either just a name or a registered client.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SaleClient {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<ObjectId>,
+}
+
+/// A unit taken from a lot by a sale, and what was charged for it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SoldUnit {
+    pub coll: String,
+    #[serde(rename = "_id")]
+    pub item: ObjectId,
+    pub lot: ObjectId,
+    pub code: ObjectId,
+    pub name: String,
+    pub price: f64,
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+}
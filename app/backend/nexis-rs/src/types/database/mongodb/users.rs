@@ -4,12 +4,25 @@ use chrono::{ DateTime, Utc };
 use mongodb::bson::oid::ObjectId;
 use crate::types::requests::users::NewUser;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CartItem {
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CartItem {
     #[serde(rename = "dateAdded")]
-    date_added: DateTime<Utc>,
-    coll: String,
-    item: ObjectId,
+    pub date_added: DateTime<Utc>,
+    pub coll: String,
+    pub item: ObjectId,
+    #[serde(default = "default_cart_quantity")]
+    pub quantity: u32,
+    /// Total weight wanted of an item sold by `pricePerKg`.
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+    /// Unit price of the item when it was added, used to warn the
+    /// client if it changed since.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+}
+
+fn default_cart_quantity() -> u32 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,9 +42,9 @@ struct Client {
     pub phone_num: String,
     pub interests: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub cart: Option<Box<Vec<CartItem>>>,
+    pub cart: Option<Vec<CartItem>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub reviews: Option<Box<Vec<Review>>>,
+    pub reviews: Option<Vec<Review>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -78,38 +91,27 @@ impl TryFrom<NewUser> for User {
     type Error = anyhow::Error;
 
     fn try_from(new: NewUser) -> std::result::Result<Self, Self::Error> {
-        let client =
-            if let Some(new) = new.client {
-                Some(Box::new(Client {
-                    age: new.age,
-                    gender: new.gender,
-                    phone_num: new.phone_num,
-                    interests: new.interests,
-                    cart: None,
-                    reviews: None,
-                }))
-            } else {
-                None
-            };
+        let client = new.client.map(|new|
+            Box::new(Client {
+                age: new.age,
+                gender: new.gender,
+                phone_num: new.phone_num,
+                interests: new.interests,
+                cart: None,
+                reviews: None,
+            })
+        );
 
-        let employee =
-            if let Some(new) = new.employee {
-                Some(Box::new(Employee {
-                    age: new.age,
-                    gender: new.gender,
-                    phone_num: new.phone_num,
-                    schedule: new.schedule,
-                }))
-            } else {
-                None
-            };
+        let employee = new.employee.map(|new|
+            Box::new(Employee {
+                age: new.age,
+                gender: new.gender,
+                phone_num: new.phone_num,
+                schedule: new.schedule,
+            })
+        );
 
-        let admin =
-            if let Some(_new) = new.admin {
-                Some(Box::new(Admin {}))
-            } else {
-                None
-            };
+        let admin = new.admin.map(|_new| Box::new(Admin {}));
         
         if client.is_some() || employee.is_some() || admin.is_some() {
             Ok(
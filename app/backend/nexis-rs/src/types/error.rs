@@ -8,6 +8,193 @@ pub enum Redis {
     SessionExpired(String),
 }
 
+#[derive(Debug, Error)]
+pub enum Auth {
+    #[error("Session cookie missing")]
+    MissingSession,
+    #[error("Invalid session: {0}")]
+    InvalidSession(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Stores {
+    #[error("Invalid store registry: {0}")]
+    InvalidRegistry(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Cart {
+    #[error("Item not found: {0}")]
+    ItemNotFound(String),
+    #[error("Item not in cart")]
+    NotInCart,
+    #[error("Not enough stock: only {available} units available")]
+    OutOfStock { available: i64 },
+    #[error("Invalid quantity: {0}")]
+    InvalidQuantity(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Checkout {
+    #[error("The cart is empty")]
+    EmptyCart,
+    #[error("Item not found: {0}")]
+    ItemNotFound(String),
+    #[error("`{0}` is sold by weight, but no weight was given")]
+    MissingWeight(String),
+    #[error("The price of some items changed since they were added to the cart")]
+    PricesChanged,
+    #[error("Not enough stock of `{name}`: only {available} units available")]
+    OutOfStock { name: String, available: i64 },
+}
+
+#[derive(Debug, Error)]
+pub enum Orders {
+    #[error("Order not found")]
+    NotFound,
+    #[error("An order can't go from `{from}` to `{to}`")]
+    IllegalTransition { from: String, to: String },
+    #[error("The order changed while updating it")]
+    Changed,
+    #[error("{0}")]
+    SubOrders(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Payments {
+    #[error("The order can't be paid: {0}")]
+    OrderNotPayable(String),
+    #[error("The order already has a payment in progress or completed")]
+    AlreadyPaid,
+    #[error("`{0}` payments aren't supported here")]
+    UnsupportedMethod(String),
+    #[error("Payment declined: {0}")]
+    Declined(String),
+    #[error("Payment provider error: {0}")]
+    ProviderError(String),
+    #[error("Invalid webhook signature: {0}")]
+    InvalidSignature(String),
+    #[error("Unknown payment")]
+    UnknownPayment,
+}
+
+#[derive(Debug, Error)]
+pub enum Pos {
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("The sale has no units")]
+    EmptySale,
+    #[error("Unit {0} was scanned twice")]
+    DuplicateCode(String),
+    #[error("Unit {0} isn't in stock at this store")]
+    UnitUnavailable(String),
+    #[error("Unit {0} of `{1}` is expired")]
+    Expired(String, String),
+    #[error("`{0}` is sold by weight, but no weight was given")]
+    MissingWeight(String),
+    #[error("Invalid sale: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Returns {
+    #[error("Return not found")]
+    NotFound,
+    #[error("Sale not found")]
+    SaleNotFound,
+    #[error("No units to return")]
+    Empty,
+    #[error("Unit {0} wasn't sold by this sale or order")]
+    UnitNotSold(String),
+    #[error("Unit {0} was already returned")]
+    AlreadyReturned(String),
+    #[error("The return window of `{0}` is over")]
+    WindowClosed(String),
+    #[error("Not returnable: {0}")]
+    NotReturnable(String),
+    #[error("The return is `{0}`")]
+    InvalidState(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Inventory {
+    #[error("Item not found")]
+    ItemNotFound,
+    #[error("Lot not found")]
+    LotNotFound,
+    #[error("Unit {0} isn't in this lot")]
+    UnitNotInLot(String),
+    #[error("The lot has only {0} units")]
+    NotEnoughUnits(usize),
+    #[error("The lot changed while updating it")]
+    Changed,
+    #[error("Invalid lot: {0}")]
+    Invalid(String),
+    #[error("Invalid reorder levels: {0}")]
+    InvalidReorder(String),
+    #[error("Invalid stock count: {0}")]
+    InvalidCount(String),
+    #[error("Stock count not found")]
+    CountNotFound,
+    #[error("Another stock count of these collections is in progress")]
+    CountInProgress,
+    #[error("The stock count is {0}")]
+    CountState(String),
+    #[error("Invalid stock transfer: {0}")]
+    InvalidTransfer(String),
+    #[error("Stock transfer not found")]
+    TransferNotFound,
+    #[error("The stock transfer is {0}")]
+    TransferState(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Purchasing {
+    #[error("Supplier not found")]
+    SupplierNotFound,
+    #[error("Purchase order not found")]
+    OrderNotFound,
+    #[error("Line {0} isn't in this purchase order")]
+    LineNotFound(String),
+    #[error("Invalid supplier: {0}")]
+    InvalidSupplier(String),
+    #[error("Invalid purchase order: {0}")]
+    Invalid(String),
+    #[error("The purchase order is `{0}`")]
+    InvalidState(String),
+    #[error("Only {remaining} units of line {line} are left to receive")]
+    OverReceived { line: String, remaining: u32 },
+    #[error("The purchase order changed while updating it")]
+    Changed,
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Error)]
+pub enum Catalog {
+    #[error("Item not found")]
+    ItemNotFound,
+    #[error("Invalid item: {0}")]
+    Invalid(String),
+    #[error("The item was changed by someone else, and is now at version {0}")]
+    Changed(i64),
+    #[error("The item is archived")]
+    Archived,
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    /// A file which can't be imported at all, as opposed to some of its rows.
+    #[error("Invalid file: {0}")]
+    InvalidFile(String),
+}
+
 #[derive(Debug, Error)]
 pub enum Mongodb {
     #[error("User repetition: {0} ")]
@@ -0,0 +1,18 @@
+use crate::prelude::*;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewCartItem {
+    pub coll: String,
+    pub item: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CartQuantity {
+    pub quantity: u32,
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+}
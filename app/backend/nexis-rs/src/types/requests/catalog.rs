@@ -0,0 +1,628 @@
+use crate::prelude::*;
+use crate::types::{ error, requests::inventory::NewLot };
+use serde_json::{ Map, Value };
+
+/// A new item of an item collection, with the lots it starts with.
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewCatalogItem {
+    /// Code the store knows the item by, unique within its collection.
+    #[serde(default)]
+    pub sku: Option<String>,
+    #[serde(default)]
+    pub lots: Vec<NewLot>,
+    /// Fields of the item, checked against the schema of its collection.
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+/// Every field of an item, replacing the current ones. `version` is the one
+/// the editor last read, so changes made since then aren't overwritten.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CatalogItemUpdate {
+    pub version: i64,
+    /// Kept as it is if missing.
+    #[serde(default)]
+    pub sku: Option<String>,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct CatalogItemVersion {
+    pub version: i64,
+}
+
+/// Format of the files items are imported from and exported to.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogFormat {
+    /// One row per item, with the columns of its collection.
+    #[default]
+    Csv,
+    /// One JSON object per line, with the same fields as the item.
+    Ndjson,
+}
+
+impl CatalogFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CatalogFormat::Csv => "text/csv; charset=utf-8",
+            CatalogFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CatalogFormat::Csv => "csv",
+            CatalogFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogImportParams {
+    #[serde(default)]
+    pub format: CatalogFormat,
+    /// Checks every row without writing anything.
+    #[serde(rename = "dry-run", alias = "dryRun", default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CatalogExportParams {
+    #[serde(default)]
+    pub format: CatalogFormat,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+/// The fields of an item, validated with the schema of its collection.
+/// Collections without a schema of their own only take a name and a price.
+#[derive(Debug, Clone)]
+pub enum CatalogItem {
+    Clothes(Clothes),
+    Food(Food),
+    Library(LibraryItem),
+    Cpu(Cpu),
+    Gpu(Gpu),
+    Tech(Tech),
+    Keyboard(Keyboard),
+    Other(OtherItem),
+}
+
+impl CatalogItem {
+    pub fn parse(coll: &str, fields: Map<String, Value>) -> Result<Self, error::Catalog> {
+        let fields = Value::Object(fields);
+        let item = match coll {
+            "clothes" => serde_json::from_value(fields).map(CatalogItem::Clothes),
+            "food" => serde_json::from_value(fields).map(CatalogItem::Food),
+            "libraryItem" => serde_json::from_value(fields).map(CatalogItem::Library),
+            "techCpu" => serde_json::from_value(fields).map(CatalogItem::Cpu),
+            "techGpu" => serde_json::from_value(fields).map(CatalogItem::Gpu),
+            "tech" => serde_json::from_value(fields).map(CatalogItem::Tech),
+            "techKeyboard" => serde_json::from_value(fields).map(CatalogItem::Keyboard),
+            _ => serde_json::from_value(fields).map(CatalogItem::Other),
+        }
+        .map_err(|e| error::Catalog::Invalid(e.to_string()))?;
+
+        item.validate()?;
+
+        Ok(item)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            CatalogItem::Clothes(item) => &item.name,
+            CatalogItem::Food(item) => &item.name,
+            CatalogItem::Library(item) => &item.name,
+            CatalogItem::Cpu(item) => &item.name,
+            CatalogItem::Gpu(item) => &item.name,
+            CatalogItem::Tech(item) => &item.name,
+            CatalogItem::Keyboard(item) => &item.name,
+            CatalogItem::Other(item) => &item.name,
+        }
+    }
+
+    /// The key of the item's price, `price` or `pricePerKg`, and its value.
+    pub fn price(&self) -> (&'static str, f64) {
+        match self {
+            CatalogItem::Clothes(item) => ("price", item.price),
+            CatalogItem::Food(Food { price: Some(price), .. }) => ("price", *price),
+            CatalogItem::Food(item) => ("pricePerKg", item.price_per_kg.unwrap_or_default()),
+            CatalogItem::Library(item) => ("price", item.price),
+            CatalogItem::Cpu(item) => ("price", item.price),
+            CatalogItem::Gpu(item) => ("price", item.price),
+            CatalogItem::Tech(item) => ("price", item.price),
+            CatalogItem::Keyboard(item) => ("price", item.price),
+            CatalogItem::Other(item) => ("price", item.price),
+        }
+    }
+
+    pub fn to_document(&self) -> bson::ser::Result<Document> {
+        match self {
+            CatalogItem::Clothes(item) => bson::to_document(item),
+            CatalogItem::Food(item) => bson::to_document(item),
+            CatalogItem::Library(item) => bson::to_document(item),
+            CatalogItem::Cpu(item) => bson::to_document(item),
+            CatalogItem::Gpu(item) => bson::to_document(item),
+            CatalogItem::Tech(item) => bson::to_document(item),
+            CatalogItem::Keyboard(item) => bson::to_document(item),
+            CatalogItem::Other(item) => bson::to_document(item),
+        }
+    }
+
+    fn validate(&self) -> Result<(), error::Catalog> {
+        check_text("name", self.name())?;
+
+        let (price_key, price) = self.price();
+        if !price.is_finite() || price <= 0.0 {
+            return Err(error::Catalog::Invalid(format!("`{}` must be greater than 0.", price_key)));
+        }
+
+        match self {
+            CatalogItem::Clothes(item) => {
+                check_text("brand", &item.brand)?;
+                check_text("type", &item.clothes_type)?;
+                check_text("size", &item.size)?;
+                check_list("color", &item.color)?;
+                if item.material.iter().any(|material| !(material.percentage > 0.0 && material.percentage <= 100.0)) {
+                    return Err(error::Catalog::Invalid("Material percentages must be between 0 and 100.".into()));
+                }
+                if item.material.iter().map(|material| material.percentage).sum::<f64>() > 100.0 + 1e-6 {
+                    return Err(error::Catalog::Invalid("Material percentages can't add up to more than 100.".into()));
+                }
+            }
+            CatalogItem::Food(item) => {
+                if item.price.is_some() == item.price_per_kg.is_some() {
+                    return Err(error::Catalog::Invalid("Food needs exactly one of `price` or `pricePerKg`.".into()));
+                }
+                check_text("type", &item.food_type)?;
+            }
+            CatalogItem::Library(item) => {
+                if let Some(book) = &item.book {
+                    if !is_valid_isbn(&book.isbn) {
+                        return Err(error::Catalog::Invalid(format!("`{}` isn't a valid ISBN.", book.isbn)));
+                    }
+                    if book.num_pages == 0 || book.edition == 0 {
+                        return Err(error::Catalog::Invalid("A book needs at least 1 page and edition.".into()));
+                    }
+                    check_text("book.publisher", &book.publisher)?;
+                    check_list("book.author", &book.author)?;
+                }
+            }
+            CatalogItem::Cpu(item) => {
+                check_text("brand", &item.brand)?;
+                check_text("model", &item.model)?;
+                check_text("arch", &item.arch)?;
+                check_text("socketType", &item.socket_type)?;
+                if item.cores == 0 || item.threads == 0 {
+                    return Err(error::Catalog::Invalid("A CPU needs at least 1 core and thread.".into()));
+                }
+                if item.memory_supp.max_size_gb == 0 {
+                    return Err(error::Catalog::Invalid("`memorySupp.maxSizeGb` must be greater than 0.".into()));
+                }
+                item.clock.check()?;
+            }
+            CatalogItem::Gpu(item) => {
+                check_text("brand", &item.brand)?;
+                check_text("model", &item.model)?;
+                check_list("ports", &item.ports)?;
+                if item.tdp == 0 {
+                    return Err(error::Catalog::Invalid("`tdp` must be greater than 0.".into()));
+                }
+                item.clock.check()?;
+            }
+            CatalogItem::Tech(item) => {
+                check_text("brand", &item.brand)?;
+                check_text("model", &item.model)?;
+                check_text("type", &item.tech_type)?;
+                check_list("color", &item.color)?;
+                if item.memory == 0 {
+                    return Err(error::Catalog::Invalid("`memory` must be greater than 0.".into()));
+                }
+            }
+            CatalogItem::Keyboard(item) => {
+                check_text("brand", &item.brand)?;
+                check_text("model", &item.model)?;
+                check_text("type", &item.keyboard_type)?;
+                check_text("keySwitch", &item.key_switch)?;
+                let Size { length, width, height } = item.dimensions;
+                if [length, width, height, item.weight_kg].iter().any(|value| !(value.is_finite() && *value > 0.0)) {
+                    return Err(error::Catalog::Invalid("The dimensions and weight must be greater than 0.".into()));
+                }
+            }
+            CatalogItem::Other(item) => {
+                if let Some(brand) = &item.brand {
+                    check_text("brand", brand)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Most characters an SKU can have.
+const MAX_SKU_LEN: usize = 64;
+
+pub fn check_sku(sku: &str) -> Result<(), error::Catalog> {
+    let valid = !sku.is_empty()
+        && sku.len() <= MAX_SKU_LEN
+        && sku.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+
+    if !valid {
+        return Err(error::Catalog::Invalid(format!(
+            "SKUs have between 1 and {} letters, digits, `-`, `_`, `.` or `/`.", MAX_SKU_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn check_text(field: &str, value: &str) -> Result<(), error::Catalog> {
+    if value.trim().is_empty() || value.len() > 200 {
+        return Err(error::Catalog::Invalid(format!("`{}` must have between 1 and 200 characters.", field)));
+    }
+    Ok(())
+}
+
+fn check_list(field: &str, values: &[String]) -> Result<(), error::Catalog> {
+    if values.is_empty() {
+        return Err(error::Catalog::Invalid(format!("`{}` can't be empty.", field)));
+    }
+    values.iter().try_for_each(|value| check_text(field, value))
+}
+
+/// Checks the check digit of an ISBN-10 or ISBN-13, ignoring hyphens and spaces.
+fn is_valid_isbn(isbn: &str) -> bool {
+    let chars: Vec<char> = isbn.chars().filter(|c| !matches!(c, '-' | ' ')).collect();
+
+    match chars.len() {
+        10 => {
+            let mut sum = 0;
+            for (i, c) in chars.iter().enumerate() {
+                let digit = match (c.to_digit(10), c) {
+                    (Some(digit), _) => digit,
+                    (None, 'X' | 'x') if i == 9 => 10,
+                    _ => return false,
+                };
+                sum += digit * (10 - i as u32);
+            }
+            sum % 11 == 0
+        }
+        13 => {
+            let mut sum = 0;
+            for (i, c) in chars.iter().enumerate() {
+                let Some(digit) = c.to_digit(10) else { return false };
+                sum += if i % 2 == 0 { digit } else { digit * 3 };
+            }
+            sum % 10 == 0
+        }
+        _ => false,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    pub percentage: f64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Clothes {
+    pub name: String,
+    pub price: f64,
+    pub gender: String,
+    pub age: String,
+    pub size: String,
+    pub color: Vec<String>,
+    #[serde(rename = "type")]
+    pub clothes_type: String,
+    pub brand: String,
+    #[serde(default)]
+    pub material: Vec<Material>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Food {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    #[serde(rename = "pricePerKg", skip_serializing_if = "Option::is_none")]
+    pub price_per_kg: Option<f64>,
+    #[serde(rename = "type")]
+    pub food_type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Book {
+    pub isbn: String,
+    #[serde(rename = "numPages")]
+    pub num_pages: u32,
+    pub author: Vec<String>,
+    pub publisher: String,
+    pub edition: u8,
+    #[serde(default)]
+    pub audience: Vec<String>,
+    #[serde(default)]
+    pub genre: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LibraryItem {
+    pub name: String,
+    pub price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub book: Option<Book>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MemorySupport {
+    #[serde(rename = "type")]
+    pub memory_type: String,
+    #[serde(rename = "maxSizeGb")]
+    pub max_size_gb: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDetails {
+    #[serde(rename = "type")]
+    pub memory_type: String,
+    #[serde(rename = "sizeGb")]
+    pub size_gb: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Clock {
+    #[serde(rename = "coreSpeedGhz")]
+    pub core_speed_ghz: f64,
+    #[serde(rename = "boostSpeedGhz")]
+    pub boost_speed_ghz: f64,
+}
+
+impl Clock {
+    fn check(&self) -> Result<(), error::Catalog> {
+        let valid = self.core_speed_ghz.is_finite() && self.core_speed_ghz > 0.0 && self.boost_speed_ghz >= self.core_speed_ghz;
+        if !valid {
+            return Err(error::Catalog::Invalid("The clock speeds must be greater than 0, boosting to at least the core speed.".into()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Cpu {
+    pub name: String,
+    pub price: f64,
+    pub brand: String,
+    pub model: String,
+    pub arch: String,
+    pub cores: u8,
+    pub threads: u8,
+    #[serde(rename = "socketType")]
+    pub socket_type: String,
+    #[serde(rename = "overclockSupp")]
+    pub overclock_supp: bool,
+    #[serde(rename = "soldSep")]
+    pub sold_sep: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warranty: Option<String>,
+    #[serde(rename = "memorySupp")]
+    pub memory_supp: MemorySupport,
+    pub clock: Clock,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Gpu {
+    pub name: String,
+    pub price: f64,
+    pub brand: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cuda_cores: Option<u16>,
+    pub tdp: u16,
+    pub ports: Vec<String>,
+    pub dedicated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warranty: Option<String>,
+    pub memory: MemoryDetails,
+    pub clock: Clock,
+}
+
+/// A computer, tablet or phone, built with a CPU of `techCpu` and maybe a GPU of `techGpu`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Tech {
+    pub name: String,
+    pub price: f64,
+    pub brand: String,
+    pub model: String,
+    pub color: Vec<String>,
+    #[serde(rename = "type")]
+    pub tech_type: String,
+    pub memory: u16,
+    pub cpu: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<ObjectId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Size {
+    pub length: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Keyboard {
+    pub name: String,
+    pub price: f64,
+    pub brand: String,
+    pub model: String,
+    #[serde(rename = "type")]
+    pub keyboard_type: String,
+    #[serde(rename = "keySwitch")]
+    pub key_switch: String,
+    pub backlight: bool,
+    pub wireless: bool,
+    pub dimensions: Size,
+    #[serde(rename = "weightKg")]
+    pub weight_kg: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OtherItem {
+    pub name: String,
+    pub price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(coll: &str, fields: Value) -> Result<CatalogItem, error::Catalog> {
+        let Value::Object(fields) = fields else { panic!("Fields must be an object.") };
+        CatalogItem::parse(coll, fields)
+    }
+
+    fn book(isbn: &str) -> Value {
+        json!({
+            "name": "Book",
+            "price": 20.0,
+            "book": {
+                "isbn": isbn,
+                "numPages": 300,
+                "author": ["Author"],
+                "publisher": "Publisher",
+                "edition": 1,
+            },
+        })
+    }
+
+    #[test]
+    fn accepts_valid_isbns() {
+        assert!(is_valid_isbn("0306406152"));
+        assert!(is_valid_isbn("0-306-40615-2"));
+        assert!(is_valid_isbn("080442957X"));
+        assert!(is_valid_isbn("080442957x"));
+        assert!(is_valid_isbn("9780306406157"));
+        assert!(is_valid_isbn("978-0 306-40615-7"));
+    }
+
+    #[test]
+    fn rejects_invalid_isbns() {
+        // Wrong check digits
+        assert!(!is_valid_isbn("0306406153"));
+        assert!(!is_valid_isbn("9780306406158"));
+        // `X` is only a check digit of ISBN-10
+        assert!(!is_valid_isbn("X306406152"));
+        assert!(!is_valid_isbn("978030640615X"));
+        // Wrong lengths or characters
+        assert!(!is_valid_isbn(""));
+        assert!(!is_valid_isbn("030640615"));
+        assert!(!is_valid_isbn("97803064061570"));
+        assert!(!is_valid_isbn("03064O6152"));
+        assert!(!is_valid_isbn("0306406152\u{0662}"));
+    }
+
+    #[test]
+    fn validates_books() {
+        assert!(parse("libraryItem", book("978-0-306-40615-7")).is_ok());
+        assert!(parse("libraryItem", book("978-0-306-40615-8")).is_err());
+        assert!(parse("libraryItem", json!({ "name": "Poster", "price": 5.0 })).is_ok());
+
+        let mut no_pages = book("0306406152");
+        no_pages["book"]["numPages"] = json!(0);
+        assert!(parse("libraryItem", no_pages).is_err());
+
+        let mut no_authors = book("0306406152");
+        no_authors["book"]["author"] = json!([]);
+        assert!(parse("libraryItem", no_authors).is_err());
+    }
+
+    #[test]
+    fn validates_names_and_prices() {
+        assert!(parse("other", json!({ "name": "Mug", "price": 8.5 })).is_ok());
+        assert!(parse("other", json!({ "name": "  ", "price": 8.5 })).is_err());
+        assert!(parse("other", json!({ "name": "x".repeat(201), "price": 8.5 })).is_err());
+        assert!(parse("other", json!({ "name": "Mug", "price": 0.0 })).is_err());
+        assert!(parse("other", json!({ "name": "Mug", "price": -1.0 })).is_err());
+        // Fields outside the schema are rejected
+        assert!(parse("other", json!({ "name": "Mug", "price": 8.5, "colour": "red" })).is_err());
+    }
+
+    #[test]
+    fn validates_food_prices() {
+        assert!(parse("food", json!({ "name": "Rice", "price": 2.0, "type": "grain" })).is_ok());
+        assert!(parse("food", json!({ "name": "Apples", "pricePerKg": 3.0, "type": "fruit" })).is_ok());
+        assert!(parse("food", json!({ "name": "Rice", "type": "grain" })).is_err());
+        assert!(parse("food", json!({ "name": "Rice", "price": 2.0, "pricePerKg": 3.0, "type": "grain" })).is_err());
+    }
+
+    #[test]
+    fn validates_clothes_materials() {
+        let clothes = |material: Value| json!({
+            "name": "Shirt",
+            "price": 15.0,
+            "gender": "unisex",
+            "age": "adult",
+            "size": "M",
+            "color": ["white"],
+            "type": "shirt",
+            "brand": "Acme",
+            "material": material,
+        });
+
+        assert!(parse("clothes", clothes(json!([{ "percentage": 60, "name": "cotton" }, { "percentage": 40, "name": "linen" }]))).is_ok());
+        assert!(parse("clothes", clothes(json!([{ "percentage": 60, "name": "cotton" }, { "percentage": 50, "name": "linen" }]))).is_err());
+        assert!(parse("clothes", clothes(json!([{ "percentage": 0, "name": "cotton" }]))).is_err());
+    }
+
+    #[test]
+    fn validates_clock_speeds() {
+        let cpu = |core: f64, boost: f64| json!({
+            "name": "CPU",
+            "price": 300.0,
+            "brand": "Acme",
+            "model": "X1",
+            "arch": "x86-64",
+            "cores": 8,
+            "threads": 16,
+            "socketType": "AM5",
+            "overclockSupp": true,
+            "soldSep": true,
+            "memorySupp": { "type": "DDR5", "maxSizeGb": 128 },
+            "clock": { "coreSpeedGhz": core, "boostSpeedGhz": boost },
+        });
+
+        assert!(parse("techCpu", cpu(3.6, 5.0)).is_ok());
+        assert!(parse("techCpu", cpu(3.6, 3.6)).is_ok());
+        assert!(parse("techCpu", cpu(3.6, 3.0)).is_err());
+        assert!(parse("techCpu", cpu(0.0, 3.0)).is_err());
+    }
+
+    #[test]
+    fn checks_skus() {
+        assert!(check_sku("LAP/14-B_2.0").is_ok());
+        assert!(check_sku("").is_err());
+        assert!(check_sku("has space").is_err());
+        assert!(check_sku(&"A".repeat(MAX_SKU_LEN)).is_ok());
+        assert!(check_sku(&"A".repeat(MAX_SKU_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::prelude::*;
+use chrono::{ DateTime, Utc };
+
+/// A shipment of units of an item received by a store.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewLot {
+    pub quantity: u32,
+    /// Defaults to the time it's received.
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<DateTime<Utc>>,
+    #[serde(rename = "costPrice", skip_serializing_if = "Option::is_none")]
+    pub cost_price: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LotUpdate {
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<DateTime<Utc>>,
+    #[serde(rename = "costPrice", skip_serializing_if = "Option::is_none")]
+    pub cost_price: Option<f64>,
+}
+
+/// Units to write off from a lot: either specific codes, or any `quantity` of them.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewWriteOff {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codes: Vec<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+    pub reason: String,
+}
+
+/// Reorder point and target quantity of an item, overriding its collection's defaults.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReorderUpdate {
+    pub point: i64,
+    pub target: i64,
+}
+
+/// Filters of the inventory ledger of a store. All of them are optional.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct MovementQuery {
+    pub coll: Option<String>,
+    pub item: Option<ObjectId>,
+    pub lot: Option<ObjectId>,
+    pub code: Option<ObjectId>,
+    pub kind: Option<crate::types::mongodb::inventory::MovementKind>,
+    pub reference: Option<ObjectId>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<u64>,
+}
+
+/// Opens a stock count of a store, or of only one of its item collections.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewStockCount {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coll: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CountScans {
+    pub codes: Vec<ObjectId>,
+}
+
+/// Units whose adjustment is left out of an approved count, such as ones
+/// already being looked for.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CountApproval {
+    #[serde(default)]
+    pub exclude: Vec<ObjectId>,
+}
+
+/// How the units in stock are valued: against the newest cost layers
+/// received, as if the oldest units always left first, or all at the average
+/// cost of every unit received.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ValuationMethod {
+    #[default]
+    Fifo,
+    WeightedAverage,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ValuationQuery {
+    pub method: Option<ValuationMethod>,
+    /// Defaults to now.
+    pub at: Option<DateTime<Utc>>,
+}
+
+/// Defaults to the last 30 days.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct MarginQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewTransferLine {
+    pub coll: String,
+    pub item: ObjectId,
+    pub quantity: u32,
+}
+
+/// Units a store asks another one to send it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewTransfer {
+    pub from: ObjectId,
+    pub lines: Vec<NewTransferLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Codes of the units shipped for a line of a transfer.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ShippedLine {
+    pub line: ObjectId,
+    pub codes: Vec<ObjectId>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TransferShipment {
+    pub lines: Vec<ShippedLine>,
+}
+
+/// Shipped units which didn't arrive. Every other one is received.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TransferReceipt {
+    #[serde(default)]
+    pub lost: Vec<ObjectId>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TransferQuery {
+    pub status: Option<crate::types::mongodb::inventory::TransferStatus>,
+    pub direction: Option<TransferDirection>,
+}
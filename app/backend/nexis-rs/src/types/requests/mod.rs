@@ -1 +1,11 @@
-pub mod users;
\ No newline at end of file
+pub mod users;
+pub mod stores;
+pub mod cart;
+pub mod orders;
+pub mod payments;
+pub mod pos;
+pub mod returns;
+pub mod receipts;
+pub mod inventory;
+pub mod purchasing;
+pub mod catalog;
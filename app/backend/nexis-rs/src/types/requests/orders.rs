@@ -0,0 +1,16 @@
+use crate::prelude::*;
+use crate::types::mongodb::orders::OrderStatus;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CheckoutRequest {
+    /// Whether to check out even if prices changed since the items were added to the cart.
+    #[serde(rename = "acceptPriceChanges", default)]
+    pub accept_price_changes: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StatusChange {
+    pub status: OrderStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
@@ -0,0 +1,13 @@
+use crate::prelude::*;
+use crate::types::mongodb::payments::PaymentMethod;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PayOrder {
+    #[serde(rename = "type")]
+    pub method: PaymentMethod,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MockSettlement {
+    pub succeeded: bool,
+}
@@ -0,0 +1,29 @@
+use crate::prelude::*;
+use crate::types::mongodb::{ payments::PaymentMethod, stores::SaleClient };
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewSale {
+    pub store: ObjectId,
+    pub terminal: String,
+    pub units: Vec<ScannedUnit>,
+    pub payment: SalePaymentRequest,
+    #[serde(default)]
+    pub client: SaleClient,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScannedUnit {
+    pub code: ObjectId,
+    /// Weight of the unit, for items sold by `pricePerKg`.
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SalePaymentRequest {
+    #[serde(rename = "type")]
+    pub method: PaymentMethod,
+    /// Cash handed over by the buyer, to work out the change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tendered: Option<f64>,
+}
@@ -0,0 +1,85 @@
+use crate::prelude::*;
+use chrono::{ DateTime, Utc };
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewSupplier {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SupplierUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewPurchaseOrderLine {
+    pub coll: String,
+    pub item: ObjectId,
+    pub quantity: u32,
+    #[serde(rename = "costPrice")]
+    pub cost_price: f64,
+    #[serde(rename = "expectedAt", skip_serializing_if = "Option::is_none")]
+    pub expected_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewPurchaseOrder {
+    pub supplier: ObjectId,
+    pub lines: Vec<NewPurchaseOrderLine>,
+    #[serde(rename = "expectedAt", skip_serializing_if = "Option::is_none")]
+    pub expected_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Changes to a draft purchase order. `lines` replaces all of its lines.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PurchaseOrderUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supplier: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<NewPurchaseOrderLine>>,
+    #[serde(rename = "expectedAt", skip_serializing_if = "Option::is_none")]
+    pub expected_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// Units delivered for a line of a purchase order, received as a new lot.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReceivedLine {
+    pub line: ObjectId,
+    pub quantity: u32,
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PurchaseOrderDelivery {
+    pub lines: Vec<ReceivedLine>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct PurchaseOrderQuery {
+    pub status: Option<crate::types::mongodb::purchasing::PurchaseOrderStatus>,
+    pub supplier: Option<ObjectId>,
+}
@@ -0,0 +1,8 @@
+use crate::prelude::*;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ResendReceipt {
+    /// Address to send the receipt of a sale to, for buyers who aren't registered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
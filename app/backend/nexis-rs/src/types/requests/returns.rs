@@ -0,0 +1,30 @@
+use crate::prelude::*;
+use crate::types::mongodb::returns::UnitCondition;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SaleReturn {
+    pub store: ObjectId,
+    pub sale: ObjectId,
+    pub codes: Vec<ObjectId>,
+    pub condition: UnitCondition,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OrderReturnRequest {
+    pub codes: Vec<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReturnApproval {
+    pub condition: UnitCondition,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReturnRejection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,20 @@
+use crate::prelude::*;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewStore {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub floor: Option<u8>,
+    #[serde(rename = "itemColls")]
+    pub item_colls: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StoreUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "itemColls", skip_serializing_if = "Option::is_none")]
+    pub item_colls: Option<Vec<String>>,
+}
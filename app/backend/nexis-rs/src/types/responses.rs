@@ -1,5 +1,3 @@
-use types::constants::STORE_COLLS;
-
 use crate::prelude::*;
 use crate::types::mongodb::Item;
 
@@ -40,6 +38,113 @@ impl From<Item> for ItemSuggestion {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchSuggestions {
+    pub suggestions: Vec<ItemSuggestion>,
+    #[serde(rename = "didYouMean", skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CartLine {
+    pub item: ObjectId,
+    pub coll: String,
+    pub name: String,
+    pub quantity: u32,
+    #[serde(rename = "weightKg", skip_serializing_if = "Option::is_none")]
+    pub weight_kg: Option<f64>,
+    /// Whether `price` is per kilogram rather than per unit.
+    #[serde(rename = "perKg")]
+    pub per_kg: bool,
+    /// Price when the item was added to the cart.
+    pub price: f64,
+    #[serde(rename = "currentPrice")]
+    pub current_price: f64,
+    #[serde(rename = "priceChanged")]
+    pub price_changed: bool,
+    /// Total at the current price. `None` for items sold by weight without a `weightKg`.
+    #[serde(rename = "lineTotal")]
+    pub line_total: Option<f64>,
+    pub stock: i64,
+    /// Whether there's enough stock for the quantity in the cart.
+    pub available: bool,
+    #[serde(rename = "dateAdded")]
+    pub date_added: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CartResponse {
+    pub items: Vec<CartLine>,
+    pub total: f64,
+    #[serde(rename = "priceChanged")]
+    pub price_changed: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PosSale {
+    #[serde(flatten)]
+    pub sale: crate::types::mongodb::stores::DaySale,
+    /// Change owed to the buyer, for cash sales where the tendered amount was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<f64>,
+}
+
+/// Where a scanned unit code is.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum UnitStatus {
+    /// In its lot and sellable.
+    InStock,
+    /// In its lot, but the lot expired.
+    Expired,
+    /// Taken by an order that isn't completed yet.
+    Reserved,
+    Sold,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnitLookup {
+    pub code: ObjectId,
+    pub status: UnitStatus,
+    pub sellable: bool,
+    pub coll: String,
+    pub store: String,
+    pub item: UnitItem,
+    pub lot: UnitLot,
+    /// Sale of the store's `daySales` that sold the unit, if it has an id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sale: Option<ObjectId>,
+    /// Order holding or that bought the unit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<ObjectId>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnitItem {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    /// Current price of a unit, or of a kilogram if `perKg` is set.
+    pub price: f64,
+    #[serde(rename = "perKg")]
+    pub per_kg: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnitLot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(rename = "enterDate", skip_serializing_if = "Option::is_none")]
+    pub enter_date: Option<bson::DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<bson::DateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TrendingSearches {
+    pub trending: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ItemResult {
     #[serde(rename = "_id")]
@@ -48,24 +153,186 @@ pub struct ItemResult {
     price: f64,
     store: String,
     coll: String,
+    stock: i64,
 }
 
 impl From<Item> for ItemResult {
     fn from(item: Item) -> Self {
-        let mut store = "Unknown";
-        for (store_name, colls) in STORE_COLLS.iter() {
-            if colls.contains(&item.coll.as_str()) {
-                store = store_name;
-                break;
-            }
-        };
-
         ItemResult {
             id: item.id,
             name: item.name,
             price: item.price,
-            store: store.to_string(),
+            store: item.store,
             coll: item.coll,
+            stock: item.stock,
         } 
     }
-}
\ No newline at end of file
+}
+
+/// A lot of a store's item which is about to expire, or already did.
+#[derive(Serialize, Debug, Clone)]
+pub struct ExpiringLot {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub lot: ObjectId,
+    /// Store holding the lot, if it was transferred to one other than the store selling the item.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<ObjectId>,
+    pub expiry: bson::DateTime,
+    pub units: usize,
+    /// Negative once it expired.
+    #[serde(rename = "daysLeft")]
+    pub days_left: f64,
+    /// Current price of the item, after any markdown.
+    pub price: f64,
+    /// Discount of the item's current markdown, if this lot caused it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExpiryReport {
+    pub store: String,
+    pub expiring: Vec<ExpiringLot>,
+    /// Expired lots the next check will write off.
+    pub expired: Vec<ExpiringLot>,
+    /// Expired units written off as waste within the report's window.
+    pub wasted: Vec<crate::types::mongodb::inventory::WriteOff>,
+}
+
+/// An item of a store at or under its reorder point.
+#[derive(Serialize, Debug, Clone)]
+pub struct LowStockItem {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    /// Sellable units left.
+    pub units: i64,
+    pub reorder: crate::types::mongodb::inventory::ReorderLevels,
+    /// Whether the levels are the item's own, or its collection's defaults.
+    #[serde(rename = "ownLevels")]
+    pub own_levels: bool,
+    /// Units to order to get back to the target.
+    #[serde(rename = "toOrder")]
+    pub to_order: i64,
+    /// When its open alert was raised, if there's one.
+    #[serde(rename = "alertedAt", skip_serializing_if = "Option::is_none")]
+    pub alerted_at: Option<bson::DateTime>,
+}
+
+/// Difference between the units the inventory ledger says an item has and the
+/// ones in its lots.
+#[derive(Serialize, Debug, Clone)]
+pub struct StockDrift {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    /// Units in stock according to the ledger.
+    pub expected: i64,
+    /// Units in the item's lots.
+    pub actual: i64,
+    /// Units the ledger has in stock, but which aren't in their lot.
+    pub missing: Vec<DriftUnit>,
+    /// Units in a lot without a ledger entry putting them there.
+    pub unrecorded: Vec<DriftUnit>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DriftUnit {
+    pub lot: ObjectId,
+    pub code: bson::Bson,
+    /// Net quantity of the unit's ledger entries for the lot.
+    pub balance: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReconciliationReport {
+    pub store: String,
+    #[serde(rename = "checkedAt")]
+    pub checked_at: bson::DateTime,
+    pub items: usize,
+    pub drift: Vec<StockDrift>,
+}
+
+/// Value of the units of an item in stock.
+#[derive(Serialize, Debug, Clone)]
+pub struct ItemValuation {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub units: i64,
+    pub value: f64,
+    /// Value of a unit, on average.
+    #[serde(rename = "unitCost", skip_serializing_if = "Option::is_none")]
+    pub unit_cost: Option<f64>,
+    /// Units left out of the value, since their lot has no cost price.
+    #[serde(rename = "uncostedUnits")]
+    pub uncosted_units: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ValuationReport {
+    pub store: String,
+    pub method: crate::types::requests::inventory::ValuationMethod,
+    pub at: bson::DateTime,
+    pub units: i64,
+    pub value: f64,
+    #[serde(rename = "uncostedUnits")]
+    pub uncosted_units: i64,
+    pub items: Vec<ItemValuation>,
+}
+
+/// What an item sold for and what the units sold cost, net of returns.
+#[derive(Serialize, Debug, Clone)]
+pub struct ItemMargin {
+    pub coll: String,
+    pub item: ObjectId,
+    pub name: String,
+    pub units: i64,
+    pub revenue: f64,
+    pub cogs: f64,
+    pub margin: f64,
+    /// Units sold whose lot has no cost price, left out of `cogs`.
+    #[serde(rename = "uncostedUnits")]
+    pub uncosted_units: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MarginReport {
+    pub store: String,
+    pub from: bson::DateTime,
+    pub to: bson::DateTime,
+    pub revenue: f64,
+    pub cogs: f64,
+    #[serde(rename = "grossMargin")]
+    pub gross_margin: f64,
+    /// Gross margin as a percentage of revenue, if anything was sold.
+    #[serde(rename = "marginPercent", skip_serializing_if = "Option::is_none")]
+    pub margin_percent: Option<f64>,
+    #[serde(rename = "uncostedUnits")]
+    pub uncosted_units: i64,
+    pub items: Vec<ItemMargin>,
+}
+
+/// Outcome of importing a file of items. `rows` doesn't count the CSV header or blank lines.
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    pub rows: u64,
+    pub created: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    pub failed: u64,
+    /// The first rows which failed, by their line in the file.
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportRowError {
+    pub row: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    pub error: String,
+}
@@ -1,7 +1,17 @@
 pub mod password;
 pub mod tokens;
+pub mod roles;
 
 pub use password::verify_password;
+pub use roles::{
+    Role,
+    authorize,
+    get_session_user_id,
+    user_has_role,
+    is_store_staff,
+    is_store_manager,
+    auth_error_response,
+};
 pub use tokens::{
     issue_session_token,
     verify_session_token,
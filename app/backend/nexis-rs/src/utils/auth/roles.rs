@@ -0,0 +1,114 @@
+use crate::prelude::*;
+use anyhow::Result;
+use crate::types::{ ErrorResponse, error };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Employee,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Client => "client",
+            Role::Employee => "employee",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Verifies the session cookie of a request and, if a role is given,
+/// ensures the session's user has it.
+/// Returns the user id.
+#[tracing::instrument(name = "Authorizing request", skip(req, db, redis_pool))]
+pub async fn authorize(
+    req: &HttpRequest,
+    role: Option<Role>,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+) -> Result<ObjectId> {
+    let user_id = get_session_user_id(req, db, redis_pool)
+        .await?
+        .ok_or(error::Auth::MissingSession)?;
+
+    if let Some(role) = role {
+        if !user_has_role(db, user_id, role).await? {
+            bail!(error::Auth::Forbidden(format!("This action requires the `{}` role.", role.as_str())));
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Returns the id of the user owning the session cookie of a request,
+/// or `None` if the request carries no session cookie.
+pub async fn get_session_user_id(
+    req: &HttpRequest,
+    db: &mongodb::Database,
+    redis_pool: &deadpool_redis::Pool,
+) -> Result<Option<ObjectId>> {
+    let Some(sss_uuid_cookie) = req.cookie("session_uuid") else {
+        return Ok(None);
+    };
+
+    match super::verify_session_token(sss_uuid_cookie.value().to_string(), db, redis_pool).await {
+        Ok(user_id) => Ok(Some(user_id)),
+        Err(e) if e.is::<error::Redis>() => Err(e),
+        Err(e) => bail!(error::Auth::InvalidSession(format!("{}", e))),
+    }
+}
+
+pub async fn user_has_role(
+    db: &mongodb::Database,
+    user_id: ObjectId,
+    role: Role,
+) -> Result<bool> {
+    let users_coll: Collection<Document> = db.collection("user");
+
+    let count = users_coll.count_documents(
+        doc! { "_id": user_id, role.as_str(): { "$exists": true }}
+    ).await?;
+
+    Ok(count > 0)
+}
+
+/// Whether a user works at any of some stores, or is an admin.
+pub async fn is_store_staff(db: &mongodb::Database, user_id: ObjectId, store_ids: &[ObjectId]) -> Result<bool> {
+    if user_has_role(db, user_id, Role::Admin).await? {
+        return Ok(true);
+    }
+
+    Ok(crate::database::get_staff_stores(db, user_id).await?
+        .iter()
+        .any(|store| store_ids.contains(&store.id)))
+}
+
+/// Whether a user owns a store, or is an admin.
+pub async fn is_store_manager(db: &mongodb::Database, user_id: ObjectId, store_id: ObjectId) -> Result<bool> {
+    if crate::database::is_store_owner(db, store_id, user_id).await? {
+        return Ok(true);
+    }
+
+    user_has_role(db, user_id, Role::Admin).await
+}
+
+/// Maps an error returned by `authorize` to the response sent back to the user.
+pub fn auth_error_response(e: anyhow::Error) -> HttpResponse {
+    if let Some(e) = e.downcast_ref::<error::Auth>() {
+        match e {
+            error::Auth::MissingSession =>
+                HttpResponse::Unauthorized().json(ErrorResponse { error: "Session cookie missing.".to_string() }),
+            error::Auth::InvalidSession(msg) =>
+                HttpResponse::Unauthorized().json(ErrorResponse { error: format!("Failed to verify session: {}", msg) }),
+            error::Auth::Forbidden(msg) =>
+                HttpResponse::Forbidden().json(ErrorResponse { error: msg.clone() }),
+        }
+    } else if let Some(error::Redis::SessionExpired(msg)) = e.downcast_ref::<error::Redis>() {
+        HttpResponse::Unauthorized().json(ErrorResponse { error: msg.clone() })
+    } else {
+        tracing::error!(target: "backend", "Failed to authorize request: {}", e);
+        HttpResponse::InternalServerError().finish()
+    }
+}
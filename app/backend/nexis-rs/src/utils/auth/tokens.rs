@@ -259,7 +259,7 @@ pub async fn issue_confirmation_token(
     redis_conn
         .expire::<_, ()>(
             redis_key.clone(),
-            time_to_live.num_seconds().try_into().unwrap()
+            time_to_live.num_seconds()
         )
         .await
         .map_err(|e| {
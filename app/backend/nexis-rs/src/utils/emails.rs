@@ -16,11 +16,7 @@ pub async fn send_email(
             format!(
                 "{} <{}>",
                 "Joemama",
-                if sender_email.is_some() {
-                    sender_email.unwrap()
-                } else {
-                    settings.email.host_user.clone()
-                }
+                sender_email.unwrap_or_else(|| settings.email.host_user.clone())
             )
             .parse()
             .unwrap(),
@@ -87,7 +83,7 @@ pub async fn send_multipart_email(
 
     let issued_token = match crate::utils::issue_confirmation_token(
         user_id,
-        &redis_pool,
+        redis_pool,
         None,
     )
     .await
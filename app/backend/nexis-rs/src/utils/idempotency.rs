@@ -0,0 +1,237 @@
+use crate::prelude::*;
+use crate::database::get_redis_conn;
+use crate::settings::IdempotencySettings;
+use crate::types::ErrorResponse;
+use actix_web::{
+    body::{ self, BoxBody, MessageBody },
+    dev::{ Payload, ServiceRequest, ServiceResponse },
+    error::{ ErrorInternalServerError, PayloadError },
+    middleware::Next,
+};
+use futures_util::StreamExt;
+use sha2::{ Digest, Sha256 };
+use std::time::Duration;
+
+/// Header carrying the key clients pick for a money-moving request.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Set on responses replayed from a previous request with the same key.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// Store the idempotency key prefix as a const so it can't be typo'd anywhere it's used.
+const IDEMPOTENCY_KEY_PREFIX: &str = "idempotency_";
+const MAX_KEY_LENGTH: usize = 255;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What's stored in redis for a key: a lock while the first request runs,
+/// and its response once it's done.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+enum StoredRequest {
+    InProgress {
+        fingerprint: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Completed {
+        fingerprint: String,
+        status: u16,
+        content_type: Option<String>,
+        /// Hex encoded, since it's not always UTF-8.
+        body: String,
+    },
+}
+
+impl StoredRequest {
+    fn fingerprint(&self) -> &str {
+        match self {
+            Self::InProgress { fingerprint } | Self::Completed { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+/// Makes retries of a request carrying an `Idempotency-Key` header safe.
+///
+/// The first request with a key runs normally and its response is stored
+/// per key and user, then replayed to any retry. Retries arriving while it's
+/// still running wait for it, or get a `409 Conflict` if it takes too long.
+/// Reusing a key for a different request gets a `422 Unprocessable Entity`.
+/// Requests without the header aren't affected.
+pub async fn idempotency(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => return Ok(req.into_response(HttpResponse::BadRequest().json(ErrorResponse {
+            error: format!("The {} header must have between 1 and {} visible ASCII characters.", IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH)
+        }))),
+    };
+
+    let (Some(db), Some(redis_pool), Some(settings)) = (
+        req.app_data::<web::Data<mongodb::Database>>().cloned(),
+        req.app_data::<web::Data<deadpool_redis::Pool>>().cloned(),
+        req.app_data::<web::Data<IdempotencySettings>>().cloned(),
+    ) else {
+        tracing::error!(target: "backend", "The idempotency middleware is missing its application data.");
+        return Ok(req.into_response(HttpResponse::InternalServerError().finish()));
+    };
+
+    // Keys are scoped per user. Requests without a valid session, like payment
+    // callbacks, share a scope, and the handler decides what to do with them.
+    let scope = match utils::get_session_user_id(req.request(), &db, &redis_pool).await {
+        Ok(Some(user_id)) => user_id.to_hex(),
+        _ => "anonymous".to_string(),
+    };
+    let redis_key = format!("{}{}_{}", IDEMPOTENCY_KEY_PREFIX, scope, key);
+
+    // The body has to be read to fingerprint the request, then put back for the handler
+    let request_body = req.extract::<web::Bytes>().await?;
+    let fingerprint = fingerprint(&req, &request_body);
+    req.set_payload(bytes_to_payload(request_body));
+
+    let mut redis_conn = match get_redis_conn(&redis_pool).await {
+        Ok(redis_conn) => redis_conn,
+        Err(_) => return Ok(req.into_response(unavailable())),
+    };
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(settings.wait_seconds);
+    loop {
+        let lock = serde_json::to_string(&StoredRequest::InProgress { fingerprint: fingerprint.clone() })?;
+        let locked: Option<String> = match redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(lock)
+            .arg("NX")
+            .arg("EX")
+            .arg(settings.lock_seconds)
+            .query_async(&mut redis_conn)
+            .await
+        {
+            Ok(locked) => locked,
+            Err(e) => {
+                tracing::error!(target: "redis", "Failed to lock idempotency key: {}", e);
+                return Ok(req.into_response(unavailable()));
+            }
+        };
+
+        if locked.is_some() {
+            break;
+        }
+
+        let stored: Option<String> = match redis::cmd("GET").arg(&redis_key).query_async(&mut redis_conn).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::error!(target: "redis", "Failed to read idempotency key: {}", e);
+                return Ok(req.into_response(unavailable()));
+            }
+        };
+
+        // The lock expired or was released in the meantime
+        let Some(stored) = stored else { continue };
+        let stored: StoredRequest = serde_json::from_str(&stored)?;
+
+        if stored.fingerprint() != fingerprint {
+            return Ok(req.into_response(HttpResponse::UnprocessableEntity().json(ErrorResponse {
+                error: format!("This {} was already used for a different request.", IDEMPOTENCY_KEY_HEADER)
+            })));
+        }
+
+        match stored {
+            StoredRequest::Completed { status, content_type, body, .. } => {
+                tracing::info!(target: "backend", "Replaying the response of idempotency key `{}`.", key);
+                return Ok(req.into_response(replay(status, content_type, &body)?));
+            }
+            StoredRequest::InProgress { .. } if tokio::time::Instant::now() >= deadline => {
+                return Ok(req.into_response(HttpResponse::Conflict().json(ErrorResponse {
+                    error: format!("A request with this {} is still being processed.", IDEMPOTENCY_KEY_HEADER)
+                })));
+            }
+            StoredRequest::InProgress { .. } => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+    }
+
+    let res = match next.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            release(&mut redis_conn, &redis_key).await;
+            return Err(e);
+        }
+    };
+
+    // Server errors and rejected credentials aren't stored, so they can be retried
+    // with the same key, and so unauthenticated requests can't claim a key.
+    let status = res.status();
+    if status.is_server_error() || matches!(status, http::StatusCode::UNAUTHORIZED | http::StatusCode::FORBIDDEN) {
+        release(&mut redis_conn, &redis_key).await;
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (http_req, res) = res.map_into_boxed_body().into_parts();
+    let (res, response_body) = res.into_parts();
+    let response_body = body::to_bytes(response_body).await.map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    let completed = StoredRequest::Completed {
+        fingerprint,
+        status: status.as_u16(),
+        content_type: res.headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        body: hex::encode(&response_body),
+    };
+    if let Err(e) = redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(serde_json::to_string(&completed)?)
+        .arg("EX")
+        .arg(settings.ttl_seconds)
+        .query_async::<_, ()>(&mut redis_conn)
+        .await
+    {
+        tracing::error!(target: "redis", "Failed to store the response of idempotency key `{}`: {}", key, e);
+    }
+
+    Ok(ServiceResponse::new(http_req, res.set_body(BoxBody::new(response_body))))
+}
+
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b" ");
+    hasher.update(req.path());
+    hasher.update(b"?");
+    hasher.update(req.query_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn bytes_to_payload(bytes: web::Bytes) -> Payload {
+    Payload::from(futures_util::stream::once(async move { Ok::<_, PayloadError>(bytes) }).boxed_local())
+}
+
+fn replay(status: u16, content_type: Option<String>, body: &str) -> Result<HttpResponse, actix_web::Error> {
+    let status = http::StatusCode::from_u16(status).map_err(ErrorInternalServerError)?;
+    let body = hex::decode(body).map_err(ErrorInternalServerError)?;
+
+    let mut res = HttpResponse::build(status);
+    res.insert_header((IDEMPOTENT_REPLAYED_HEADER, "true"));
+    if let Some(content_type) = content_type {
+        res.insert_header((http::header::CONTENT_TYPE, content_type));
+    }
+
+    Ok(res.body(body))
+}
+
+async fn release(redis_conn: &mut deadpool_redis::Connection, redis_key: &str) {
+    if let Err(e) = redis::cmd("DEL").arg(redis_key).query_async::<_, ()>(redis_conn).await {
+        tracing::error!(target: "redis", "Failed to release idempotency key: {}", e);
+    }
+}
+
+fn unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(ErrorResponse {
+        error: "Idempotency keys can't be checked right now. Please try again later.".to_string()
+    })
+}
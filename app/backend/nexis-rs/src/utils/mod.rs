@@ -1,7 +1,10 @@
 pub mod auth;
 pub mod emails;
+pub mod idempotency;
+pub mod stock;
 
 pub use emails::send_multipart_email;
+pub use idempotency::idempotency;
 pub use auth::{
     verify_password,
     issue_session_token,
@@ -9,4 +12,11 @@ pub use auth::{
     revoke_session_token,
     issue_confirmation_token,
     verify_confirmation_token,
+    Role,
+    authorize,
+    get_session_user_id,
+    user_has_role,
+    is_store_staff,
+    is_store_manager,
+    auth_error_response,
 };
\ No newline at end of file
@@ -0,0 +1,134 @@
+use crate::prelude::*;
+use crate::types::mongodb::{ items::LotUnits, stores::StoreInfo };
+
+/// Reads a date field which may be stored either as a BSON date or,
+/// as the mock generator does, as an RFC 3339 string.
+pub fn get_date(doc: &Document, key: &str) -> Option<bson::DateTime> {
+    match doc.get(key)? {
+        bson::Bson::DateTime(date) => Some(*date),
+        bson::Bson::String(date) => bson::DateTime::parse_rfc3339_str(date).ok(),
+        _ => None,
+    }
+}
+
+/// Reads when a lot entered the store. Lots made by the mock generator
+/// may name the field `enter_date`.
+pub fn lot_enter_date(lot: &Document) -> Option<bson::DateTime> {
+    get_date(lot, "enterDate").or_else(|| get_date(lot, "enter_date"))
+}
+
+/// Whether the units of a lot can still be sold. Only food lots have an
+/// `expiry`, and they can't be sold once it's reached.
+pub fn lot_is_sellable(lot: &Document, now: bson::DateTime) -> bool {
+    match get_date(lot, "expiry") {
+        Some(expiry) => expiry > now,
+        None => true,
+    }
+}
+
+/// Whether a lot is held by the store selling its item. Lots transferred to
+/// another store name it in their `store`, and aren't part of the item's stock.
+pub fn lot_is_home(lot: &Document) -> bool {
+    !lot.contains_key("store")
+}
+
+/// Item collections with lots a store may hold, and whether they're its own.
+/// It holds the lots of its own collections which weren't transferred away,
+/// and the lots of the others transferred to it.
+pub fn holdable_colls(store: &StoreInfo) -> Vec<(String, bool)> {
+    let mut colls: Vec<(String, bool)> = store.item_colls.iter().map(|coll| (coll.clone(), true)).collect();
+    colls.extend(stores::item_colls().into_iter().filter(|coll| !store.item_colls.contains(coll)).map(|coll| (coll, false)));
+    colls
+}
+
+/// Whether a store holds a lot of one of the collections of `holdable_colls`.
+pub fn lot_held_by(lot: &Document, store: ObjectId, own: bool) -> bool {
+    if own {
+        lot_is_home(lot)
+    } else {
+        lot.get_object_id("store").ok() == Some(store)
+    }
+}
+
+/// Summarizes the lots of an item document held by its store into their unit
+/// count and expiry.
+pub fn lot_units(item: &Document) -> Vec<LotUnits> {
+    item.get_array("lot")
+        .map(|lots| lots.iter()
+            .filter_map(|lot| lot.as_document())
+            .filter(|lot| lot_is_home(lot))
+            .map(|lot| LotUnits {
+                units: lot.get_array("code").map(|codes| codes.len() as i64).unwrap_or(0),
+                expiry: get_date(lot, "expiry"),
+            })
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Counts the units of an item that can be sold at `now`.
+pub fn available_units(lots: &[LotUnits], now: bson::DateTime) -> i64 {
+    lots.iter()
+        .filter(|lot| lot.expiry.is_none_or(|expiry| expiry > now))
+        .map(|lot| lot.units)
+        .sum()
+}
+
+/// Aggregation expression computing the available units of an `items`
+/// document from its `lotUnits` at the time the query runs.
+pub fn stock_expr() -> Document {
+    doc! {
+        "$sum": {
+            "$map": {
+                "input": {
+                    "$filter": {
+                        "input": { "$ifNull": ["$lotUnits", []] },
+                        "as": "lot",
+                        "cond": { "$or": [
+                            { "$eq": [{ "$ifNull": ["$$lot.expiry", null] }, null] },
+                            { "$gt": ["$$lot.expiry", "$$NOW"] },
+                        ]},
+                    }
+                },
+                "as": "lot",
+                "in": "$$lot.units",
+            }
+        }
+    }
+}
+
+/// Picks `quantity` unit codes of an item from the sellable lots held by its store, taking the ones
+/// expiring first, then the oldest. Returns the codes grouped by lot id, or `None`
+/// if there aren't enough units.
+pub fn pick_units(item: &Document, quantity: u32, now: bson::DateTime) -> Option<Vec<(ObjectId, Vec<bson::Bson>)>> {
+    let mut lots: Vec<&Document> = item.get_array("lot")
+        .map(|lots| lots.iter()
+            .filter_map(|lot| lot.as_document())
+            .filter(|lot| lot_is_home(lot) && lot_is_sellable(lot, now))
+            .collect())
+        .unwrap_or_default();
+
+    lots.sort_by_key(|lot| (
+        get_date(lot, "expiry").is_none(),
+        get_date(lot, "expiry"),
+        lot_enter_date(lot),
+    ));
+
+    let mut missing = quantity as usize;
+    let mut picked = Vec::new();
+
+    for lot in lots {
+        if missing == 0 {
+            break;
+        }
+        let (Ok(lot_id), Ok(codes)) = (lot.get_object_id("_id"), lot.get_array("code")) else { continue };
+        if codes.is_empty() {
+            continue;
+        }
+
+        let codes: Vec<bson::Bson> = codes.iter().take(missing).cloned().collect();
+        missing -= codes.len();
+        picked.push((lot_id, codes));
+    }
+
+    if missing == 0 { Some(picked) } else { None }
+}
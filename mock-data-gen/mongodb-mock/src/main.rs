@@ -140,15 +140,9 @@ async fn main() -> mongodb::error::Result<()> {
     ]).await?;
     println!("- Inserted: other techs");
 
-    let store_ids: HashMap<&str, ObjectIdWrapper> = (0..4).map(|i| {
-        match i {
-            0 => ("clothes", ObjectIdWrapper::dummy_with_rng(&Faker, &mut rng)),
-            1 => ("food", ObjectIdWrapper::dummy_with_rng(&Faker, &mut rng)),
-            2 => ("library", ObjectIdWrapper::dummy_with_rng(&Faker, &mut rng)),
-            3 => ("tech", ObjectIdWrapper::dummy_with_rng(&Faker, &mut rng)),
-            _ => unimplemented!(),
-        }
-    }).collect();
+    let store_ids: HashMap<&str, ObjectIdWrapper> = STORES.iter().map(|store|
+        (store.store_type, ObjectIdWrapper::dummy_with_rng(&Faker, &mut rng))
+    ).collect();
 
     let store_ids_values: Vec<ObjectIdWrapper> = store_ids.values().map(|val| val.clone()).collect();
 
@@ -187,21 +181,11 @@ async fn main() -> mongodb::error::Result<()> {
 
     let stores_coll: Collection<Store> = db.collection("store");
 
-    let store: Store = Store::dummy_with_rng("clothes", &store_ids, &client, &fake::Faker, &mut rng).await?;
-    stores_coll.insert_one(store).await?;
-    println!("- Inserted: clothes store");
-    
-    let store: Store = Store::dummy_with_rng("food", &store_ids, &client, &fake::Faker, &mut rng).await?;
-    stores_coll.insert_one(store).await?;
-    println!("- Inserted: food store");
-
-    let store: Store = Store::dummy_with_rng("library", &store_ids, &client, &fake::Faker, &mut rng).await?;
-    stores_coll.insert_one(store).await?;
-    println!("- Inserted: library store");
-
-    let store: Store = Store::dummy_with_rng("tech", &store_ids, &client, &fake::Faker, &mut rng).await?;
-    stores_coll.insert_one(store).await?;
-    println!("- Inserted: tech store");
+    for store_def in STORES.iter() {
+        let store: Store = Store::dummy_with_rng(store_def.store_type, &store_ids, &client, &fake::Faker, &mut rng).await?;
+        stores_coll.insert_one(store).await?;
+        println!("- Inserted: {} store", store_def.store_type);
+    }
 
     let items_coll: Collection<Item> = db.collection("items");
     let mut items: Vec<Item> = Vec::new();
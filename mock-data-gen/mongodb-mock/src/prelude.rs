@@ -29,10 +29,31 @@ use argon2::{
 };
 use once_cell::sync::Lazy;
 
-pub static ITEM_COLLS: Lazy<Vec<&'static str>> = Lazy::new(|| vec![
-    "clothes", "food", "libraryItem", "techCpu", "techGpu", "tech", "techOther", "techKeyboard"
+pub struct StoreDef {
+    pub store_type: &'static str,
+    pub name: &'static str,
+    pub item_colls: Vec<&'static str>,
+}
+
+/// Stores of the mall and the item collections each of them sells.
+/// The backend reads the same mapping from the `itemColls` field of each store.
+pub static STORES: Lazy<Vec<StoreDef>> = Lazy::new(|| vec![
+    StoreDef { store_type: "clothes", name: "vesti", item_colls: vec!["clothes"] },
+    StoreDef { store_type: "food", name: "savoro", item_colls: vec!["food"] },
+    StoreDef { store_type: "library", name: "readon", item_colls: vec!["libraryItem"] },
+    StoreDef { store_type: "tech", name: "cyberion", item_colls: vec!["techCpu", "techGpu", "tech", "techOther", "techKeyboard"] },
 ]);
 
+pub static ITEM_COLLS: Lazy<Vec<&'static str>> = Lazy::new(||
+    STORES.iter().flat_map(|store| store.item_colls.clone()).collect()
+);
+
+pub fn get_store_def(store_type: &str) -> &'static StoreDef {
+    STORES.iter()
+        .find(|store| store.store_type == store_type)
+        .unwrap_or_else(|| unimplemented!("Unknown store type `{store_type}`"))
+}
+
 pub static COLORS: Lazy<Vec<&'static str>> = Lazy::new(|| vec![
     "red", "green", "blue", "yellow", "orange", "teal", "purple", "pink", "white", "black", "brown"
 ]);
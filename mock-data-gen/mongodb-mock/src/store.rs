@@ -99,13 +99,7 @@ fn process_item<T: SimpleItemTrait>(item: T, collection: String, items: &mut Vec
 
 impl DaySales {
     pub async fn dummy_with_rng<R: rand::Rng + ?Sized>(payment: Payment, client_info: ClientSaleInfo, store: &str, client: &Client, rng: &mut R) -> Result<Self, mongodb::error::Error> {
-        let item_colls = match store {
-            "clothes" => vec!["clothes"],
-            "food" => vec!["food"],
-            "library" => vec!["libraryItem"],
-            "tech" => vec!["tech", "techCpu", "techGpu", "techKeyboard", "techOther"],
-            _ => unimplemented!()
-        };
+        let item_colls = &get_store_def(store).item_colls;
 
         let db = client.database("nexis");
 
@@ -175,6 +169,8 @@ pub struct Store {
     day_sales: Vec<DaySales>,
     owner: Vec<Owner>,
     employee: Vec<ObjectIdWrapper>,
+    #[serde(rename = "itemColls")]
+    item_colls: Vec<String>,
 }
 
 impl Store {
@@ -254,20 +250,12 @@ impl Store {
             employees
         };
 
-        let name = String::from(
-            match store_type {
-                "clothes" => "vesti",
-                "food" => "savoro",
-                "library" => "readon",
-                "tech" => "cyberion",
-                _ => unimplemented!()
-            }
-        );
+        let store_def = get_store_def(store_type);
 
         Ok(
             Store {
                 _id: id.clone(),
-                name,
+                name: store_def.name.to_string(),
                 num: rng.gen_range(100..200),
                 floor: rng.gen_range(0..1),
                 size: Size::dummy_with_rng(config, rng),
@@ -279,6 +267,7 @@ impl Store {
                     }
                 ).collect(),
                 employee: employees,
+                item_colls: store_def.item_colls.iter().map(|coll| coll.to_string()).collect(),
             }
         )
     }